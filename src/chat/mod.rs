@@ -1,5 +1,8 @@
 use crate::config::{Config, LLMConfig};
-use crate::llm::client::create_client;
+use crate::llm::client::{
+    collect_stream, create_client, ChatRequest, LLMClient, LLMResponse, Message, ToolCall,
+    ToolCallResult,
+};
 use crate::models::problem::SWEBenchProblem;
 use anyhow::{Context, Result};
 use tokio::sync::mpsc;
@@ -14,6 +17,23 @@ pub struct ChatMessage {
     pub content: String,
 }
 
+/// Updates sent from the chat loop to the UI
+#[derive(Debug, Clone)]
+pub enum ChatUpdate {
+    /// A complete message to add to the history
+    Message(ChatMessage),
+    /// A chunk of a streamed assistant response
+    Delta(String),
+    /// The streamed response is complete
+    StreamEnd,
+    /// The model is working on a response, shown until the response starts
+    /// streaming in or the stream ends
+    Thinking,
+}
+
+/// Maximum number of tool-calling rounds for a single user message
+const MAX_TOOL_ROUNDS: usize = 10;
+
 /// Configuration for the chat session
 #[derive(Debug, Clone)]
 pub struct ChatConfig {
@@ -52,7 +72,7 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
     );
 
     // Create channels for communication between UI and chat processing
-    let (ui_tx, ui_rx) = mpsc::channel::<ChatMessage>(100);
+    let (ui_tx, ui_rx) = mpsc::unbounded_channel::<ChatUpdate>();
     let (input_tx, mut input_rx) = mpsc::channel::<String>(10);
 
     // Load the application config for tool execution
@@ -65,16 +85,10 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
     )
     .with_codebase_path(&app_config.codebase.path);
 
-    // Keep track of the conversation history
-    let mut history = Vec::new();
-
-    // Add initial system message
-    let system_message = ChatMessage {
-        role: "system".to_string(),
-        content: create_system_prompt(),
-    };
-
-    history.push(system_message.clone());
+    // Keep track of the conversation history sent to the LLM
+    let mut history: Vec<Message> = Vec::new();
+    let system_prompt = create_system_prompt();
+    let tool_definitions = tools::tool_definitions();
 
     // Send welcome message to UI
     let welcome_message = ChatMessage {
@@ -95,10 +109,7 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
     // Allow UI time to initialize before sending welcome message
     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-    if let Err(e) = ui_tx.send(welcome_message.clone()).await {
-        log::error!("Failed to send welcome message: {}", e);
-    }
-    history.push(welcome_message);
+    send_message(&ui_tx, welcome_message);
 
     // Main chat loop
     while let Some(input) = input_rx.recv().await {
@@ -106,13 +117,6 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
             break;
         }
 
-        // Add user message to history
-        let user_message = ChatMessage {
-            role: "user".to_string(),
-            content: input.clone(),
-        };
-        history.push(user_message);
-
         // Handle built-in commands
         if input.trim().eq_ignore_ascii_case("help") {
             let help_message = ChatMessage {
@@ -125,120 +129,84 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
                         .join("\n"),
             };
 
-            if let Err(e) = ui_tx.send(help_message.clone()).await {
-                log::error!("Failed to send help message: {}", e);
-            }
-            history.push(help_message);
+            send_message(&ui_tx, help_message);
             continue;
         }
 
-        // Create "thinking" message
-        let thinking_message = ChatMessage {
-            role: "assistant".to_string(),
-            content: "Thinking...".to_string(),
-        };
-        if let Err(e) = ui_tx.send(thinking_message).await {
-            log::error!("Failed to send thinking message: {}", e);
-        }
-
-        // Create prompt from history
-        let prompt = create_prompt(&history);
-
-        // Get response from LLM
-        match llm_client
-            .completion(&prompt, config.max_tokens, config.temperature)
-            .await
-        {
-            Ok(response) => {
-                // Check if the response contains a tool call
-                if let Some((tool_name, params)) = tools::parse_tool_call(&response.content) {
-                    // Execute the tool
-                    let tool_call_message = ChatMessage {
-                        role: "assistant".to_string(),
-                        content: format!("I'll run the '{}' command for you...", tool_name),
-                    };
-                    if let Err(e) = ui_tx.send(tool_call_message.clone()).await {
-                        log::error!("Failed to send tool call message: {}", e);
-                    }
-                    history.push(tool_call_message);
-
-                    // Create a temporary directory to hold outputs
-                    let temp_dir = tempfile::tempdir().unwrap();
-                    let log_file_path = temp_dir.path().join("tool_output.log");
-
-                    // Set a special environment variable to signal to use a different log file
-                    std::env::set_var("ENGINE_BUILDER_TOOL_LOG", log_file_path.to_str().unwrap());
-
-                    // Use gag crate to redirect stdout to a file
-                    let stdout_file =
-                        std::fs::File::create(temp_dir.path().join("stdout.log")).unwrap();
-                    let stdout_redirect = gag::Redirect::stdout(stdout_file).unwrap();
-
-                    // Execute the tool
-                    let result =
-                        tools::execute_tool(&tool_name, &params, &app_config, &problem).await;
-
-                    // Stop redirecting stdout
-                    drop(stdout_redirect);
-
-                    // Unset the environment variable
-                    std::env::remove_var("ENGINE_BUILDER_TOOL_LOG");
-
-                    match result {
-                        Ok(result) => {
-                            // Create tool result message
-                            let result_message = ChatMessage {
-                                role: "assistant".to_string(),
-                                content: format!(
-                                    "Result: {} - {}",
-                                    if result.success { "SUCCESS" } else { "FAILED" },
-                                    result.output
-                                ),
-                            };
-
-                            if let Err(e) = ui_tx.send(result_message.clone()).await {
-                                log::error!("Failed to send result message: {}", e);
-                            }
-                            history.push(result_message);
-                        }
-                        Err(e) => {
-                            // Create error message
-                            let error_message = ChatMessage {
-                                role: "assistant".to_string(),
-                                content: format!("Error executing tool: {}", e),
-                            };
-
-                            if let Err(e) = ui_tx.send(error_message.clone()).await {
-                                log::error!("Failed to send error message: {}", e);
-                            }
-                            history.push(error_message);
-                        }
-                    }
-                } else {
-                    // Regular response
-                    let response_message = ChatMessage {
-                        role: "assistant".to_string(),
-                        content: response.content.clone(),
-                    };
-
-                    if let Err(e) = ui_tx.send(response_message.clone()).await {
-                        log::error!("Failed to send response message: {}", e);
-                    }
-                    history.push(response_message);
+        // Add user message to history, remembering where this turn started so
+        // a failed turn can be rolled back
+        let turn_start = history.len();
+        history.push(Message::user(input));
+
+        // Let the model call tools until it gives a final answer
+        let mut final_answer = false;
+        for _ in 0..MAX_TOOL_ROUNDS {
+            send_update(&ui_tx, ChatUpdate::Thinking);
+
+            let mut request = ChatRequest::new(config.max_tokens, config.temperature)
+                .with_system(system_prompt.clone())
+                .with_tools(tool_definitions.clone());
+            request.messages = history.clone();
+
+            // Stream the response from the LLM into the UI as it arrives
+            let response = match stream_response(llm_client.as_ref(), &request, &ui_tx).await {
+                Ok(response) => response,
+                Err(e) => {
+                    send_message(
+                        &ui_tx,
+                        assistant_message(format!("Error getting response: {}", e)),
+                    );
+
+                    // Drop the unanswered turn so the history stays valid
+                    history.truncate(turn_start);
+                    final_answer = true;
+                    break;
                 }
+            };
+
+            if response.tool_calls.is_empty() {
+                // Regular response, already shown in the UI as it streamed
+                history.push(Message::assistant(response.content));
+                final_answer = true;
+                break;
             }
-            Err(e) => {
-                // Send error message
-                let error_message = ChatMessage {
-                    role: "assistant".to_string(),
-                    content: format!("Error getting response: {}", e),
-                };
-
-                if let Err(e) = ui_tx.send(error_message.clone()).await {
-                    log::error!("Failed to send LLM error message: {}", e);
-                }
-                history.push(error_message);
+
+            history.push(Message::assistant_tool_calls(
+                response.content,
+                response.tool_calls.clone(),
+            ));
+
+            // Run each requested tool and send all the results back together
+            let mut results = Vec::new();
+            for call in &response.tool_calls {
+                send_message(
+                    &ui_tx,
+                    assistant_message(format!("I'll run the '{}' command for you...", call.name)),
+                );
+
+                let result = run_tool_call(call, &app_config, &problem).await;
+
+                send_message(
+                    &ui_tx,
+                    assistant_message(format!(
+                        "Result: {} - {}",
+                        if result.is_error { "FAILED" } else { "SUCCESS" },
+                        result.content
+                    )),
+                );
+                results.push(result);
             }
+            history.push(Message::tool_results(results));
+        }
+
+        if !final_answer {
+            send_message(
+                &ui_tx,
+                assistant_message(format!(
+                    "Stopped after {} rounds of tool calls without a final answer.",
+                    MAX_TOOL_ROUNDS
+                )),
+            );
         }
     }
 
@@ -249,71 +217,98 @@ pub async fn start_chat(config: ChatConfig) -> Result<()> {
     Ok(())
 }
 
-/// Create the system prompt with tool descriptions
-fn create_system_prompt() -> String {
-    let mut prompt = String::new();
+/// Create an assistant message for the UI
+fn assistant_message(content: impl Into<String>) -> ChatMessage {
+    ChatMessage {
+        role: "assistant".to_string(),
+        content: content.into(),
+    }
+}
 
-    prompt.push_str(
-        "You are a helpful assistant with access to all command-line tools from engine-builder.\n",
-    );
-    prompt.push_str("You can use the following tools to help the user with their tasks:\n\n");
-
-    // Add tool descriptions
-    for tool in tools::get_tools() {
-        prompt.push_str(&format!("- {}: {}\n", tool.name, tool.description));
-
-        // Add parameter descriptions if any
-        if !tool.parameters.is_empty() {
-            prompt.push_str("  Parameters:\n");
-            for (name, param) in &tool.parameters {
-                let default_str = param
-                    .default
-                    .as_ref()
-                    .map(|d| format!(" (default: {})", d))
-                    .unwrap_or_else(|| "".to_string());
-
-                prompt.push_str(&format!(
-                    "  - {}: {}{}\n",
-                    name, param.description, default_str
-                ));
-            }
-        }
+/// Send a message to the UI
+fn send_message(ui_tx: &mpsc::UnboundedSender<ChatUpdate>, message: ChatMessage) {
+    send_update(ui_tx, ChatUpdate::Message(message));
+}
 
-        prompt.push_str("\n");
+/// Send an update to the UI
+fn send_update(ui_tx: &mpsc::UnboundedSender<ChatUpdate>, update: ChatUpdate) {
+    if let Err(e) = ui_tx.send(update) {
+        log::error!("Failed to send update to UI: {}", e);
     }
+}
 
-    prompt.push_str(
-        "\nTo use a tool, use the format: TOOL: tool_name(param1=value1, param2=value2)\n",
-    );
-    prompt.push_str("For example: TOOL: build_image(tag=\"my-image\")\n\n");
-    prompt.push_str("You should always provide a brief explanation before using a tool, and explain the results after.\n\n");
+/// Execute a tool call with its output captured, so it doesn't corrupt the UI
+async fn run_tool_call(
+    call: &ToolCall,
+    config: &Config,
+    problem: &SWEBenchProblem,
+) -> ToolCallResult {
+    // Create a temporary directory to hold outputs
+    let temp_dir = tempfile::tempdir().unwrap();
+    let log_file_path = temp_dir.path().join("tool_output.log");
 
-    prompt
-}
+    // Set a special environment variable to signal to use a different log file
+    std::env::set_var("ENGINE_BUILDER_TOOL_LOG", log_file_path.to_str().unwrap());
 
-/// Create a prompt from the conversation history
-fn create_prompt(history: &[ChatMessage]) -> String {
-    // This implementation works for both Anthropic and OpenAI models
-    let mut prompt = String::new();
+    // Use gag crate to redirect stdout to a file
+    let stdout_file = std::fs::File::create(temp_dir.path().join("stdout.log")).unwrap();
+    let stdout_redirect = gag::Redirect::stdout(stdout_file).unwrap();
 
-    // Add the conversation history
-    for message in history {
-        match message.role.as_str() {
-            "system" => {
-                prompt.push_str(&format!("System: {}\n\n", message.content));
-            }
-            "user" => {
-                prompt.push_str(&format!("Human: {}\n\n", message.content));
-            }
-            "assistant" => {
-                prompt.push_str(&format!("Assistant: {}\n\n", message.content));
-            }
-            _ => {}
-        }
+    // Execute the tool
+    let result = tools::execute_tool_call(call, config, problem).await;
+
+    // Stop redirecting stdout
+    drop(stdout_redirect);
+
+    // Unset the environment variable
+    std::env::remove_var("ENGINE_BUILDER_TOOL_LOG");
+
+    let (is_error, content) = match result {
+        Ok(result) => (!result.success, result.output),
+        Err(e) => (true, format!("Error executing tool: {}", e)),
+    };
+
+    ToolCallResult {
+        tool_call_id: call.id.clone(),
+        content,
+        is_error,
     }
+}
 
-    // Add the final prompt for the assistant
-    prompt.push_str("Assistant: ");
+/// Stream a response into the UI, returning the complete response once done
+async fn stream_response(
+    client: &dyn LLMClient,
+    request: &ChatRequest,
+    ui_tx: &mpsc::UnboundedSender<ChatUpdate>,
+) -> Result<LLMResponse> {
+    let result = match client.chat_stream(request).await {
+        Ok(stream) => {
+            collect_stream(stream, &mut |delta| {
+                send_update(ui_tx, ChatUpdate::Delta(delta.to_string()))
+            })
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    // Sent even if the request failed, to stop showing that the model is
+    // thinking
+    send_update(ui_tx, ChatUpdate::StreamEnd);
+    result
+}
+
+/// Create the system prompt. The tools themselves are sent as schemas with
+/// each request.
+fn create_system_prompt() -> String {
+    let mut prompt = String::new();
+
+    prompt.push_str(
+        "You are a helpful assistant with access to all command-line tools from engine-builder.\n",
+    );
+    prompt.push_str(
+        "Use the provided tools to help the user with their tasks. You can call several tools in a row, and you will see each tool's result before continuing.\n\n",
+    );
+    prompt.push_str("You should always provide a brief explanation before using a tool, and explain the results after.\n\n");
 
     prompt
 }
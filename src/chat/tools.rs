@@ -1,8 +1,10 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
 
-use crate::config::Config;
+use crate::config::{Config, ContainerConfig};
+use crate::llm::client::{ToolCall, ToolDefinition};
 use crate::models::problem::SWEBenchProblem;
 use crate::stages;
 
@@ -11,7 +13,8 @@ use crate::stages;
 pub struct Tool {
     pub name: String,
     pub description: String,
-    pub parameters: HashMap<String, ToolParameter>,
+    /// Parameters by name; ordered so the generated schema is stable
+    pub parameters: BTreeMap<String, ToolParameter>,
     pub required_parameters: Vec<String>,
 }
 
@@ -20,8 +23,173 @@ pub struct Tool {
 pub struct ToolParameter {
     pub name: String,
     pub description: String,
-    pub parameter_type: String,
-    pub default: Option<String>,
+    pub parameter_type: ParameterType,
+    pub default: Option<ToolValue>,
+}
+
+/// The type of a tool parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    String,
+    Boolean,
+    Integer,
+}
+
+impl ParameterType {
+    /// The JSON Schema name of the type
+    pub fn schema_type(&self) -> &'static str {
+        match self {
+            ParameterType::String => "string",
+            ParameterType::Boolean => "boolean",
+            ParameterType::Integer => "integer",
+        }
+    }
+}
+
+/// A typed value for a tool parameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolValue {
+    String(String),
+    Boolean(bool),
+    Integer(i64),
+}
+
+impl ToolValue {
+    /// Convert a JSON argument to the given type. Models occasionally quote
+    /// booleans and numbers, so strings holding them are accepted too.
+    fn from_json(value: &Value, parameter_type: ParameterType) -> Option<Self> {
+        match (parameter_type, value) {
+            (ParameterType::String, Value::String(s)) => Some(ToolValue::String(s.clone())),
+            (ParameterType::Boolean, Value::Bool(b)) => Some(ToolValue::Boolean(*b)),
+            (ParameterType::Boolean, Value::String(s)) => match s.to_lowercase().as_str() {
+                "true" => Some(ToolValue::Boolean(true)),
+                "false" => Some(ToolValue::Boolean(false)),
+                _ => None,
+            },
+            (ParameterType::Integer, Value::Number(n)) => n.as_i64().map(ToolValue::Integer),
+            (ParameterType::Integer, Value::String(s)) => {
+                s.trim().parse().ok().map(ToolValue::Integer)
+            }
+            _ => None,
+        }
+    }
+
+    /// The value as JSON
+    fn to_json(&self) -> Value {
+        match self {
+            ToolValue::String(s) => json!(s),
+            ToolValue::Boolean(b) => json!(b),
+            ToolValue::Integer(i) => json!(i),
+        }
+    }
+}
+
+/// Validated arguments for a tool call, with defaults applied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolArguments(HashMap<String, ToolValue>);
+
+impl ToolArguments {
+    /// Get a string argument
+    pub fn string(&self, name: &str) -> Option<&str> {
+        match self.0.get(name) {
+            Some(ToolValue::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get a boolean argument
+    pub fn boolean(&self, name: &str) -> Option<bool> {
+        match self.0.get(name) {
+            Some(ToolValue::Boolean(b)) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Get an integer argument
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.0.get(name) {
+            Some(ToolValue::Integer(i)) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+impl Tool {
+    /// Describe the tool for the LLM, with a JSON Schema for its parameters
+    pub fn definition(&self) -> ToolDefinition {
+        let properties: serde_json::Map<String, Value> = self
+            .parameters
+            .iter()
+            .map(|(name, param)| {
+                let mut schema = json!({
+                    "type": param.parameter_type.schema_type(),
+                    "description": param.description,
+                });
+                if let Some(default) = &param.default {
+                    schema["default"] = default.to_json();
+                }
+                (name.clone(), schema)
+            })
+            .collect();
+
+        ToolDefinition {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            input_schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": self.required_parameters,
+            }),
+        }
+    }
+
+    /// Validate the JSON arguments of a call to this tool and apply defaults
+    pub fn parse_arguments(&self, input: &Value) -> Result<ToolArguments> {
+        let empty = serde_json::Map::new();
+        let input = match input {
+            Value::Object(map) => map,
+            Value::Null => &empty,
+            other => return Err(anyhow!("Arguments must be a JSON object, got: {}", other)),
+        };
+
+        if let Some(unknown) = input.keys().find(|key| !self.parameters.contains_key(*key)) {
+            return Err(anyhow!(
+                "Unknown parameter '{}' for tool '{}'",
+                unknown,
+                self.name
+            ));
+        }
+
+        let mut arguments = HashMap::new();
+        for (name, param) in &self.parameters {
+            match input.get(name) {
+                Some(value) => {
+                    let value =
+                        ToolValue::from_json(value, param.parameter_type).ok_or_else(|| {
+                            anyhow!(
+                                "Parameter '{}' must be of type {}, got: {}",
+                                name,
+                                param.parameter_type.schema_type(),
+                                value
+                            )
+                        })?;
+                    arguments.insert(name.clone(), value);
+                }
+                None if self.required_parameters.contains(name) => {
+                    return Err(anyhow!("Missing required parameter '{}'", name));
+                }
+                None => {
+                    if let Some(default) = &param.default {
+                        arguments.insert(name.clone(), default.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(ToolArguments(arguments))
+    }
 }
 
 /// Result of a tool execution
@@ -37,25 +205,25 @@ pub fn get_tools() -> Vec<Tool> {
         Tool {
             name: "relevance".to_string(),
             description: "Run file relevance assessment".to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
             name: "ranking".to_string(),
             description: "Run file ranking".to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
             name: "pipeline".to_string(),
             description: "Run full pipeline (relevance and dockerfile generation)".to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
             name: "file_selection".to_string(),
             description: "Run only the file selection step".to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
@@ -63,143 +231,138 @@ pub fn get_tools() -> Vec<Tool> {
             description:
                 "Generate a test-focused Dockerfile for running tests based on relevant files"
                     .to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
             name: "build_image".to_string(),
             description: "Build a Docker image from the generated Dockerfile".to_string(),
-            parameters: {
-                let mut params = HashMap::new();
-                params.insert(
-                    "tag".to_string(),
-                    ToolParameter {
-                        name: "tag".to_string(),
-                        description: "Tag name for the Docker image".to_string(),
-                        parameter_type: "string".to_string(),
-                        default: Some("engine-builder-test".to_string()),
-                    },
-                );
-                params
-            },
+            parameters: BTreeMap::from([tag_parameter()]),
             required_parameters: vec![],
         },
         Tool {
             name: "generate_scripts".to_string(),
             description: "Generate lint and test scripts based on relevant files".to_string(),
-            parameters: HashMap::new(),
+            parameters: BTreeMap::new(),
             required_parameters: vec![],
         },
         Tool {
             name: "run_lint".to_string(),
             description: "Run lint script in a Docker container".to_string(),
-            parameters: {
-                let mut params = HashMap::new();
-                params.insert(
-                    "tag".to_string(),
-                    ToolParameter {
-                        name: "tag".to_string(),
-                        description: "Tag name for the Docker image".to_string(),
-                        parameter_type: "string".to_string(),
-                        default: Some("engine-builder-test".to_string()),
-                    },
-                );
-                params
-            },
+            parameters: BTreeMap::from([tag_parameter(), timeout_parameter()]),
             required_parameters: vec![],
         },
         Tool {
             name: "run_test".to_string(),
             description: "Run test script in a Docker container".to_string(),
-            parameters: {
-                let mut params = HashMap::new();
-                params.insert(
-                    "tag".to_string(),
-                    ToolParameter {
-                        name: "tag".to_string(),
-                        description: "Tag name for the Docker image".to_string(),
-                        parameter_type: "string".to_string(),
-                        default: Some("engine-builder-test".to_string()),
-                    },
-                );
-                params
-            },
+            parameters: BTreeMap::from([tag_parameter(), timeout_parameter()]),
             required_parameters: vec![],
         },
         Tool {
             name: "run_all".to_string(),
             description: "Run both lint and test scripts in Docker containers".to_string(),
-            parameters: {
-                let mut params = HashMap::new();
-                params.insert(
-                    "tag".to_string(),
-                    ToolParameter {
-                        name: "tag".to_string(),
-                        description: "Tag name for the Docker image".to_string(),
-                        parameter_type: "string".to_string(),
-                        default: Some("engine-builder-test".to_string()),
-                    },
-                );
-                params.insert(
+            parameters: BTreeMap::from([
+                tag_parameter(),
+                timeout_parameter(),
+                (
                     "parallel".to_string(),
                     ToolParameter {
                         name: "parallel".to_string(),
                         description: "Run in parallel mode (both containers at once)".to_string(),
-                        parameter_type: "boolean".to_string(),
-                        default: Some("false".to_string()),
+                        parameter_type: ParameterType::Boolean,
+                        default: Some(ToolValue::Boolean(false)),
                     },
-                );
-                params
-            },
+                ),
+            ]),
             required_parameters: vec![],
         },
     ]
 }
 
-/// Parse tool call from the LLM response
-pub fn parse_tool_call(response: &str) -> Option<(String, HashMap<String, String>)> {
-    // This is a simple implementation. It assumes the model will wrap the tool call in markers
-    if let Some(start) = response.find("TOOL:") {
-        if let Some(end) = response[start..].find("\n") {
-            let tool_call = &response[start + 5..start + end].trim();
-
-            // Parse tool name and parameters
-            if let Some(open_paren) = tool_call.find('(') {
-                if let Some(close_paren) = tool_call.find(')') {
-                    let tool_name = tool_call[..open_paren].trim().to_string();
-                    let params_str = &tool_call[open_paren + 1..close_paren];
-
-                    // Parse parameters
-                    let mut params = HashMap::new();
-                    for param in params_str.split(',') {
-                        if let Some(eq) = param.find('=') {
-                            let key = param[..eq].trim().to_string();
-                            let value = param[eq + 1..].trim().to_string();
-
-                            // Remove quotes if present
-                            let value = if value.starts_with('"') && value.ends_with('"') {
-                                value[1..value.len() - 1].to_string()
-                            } else {
-                                value
-                            };
+/// The Docker image tag parameter shared by the container tools
+fn tag_parameter() -> (String, ToolParameter) {
+    (
+        "tag".to_string(),
+        ToolParameter {
+            name: "tag".to_string(),
+            description: "Tag name for the Docker image".to_string(),
+            parameter_type: ParameterType::String,
+            default: Some(ToolValue::String("engine-builder-test".to_string())),
+        },
+    )
+}
 
-                            params.insert(key, value);
-                        }
-                    }
+/// The container timeout parameter shared by the tools that run containers
+fn timeout_parameter() -> (String, ToolParameter) {
+    (
+        "timeout".to_string(),
+        ToolParameter {
+            name: "timeout".to_string(),
+            description:
+                "Timeout for container execution in seconds (defaults to the configured timeout)"
+                    .to_string(),
+            parameter_type: ParameterType::Integer,
+            default: None,
+        },
+    )
+}
 
-                    return Some((tool_name, params));
-                }
-            }
+/// Find a tool by name
+pub fn find_tool(name: &str) -> Option<Tool> {
+    get_tools().into_iter().find(|tool| tool.name == name)
+}
+
+/// Provider tool schemas for all available tools
+pub fn tool_definitions() -> Vec<ToolDefinition> {
+    get_tools().iter().map(Tool::definition).collect()
+}
+
+/// Validate and execute a tool call requested by the LLM. Invalid calls are
+/// reported as failed results so the model can correct itself.
+pub async fn execute_tool_call(
+    call: &ToolCall,
+    config: &Config,
+    problem: &SWEBenchProblem,
+) -> Result<ToolResult> {
+    let tool = match find_tool(&call.name) {
+        Some(tool) => tool,
+        None => {
+            return Ok(ToolResult {
+                success: false,
+                output: format!("Unknown tool: {}", call.name),
+            })
         }
-    }
+    };
+
+    let arguments = match tool.parse_arguments(&call.input) {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            return Ok(ToolResult {
+                success: false,
+                output: format!("Invalid arguments for tool '{}': {}", call.name, e),
+            })
+        }
+    };
+
+    execute_tool(&call.name, &arguments, config, problem).await
+}
 
-    None
+/// The container settings for a tool call, with the timeout overridden if given
+fn container_config(config: &Config, params: &ToolArguments) -> ContainerConfig {
+    let mut container_config = config.container.clone();
+    if let Some(timeout) = params.integer("timeout") {
+        match u64::try_from(timeout) {
+            Ok(timeout) if timeout > 0 => container_config.timeout = timeout,
+            _ => log::warn!("Ignoring invalid container timeout: {}", timeout),
+        }
+    }
+    container_config
 }
 
 /// Execute a tool based on its name and parameters
 pub async fn execute_tool(
     tool_name: &str,
-    params: &HashMap<String, String>,
+    params: &ToolArguments,
     config: &Config,
     problem: &SWEBenchProblem,
 ) -> Result<ToolResult> {
@@ -333,10 +496,7 @@ pub async fn execute_tool(
             }
         }
         "build_image" => {
-            let tag = params
-                .get("tag")
-                .map(|s| s.as_str())
-                .unwrap_or("engine-builder-test");
+            let tag = params.string("tag").unwrap_or("engine-builder-test");
 
             let result = stages::dockerfile::build_docker_image(config, problem, tag).await;
 
@@ -367,13 +527,11 @@ pub async fn execute_tool(
             }
         }
         "run_lint" => {
-            let tag = params
-                .get("tag")
-                .map(|s| s.as_str())
-                .unwrap_or("engine-builder-test");
+            let tag = params.string("tag").unwrap_or("engine-builder-test");
 
+            let container_config = container_config(config, params);
             let result =
-                stages::container::run_lint_container(problem, tag, &config.container).await;
+                stages::container::run_lint_container(problem, tag, &container_config).await;
 
             match result {
                 Ok(container_result) => {
@@ -398,13 +556,11 @@ pub async fn execute_tool(
             }
         }
         "run_test" => {
-            let tag = params
-                .get("tag")
-                .map(|s| s.as_str())
-                .unwrap_or("engine-builder-test");
+            let tag = params.string("tag").unwrap_or("engine-builder-test");
 
+            let container_config = container_config(config, params);
             let result =
-                stages::container::run_test_container(problem, tag, &config.container).await;
+                stages::container::run_test_container(problem, tag, &container_config).await;
 
             match result {
                 Ok(container_result) => {
@@ -429,18 +585,12 @@ pub async fn execute_tool(
             }
         }
         "run_all" => {
-            let tag = params
-                .get("tag")
-                .map(|s| s.as_str())
-                .unwrap_or("engine-builder-test");
+            let tag = params.string("tag").unwrap_or("engine-builder-test");
 
-            let parallel = params
-                .get("parallel")
-                .map(|s| s.to_lowercase() == "true")
-                .unwrap_or(false);
+            let parallel = params.boolean("parallel").unwrap_or(false);
 
             // Clone container config and override parallel flag if specified
-            let mut container_config = config.container.clone();
+            let mut container_config = container_config(config, params);
             if parallel {
                 container_config.parallel = true;
             }
@@ -13,7 +13,7 @@ use ratatui::{
 use std::{io, time::Duration};
 use tokio::sync::mpsc;
 
-use crate::chat::ChatMessage;
+use crate::chat::{ChatMessage, ChatUpdate};
 
 /// App structure to hold UI state
 pub struct ChatApp {
@@ -29,6 +29,10 @@ pub struct ChatApp {
     pub running: bool,
     /// Show help
     pub show_help: bool,
+    /// Is an assistant response currently streaming in
+    pub streaming: bool,
+    /// Is the model working on a response that hasn't started streaming in
+    pub thinking: bool,
 }
 
 impl ChatApp {
@@ -41,6 +45,34 @@ impl ChatApp {
             tx,
             running: true,
             show_help: false,
+            streaming: false,
+            thinking: false,
+        }
+    }
+
+    /// Apply an update from the chat loop to the message history
+    pub fn apply_update(&mut self, update: ChatUpdate) {
+        match update {
+            ChatUpdate::Message(message) => self.messages.push(message),
+            ChatUpdate::Thinking => self.thinking = true,
+            ChatUpdate::Delta(text) => {
+                self.thinking = false;
+                if !self.streaming {
+                    self.messages.push(ChatMessage {
+                        role: "assistant".to_string(),
+                        content: String::new(),
+                    });
+                    self.streaming = true;
+                }
+
+                if let Some(last) = self.messages.last_mut() {
+                    last.content.push_str(&text);
+                }
+            }
+            ChatUpdate::StreamEnd => {
+                self.thinking = false;
+                self.streaming = false;
+            }
         }
     }
 
@@ -276,6 +308,9 @@ impl ChatApp {
             formatted_text.push_str(&content);
             formatted_text.push_str("\n");
         }
+        if self.thinking {
+            formatted_text.push_str("\n[Assistant]: Thinking...\n");
+        }
 
         // Create a paragraph from the formatted text
         let paragraph = Paragraph::new(formatted_text)
@@ -351,7 +386,10 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
 }
 
 /// Run the chat UI
-pub async fn run_chat_ui(rx: mpsc::Receiver<ChatMessage>, tx: mpsc::Sender<String>) -> Result<()> {
+pub async fn run_chat_ui(
+    rx: mpsc::UnboundedReceiver<ChatUpdate>,
+    tx: mpsc::Sender<String>,
+) -> Result<()> {
     // Set up terminal
     let mut stdout = io::stdout();
     terminal::enable_raw_mode()?;
@@ -369,9 +407,10 @@ pub async fn run_chat_ui(rx: mpsc::Receiver<ChatMessage>, tx: mpsc::Sender<Strin
 
     // Main UI loop
     while app.running {
-        // Non-blocking check for new messages
-        if let Ok(message) = user_input_rx.try_recv() {
-            app.messages.push(message);
+        // Non-blocking check for new messages, draining everything pending so
+        // streamed responses keep up with the model
+        while let Ok(update) = user_input_rx.try_recv() {
+            app.apply_update(update);
         }
 
         // Draw UI
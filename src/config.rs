@@ -1,14 +1,21 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 
+use crate::models::file_rules::FileRule;
+use crate::utils::git::GitScope;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub anthropic_api_key: String,
     #[serde(default = "default_model")]
     pub model: String,
+    /// Default provider settings, overridable per stage
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
     #[serde(default)]
     pub relevance: RelevanceConfig,
     #[serde(default)]
@@ -25,6 +32,12 @@ pub struct Config {
     #[serde(default)]
     pub observability: ObservabilityConfig,
     #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    #[serde(default)]
+    pub pricing: PricingConfig,
+    #[serde(default)]
     pub output_path: Option<String>,
 }
 
@@ -32,6 +45,40 @@ fn default_model() -> String {
     "claude-3-7-sonnet-20250219".to_string()
 }
 
+fn default_provider() -> String {
+    "anthropic".to_string()
+}
+
+fn default_request_timeout() -> u64 {
+    60
+}
+
+fn default_request_max_retries() -> u32 {
+    3
+}
+
+/// LLM provider settings. Used at the top level of the config as the defaults
+/// and inside each stage section as overrides; unset fields fall through.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    /// Provider name (e.g. "anthropic", "openai")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// API key for the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Base URL for the provider API
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Request timeout in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_timeout: Option<u64>,
+    /// Maximum number of retries for a failed request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_max_retries: Option<u32>,
+}
+
 /// Legacy LLMConfig structure for compatibility with LLM client code
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMConfig {
@@ -58,6 +105,21 @@ pub struct CodebaseConfig {
     /// Path to the exclusions config file
     #[serde(default = "default_exclusions_path")]
     pub exclusions_path: String,
+
+    /// Rules that decide the relevance of well-known files without a model
+    #[serde(default)]
+    pub file_rules: FileRulesConfig,
+
+    /// Limit file selection and relevance to the files a git diff touches,
+    /// and the other files in their directories
+    #[serde(default)]
+    pub git_scope: Option<GitScope>,
+
+    /// Most tokens the codebase tree shown for file selection may take.
+    /// Larger directories are collapsed into summaries to fit; 0 renders
+    /// every path.
+    #[serde(default = "default_tree_max_tokens")]
+    pub tree_max_tokens: usize,
 }
 
 fn default_codebase_path() -> PathBuf {
@@ -68,18 +130,68 @@ fn default_exclusions_path() -> String {
     "exclusions.json".to_string()
 }
 
+fn default_tree_max_tokens() -> usize {
+    20_000
+}
+
+/// Rules for files whose relevance is known from their path, such as build
+/// manifests, CI workflows and test fixtures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileRulesConfig {
+    /// Whether to use the built-in rules after the configured ones
+    #[serde(default = "default_builtin_file_rules")]
+    pub builtin: bool,
+    /// Rules tried before the built-in ones; the first that matches decides
+    pub rules: Vec<FileRule>,
+}
+
+fn default_builtin_file_rules() -> bool {
+    true
+}
+
+impl Default for FileRulesConfig {
+    fn default() -> Self {
+        Self {
+            builtin: default_builtin_file_rules(),
+            rules: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct RelevanceConfig {
     pub model: Option<String>,
+    /// Fallback chain, see [`Config::to_stage_llm_configs`]
+    pub models: Vec<String>,
     #[serde(default = "default_max_workers")]
     pub max_workers: usize,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
     #[serde(default = "default_relevance_timeout")]
     pub timeout: f64,
+    /// Files longer than this are assessed in chunks of at most this size
     #[serde(default = "default_max_file_tokens")]
     pub max_file_tokens: usize,
+    /// Lines each chunk repeats from the end of the one before it
+    #[serde(default = "default_chunk_overlap_lines")]
+    pub chunk_overlap_lines: usize,
+    /// Files that would take more chunks than this are skipped
+    #[serde(default = "default_max_chunks")]
+    pub max_chunks: usize,
+    /// Assess files of up to `small_file_tokens` tokens several to a request
+    pub batch_small_files: bool,
+    #[serde(default = "default_small_file_tokens")]
+    pub small_file_tokens: usize,
+    /// Most files in one batch
+    #[serde(default = "default_batch_max_files")]
+    pub batch_max_files: usize,
+    /// Most tokens of file contents in one batch
+    #[serde(default = "default_batch_max_tokens")]
+    pub batch_max_tokens: usize,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 
 fn default_max_workers() -> usize {
@@ -94,15 +206,38 @@ fn default_relevance_timeout() -> f64 {
 fn default_max_file_tokens() -> usize {
     100_000
 }
+fn default_chunk_overlap_lines() -> usize {
+    20
+}
+fn default_max_chunks() -> usize {
+    10
+}
+fn default_small_file_tokens() -> usize {
+    500
+}
+fn default_batch_max_files() -> usize {
+    20
+}
+fn default_batch_max_tokens() -> usize {
+    8000
+}
 
 impl Default for RelevanceConfig {
     fn default() -> Self {
         Self {
             model: None,
+            models: Vec::new(),
             max_workers: default_max_workers(),
             max_tokens: default_max_tokens(),
             timeout: default_relevance_timeout(),
             max_file_tokens: default_max_file_tokens(),
+            chunk_overlap_lines: default_chunk_overlap_lines(),
+            max_chunks: default_max_chunks(),
+            batch_small_files: false,
+            small_file_tokens: default_small_file_tokens(),
+            batch_max_files: default_batch_max_files(),
+            batch_max_tokens: default_batch_max_tokens(),
+            llm: ProviderConfig::default(),
         }
     }
 }
@@ -111,12 +246,24 @@ impl Default for RelevanceConfig {
 #[serde(default)]
 pub struct RankingConfig {
     pub model: Option<String>,
+    /// Fallback chain, see [`Config::to_stage_llm_configs`]
+    pub models: Vec<String>,
     #[serde(default = "default_ranking_max_workers")]
     pub max_workers: usize,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
     #[serde(default = "default_temperature")]
     pub temperature: f64,
+    /// Number of rankings to ask for, which are merged into the final ranking
+    #[serde(default = "default_num_rankings")]
+    pub num_rankings: usize,
+    /// Shuffle the files in the prompt of every ranking after the first, so
+    /// that their order doesn't sway the merged ranking
+    pub shuffle_files: bool,
+    /// How the rankings are merged
+    pub aggregation: RankAggregation,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 fn default_ranking_max_workers() -> usize {
     4
@@ -124,14 +271,34 @@ fn default_ranking_max_workers() -> usize {
 fn default_temperature() -> f64 {
     0.0
 }
+fn default_num_rankings() -> usize {
+    1
+}
+
+/// How several rankings of the same files are merged into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RankAggregation {
+    /// Each ranking gives a file n points for first place, n - 1 for second
+    /// and so on, where n is the number of files ranked
+    #[default]
+    Borda,
+    /// Each ranking gives a file 1 / (60 + its position)
+    ReciprocalRank,
+}
 
 impl Default for RankingConfig {
     fn default() -> Self {
         Self {
             model: None,
+            models: Vec::new(),
             max_workers: default_ranking_max_workers(),
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
+            num_rankings: default_num_rankings(),
+            shuffle_files: false,
+            aggregation: RankAggregation::default(),
+            llm: ProviderConfig::default(),
         }
     }
 }
@@ -140,12 +307,18 @@ impl Default for RankingConfig {
 #[serde(default)]
 pub struct DockerfileConfig {
     pub model: Option<String>,
+    /// Fallback chain, see [`Config::to_stage_llm_configs`]
+    pub models: Vec<String>,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
     #[serde(default = "default_temperature")]
     pub temperature: f64,
     #[serde(default = "default_max_retries")]
     pub max_retries: usize,
+    /// How much of the relevant files' contents goes into the prompts
+    pub context: ContextConfig,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 
 fn default_max_retries() -> usize {
@@ -156,9 +329,12 @@ impl Default for DockerfileConfig {
     fn default() -> Self {
         Self {
             model: None,
+            models: Vec::new(),
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
             max_retries: default_max_retries(),
+            context: ContextConfig::default(),
+            llm: ProviderConfig::default(),
         }
     }
 }
@@ -167,21 +343,59 @@ impl Default for DockerfileConfig {
 #[serde(default)]
 pub struct ScriptConfig {
     pub model: Option<String>,
+    /// Fallback chain, see [`Config::to_stage_llm_configs`]
+    pub models: Vec<String>,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
     #[serde(default = "default_temperature")]
     pub temperature: f64,
     #[serde(default = "default_max_retries")]
     pub max_retries: usize,
+    /// How much of the relevant files' contents goes into the prompts
+    pub context: ContextConfig,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 
 impl Default for ScriptConfig {
     fn default() -> Self {
         Self {
             model: None,
+            models: Vec::new(),
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
             max_retries: default_max_retries(),
+            context: ContextConfig::default(),
+            llm: ProviderConfig::default(),
+        }
+    }
+}
+
+/// Token budget for the file contents a stage puts in its prompts. The
+/// budget is lowered further when the model's context window is smaller.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// Maximum tokens of file contents in a prompt
+    #[serde(default = "default_context_max_tokens")]
+    pub max_tokens: usize,
+    /// Files longer than this are cut down to excerpts of their beginning and end
+    #[serde(default = "default_context_max_file_tokens")]
+    pub max_file_tokens: usize,
+}
+
+fn default_context_max_tokens() -> usize {
+    60_000
+}
+fn default_context_max_file_tokens() -> usize {
+    8_000
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_context_max_tokens(),
+            max_file_tokens: default_context_max_file_tokens(),
         }
     }
 }
@@ -196,6 +410,8 @@ pub struct ChatConfig {
     pub max_tokens: usize,
     #[serde(default = "default_chat_temperature")]
     pub temperature: f64,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 
 fn default_chat_temperature() -> f64 {
@@ -212,6 +428,7 @@ impl Default for ChatConfig {
             model: default_chat_model(),
             max_tokens: default_max_tokens(),
             temperature: default_chat_temperature(),
+            llm: ProviderConfig::default(),
         }
     }
 }
@@ -227,6 +444,12 @@ pub struct ContainerConfig {
     pub max_retries: usize, // Maximum number of test retry attempts
     #[serde(default = "default_retry_tests")]
     pub retry_tests: bool,  // Whether to retry failed tests with regenerated scripts/dockerfile
+    /// Model that analyzes test failures to decide what to regenerate
+    pub model: Option<String>,
+    /// Fallback chain, see [`Config::to_stage_llm_configs`]
+    pub models: Vec<String>,
+    #[serde(flatten)]
+    pub llm: ProviderConfig,
 }
 
 fn default_retry_tests() -> bool {
@@ -241,10 +464,117 @@ impl Default for ContainerConfig {
             remove: true,    // Remove containers by default
             max_retries: default_max_retries(),
             retry_tests: default_retry_tests(),
+            model: None,
+            models: Vec::new(),
+            llm: ProviderConfig::default(),
+        }
+    }
+}
+
+/// How LLM responses are cached on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheMode {
+    /// Responses are neither read from nor written to the cache
+    #[default]
+    Off,
+    /// Cached responses are reused, and new responses are cached
+    ReadWrite,
+    /// Cached responses are reused, but new responses are not cached
+    ReadOnly,
+    /// Cached responses are ignored, and new responses replace them
+    Refresh,
+}
+
+impl CacheMode {
+    /// Whether cached responses are reused
+    pub fn reads(self) -> bool {
+        matches!(self, CacheMode::ReadWrite | CacheMode::ReadOnly)
+    }
+
+    /// Whether new responses are cached
+    pub fn writes(self) -> bool {
+        matches!(self, CacheMode::ReadWrite | CacheMode::Refresh)
+    }
+}
+
+impl std::str::FromStr for CacheMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(CacheMode::Off),
+            "read-write" => Ok(CacheMode::ReadWrite),
+            "read-only" => Ok(CacheMode::ReadOnly),
+            "refresh" => Ok(CacheMode::Refresh),
+            _ => Err(anyhow::anyhow!(
+                "Invalid cache mode '{}': expected off, read-write, read-only or refresh",
+                s
+            )),
         }
     }
 }
 
+/// Configuration for the on-disk LLM response cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub mode: CacheMode,
+    /// Directory for cached responses (defaults to `cache` in the output directory)
+    pub path: Option<String>,
+}
+
+/// Limits on LLM spending for a run; unset limits are unlimited
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// Maximum tokens across all stages, including cached prompt tokens
+    pub max_tokens: Option<usize>,
+    /// Maximum cost in US dollars across all stages
+    pub max_usd: Option<f64>,
+    /// Limits for individual stages, by stage name (e.g. "relevance")
+    pub stages: HashMap<String, StageBudget>,
+}
+
+/// Limits on LLM spending for a single stage
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StageBudget {
+    pub max_tokens: Option<usize>,
+    pub max_usd: Option<f64>,
+}
+
+impl BudgetConfig {
+    /// Whether any limit is set
+    pub fn is_limited(&self) -> bool {
+        self.max_tokens.is_some() || self.max_usd.is_some() || !self.stages.is_empty()
+    }
+}
+
+/// Price of a model in US dollars per million tokens. Cache prices default
+/// to the usual multiples of the input price when not given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// Price of writing a prompt prefix to the cache
+    #[serde(default)]
+    pub cache_write: Option<f64>,
+    /// Price of reading a cached prompt prefix
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+}
+
+/// Overrides of the bundled model pricing table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PricingConfig {
+    /// A pricing table to use instead of the bundled one
+    pub path: Option<String>,
+    /// Prices by provider, then by model name or model name prefix
+    pub models: HashMap<String, HashMap<String, ModelPrice>>,
+}
+
 /// Configuration for observability and tracing
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -350,6 +680,7 @@ impl Config {
         Self {
             anthropic_api_key: "".to_string(),
             model: default_model(),
+            llm: ProviderConfig::default(),
             relevance: RelevanceConfig::default(),
             ranking: RankingConfig::default(),
             codebase: CodebaseConfig {
@@ -357,12 +688,18 @@ impl Config {
                 problem_id: "custom_problem".to_string(),
                 problem_statement: "Please analyze this codebase".to_string(),
                 exclusions_path: "exclusions.json".to_string(),
+                file_rules: FileRulesConfig::default(),
+                git_scope: None,
+                tree_max_tokens: default_tree_max_tokens(),
             },
             dockerfile: DockerfileConfig::default(),
             scripts: ScriptConfig::default(),
             chat: ChatConfig::default(),
             container: ContainerConfig::default(),
             observability: ObservabilityConfig::default(),
+            cache: CacheConfig::default(),
+            budget: BudgetConfig::default(),
+            pricing: PricingConfig::default(),
             output_path: Some(".engines".to_string()),
         }
     }
@@ -375,17 +712,81 @@ impl Config {
         }
     }
 
-    /// Convert to the LLMConfig format needed by LLM clients
+    /// Convert to the LLMConfig format needed by LLM clients, using only the
+    /// top-level provider settings
     pub fn to_llm_config(&self, stage_model: &Option<String>) -> LLMConfig {
+        self.to_stage_llm_config(stage_model, &ProviderConfig::default())
+    }
+
+    /// Convert to the LLMConfig format for a stage, applying the stage's
+    /// provider overrides on top of the top-level settings
+    pub fn to_stage_llm_config(
+        &self,
+        stage_model: &Option<String>,
+        overrides: &ProviderConfig,
+    ) -> LLMConfig {
         let model = self.get_model_for_stage(stage_model);
 
+        let model_type = overrides
+            .provider
+            .clone()
+            .or_else(|| self.llm.provider.clone())
+            .unwrap_or_else(default_provider);
+
+        let api_key = overrides
+            .api_key
+            .clone()
+            .or_else(|| self.llm.api_key.clone())
+            .unwrap_or_else(|| self.default_api_key(&model_type));
+
         LLMConfig {
-            model_type: "anthropic".to_string(),
             model,
-            api_key: self.anthropic_api_key.clone(),
-            base_url: None,
-            timeout: 60,    // Fixed default timeout
-            max_retries: 3, // Fixed default max retries
+            api_key,
+            base_url: overrides
+                .base_url
+                .clone()
+                .or_else(|| self.llm.base_url.clone()),
+            timeout: overrides
+                .request_timeout
+                .or(self.llm.request_timeout)
+                .unwrap_or_else(default_request_timeout),
+            max_retries: overrides
+                .request_max_retries
+                .or(self.llm.request_max_retries)
+                .unwrap_or_else(default_request_max_retries),
+            model_type,
+        }
+    }
+
+    /// Convert to an LLMConfig for each model of a stage's fallback chain.
+    ///
+    /// A stage's `models` are tried in order, moving to the next when a
+    /// request fails or its answer is unusable (see [`ModelChain::run`]).
+    /// They take precedence over `model`; without a chain, the stage's single
+    /// model is used.
+    ///
+    /// [`ModelChain::run`]: crate::llm::fallback::ModelChain::run
+    pub fn to_stage_llm_configs(
+        &self,
+        stage_model: &Option<String>,
+        stage_models: &[String],
+        overrides: &ProviderConfig,
+    ) -> Vec<LLMConfig> {
+        if stage_models.is_empty() {
+            return vec![self.to_stage_llm_config(stage_model, overrides)];
+        }
+        stage_models
+            .iter()
+            .map(|model| self.to_stage_llm_config(&Some(model.clone()), overrides))
+            .collect()
+    }
+
+    /// Fallback API key for a provider when none is configured explicitly
+    fn default_api_key(&self, provider: &str) -> String {
+        match provider {
+            "anthropic" => self.anthropic_api_key.clone(),
+            "openai" => std::env::var("OPENAI_API_KEY").unwrap_or_default(),
+            _ => String::new(),
         }
     }
 
@@ -396,6 +797,14 @@ impl Config {
             .unwrap_or_else(|| ".engines".to_string())
     }
 
+    /// Get the directory for cached LLM responses
+    pub fn get_cache_dir(&self) -> String {
+        self.cache
+            .path
+            .clone()
+            .unwrap_or_else(|| format!("{}/cache", self.get_output_dir()))
+    }
+
     /// Get the trajectory store directory (shared across all problems)
     pub fn get_trajectory_dir(&self, _problem_id: &str) -> String {
         self.get_output_dir()
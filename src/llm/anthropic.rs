@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures::StreamExt;
 use log::debug;
 use reqwest::{header, Client};
 use serde::Deserialize;
@@ -7,7 +8,12 @@ use serde_json::json;
 use std::time::Duration;
 
 use crate::config::LLMConfig;
-use crate::llm::client::{LLMClient, LLMResponse, TokenUsage};
+use crate::llm::client::{
+    ChatRequest, LLMClient, LLMResponse, LLMStream, Message, StreamEvent, TokenUsage, ToolCall,
+};
+use crate::llm::error::LLMError;
+use crate::llm::pricing::{model_pricing, ModelPricing};
+use crate::llm::sse::{response_events, SseEvent};
 
 /// Anthropic API response for chat completions
 #[derive(Debug, Deserialize)]
@@ -24,6 +30,15 @@ struct AnthropicContent {
     text: String,
     #[serde(default)]
     r#type: String,
+    /// Tool use ID, for tool_use blocks
+    #[serde(default)]
+    id: String,
+    /// Tool name, for tool_use blocks
+    #[serde(default)]
+    name: String,
+    /// Tool arguments, for tool_use blocks
+    #[serde(default)]
+    input: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,33 +47,201 @@ struct AnthropicUsage {
     input_tokens: usize,
     #[serde(default)]
     output_tokens: usize,
+    #[serde(default)]
+    cache_creation_input_tokens: usize,
+    #[serde(default)]
+    cache_read_input_tokens: usize,
 }
 
-use std::collections::HashMap;
-use std::sync::RwLock;
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(usage: AnthropicUsage) -> Self {
+        // input_tokens only counts the tokens after the last cache breakpoint
+        TokenUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: usage.input_tokens
+                + usage.output_tokens
+                + usage.cache_creation_input_tokens
+                + usage.cache_read_input_tokens,
+            cache_creation_input_tokens: usage.cache_creation_input_tokens,
+            cache_read_input_tokens: usage.cache_read_input_tokens,
+        }
+    }
+}
 
-/// Anthropic pricing response structure  
-#[derive(Debug, Deserialize)]
-struct AnthropicPricingResponse {
-    #[allow(dead_code)]
-    models: Vec<AnthropicModelPricing>,
+/// Anthropic allows at most this many cache_control blocks per request
+const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+/// Marks the end of a cacheable prompt prefix
+fn cache_control() -> serde_json::Value {
+    json!({ "type": "ephemeral" })
 }
 
-#[derive(Debug, Deserialize)]
-struct AnthropicModelPricing {
-    #[allow(dead_code)]
-    name: String,
-    #[allow(dead_code)]
-    input_price: Option<f64>,
-    #[allow(dead_code)]
-    output_price: Option<f64>,
+/// State carried across the events of an Anthropic response stream
+#[derive(Default)]
+struct StreamState {
+    /// Usage so far; input tokens arrive first and output tokens at the end
+    usage: TokenUsage,
+    /// ID, name and partial JSON arguments of the tool call being received
+    tool_call: Option<(String, String, String)>,
+}
+
+/// Parse the accumulated JSON arguments of a tool call. Tools without
+/// parameters may stream no arguments at all.
+fn parse_tool_input(input: &str) -> Result<serde_json::Value> {
+    if input.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_str(input).map_err(|e| {
+        LLMError::malformed(
+            "Anthropic",
+            format!("failed to parse tool call arguments: {}", e),
+        )
+        .into()
+    })
+}
+
+/// Split text content at a cache breakpoint into text blocks, marking the
+/// end of the cacheable prefix
+fn cached_text_blocks(content: &str, breakpoint: usize) -> Vec<serde_json::Value> {
+    let (prefix, rest) = content.split_at(breakpoint);
+    let mut blocks = vec![json!({
+        "type": "text",
+        "text": prefix,
+        "cache_control": cache_control(),
+    })];
+    if !rest.is_empty() {
+        blocks.push(json!({ "type": "text", "text": rest }));
+    }
+    blocks
+}
+
+/// Convert a typed message to the messages API format. Plain text messages
+/// use string content; cached prefixes, tool calls and results become content
+/// blocks. Cache breakpoints are only honoured when `use_cache` is set.
+fn message_to_json(message: &Message, use_cache: bool) -> serde_json::Value {
+    if message.is_text_only() {
+        let breakpoint = message.cache_breakpoint.filter(|&breakpoint| {
+            use_cache
+                && breakpoint > 0
+                && breakpoint <= message.content.len()
+                && message.content.is_char_boundary(breakpoint)
+        });
+        return match breakpoint {
+            Some(breakpoint) => json!({
+                "role": message.role,
+                "content": cached_text_blocks(&message.content, breakpoint),
+            }),
+            None => json!({ "role": message.role, "content": message.content }),
+        };
+    }
+
+    let mut blocks = Vec::new();
+
+    // Tool results must come first in the user turn that follows a tool call
+    for result in &message.tool_results {
+        blocks.push(json!({
+            "type": "tool_result",
+            "tool_use_id": result.tool_call_id,
+            "content": result.content,
+            "is_error": result.is_error,
+        }));
+    }
+    if !message.content.is_empty() {
+        blocks.push(json!({ "type": "text", "text": message.content }));
+    }
+    for call in &message.tool_calls {
+        blocks.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.name,
+            "input": call.input,
+        }));
+    }
+
+    json!({ "role": message.role, "content": blocks })
+}
+
+/// Translate an Anthropic streaming event into a stream item, accumulating
+/// token usage and tool call arguments along the way
+fn parse_stream_event(event: &SseEvent, state: &mut StreamState) -> Option<Result<StreamEvent>> {
+    let data: serde_json::Value = match serde_json::from_str(&event.data) {
+        Ok(data) => data,
+        Err(e) => {
+            return Some(Err(LLMError::malformed(
+                "Anthropic",
+                format!("failed to parse stream event: {}", e),
+            )
+            .into()))
+        }
+    };
+
+    match data["type"].as_str().unwrap_or_default() {
+        "message_start" => {
+            let message_usage = &data["message"]["usage"];
+            let tokens = |key: &str| message_usage[key].as_u64().unwrap_or(0) as usize;
+            let usage = &mut state.usage;
+            usage.prompt_tokens = tokens("input_tokens");
+            usage.cache_creation_input_tokens = tokens("cache_creation_input_tokens");
+            usage.cache_read_input_tokens = tokens("cache_read_input_tokens");
+            None
+        }
+        "content_block_start" if data["content_block"]["type"] == "tool_use" => {
+            // The arguments arrive as partial JSON in the following deltas
+            state.tool_call = Some((
+                data["content_block"]["id"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                data["content_block"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                String::new(),
+            ));
+            None
+        }
+        "content_block_delta" if data["delta"]["type"] == "text_delta" => {
+            let text = data["delta"]["text"].as_str().unwrap_or_default();
+            Some(Ok(StreamEvent::Delta(text.to_string())))
+        }
+        "content_block_delta" if data["delta"]["type"] == "input_json_delta" => {
+            if let Some((_, _, input)) = state.tool_call.as_mut() {
+                input.push_str(data["delta"]["partial_json"].as_str().unwrap_or_default());
+            }
+            None
+        }
+        "content_block_stop" => {
+            let (id, name, input) = state.tool_call.take()?;
+            Some(
+                parse_tool_input(&input)
+                    .map(|input| StreamEvent::ToolCall(ToolCall { id, name, input })),
+            )
+        }
+        "message_delta" => {
+            let output_tokens = data["usage"]["output_tokens"].as_u64().unwrap_or(0) as usize;
+            let usage = &mut state.usage;
+            usage.completion_tokens = output_tokens;
+            usage.total_tokens = usage.prompt_tokens
+                + usage.completion_tokens
+                + usage.cache_creation_input_tokens
+                + usage.cache_read_input_tokens;
+            Some(Ok(StreamEvent::Usage(usage.clone())))
+        }
+        "error" => Some(Err(LLMError::from_error_type(
+            "Anthropic",
+            data["error"]["type"].as_str().unwrap_or_default(),
+            data["error"]["message"].as_str().unwrap_or_default(),
+        )
+        .into())),
+        _ => None,
+    }
 }
 
 /// A client for the Anthropic API
 pub struct AnthropicClient {
     client: Client,
     config: LLMConfig,
-    pricing_cache: RwLock<HashMap<String, (f64, f64)>>,
 }
 
 impl AnthropicClient {
@@ -101,150 +284,70 @@ impl AnthropicClient {
         Ok(Self {
             client,
             config: config.clone(),
-            pricing_cache: RwLock::new(HashMap::new()),
         })
     }
 
-    /// Parse a prompt string into Anthropic-compatible message format
-    /// Returns a tuple of (Optional system message, Vec of message objects)
-    fn parse_prompt(&self, prompt: &str) -> (Option<String>, Vec<serde_json::Value>) {
-        let mut messages = Vec::new();
-        let mut system_message = None;
-
-        // Split prompt into lines
-        let lines: Vec<&str> = prompt.split('\n').collect();
-        let mut i = 0;
-
-        // Extract messages and roles
-        while i < lines.len() {
-            let line = lines[i].trim();
-
-            if line.starts_with("System:") {
-                // Extract system message
-                let msg_start = line.find(':').map(|idx| idx + 1).unwrap_or(0);
-                let mut msg = line[msg_start..].trim().to_string();
-
-                // Collect additional lines until next role or end
-                i += 1;
-                while i < lines.len()
-                    && !lines[i].trim().starts_with("Human:")
-                    && !lines[i].trim().starts_with("Assistant:")
-                {
-                    msg.push_str("\n");
-                    msg.push_str(lines[i].trim());
-                    i += 1;
-                }
-
-                system_message = Some(msg);
-                continue;
-            } else if line.starts_with("Human:") {
-                // Extract human message
-                let msg_start = line.find(':').map(|idx| idx + 1).unwrap_or(0);
-                let mut msg = line[msg_start..].trim().to_string();
-
-                // Collect additional lines until next role or end
-                i += 1;
-                while i < lines.len()
-                    && !lines[i].trim().starts_with("Human:")
-                    && !lines[i].trim().starts_with("Assistant:")
-                    && !lines[i].trim().starts_with("System:")
-                {
-                    if !lines[i].trim().is_empty() {
-                        if !msg.is_empty() {
-                            msg.push_str("\n");
-                        }
-                        msg.push_str(lines[i].trim());
-                    }
-                    i += 1;
-                }
-
-                if !msg.is_empty() {
-                    messages.push(json!({
-                        "role": "user",
-                        "content": msg
-                    }));
-                }
-                continue;
-            } else if line.starts_with("Assistant:") {
-                // Extract assistant message
-                let msg_start = line.find(':').map(|idx| idx + 1).unwrap_or(0);
-                let mut msg = line[msg_start..].trim().to_string();
-
-                // Collect additional lines until next role or end
-                i += 1;
-                while i < lines.len()
-                    && !lines[i].trim().starts_with("Human:")
-                    && !lines[i].trim().starts_with("Assistant:")
-                    && !lines[i].trim().starts_with("System:")
-                {
-                    if !lines[i].trim().is_empty() {
-                        if !msg.is_empty() {
-                            msg.push_str("\n");
-                        }
-                        msg.push_str(lines[i].trim());
-                    }
-                    i += 1;
-                }
-
-                if !msg.is_empty() {
-                    messages.push(json!({
-                        "role": "assistant",
-                        "content": msg
-                    }));
+    /// Build the messages API request body from the typed messages
+    fn request_body(&self, request: &ChatRequest) -> serde_json::Value {
+        // Only a limited number of prefixes can be cached; the system prompt
+        // comes first, then the earliest message breakpoints
+        let mut cache_breakpoints = if request.cache_system { 1 } else { 0 };
+        let messages: Vec<serde_json::Value> = request
+            .messages
+            .iter()
+            .map(|message| {
+                let use_cache =
+                    message.cache_breakpoint.is_some() && cache_breakpoints < MAX_CACHE_BREAKPOINTS;
+                if use_cache {
+                    cache_breakpoints += 1;
                 }
-                continue;
-            }
+                message_to_json(message, use_cache)
+            })
+            .collect();
+        let mut request_body = json!({
+            "model": self.config.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens,
+            "temperature": request.temperature,
+        });
 
-            // If no role prefix is found, move to next line
-            i += 1;
+        // Add system prompt if available, as a cacheable block if requested
+        if let Some(system) = &request.system {
+            request_body["system"] = if request.cache_system {
+                json!([{
+                    "type": "text",
+                    "text": system,
+                    "cache_control": cache_control(),
+                }])
+            } else {
+                json!(system)
+            };
         }
 
-        // If no valid messages found but prompt isn't empty, treat it as a user message
-        if messages.is_empty() && !prompt.trim().is_empty() {
-            messages.push(json!({
-                "role": "user",
-                "content": prompt.trim()
-            }));
+        // Add tool definitions if the model may call tools
+        if !request.tools.is_empty() {
+            let tools: Vec<serde_json::Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.input_schema,
+                    })
+                })
+                .collect();
+            request_body["tools"] = json!(tools);
         }
 
-        (system_message, messages)
+        request_body
     }
 
-    /// Get token pricing for the configured model - fallback to static values if not in cache
-    fn get_model_pricing(&self) -> (f64, f64) {
-        // Try to get from cache first
-        if let Some(pricing) = self.pricing_cache.read().unwrap().get(&self.config.model) {
-            return *pricing;
-        }
-
-        // Fallback to hardcoded pricing
-        match self.config.model.as_str() {
-            m if m.contains("claude-3-opus") || m.contains("claude-3-7-opus") => (0.015, 0.075),
-            m if m.contains("claude-3-sonnet") || m.contains("claude-3-7-sonnet") => (0.003, 0.015),
-            m if m.contains("claude-3-haiku") || m.contains("claude-3-5-haiku") => {
-                (0.00025, 0.00125)
-            }
-            m if m.contains("claude-2") => (0.01, 0.03),
-            m if m.contains("claude-instant") => (0.0008, 0.0024),
-            _ => {
-                debug!(
-                    "Unknown model pricing for {}, using Claude 3 Sonnet pricing",
-                    self.config.model
-                );
-                (0.003, 0.015)
-            }
-        }
-    }
-}
-
-#[async_trait]
-impl LLMClient for AnthropicClient {
-    async fn completion(
+    /// Send a request to the messages API, returning the response if it succeeded
+    async fn send_messages_request(
         &self,
-        prompt: &str,
-        max_tokens: usize,
-        temperature: f64,
-    ) -> Result<LLMResponse> {
+        request_body: serde_json::Value,
+    ) -> Result<reqwest::Response> {
         let base_url = self
             .config
             .base_url
@@ -252,55 +355,44 @@ impl LLMClient for AnthropicClient {
             .unwrap_or("https://api.anthropic.com");
         let url = format!("{}/v1/messages", base_url);
 
-        // Parse the prompt to extract system message and conversation
-        let (system_message, messages) = self.parse_prompt(prompt);
-
-        // Build the request body with proper formatting
-        let mut request_body = json!({
-            "model": self.config.model,
-            "messages": messages,
-            "max_tokens": max_tokens,
-            "temperature": temperature,
-        });
-
-        // Add system prompt if available
-        if let Some(system) = system_message {
-            request_body["system"] = json!(system);
-        }
-
         let response = self
             .client
             .post(&url)
             .json(&request_body)
             .send()
             .await
-            .context("Failed to send request to Anthropic API")?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response from Anthropic API")?;
-            debug!("Anthropic API error: {}", error_text);
-            return Err(anyhow::anyhow!(
-                "Anthropic API error ({}): {}",
-                status,
-                error_text
-            ));
+            .map_err(|e| LLMError::transport("Anthropic", e))?;
+
+        if !response.status().is_success() {
+            let error = LLMError::from_response("Anthropic", response).await;
+            debug!("Anthropic API error: {}", error);
+            return Err(error.into());
         }
 
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LLMClient for AnthropicClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        let response = self
+            .send_messages_request(self.request_body(request))
+            .await?;
+
         let response_text = response
             .text()
             .await
-            .context("Failed to read response text from Anthropic API")?;
+            .map_err(|e| LLMError::transport("Anthropic", e))?;
         debug!("Anthropic API response: {}", response_text);
 
-        let response_data: AnthropicResponse = serde_json::from_str(&response_text)
-            .context("Failed to parse Anthropic API response")?;
+        let response_data: AnthropicResponse =
+            serde_json::from_str(&response_text).map_err(|e| {
+                LLMError::malformed("Anthropic", format!("failed to parse response: {}", e))
+            })?;
 
         if response_data.content.is_empty() {
-            return Err(anyhow::anyhow!("Anthropic API returned no content"));
+            return Err(LLMError::malformed("Anthropic", "response has no content").into());
         }
 
         // Find the text content
@@ -318,13 +410,21 @@ impl LLMClient for AnthropicClient {
                     .unwrap_or_default()
             });
 
+        // Collect any tool calls
+        let tool_calls = response_data
+            .content
+            .iter()
+            .filter(|content| content.r#type == "tool_use")
+            .map(|content| ToolCall {
+                id: content.id.clone(),
+                name: content.name.clone(),
+                input: content.input.clone(),
+            })
+            .collect();
+
         // Extract usage information
         let usage = if let Some(api_usage) = response_data.usage {
-            TokenUsage {
-                prompt_tokens: api_usage.input_tokens,
-                completion_tokens: api_usage.output_tokens,
-                total_tokens: api_usage.input_tokens + api_usage.output_tokens,
-            }
+            TokenUsage::from(api_usage)
         } else {
             // Fallback if API doesn't return usage
             debug!("No usage information returned from Anthropic API");
@@ -334,9 +434,29 @@ impl LLMClient for AnthropicClient {
         Ok(LLMResponse {
             content: text_content,
             usage,
+            tool_calls,
         })
     }
 
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        let mut request_body = self.request_body(request);
+        request_body["stream"] = json!(true);
+
+        let response = self.send_messages_request(request_body).await?;
+
+        // Input tokens arrive with message_start and output tokens with message_delta
+        let mut state = StreamState::default();
+        let stream = response_events(response).filter_map(move |event| {
+            let item = match event {
+                Ok(event) => parse_stream_event(&event, &mut state),
+                Err(e) => Some(Err(e)),
+            };
+            futures::future::ready(item)
+        });
+
+        Ok(Box::pin(stream))
+    }
+
     fn name(&self) -> &str {
         "anthropic"
     }
@@ -346,52 +466,22 @@ impl LLMClient for AnthropicClient {
     }
 
     fn get_token_prices(&self) -> (f64, f64) {
-        self.get_model_pricing()
+        self.pricing().per_1k()
     }
 
-    async fn fetch_pricing_data(&self) -> Result<()> {
-        debug!("Fetching Anthropic pricing data");
-
-        let base_url = self
-            .config
-            .base_url
-            .as_deref()
-            .unwrap_or("https://api.anthropic.com");
-        let url = format!("{}/v1/models", base_url);
-
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to fetch Anthropic models for pricing")?;
+    fn pricing(&self) -> ModelPricing {
+        model_pricing("anthropic", &self.config.model)
+    }
 
-        if !response.status().is_success() {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response from Anthropic API")?;
-            debug!("Error fetching Anthropic pricing: {}", error_text);
-            return Ok(()); // Continue with hardcoded pricing
+    fn context_window(&self) -> Option<usize> {
+        let model = self.config.model.as_str();
+        if model.starts_with("claude-2") || model.starts_with("claude-instant") {
+            Some(100_000)
+        } else if model.starts_with("claude") {
+            // Every Claude model since Claude 3 takes 200K tokens
+            Some(200_000)
+        } else {
+            None
         }
-
-        let _response_text = response
-            .text()
-            .await
-            .context("Failed to read response text from Anthropic API")?;
-
-        // Anthropic doesn't currently provide pricing in their API
-        // We would need to fetch from their pricing page or use another source
-        // For now, we'll populate the cache with the static values
-
-        let model_name = self.config.model.clone();
-        let pricing = self.get_model_pricing(); // Get hardcoded pricing
-
-        // Update cache
-        let mut pricing_cache = self.pricing_cache.write().unwrap();
-        pricing_cache.insert(model_name, pricing);
-
-        debug!("Updated pricing cache for Anthropic model");
-        Ok(())
     }
 }
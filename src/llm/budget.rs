@@ -0,0 +1,302 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::info;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::config::BudgetConfig;
+use crate::llm::client::{
+    ChatRequest, LLMClient, LLMResponse, LLMStream, StreamEvent, TokenCost, TokenUsage,
+};
+use crate::llm::pricing::ModelPricing;
+
+/// The budget LLM clients of the current run spend from, if any
+static BUDGET: RwLock<Option<Arc<BudgetAccountant>>> = RwLock::new(None);
+
+/// Set the budget that stage clients spend from
+pub fn set_budget(budget: Option<Arc<BudgetAccountant>>) {
+    *BUDGET.write().unwrap() = budget;
+}
+
+/// The budget of the current run, if one is set
+pub fn budget() -> Option<Arc<BudgetAccountant>> {
+    BUDGET.read().unwrap().clone()
+}
+
+/// Check that the current run's budget allows another request for a stage
+pub fn check_budget(stage: &str) -> Result<()> {
+    match budget() {
+        Some(budget) => budget.check(stage),
+        None => Ok(()),
+    }
+}
+
+/// Wrap a stage's client so that its requests spend from the current run's
+/// budget, if one is set
+pub fn with_budget(client: Box<dyn LLMClient>, stage: &str) -> Box<dyn LLMClient> {
+    match budget() {
+        Some(budget) => Box::new(BudgetedClient::new(client, stage, budget)),
+        None => client,
+    }
+}
+
+/// A request was refused because the budget has run out
+#[derive(Debug, thiserror::Error)]
+#[error("LLM budget exhausted: {scope} has used {spent} of its {limit} limit")]
+pub struct BudgetExceeded {
+    /// "the run", or the stage whose limit was reached
+    pub scope: String,
+    pub spent: String,
+    pub limit: String,
+}
+
+/// Whether an error is a request refused because the budget ran out
+pub fn is_budget_exceeded(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<BudgetExceeded>().is_some())
+}
+
+/// Tokens used and their cost
+#[derive(Debug, Clone, Default)]
+pub struct Spending {
+    pub usage: TokenUsage,
+    pub cost: f64,
+}
+
+impl Spending {
+    fn add(&mut self, usage: &TokenUsage, cost: f64) {
+        self.usage += usage;
+        self.cost += cost;
+    }
+
+    /// Check the spending against limits, describing the first limit reached
+    fn check(
+        &self,
+        scope: &str,
+        max_tokens: Option<usize>,
+        max_usd: Option<f64>,
+    ) -> Result<(), BudgetExceeded> {
+        if let Some(max_tokens) = max_tokens {
+            if self.usage.total_tokens >= max_tokens {
+                return Err(BudgetExceeded {
+                    scope: scope.to_string(),
+                    spent: format!("{} tokens", self.usage.total_tokens),
+                    limit: format!("{} token", max_tokens),
+                });
+            }
+        }
+        if let Some(max_usd) = max_usd {
+            if self.cost >= max_usd {
+                return Err(BudgetExceeded {
+                    scope: scope.to_string(),
+                    spent: format!("${:.4}", self.cost),
+                    limit: format!("${:.2}", max_usd),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Ledger {
+    total: Spending,
+    stages: BTreeMap<String, Spending>,
+}
+
+/// Tracks the tokens and cost spent by each stage of a run against the
+/// configured limits
+///
+/// Limits are checked before each request, so requests already in flight when
+/// the budget runs out still complete and may take spending somewhat past it.
+pub struct BudgetAccountant {
+    config: BudgetConfig,
+    ledger: Mutex<Ledger>,
+}
+
+impl BudgetAccountant {
+    pub fn new(config: BudgetConfig) -> Self {
+        Self {
+            config,
+            ledger: Mutex::new(Ledger::default()),
+        }
+    }
+
+    /// Check that neither the run's nor the stage's limits have been reached
+    pub fn check(&self, stage: &str) -> Result<()> {
+        let ledger = self.ledger.lock().unwrap();
+        ledger
+            .total
+            .check("the run", self.config.max_tokens, self.config.max_usd)?;
+
+        if let Some(limits) = self.config.stages.get(stage) {
+            let spent = ledger.stages.get(stage).cloned().unwrap_or_default();
+            spent.check(
+                &format!("the {} stage", stage),
+                limits.max_tokens,
+                limits.max_usd,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Record the tokens and cost of a completed request
+    pub fn record(&self, stage: &str, usage: &TokenUsage, cost: &TokenCost) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.total.add(usage, cost.total_cost);
+        ledger
+            .stages
+            .entry(stage.to_string())
+            .or_default()
+            .add(usage, cost.total_cost);
+    }
+
+    /// Spending across all stages
+    pub fn total(&self) -> Spending {
+        self.ledger.lock().unwrap().total.clone()
+    }
+
+    /// Spending of one stage
+    pub fn stage(&self, stage: &str) -> Spending {
+        self.ledger
+            .lock()
+            .unwrap()
+            .stages
+            .get(stage)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Log the spending of each stage and the total
+    pub fn log_summary(&self) {
+        let ledger = self.ledger.lock().unwrap();
+        for (stage, spending) in &ledger.stages {
+            info!(
+                "Budget spent by {}: {} tokens, ${:.4}",
+                stage, spending.usage.total_tokens, spending.cost
+            );
+        }
+        info!(
+            "Budget spent in total: {} tokens, ${:.4}",
+            ledger.total.usage.total_tokens, ledger.total.cost
+        );
+    }
+}
+
+/// An LLM client whose requests spend from a budget, and which refuses
+/// requests once the budget has run out
+pub struct BudgetedClient {
+    inner: Arc<dyn LLMClient>,
+    stage: String,
+    budget: Arc<BudgetAccountant>,
+}
+
+impl BudgetedClient {
+    pub fn new(inner: Box<dyn LLMClient>, stage: &str, budget: Arc<BudgetAccountant>) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            stage: stage.to_string(),
+            budget,
+        }
+    }
+
+    fn record(&self, response: &LLMResponse) {
+        let cost = self.inner.calculate_cost(&response.usage);
+        self.budget.record(&self.stage, &response.usage, &cost);
+    }
+}
+
+#[async_trait]
+impl LLMClient for BudgetedClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        self.budget.check(&self.stage)?;
+        let response = self.inner.chat(request).await?;
+        self.record(&response);
+        Ok(response)
+    }
+
+    async fn chat_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<LLMResponse> {
+        self.budget.check(&self.stage)?;
+        let response = self
+            .inner
+            .chat_with_tracing(request, trace_id, generation_name, metadata)
+            .await?;
+        self.record(&response);
+        Ok(response)
+    }
+
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        self.budget.check(&self.stage)?;
+        let stream = self.inner.chat_stream(request).await?;
+
+        // Spend the usage reported at the end of the stream
+        let budget = self.budget.clone();
+        let stage = self.stage.clone();
+        let inner = self.inner.clone();
+        let stream = stream.map(move |event| {
+            if let Ok(StreamEvent::Usage(usage)) = &event {
+                budget.record(&stage, usage, &inner.calculate_cost(usage));
+            }
+            event
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn chat_stream_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+        on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+    ) -> Result<LLMResponse> {
+        self.budget.check(&self.stage)?;
+        let response = self
+            .inner
+            .chat_stream_with_tracing(request, trace_id, generation_name, metadata, on_delta)
+            .await?;
+        self.record(&response);
+        Ok(response)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.inner.get_token_prices()
+    }
+
+    fn pricing(&self) -> ModelPricing {
+        self.inner.pricing()
+    }
+
+    async fn fetch_pricing_data(&self) -> Result<()> {
+        self.inner.fetch_pricing_data().await
+    }
+
+    async fn fetch_model_info(&self) -> Result<()> {
+        self.inner.fetch_model_info().await
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
+        self.inner.calculate_cost(usage)
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::config::CacheMode;
+use crate::llm::client::{
+    ChatRequest, LLMClient, LLMResponse, LLMStream, StreamEvent, TokenCost, TokenUsage,
+};
+use crate::llm::pricing::ModelPricing;
+
+/// Bumped whenever the key or entry format changes, so old entries are ignored
+const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// The response cache used by clients from `create_client`, if any
+static RESPONSE_CACHE: RwLock<Option<ResponseCache>> = RwLock::new(None);
+
+/// Set the response cache used by clients created from now on
+pub fn set_response_cache(cache: Option<ResponseCache>) {
+    *RESPONSE_CACHE.write().unwrap() = cache;
+}
+
+/// The configured response cache, unless caching is off
+pub fn response_cache() -> Option<ResponseCache> {
+    RESPONSE_CACHE
+        .read()
+        .unwrap()
+        .clone()
+        .filter(|cache| cache.mode != CacheMode::Off)
+}
+
+/// A cached response, with the model it came from for easier inspection
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    model: String,
+    response: LLMResponse,
+}
+
+/// A streamed response assembled as it streams past, to be cached once
+/// complete
+struct StreamCapture {
+    cache: ResponseCache,
+    key: String,
+    model: String,
+    response: LLMResponse,
+    /// Whether the stream reported an error, leaving the response incomplete
+    failed: bool,
+}
+
+impl StreamCapture {
+    fn add(&mut self, event: &Result<StreamEvent>) {
+        match event {
+            Ok(StreamEvent::Delta(text)) => self.response.content.push_str(text),
+            Ok(StreamEvent::ToolCall(call)) => self.response.tool_calls.push(call.clone()),
+            Ok(StreamEvent::Usage(usage)) => self.response.usage = usage.clone(),
+            Err(_) => self.failed = true,
+        }
+    }
+
+    /// Cache the response, unless the stream failed
+    fn finish(&self) {
+        if !self.failed {
+            self.cache.store(&self.key, &self.model, &self.response);
+        }
+    }
+}
+
+/// LLM responses stored on disk, one JSON file per request
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    mode: CacheMode,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl AsRef<Path>, mode: CacheMode) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            mode,
+        }
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// The cache key for a request: a hash of the model, the conversation
+    /// (including the system prompt and tools) and the generation parameters
+    pub fn key(model: &str, request: &ChatRequest) -> String {
+        // Cache breakpoints only affect pricing, not the response
+        let messages: Vec<serde_json::Value> = request
+            .messages
+            .iter()
+            .map(|message| {
+                let mut message = serde_json::json!(message);
+                if let Some(fields) = message.as_object_mut() {
+                    fields.remove("cache_breakpoint");
+                }
+                message
+            })
+            .collect();
+
+        let key_data = serde_json::json!({
+            "version": CACHE_FORMAT_VERSION,
+            "model": model,
+            "system": request.system,
+            "messages": messages,
+            "tools": request.tools,
+            "temperature": request.temperature,
+            "max_tokens": request.max_tokens,
+        });
+
+        hex::encode(Sha256::digest(key_data.to_string().as_bytes()))
+    }
+
+    /// Entries are spread over subdirectories named after the first two
+    /// characters of the key, to keep directories small
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.json", key))
+    }
+
+    /// Look up a cached response, if the mode reads from the cache
+    pub fn get(&self, key: &str) -> Option<LLMResponse> {
+        if !self.mode.reads() {
+            return None;
+        }
+
+        let path = self.entry_path(key);
+        let content = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<CacheEntry>(&content) {
+            Ok(entry) => Some(entry.response),
+            Err(e) => {
+                warn!("Ignoring unreadable cache entry {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Store a response, if the mode writes to the cache
+    pub fn put(&self, key: &str, model: &str, response: &LLMResponse) -> Result<()> {
+        if !self.mode.writes() {
+            return Ok(());
+        }
+
+        let path = self.entry_path(key);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).context(format!("Failed to create cache directory: {:?}", dir))?;
+
+        let entry = CacheEntry {
+            model: model.to_string(),
+            response: response.clone(),
+        };
+
+        // Write to a temporary file first, so concurrent readers never see a partial entry
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .context("Failed to create temporary cache file")?;
+        file.write_all(serde_json::to_string_pretty(&entry)?.as_bytes())
+            .context("Failed to write cache entry")?;
+        file.persist(&path)
+            .context(format!("Failed to save cache entry: {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Store a response, logging rather than failing if it can't be saved
+    fn store(&self, key: &str, model: &str, response: &LLMResponse) {
+        if let Err(e) = self.put(key, model, response) {
+            warn!("Failed to cache LLM response: {}", e);
+        }
+    }
+}
+
+/// A cached response as it is returned: nothing was generated, so no tokens
+/// were used
+fn cache_hit(response: LLMResponse) -> LLMResponse {
+    LLMResponse {
+        usage: TokenUsage::default(),
+        ..response
+    }
+}
+
+/// An LLM client that answers repeated requests from an on-disk cache
+pub struct CachingClient {
+    inner: Box<dyn LLMClient>,
+    cache: ResponseCache,
+}
+
+impl CachingClient {
+    pub fn new(inner: Box<dyn LLMClient>, cache: ResponseCache) -> Self {
+        Self { inner, cache }
+    }
+
+    /// Look up the response to a request, returning its key either way
+    fn lookup(&self, request: &ChatRequest) -> (String, Option<LLMResponse>) {
+        let key = ResponseCache::key(self.inner.model_name(), request);
+        let cached = self.cache.get(&key);
+        if cached.is_some() {
+            debug!("Using cached response {}", key);
+        }
+        (key, cached.map(cache_hit))
+    }
+}
+
+#[async_trait]
+impl LLMClient for CachingClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        let (key, cached) = self.lookup(request);
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+
+        let response = self.inner.chat(request).await?;
+        self.cache.store(&key, self.model_name(), &response);
+        Ok(response)
+    }
+
+    /// Cache hits are not traced, since nothing was generated
+    async fn chat_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<LLMResponse> {
+        let (key, cached) = self.lookup(request);
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+
+        let response = self
+            .inner
+            .chat_with_tracing(request, trace_id, generation_name, metadata)
+            .await?;
+        self.cache.store(&key, self.model_name(), &response);
+        Ok(response)
+    }
+
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        let (key, cached) = self.lookup(request);
+        if let Some(response) = cached {
+            let mut events = vec![Ok(StreamEvent::Delta(response.content))];
+            events.extend(
+                response
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| Ok(StreamEvent::ToolCall(call))),
+            );
+            events.push(Ok(StreamEvent::Usage(response.usage)));
+            return Ok(Box::pin(futures::stream::iter(events)));
+        }
+
+        // Assemble the response as it streams past, and cache it once the
+        // stream ends without an error. Not every provider reports usage, so
+        // it's left at zero if none arrives.
+        let capture = StreamCapture {
+            cache: self.cache.clone(),
+            key,
+            model: self.model_name().to_string(),
+            response: LLMResponse::default(),
+            failed: false,
+        };
+        let inner = self.inner.chat_stream(request).await?;
+        let stream =
+            futures::stream::unfold((inner, capture), |(mut inner, mut capture)| async move {
+                match inner.next().await {
+                    Some(event) => {
+                        capture.add(&event);
+                        Some((event, (inner, capture)))
+                    }
+                    None => {
+                        capture.finish();
+                        None
+                    }
+                }
+            });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn chat_stream_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+        on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+    ) -> Result<LLMResponse> {
+        let (key, cached) = self.lookup(request);
+        if let Some(response) = cached {
+            on_delta(&response.content);
+            return Ok(response);
+        }
+
+        let response = self
+            .inner
+            .chat_stream_with_tracing(request, trace_id, generation_name, metadata, on_delta)
+            .await?;
+        self.cache.store(&key, self.model_name(), &response);
+        Ok(response)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.inner.get_token_prices()
+    }
+
+    fn pricing(&self) -> ModelPricing {
+        self.inner.pricing()
+    }
+
+    async fn fetch_pricing_data(&self) -> Result<()> {
+        self.inner.fetch_pricing_data().await
+    }
+
+    async fn fetch_model_info(&self) -> Result<()> {
+        self.inner.fetch_model_info().await
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
+        self.inner.calculate_cost(usage)
+    }
+}
@@ -1,19 +1,69 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
 use log;
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::sync::{Arc, Once};
+use std::io::{IsTerminal, Write};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 
 use crate::config::LLMConfig;
 use crate::llm::anthropic::AnthropicClient;
+use crate::llm::cache::{response_cache, CachingClient};
+use crate::llm::local::LocalClient;
 use crate::llm::openai::OpenAIClient;
+use crate::llm::pricing::ModelPricing;
+use crate::llm::replay::{RecordingClient, ReplayingClient};
+use crate::llm::retry::{RetryPolicy, RetryingClient};
+use crate::utils::cassette::cassette;
+
+/// Price of writing a prompt prefix to the cache, relative to the prompt price
+pub const CACHE_WRITE_PRICE_MULTIPLIER: f64 = 1.25;
+
+/// Price of reading a cached prompt prefix, relative to the prompt price
+pub const CACHE_READ_PRICE_MULTIPLIER: f64 = 0.1;
 
 /// Common structure for token usage tracking across different LLMs
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct TokenUsage {
+    /// Prompt tokens processed without the cache
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
+    /// All prompt and completion tokens, including cached ones
     pub total_tokens: usize,
+    /// Prompt tokens written to the cache
+    pub cache_creation_input_tokens: usize,
+    /// Prompt tokens read from the cache
+    pub cache_read_input_tokens: usize,
+}
+
+impl TokenUsage {
+    /// Usage as a map with the provider's field names, for recording in trajectories
+    pub fn to_usage_map(&self) -> std::collections::HashMap<String, serde_json::Value> {
+        [
+            ("input_tokens", self.prompt_tokens),
+            ("output_tokens", self.completion_tokens),
+            (
+                "cache_creation_input_tokens",
+                self.cache_creation_input_tokens,
+            ),
+            ("cache_read_input_tokens", self.cache_read_input_tokens),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), serde_json::json!(value)))
+        .collect()
+    }
+}
+
+impl std::ops::AddAssign<&TokenUsage> for TokenUsage {
+    fn add_assign(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+    }
 }
 
 impl fmt::Display for TokenUsage {
@@ -22,32 +72,78 @@ impl fmt::Display for TokenUsage {
             f,
             "Prompt tokens: {}, Completion tokens: {}, Total tokens: {}",
             self.prompt_tokens, self.completion_tokens, self.total_tokens
-        )
+        )?;
+        if self.cache_creation_input_tokens > 0 || self.cache_read_input_tokens > 0 {
+            write!(
+                f,
+                ", Cache write tokens: {}, Cache read tokens: {}",
+                self.cache_creation_input_tokens, self.cache_read_input_tokens
+            )?;
+        }
+        Ok(())
     }
 }
 
 /// Cost calculation for token usage
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TokenCost {
     pub prompt_cost: f64,
     pub completion_cost: f64,
+    /// Cost of writing prompt prefixes to the cache
+    pub cache_write_cost: f64,
+    /// Cost of reading cached prompt prefixes
+    pub cache_read_cost: f64,
     pub total_cost: f64,
 }
 
+impl std::ops::AddAssign<&TokenCost> for TokenCost {
+    fn add_assign(&mut self, other: &TokenCost) {
+        self.prompt_cost += other.prompt_cost;
+        self.completion_cost += other.completion_cost;
+        self.cache_write_cost += other.cache_write_cost;
+        self.cache_read_cost += other.cache_read_cost;
+        self.total_cost += other.total_cost;
+    }
+}
+
 impl TokenCost {
-    /// Calculate cost from token usage and per-token rates
+    /// Calculate cost from token usage and per-token rates. Cache writes and
+    /// reads are priced relative to the prompt price.
     pub fn from_usage(
         usage: &TokenUsage,
         prompt_price_per_1k: f64,
         completion_price_per_1k: f64,
+    ) -> Self {
+        Self::from_usage_with_cache_prices(
+            usage,
+            prompt_price_per_1k,
+            completion_price_per_1k,
+            prompt_price_per_1k * CACHE_WRITE_PRICE_MULTIPLIER,
+            prompt_price_per_1k * CACHE_READ_PRICE_MULTIPLIER,
+        )
+    }
+
+    /// Calculate cost from token usage with explicit per-token cache rates
+    pub fn from_usage_with_cache_prices(
+        usage: &TokenUsage,
+        prompt_price_per_1k: f64,
+        completion_price_per_1k: f64,
+        cache_write_price_per_1k: f64,
+        cache_read_price_per_1k: f64,
     ) -> Self {
         let prompt_cost = (usage.prompt_tokens as f64 / 1000.0) * prompt_price_per_1k;
         let completion_cost = (usage.completion_tokens as f64 / 1000.0) * completion_price_per_1k;
+        let cache_write_cost =
+            (usage.cache_creation_input_tokens as f64 / 1000.0) * cache_write_price_per_1k;
+        let cache_read_cost =
+            (usage.cache_read_input_tokens as f64 / 1000.0) * cache_read_price_per_1k;
 
         TokenCost {
             prompt_cost,
             completion_cost,
-            total_cost: prompt_cost + completion_cost,
+            cache_write_cost,
+            cache_read_cost,
+            total_cost: prompt_cost + completion_cost + cache_write_cost + cache_read_cost,
         }
     }
 
@@ -61,114 +157,546 @@ impl fmt::Display for TokenCost {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Cost: ${:.4} (Prompt: ${:.4}, Completion: ${:.4})",
+            "Cost: ${:.4} (Prompt: ${:.4}, Completion: ${:.4}",
             self.total_cost, self.prompt_cost, self.completion_cost
-        )
+        )?;
+        if self.cache_write_cost > 0.0 || self.cache_read_cost > 0.0 {
+            write!(
+                f,
+                ", Cache write: ${:.4}, Cache read: ${:.4}",
+                self.cache_write_cost, self.cache_read_cost
+            )?;
+        }
+        write!(f, ")")
     }
 }
 
 /// Response from an LLM request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LLMResponse {
     pub content: String,
     pub usage: TokenUsage,
+    /// Tools the model asked to call; empty when the response is a final answer
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// An event in a streamed LLM response
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// A chunk of generated text
+    Delta(String),
+    /// A complete tool call, sent once its arguments have been fully received
+    ToolCall(ToolCall),
+    /// Token usage for the whole response, sent once it is complete
+    Usage(TokenUsage),
+}
+
+/// A streamed LLM response
+pub type LLMStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+/// Consume a response stream, passing each text delta to `on_delta`, and
+/// return the assembled response
+pub async fn collect_stream(
+    mut stream: LLMStream,
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<LLMResponse> {
+    let mut content = String::new();
+    let mut usage = TokenUsage::default();
+    let mut tool_calls = Vec::new();
+
+    while let Some(event) = stream.next().await {
+        match event? {
+            StreamEvent::Delta(text) => {
+                on_delta(&text);
+                content.push_str(&text);
+            }
+            StreamEvent::ToolCall(call) => tool_calls.push(call),
+            StreamEvent::Usage(final_usage) => usage = final_usage,
+        }
+    }
+
+    Ok(LLMResponse {
+        content,
+        usage,
+        tool_calls,
+    })
+}
+
+/// Stream a chat response to the terminal as it is generated, and return the
+/// assembled response. Nothing is echoed when stdout is not a terminal.
+pub async fn stream_to_terminal(
+    client: &dyn LLMClient,
+    request: &ChatRequest,
+    trace_id: Option<&str>,
+    generation_name: Option<&str>,
+    metadata: Option<serde_json::Value>,
+) -> Result<LLMResponse> {
+    let echo = std::io::stdout().is_terminal();
+    let mut on_delta = |delta: &str| {
+        if echo {
+            print!("{}", delta);
+            let _ = std::io::stdout().flush();
+        }
+    };
+
+    let result = client
+        .chat_stream_with_tracing(request, trace_id, generation_name, metadata, &mut on_delta)
+        .await;
+
+    if echo {
+        println!();
+    }
+
+    result
+}
+
+/// A generation being traced in Langfuse
+pub(crate) struct TracedGeneration {
+    trace_id: String,
+    name: String,
+    metadata: Option<serde_json::Value>,
+    start_time: u64,
+}
+
+impl TracedGeneration {
+    /// Record the start of a generation, creating a new trace if one wasn't provided
+    pub(crate) async fn start(
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Self {
+        use crate::llm::langfuse;
+
+        let start_time = now_millis();
+
+        let trace_id = match trace_id {
+            Some(id) => id.to_string(),
+            None => {
+                // Create a new trace for this request
+                let trace_name = generation_name.unwrap_or("llm_completion");
+                match langfuse::get_tracer() {
+                    Ok(tracer) => tracer
+                        .create_trace(trace_name, metadata.clone())
+                        .await
+                        .unwrap_or_default(),
+                    Err(_) => String::new(),
+                }
+            }
+        };
+
+        Self {
+            trace_id,
+            name: generation_name.unwrap_or("llm_generation").to_string(),
+            metadata,
+            start_time,
+        }
+    }
+
+    /// Add a key to the generation's metadata
+    pub(crate) fn add_metadata(&mut self, key: &str, value: serde_json::Value) {
+        match &mut self.metadata {
+            Some(serde_json::Value::Object(map)) => {
+                map.insert(key.to_string(), value);
+            }
+            // Metadata that isn't an object is left as it is
+            Some(_) => {}
+            None => self.metadata = Some(serde_json::json!({ key: value })),
+        }
+    }
+
+    /// Log the finished generation to Langfuse, if enabled and we have a valid trace ID
+    pub(crate) async fn finish(
+        self,
+        model: &str,
+        request: &ChatRequest,
+        response: &LLMResponse,
+        cost: &TokenCost,
+    ) {
+        use crate::llm::langfuse;
+
+        if self.trace_id.is_empty() {
+            return;
+        }
+
+        let end_time = now_millis();
+
+        if let Ok(tracer) = langfuse::get_tracer() {
+            // Create JSON for the request messages and completion
+            let input_json = request.to_trace_input();
+            let output_json = if response.tool_calls.is_empty() {
+                serde_json::json!(response.content)
+            } else {
+                serde_json::json!({
+                    "content": response.content,
+                    "tool_calls": response.tool_calls,
+                })
+            };
+
+            // Log the generation with full model name instead of just provider name
+            let _ = tracer
+                .log_generation(
+                    &self.trace_id,
+                    &self.name,
+                    model,
+                    &serde_json::to_string(&input_json).unwrap_or_else(|_| request.to_prompt()),
+                    &serde_json::to_string(&output_json)
+                        .unwrap_or_else(|_| response.content.clone()),
+                    &response.usage,
+                    Some(cost),
+                    self.metadata,
+                    Some(self.start_time),
+                    Some(end_time),
+                )
+                .await;
+        }
+    }
+}
+
+/// Current timestamp in milliseconds
+fn now_millis() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Role of a message in a chat conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A tool the model may call, described by a JSON Schema for its input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+/// A request from the model to call a tool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Provider-assigned ID, used to match the result to the call
+    pub id: String,
+    pub name: String,
+    /// Arguments as a JSON object
+    pub input: serde_json::Value,
+}
+
+/// The outcome of a tool call, sent back to the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallResult {
+    /// ID of the tool call this is the result of
+    pub tool_call_id: String,
+    pub content: String,
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+/// A single message in a chat conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Tool calls made by the assistant in this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Results of the previous assistant message's tool calls, sent by the user
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<ToolCallResult>,
+    /// Byte offset into `content` marking the end of a prefix that stays the
+    /// same across requests, which providers that support it may cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_breakpoint: Option<usize>,
+}
+
+impl Message {
+    /// Create a user message
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_results: Vec::new(),
+            cache_breakpoint: None,
+        }
+    }
+
+    /// Create an assistant message
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_results: Vec::new(),
+            cache_breakpoint: None,
+        }
+    }
+
+    /// Create a user message whose first part is a cacheable prefix shared
+    /// with other requests
+    pub fn user_with_cached_prefix(prefix: impl Into<String>, rest: impl AsRef<str>) -> Self {
+        let mut content = prefix.into();
+        let breakpoint = content.len();
+        content.push_str(rest.as_ref());
+        Self {
+            cache_breakpoint: Some(breakpoint),
+            ..Self::user(content)
+        }
+    }
+
+    /// Create an assistant message that calls tools
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::assistant(content)
+        }
+    }
+
+    /// Create a user message carrying the results of tool calls
+    pub fn tool_results(tool_results: Vec<ToolCallResult>) -> Self {
+        Self {
+            tool_results,
+            ..Self::user("")
+        }
+    }
+
+    /// Whether the message is plain text, without tool calls or results
+    pub fn is_text_only(&self) -> bool {
+        self.tool_calls.is_empty() && self.tool_results.is_empty()
+    }
+}
+
+/// A request to an LLM with a system prompt, typed conversation messages and
+/// generation parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub system: Option<String>,
+    /// Whether the system prompt should be cached by providers that support it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cache_system: bool,
+    pub messages: Vec<Message>,
+    pub max_tokens: usize,
+    pub temperature: f64,
+    /// Tools the model may call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+}
+
+impl ChatRequest {
+    /// Create an empty request with the given generation parameters
+    pub fn new(max_tokens: usize, temperature: f64) -> Self {
+        Self {
+            system: None,
+            cache_system: false,
+            messages: Vec::new(),
+            max_tokens,
+            temperature,
+            tools: Vec::new(),
+        }
+    }
+
+    /// Set the system prompt
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    /// Set a system prompt that stays the same across requests, so providers
+    /// that support prompt caching can cache it
+    pub fn with_cached_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self.cache_system = true;
+        self
+    }
+
+    /// Append a message to the conversation
+    pub fn with_message(mut self, message: Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    /// Append a user message to the conversation
+    pub fn with_user(self, content: impl Into<String>) -> Self {
+        self.with_message(Message::user(content))
+    }
+
+    /// Offer tools for the model to call
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Render the request as a single prompt string, for clients that only
+    /// implement `completion`
+    pub fn to_prompt(&self) -> String {
+        // A lone user message is passed through untouched
+        if self.system.is_none()
+            && self.messages.len() == 1
+            && self.messages[0].role == Role::User
+            && self.messages[0].is_text_only()
+        {
+            return self.messages[0].content.clone();
+        }
+
+        let mut sections = Vec::new();
+        if let Some(system) = &self.system {
+            sections.push(format!("System: {}", system));
+        }
+        for message in &self.messages {
+            let label = match message.role {
+                Role::User => "Human",
+                Role::Assistant => "Assistant",
+            };
+            sections.push(format!("{}: {}", label, message.content));
+            for call in &message.tool_calls {
+                sections.push(format!(
+                    "{}: [tool call] {}({})",
+                    label, call.name, call.input
+                ));
+            }
+            for result in &message.tool_results {
+                sections.push(format!("{}: [tool result] {}", label, result.content));
+            }
+        }
+        sections.join("\n\n")
+    }
+
+    /// The request as a list of role/content pairs, including the system
+    /// prompt, for logging to Langfuse
+    fn to_trace_input(&self) -> serde_json::Value {
+        let mut input = Vec::new();
+        if let Some(system) = &self.system {
+            input.push(serde_json::json!({ "role": "system", "content": system }));
+        }
+        for message in &self.messages {
+            input.push(serde_json::json!(message));
+        }
+        serde_json::Value::Array(input)
+    }
 }
 
 /// A trait for LLM clients
+///
+/// Implementations must override at least one of `chat` or `completion`; each
+/// has a default implementation in terms of the other.
 #[async_trait]
 pub trait LLMClient: Send + Sync {
+    /// Generate a response to a chat request with typed messages
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        if !request.tools.is_empty() {
+            log::debug!(
+                "{} does not support tool use, so the request's tools are ignored",
+                self.name()
+            );
+        }
+        self.completion(
+            &request.to_prompt(),
+            request.max_tokens,
+            request.temperature,
+        )
+        .await
+    }
+
     /// Generate a completion from the LLM
+    ///
+    /// Compatibility shim: the prompt is sent as a single user message.
     async fn completion(
         &self,
         prompt: &str,
         max_tokens: usize,
         temperature: f64,
-    ) -> Result<LLMResponse>;
+    ) -> Result<LLMResponse> {
+        self.chat(&ChatRequest::new(max_tokens, temperature).with_user(prompt))
+            .await
+    }
 
-    /// Generate a completion with Langfuse tracing
-    async fn completion_with_tracing(
+    /// Generate a chat response with Langfuse tracing
+    async fn chat_with_tracing(
         &self,
-        prompt: &str,
-        max_tokens: usize,
-        temperature: f64,
+        request: &ChatRequest,
         trace_id: Option<&str>,
         generation_name: Option<&str>,
         metadata: Option<serde_json::Value>,
     ) -> Result<LLMResponse> {
-        use crate::llm::langfuse;
-        use std::time::{Instant, SystemTime, UNIX_EPOCH};
-
-        // Get the current timestamp in milliseconds
-        let start_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64;
+        let mut generation = TracedGeneration::start(trace_id, generation_name, metadata).await;
 
-        // Record start time for duration measurement - currently calculated using SystemTime instead
-        let _instant_start = Instant::now();
+        // Call the regular chat method
+        let result = self.chat(request).await;
 
-        // Create a new trace if one wasn't provided
-        let (_owned_trace_id, trace_id_str) = match trace_id {
-            Some(id) => (None, id.to_string()),
-            None => {
-                // Create a new trace for this completion
-                let trace_name = generation_name.unwrap_or("llm_completion");
-                match langfuse::get_tracer() {
-                    Ok(tracer) => match tracer.create_trace(trace_name, metadata.clone()).await {
-                        Ok(id) => {
-                            let id_str = id.clone();
-                            (Some(id), id_str)
-                        }
-                        Err(_) => (None, String::new()),
-                    },
-                    Err(_) => (None, String::new()),
-                }
-            }
-        };
-
-        // Call the regular completion method
-        let result = self.completion(prompt, max_tokens, temperature).await;
-
-        // Get the end timestamp
-        let end_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64;
-
-        // Log to Langfuse if enabled and we have a valid trace ID
-        if !trace_id_str.is_empty() {
-            if let Ok(response) = &result {
-                if let Ok(tracer) = langfuse::get_tracer() {
-                    let gen_name = generation_name.unwrap_or("llm_generation");
-                    let cost = self.calculate_cost(&response.usage);
-
-                    // Create JSON for prompt and completion
-                    let input_json = serde_json::json!(prompt);
-                    let output_json = serde_json::json!(response.content);
-
-                    // Log the generation with full model name instead of just provider name
-                    let _ = tracer
-                        .log_generation(
-                            &trace_id_str,
-                            gen_name,
-                            self.model_name(),
-                            &serde_json::to_string(&input_json)
-                                .unwrap_or_else(|_| prompt.to_string()),
-                            &serde_json::to_string(&output_json)
-                                .unwrap_or_else(|_| response.content.clone()),
-                            &response.usage,
-                            Some(&cost),
-                            metadata,
-                            Some(start_time),
-                            Some(end_time),
-                        )
-                        .await;
-                }
-            }
+        if let Ok(response) = &result {
+            let cost = self.calculate_cost(&response.usage);
+            generation.add_metadata("price_source", self.pricing().source.into());
+            generation
+                .finish(self.model_name(), request, response, &cost)
+                .await;
         }
 
         result
     }
 
+    /// Generate a chat response as a stream of text deltas followed by the
+    /// final token usage
+    ///
+    /// The default implementation waits for the full response and yields it
+    /// as a single delta.
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        let response = self.chat(request).await?;
+        let mut events = vec![Ok(StreamEvent::Delta(response.content))];
+        events.extend(
+            response
+                .tool_calls
+                .into_iter()
+                .map(|call| Ok(StreamEvent::ToolCall(call))),
+        );
+        events.push(Ok(StreamEvent::Usage(response.usage)));
+        Ok(Box::pin(futures::stream::iter(events)))
+    }
+
+    /// Stream a chat response with Langfuse tracing, passing each text delta
+    /// to `on_delta` as it arrives, and return the assembled response
+    async fn chat_stream_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+        on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+    ) -> Result<LLMResponse> {
+        let mut generation = TracedGeneration::start(trace_id, generation_name, metadata).await;
+
+        let stream = self.chat_stream(request).await?;
+        let response = collect_stream(stream, on_delta).await?;
+
+        let cost = self.calculate_cost(&response.usage);
+        generation.add_metadata("price_source", self.pricing().source.into());
+        generation
+            .finish(self.model_name(), request, &response, &cost)
+            .await;
+
+        Ok(response)
+    }
+
+    /// Generate a completion with Langfuse tracing
+    async fn completion_with_tracing(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        temperature: f64,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<LLMResponse> {
+        let request = ChatRequest::new(max_tokens, temperature).with_user(prompt);
+        self.chat_with_tracing(&request, trace_id, generation_name, metadata)
+            .await
+    }
+
     /// Get the name of the LLM client (provider name)
     fn name(&self) -> &str {
         "unknown"
@@ -184,6 +712,12 @@ pub trait LLMClient: Send + Sync {
         (0.01, 0.01) // Default prices
     }
 
+    /// The model's prices and where they came from
+    fn pricing(&self) -> ModelPricing {
+        let (prompt_price, completion_price) = self.get_token_prices();
+        ModelPricing::from_per_1k(prompt_price, completion_price, "client")
+    }
+
     /// Fetch the latest pricing data from the provider API
     async fn fetch_pricing_data(&self) -> Result<()> {
         // Default implementation does nothing
@@ -191,15 +725,25 @@ pub trait LLMClient: Send + Sync {
         Ok(())
     }
 
+    /// Discover model details (such as the context window) from the provider
+    async fn fetch_model_info(&self) -> Result<()> {
+        // Default implementation does nothing
+        Ok(())
+    }
+
+    /// Maximum context length of the model in tokens, if known
+    fn context_window(&self) -> Option<usize> {
+        None
+    }
+
     /// Calculate cost from token usage
     fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
-        let (prompt_price, completion_price) = self.get_token_prices();
-        TokenCost::from_usage(usage, prompt_price, completion_price)
+        self.pricing().cost(usage)
     }
 }
 
-// Default client factory function
-async fn default_client_factory(config: &LLMConfig) -> Result<Box<dyn LLMClient>> {
+/// Create the client for a configuration's provider, without contacting it
+fn provider_client(config: &LLMConfig) -> Result<Box<dyn LLMClient>> {
     let client: Box<dyn LLMClient> = match config.model_type.as_str() {
         "openai" => {
             let client = OpenAIClient::new(config)?;
@@ -209,6 +753,10 @@ async fn default_client_factory(config: &LLMConfig) -> Result<Box<dyn LLMClient>
             let client = AnthropicClient::new(config)?;
             Box::new(client)
         }
+        "local" => {
+            let client = LocalClient::new(config)?;
+            Box::new(client)
+        }
         _ => {
             return Err(anyhow::anyhow!(
                 "Unsupported LLM type: {}",
@@ -217,6 +765,13 @@ async fn default_client_factory(config: &LLMConfig) -> Result<Box<dyn LLMClient>
         }
     };
 
+    Ok(client)
+}
+
+// Default client factory function
+async fn default_client_factory(config: &LLMConfig) -> Result<Box<dyn LLMClient>> {
+    let client = provider_client(config)?;
+
     // Fetch pricing data after creating the client
     if let Err(e) = client.fetch_pricing_data().await {
         log::warn!(
@@ -225,6 +780,20 @@ async fn default_client_factory(config: &LLMConfig) -> Result<Box<dyn LLMClient>
         );
     }
 
+    let pricing = client.pricing();
+    log::debug!(
+        "Pricing {} at ${}/${} per million input/output tokens ({})",
+        client.model_name(),
+        pricing.price.input,
+        pricing.price.output,
+        pricing.source
+    );
+
+    // Discover model details such as the context window
+    if let Err(e) = client.fetch_model_info().await {
+        log::warn!("Failed to fetch model info: {}", e);
+    }
+
     Ok(client)
 }
 
@@ -235,88 +804,174 @@ type AsyncClientFactory = fn(
     Box<dyn std::future::Future<Output = Result<Arc<dyn LLMClient>>> + Send>,
 >;
 
-// Global state for client factory
-static INIT: Once = Once::new();
-static mut ASYNC_CLIENT_FACTORY: Option<AsyncClientFactory> = None;
+/// Custom client factory, used instead of the provider clients
+static CLIENT_FACTORY: RwLock<Option<AsyncClientFactory>> = RwLock::new(None);
 
 /// Set a custom async client factory function for testing
 pub fn set_client_factory(factory: AsyncClientFactory) {
-    unsafe {
-        INIT.call_once(|| {});
-        ASYNC_CLIENT_FACTORY = Some(factory);
-    }
+    *CLIENT_FACTORY.write().unwrap() = Some(factory);
+}
+
+/// Go back to creating provider clients
+pub fn clear_client_factory() {
+    *CLIENT_FACTORY.write().unwrap() = None;
 }
 
 /// Create an LLM client from a configuration and fetch pricing data
+///
+/// When a cassette is being replayed, the client answers from the cassette
+/// instead; when one is being recorded, every exchange is written to it.
 pub async fn create_client(config: &LLMConfig) -> Result<Box<dyn LLMClient>> {
-    // Check if we have a custom factory
-    unsafe {
-        if let Some(factory) = ASYNC_CLIENT_FACTORY {
-            let arc_client = factory(config).await?;
-
-            // Convert Arc<dyn LLMClient> to Box<dyn LLMClient>
-            // This is a bit of a hack, but needed for compatibility with existing code
-            struct ArcWrapper {
-                inner: Arc<dyn LLMClient>,
+    let cassette = cassette();
+    if let Some(cassette) = cassette.as_ref().filter(|cassette| cassette.is_replaying()) {
+        return Ok(Box::new(ReplayingClient::new(config, cassette.clone())));
+    }
+
+    let client = with_cache(with_retries(new_client(config).await?, config));
+    Ok(match cassette {
+        Some(cassette) => Box::new(RecordingClient::new(client, cassette)),
+        None => client,
+    })
+}
+
+/// Create a client from the custom factory if one is set, or for the
+/// configured provider
+async fn new_client(config: &LLMConfig) -> Result<Box<dyn LLMClient>> {
+    // Copy the factory out so the lock isn't held across the await
+    let factory = *CLIENT_FACTORY.read().unwrap();
+    if let Some(factory) = factory {
+        let arc_client = factory(config).await?;
+
+        // Convert Arc<dyn LLMClient> to Box<dyn LLMClient>
+        // This is a bit of a hack, but needed for compatibility with existing code
+        struct ArcWrapper {
+            inner: Arc<dyn LLMClient>,
+        }
+
+        #[async_trait]
+        impl LLMClient for ArcWrapper {
+            fn name(&self) -> &str {
+                self.inner.name()
             }
 
-            #[async_trait]
-            impl LLMClient for ArcWrapper {
-                fn name(&self) -> &str {
-                    self.inner.name()
-                }
+            fn model_name(&self) -> &str {
+                self.inner.model_name()
+            }
 
-                fn model_name(&self) -> &str {
-                    self.inner.model_name()
-                }
+            fn get_token_prices(&self) -> (f64, f64) {
+                self.inner.get_token_prices()
+            }
 
-                fn get_token_prices(&self) -> (f64, f64) {
-                    self.inner.get_token_prices()
-                }
+            fn pricing(&self) -> ModelPricing {
+                self.inner.pricing()
+            }
 
-                async fn completion(
-                    &self,
-                    prompt: &str,
-                    max_tokens: usize,
-                    temperature: f64,
-                ) -> Result<LLMResponse> {
-                    self.inner.completion(prompt, max_tokens, temperature).await
-                }
+            async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+                self.inner.chat(request).await
+            }
 
-                async fn completion_with_tracing(
-                    &self,
-                    prompt: &str,
-                    max_tokens: usize,
-                    temperature: f64,
-                    trace_id: Option<&str>,
-                    generation_name: Option<&str>,
-                    metadata: Option<serde_json::Value>,
-                ) -> Result<LLMResponse> {
-                    self.inner
-                        .completion_with_tracing(
-                            prompt,
-                            max_tokens,
-                            temperature,
-                            trace_id,
-                            generation_name,
-                            metadata,
-                        )
-                        .await
-                }
+            async fn completion(
+                &self,
+                prompt: &str,
+                max_tokens: usize,
+                temperature: f64,
+            ) -> Result<LLMResponse> {
+                self.inner.completion(prompt, max_tokens, temperature).await
+            }
 
-                async fn fetch_pricing_data(&self) -> Result<()> {
-                    self.inner.fetch_pricing_data().await
-                }
+            async fn chat_with_tracing(
+                &self,
+                request: &ChatRequest,
+                trace_id: Option<&str>,
+                generation_name: Option<&str>,
+                metadata: Option<serde_json::Value>,
+            ) -> Result<LLMResponse> {
+                self.inner
+                    .chat_with_tracing(request, trace_id, generation_name, metadata)
+                    .await
+            }
 
-                fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
-                    self.inner.calculate_cost(usage)
-                }
+            async fn completion_with_tracing(
+                &self,
+                prompt: &str,
+                max_tokens: usize,
+                temperature: f64,
+                trace_id: Option<&str>,
+                generation_name: Option<&str>,
+                metadata: Option<serde_json::Value>,
+            ) -> Result<LLMResponse> {
+                self.inner
+                    .completion_with_tracing(
+                        prompt,
+                        max_tokens,
+                        temperature,
+                        trace_id,
+                        generation_name,
+                        metadata,
+                    )
+                    .await
+            }
+
+            async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+                self.inner.chat_stream(request).await
             }
 
-            return Ok(Box::new(ArcWrapper { inner: arc_client }));
+            async fn chat_stream_with_tracing(
+                &self,
+                request: &ChatRequest,
+                trace_id: Option<&str>,
+                generation_name: Option<&str>,
+                metadata: Option<serde_json::Value>,
+                on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+            ) -> Result<LLMResponse> {
+                self.inner
+                    .chat_stream_with_tracing(
+                        request,
+                        trace_id,
+                        generation_name,
+                        metadata,
+                        on_delta,
+                    )
+                    .await
+            }
+
+            async fn fetch_pricing_data(&self) -> Result<()> {
+                self.inner.fetch_pricing_data().await
+            }
+
+            async fn fetch_model_info(&self) -> Result<()> {
+                self.inner.fetch_model_info().await
+            }
+
+            fn context_window(&self) -> Option<usize> {
+                self.inner.context_window()
+            }
+
+            fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
+                self.inner.calculate_cost(usage)
+            }
         }
+
+        return Ok(Box::new(ArcWrapper { inner: arc_client }));
     }
 
     // Otherwise use the default factory
     default_client_factory(config).await
 }
+
+/// Wrap a client so that failed requests are retried as configured
+fn with_retries(client: Box<dyn LLMClient>, config: &LLMConfig) -> Box<dyn LLMClient> {
+    Box::new(RetryingClient::new(
+        client,
+        RetryPolicy::from_config(config),
+    ))
+}
+
+/// Wrap a client so that responses come from the response cache, if one is
+/// configured
+fn with_cache(client: Box<dyn LLMClient>) -> Box<dyn LLMClient> {
+    match response_cache() {
+        Some(cache) => Box::new(CachingClient::new(client, cache)),
+        None => client,
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use log::warn;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::llm::retry::parse_retry_after;
+
+/// Phrases providers use when a prompt doesn't fit in the model's context window
+const CONTEXT_OVERFLOW_PHRASES: &[&str] = &[
+    "prompt is too long",
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "context size",
+    "too many tokens",
+];
+
+/// An error from an LLM provider, classified by how callers should react to it
+///
+/// Errors are serialized with their kind and fields, so that a recorded error
+/// is replayed as the same error.
+#[derive(Debug, Clone, thiserror::Error, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LLMError {
+    /// Too many requests; worth retrying after a delay
+    #[error("{provider} rate limit exceeded: {message}")]
+    RateLimited {
+        provider: String,
+        retry_after: Option<Duration>,
+        message: String,
+    },
+
+    /// The provider is overloaded or failed internally; worth retrying after a delay
+    #[error("{provider} is overloaded ({status}): {message}")]
+    Overloaded {
+        provider: String,
+        #[serde(with = "status_code")]
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        message: String,
+    },
+
+    /// The prompt doesn't fit in the model's context window; retrying only
+    /// helps with a shorter prompt
+    #[error("Prompt is too long for the {provider} model's context window: {message}")]
+    ContextTooLong { provider: String, message: String },
+
+    /// The API key is missing, invalid or lacks permission; no request will succeed
+    #[error("{provider} authentication failed ({status}): {message}")]
+    Auth {
+        provider: String,
+        #[serde(with = "status_code")]
+        status: StatusCode,
+        message: String,
+    },
+
+    /// The provider rejected the request
+    #[error("{provider} API error ({status}): {message}")]
+    InvalidRequest {
+        provider: String,
+        #[serde(with = "status_code")]
+        status: StatusCode,
+        message: String,
+    },
+
+    /// The request didn't reach the provider or the connection failed
+    #[error("Failed to communicate with {provider}: {message}")]
+    Transport { provider: String, message: String },
+
+    /// The provider's response couldn't be understood
+    #[error("Malformed response from {provider}: {message}")]
+    MalformedResponse { provider: String, message: String },
+}
+
+impl LLMError {
+    /// Classify an error response by its status code and message
+    pub fn from_status(
+        provider: &str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        message: impl Into<String>,
+    ) -> Self {
+        let provider = provider.to_string();
+        let message = message.into();
+
+        match status.as_u16() {
+            401 | 403 => Self::Auth {
+                provider,
+                status,
+                message,
+            },
+            429 => Self::RateLimited {
+                provider,
+                retry_after,
+                message,
+            },
+            408 => Self::Transport { provider, message },
+            413 => Self::ContextTooLong { provider, message },
+            _ if status.is_server_error() => Self::Overloaded {
+                provider,
+                status,
+                retry_after,
+                message,
+            },
+            _ if is_context_overflow_message(&message) => {
+                Self::ContextTooLong { provider, message }
+            }
+            _ => Self::InvalidRequest {
+                provider,
+                status,
+                message,
+            },
+        }
+    }
+
+    /// Read and classify a failed response
+    pub async fn from_response(provider: &str, response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = parse_retry_after(response.headers());
+        let message = response
+            .text()
+            .await
+            .unwrap_or_else(|e| format!("failed to read error response: {}", e));
+
+        Self::from_status(provider, status, retry_after, message)
+    }
+
+    /// Classify an error reported by type rather than status code, as in the
+    /// error events of a streamed response
+    pub fn from_error_type(provider: &str, error_type: &str, message: impl Into<String>) -> Self {
+        let status = match error_type {
+            "authentication_error" | "invalid_api_key" => StatusCode::UNAUTHORIZED,
+            "permission_error" => StatusCode::FORBIDDEN,
+            "rate_limit_error" | "rate_limit_exceeded" => StatusCode::TOO_MANY_REQUESTS,
+            "request_too_large" | "context_length_exceeded" => StatusCode::PAYLOAD_TOO_LARGE,
+            "overloaded_error" => {
+                StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
+            }
+            "api_error" | "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self::from_status(provider, status, None, message)
+    }
+
+    /// A failure to send the request or receive the response
+    pub fn transport(provider: &str, error: reqwest::Error) -> Self {
+        Self::Transport {
+            provider: provider.to_string(),
+            message: format!("{:#}", anyhow::Error::from(error)),
+        }
+    }
+
+    /// A response that couldn't be understood
+    pub fn malformed(provider: &str, message: impl Into<String>) -> Self {
+        Self::MalformedResponse {
+            provider: provider.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// Find the LLM error behind an error, looking through any added context
+    pub fn find(error: &anyhow::Error) -> Option<&LLMError> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<LLMError>())
+    }
+
+    /// Whether the request may succeed if sent again unchanged
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Overloaded { .. } | Self::Transport { .. }
+        )
+    }
+
+    /// How long the provider asked us to wait before retrying, if it said
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::Overloaded { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+/// (De)serialize a status code as its number
+mod status_code {
+    use reqwest::StatusCode;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        let code = u16::deserialize(deserializer)?;
+        StatusCode::from_u16(code).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Whether an error is an authentication failure, which no retry will fix
+pub fn is_auth_error(error: &anyhow::Error) -> bool {
+    matches!(LLMError::find(error), Some(LLMError::Auth { .. }))
+}
+
+/// Whether an error is a prompt that was too long for the model
+pub fn is_context_too_long(error: &anyhow::Error) -> bool {
+    matches!(LLMError::find(error), Some(LLMError::ContextTooLong { .. }))
+}
+
+/// Generate with the `file_count` highest-ranked files, and while the prompt
+/// is too long for the model, drop a quarter of the remaining files (at least
+/// one) from the bottom of the ranking and try again
+pub async fn retry_with_fewer_files<T, F, Fut>(file_count: usize, mut generate: F) -> Result<T>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut count = file_count;
+    loop {
+        match generate(count).await {
+            Err(e) if count > 0 && is_context_too_long(&e) => {
+                let dropped = (count / 4).max(1);
+                warn!(
+                    "Prompt with {} files is too long for the model, retrying without the {} lowest-ranked",
+                    count, dropped
+                );
+                count -= dropped;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_context_overflow_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    CONTEXT_OVERFLOW_PHRASES
+        .iter()
+        .any(|phrase| message.contains(phrase))
+}
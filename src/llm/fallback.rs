@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use log::warn;
+use std::future::Future;
+
+use crate::config::LLMConfig;
+use crate::llm::budget::{is_budget_exceeded, with_budget};
+use crate::llm::client::{create_client, LLMClient};
+
+/// An answer from one of a chain's models
+pub struct Answer<'a, T> {
+    pub value: T,
+    /// The client of the model that answered
+    pub client: &'a dyn LLMClient,
+}
+
+impl<T> Answer<'_, T> {
+    /// Name of the model that answered
+    pub fn model(&self) -> &str {
+        self.client.model_name()
+    }
+}
+
+/// The models a stage may use, in the order they are tried
+///
+/// A stage moves on to the next model when a request fails, or when the
+/// answer can't be used, e.g. because it couldn't be parsed.
+pub struct ModelChain {
+    clients: Vec<Box<dyn LLMClient>>,
+}
+
+impl ModelChain {
+    pub fn new(clients: Vec<Box<dyn LLMClient>>) -> Result<Self> {
+        if clients.is_empty() {
+            return Err(anyhow::anyhow!("A model chain needs at least one model"));
+        }
+        Ok(Self { clients })
+    }
+
+    /// Create a client for each model of a stage, spending from the stage's
+    /// budget
+    pub async fn create(configs: &[LLMConfig], stage: &str) -> Result<Self> {
+        let mut clients = Vec::new();
+        for config in configs {
+            let client = create_client(config)
+                .await
+                .context(format!("Failed to create LLM client for {}", config.model))?;
+            clients.push(with_budget(client, stage));
+        }
+        Self::new(clients)
+    }
+
+    /// The client of the first model, which is tried first
+    pub fn primary(&self) -> &dyn LLMClient {
+        self.clients[0].as_ref()
+    }
+
+    /// Names of the models, in the order they are tried
+    pub fn model_names(&self) -> Vec<String> {
+        self.clients
+            .iter()
+            .map(|client| client.model_name().to_string())
+            .collect()
+    }
+
+    /// The smallest context window among the models, so that a prompt sized
+    /// for it fits whichever model ends up answering
+    pub fn context_window(&self) -> Option<usize> {
+        self.clients
+            .iter()
+            .filter_map(|client| client.context_window())
+            .min()
+    }
+
+    /// Make an attempt with each model in turn until one succeeds with an
+    /// answer that passes validation
+    ///
+    /// A request refused by the budget is never retried with another model.
+    /// If the last model's answer fails validation it is returned anyway, so
+    /// the stage can handle it as it would without a chain.
+    pub async fn run<'a, T, F, Fut, V>(
+        &'a self,
+        mut attempt: F,
+        validate: V,
+    ) -> Result<Answer<'a, T>>
+    where
+        F: FnMut(&'a dyn LLMClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+        V: Fn(&T) -> Result<()>,
+    {
+        for (i, client) in self.clients.iter().enumerate() {
+            let client: &'a dyn LLMClient = client.as_ref();
+            let next = match self.clients.get(i + 1) {
+                Some(next) => next.model_name(),
+                None => {
+                    let value = attempt(client).await?;
+                    if let Err(e) = validate(&value) {
+                        warn!(
+                            "Answer from {} is unusable and no models are left to try: {:#}",
+                            client.model_name(),
+                            e
+                        );
+                    }
+                    return Ok(Answer { value, client });
+                }
+            };
+
+            match attempt(client).await {
+                Ok(value) => match validate(&value) {
+                    Ok(()) => return Ok(Answer { value, client }),
+                    Err(e) => warn!(
+                        "Answer from {} is unusable ({:#}), falling back to {}",
+                        client.model_name(),
+                        e,
+                        next
+                    ),
+                },
+                Err(e) if is_budget_exceeded(&e) => return Err(e),
+                Err(e) => warn!(
+                    "Request to {} failed ({:#}), falling back to {}",
+                    client.model_name(),
+                    e,
+                    next
+                ),
+            }
+        }
+        unreachable!("a model chain has at least one model")
+    }
+}
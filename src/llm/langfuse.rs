@@ -277,7 +277,9 @@ impl LangfuseClient {
         let usage_details = json!({
             "prompt_tokens": token_usage.prompt_tokens,
             "completion_tokens": token_usage.completion_tokens,
-            "total_tokens": token_usage.total_tokens
+            "total_tokens": token_usage.total_tokens,
+            "cache_creation_input_tokens": token_usage.cache_creation_input_tokens,
+            "cache_read_input_tokens": token_usage.cache_read_input_tokens
         });
 
         // Build cost details
@@ -285,6 +287,8 @@ impl LangfuseClient {
             json!({
                 "prompt_cost": cost.prompt_cost,
                 "completion_cost": cost.completion_cost,
+                "cache_write_cost": cost.cache_write_cost,
+                "cache_read_cost": cost.cache_read_cost,
                 "total_cost": cost.total_cost
             })
         } else {
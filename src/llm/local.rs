@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, info};
+use reqwest::{header, Client};
+use serde_json::{json, Value};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::config::LLMConfig;
+use crate::llm::client::{ChatRequest, LLMClient, LLMResponse, LLMStream};
+use crate::llm::openai::OpenAIClient;
+use crate::llm::pricing::{model_pricing, ModelPricing};
+
+/// Default endpoint for a local model server (Ollama's OpenAI-compatible API)
+const DEFAULT_LOCAL_BASE_URL: &str = "http://localhost:11434/v1";
+
+/// A client for OpenAI-compatible chat endpoints served locally, such as
+/// Ollama, vLLM or the llama.cpp server
+pub struct LocalClient {
+    inner: OpenAIClient,
+    client: Client,
+    config: LLMConfig,
+    context_window: RwLock<Option<usize>>,
+}
+
+impl LocalClient {
+    /// Create a new local model client
+    pub fn new(config: &LLMConfig) -> Result<Self> {
+        let mut config = config.clone();
+        if config.base_url.is_none() {
+            config.base_url = Some(DEFAULT_LOCAL_BASE_URL.to_string());
+        }
+
+        // Local servers usually don't require auth, so only send a key if one is configured
+        let mut headers = header::HeaderMap::new();
+        if !config.api_key.trim().is_empty() {
+            let auth_value = format!("Bearer {}", config.api_key);
+            let auth_header = header::HeaderValue::from_str(&auth_value)
+                .context("Failed to create Authorization header")?;
+            headers.insert(header::AUTHORIZATION, auth_header);
+        }
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self {
+            inner: OpenAIClient::new(&config)?,
+            client,
+            config,
+            context_window: RwLock::new(None),
+        })
+    }
+
+    /// The OpenAI-compatible base URL (e.g. http://localhost:11434/v1)
+    fn base_url(&self) -> &str {
+        self.config
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_LOCAL_BASE_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The server root, without the OpenAI-compatible /v1 suffix
+    fn server_root(&self) -> &str {
+        let base_url = self.base_url();
+        base_url.strip_suffix("/v1").unwrap_or(base_url)
+    }
+
+    /// Look up the context length in the /v1/models listing (vLLM, llama.cpp)
+    async fn context_window_from_models(&self) -> Result<Option<usize>> {
+        let url = format!("{}/models", self.base_url());
+        let response: Value = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to fetch model list")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse model list")?;
+
+        let model = response["data"]
+            .as_array()
+            .and_then(|models| {
+                models
+                    .iter()
+                    .find(|m| m["id"].as_str() == Some(self.config.model.as_str()))
+            })
+            .cloned()
+            .unwrap_or(Value::Null);
+
+        Ok(["max_model_len", "context_length", "context_window"]
+            .iter()
+            .find_map(|key| model[key].as_u64())
+            .or_else(|| model["meta"]["n_ctx_train"].as_u64())
+            .map(|n| n as usize))
+    }
+
+    /// Look up the context length via Ollama's /api/show endpoint
+    async fn context_window_from_ollama(&self) -> Result<Option<usize>> {
+        let url = format!("{}/api/show", self.server_root());
+        let response: Value = self
+            .client
+            .post(&url)
+            .json(&json!({ "model": self.config.model }))
+            .send()
+            .await
+            .context("Failed to query Ollama model info")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse Ollama model info")?;
+
+        // Architecture-specific key, e.g. "llama.context_length"
+        Ok(response["model_info"].as_object().and_then(|info| {
+            info.iter()
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+                .map(|n| n as usize)
+        }))
+    }
+
+    /// Look up the context length via the llama.cpp server /props endpoint
+    async fn context_window_from_props(&self) -> Result<Option<usize>> {
+        let url = format!("{}/props", self.server_root());
+        let response: Value = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to query server properties")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse server properties")?;
+
+        Ok(response["default_generation_settings"]["n_ctx"]
+            .as_u64()
+            .map(|n| n as usize))
+    }
+}
+
+#[async_trait]
+impl LLMClient for LocalClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        self.inner.chat(request).await
+    }
+
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        self.inner.chat_stream(request).await
+    }
+
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn model_name(&self) -> &str {
+        &self.config.model
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.pricing().per_1k()
+    }
+
+    /// Self-hosted models are free unless priced in the config
+    fn pricing(&self) -> ModelPricing {
+        model_pricing("local", &self.config.model)
+    }
+
+    async fn fetch_model_info(&self) -> Result<()> {
+        debug!(
+            "Discovering context length for local model {}",
+            self.config.model
+        );
+
+        // Each server type exposes the context length differently, so try them in turn
+        let mut context_window = None;
+        for source in ["models", "ollama", "props"] {
+            let result = match source {
+                "models" => self.context_window_from_models().await,
+                "ollama" => self.context_window_from_ollama().await,
+                _ => self.context_window_from_props().await,
+            };
+
+            match result {
+                Ok(Some(n)) => {
+                    info!(
+                        "Context length for {}: {} tokens (via {})",
+                        self.config.model, n, source
+                    );
+                    context_window = Some(n);
+                    break;
+                }
+                Ok(None) => debug!("No context length reported via {}", source),
+                Err(e) => debug!("Context length lookup via {} failed: {}", source, e),
+            }
+        }
+
+        *self.context_window.write().unwrap() = context_window;
+        Ok(())
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        *self.context_window.read().unwrap()
+    }
+}
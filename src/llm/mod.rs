@@ -1,5 +1,14 @@
 pub mod anthropic;
+pub mod budget;
+pub mod cache;
 pub mod client;
+pub mod error;
+pub mod fallback;
 pub mod langfuse;
+pub mod local;
 pub mod openai;
+pub mod pricing;
 pub mod prompts;
+pub mod replay;
+pub mod retry;
+pub mod sse;
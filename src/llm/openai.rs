@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures::StreamExt;
 use log::debug;
 use reqwest::{header, Client};
 use serde::Deserialize;
@@ -7,7 +8,12 @@ use serde_json::json;
 use std::time::Duration;
 
 use crate::config::LLMConfig;
-use crate::llm::client::{LLMClient, LLMResponse, TokenUsage};
+use crate::llm::client::{
+    ChatRequest, LLMClient, LLMResponse, LLMStream, Message, StreamEvent, TokenUsage, ToolCall,
+};
+use crate::llm::error::LLMError;
+use crate::llm::pricing::{model_pricing, ModelPricing};
+use crate::llm::sse::{response_events, SseEvent};
 
 /// OpenAI API response for chat completions
 #[derive(Debug, Deserialize)]
@@ -24,6 +30,22 @@ struct OpenAIChoice {
 #[derive(Debug, Deserialize)]
 struct OpenAIMessage {
     content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAIToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIToolCall {
+    id: String,
+    function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIFunctionCall {
+    name: String,
+    /// Arguments as a JSON-encoded string
+    #[serde(default)]
+    arguments: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,37 +55,171 @@ struct OpenAIUsage {
     total_tokens: usize,
 }
 
-use std::collections::HashMap;
-use std::sync::RwLock;
-
-/// OpenAI pricing response structure
-#[derive(Debug, Deserialize)]
-struct OpenAIPricingResponse {
-    #[allow(dead_code)]
-    data: Vec<OpenAIPricingModel>,
+/// Parse the JSON-encoded arguments of a tool call. Tools without parameters
+/// may send an empty string.
+fn parse_tool_arguments(arguments: &str) -> Result<serde_json::Value> {
+    if arguments.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_str(arguments).map_err(|e| {
+        LLMError::malformed(
+            "OpenAI",
+            format!("failed to parse tool call arguments: {}", e),
+        )
+        .into()
+    })
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAIPricingModel {
-    #[allow(dead_code)]
-    id: String,
-    #[allow(dead_code)]
-    pricing: Option<OpenAIModelPricing>,
+/// Convert a typed message to chat completions messages. Tool results become
+/// separate `tool` messages, so one message may expand to several.
+fn message_to_json(message: &Message) -> Vec<serde_json::Value> {
+    if message.is_text_only() {
+        return vec![json!({ "role": message.role, "content": message.content })];
+    }
+
+    let mut messages = Vec::new();
+
+    for result in &message.tool_results {
+        messages.push(json!({
+            "role": "tool",
+            "tool_call_id": result.tool_call_id,
+            "content": result.content,
+        }));
+    }
+
+    if !message.tool_calls.is_empty() {
+        let tool_calls: Vec<serde_json::Value> = message
+            .tool_calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                        "name": call.name,
+                        "arguments": call.input.to_string(),
+                    },
+                })
+            })
+            .collect();
+        let content = if message.content.is_empty() {
+            serde_json::Value::Null
+        } else {
+            json!(message.content)
+        };
+        messages.push(json!({
+            "role": message.role,
+            "content": content,
+            "tool_calls": tool_calls,
+        }));
+    } else if !message.content.is_empty() {
+        messages.push(json!({ "role": message.role, "content": message.content }));
+    }
+
+    messages
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAIModelPricing {
-    #[allow(dead_code)]
-    input: Option<f64>,
-    #[allow(dead_code)]
-    output: Option<f64>,
+/// Tool calls being received in a stream, indexed by their position in the
+/// response. Each holds the ID, function name and partial JSON arguments.
+type PendingToolCalls = Vec<(String, String, String)>;
+
+/// Translate an OpenAI streaming chunk into stream items, accumulating tool
+/// call arguments until the model finishes
+fn parse_stream_event(
+    event: &SseEvent,
+    pending_tool_calls: &mut PendingToolCalls,
+) -> Vec<Result<StreamEvent>> {
+    // The stream is terminated by a literal [DONE] message
+    if event.data.trim() == "[DONE]" {
+        return Vec::new();
+    }
+
+    let data: serde_json::Value = match serde_json::from_str(&event.data) {
+        Ok(data) => data,
+        Err(e) => {
+            return vec![Err(LLMError::malformed(
+                "OpenAI",
+                format!("failed to parse stream chunk: {}", e),
+            )
+            .into())]
+        }
+    };
+
+    if let Some(error) = data.get("error") {
+        // Errors name their kind in `code` or `type`, depending on the server
+        let error_type = error["code"]
+            .as_str()
+            .or_else(|| error["type"].as_str())
+            .unwrap_or_default();
+        let message = error["message"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string());
+        return vec![Err(LLMError::from_error_type(
+            "OpenAI", error_type, message,
+        )
+        .into())];
+    }
+
+    // With include_usage set, the last chunk carries the usage and no choices
+    if let Some(usage) = data.get("usage").filter(|usage| !usage.is_null()) {
+        return vec![Ok(StreamEvent::Usage(TokenUsage {
+            prompt_tokens: usage["prompt_tokens"].as_u64().unwrap_or(0) as usize,
+            completion_tokens: usage["completion_tokens"].as_u64().unwrap_or(0) as usize,
+            total_tokens: usage["total_tokens"].as_u64().unwrap_or(0) as usize,
+            ..Default::default()
+        }))];
+    }
+
+    let choice = &data["choices"][0];
+    let mut events = Vec::new();
+
+    if let Some(text) = choice["delta"]["content"]
+        .as_str()
+        .filter(|t| !t.is_empty())
+    {
+        events.push(Ok(StreamEvent::Delta(text.to_string())));
+    }
+
+    // The ID and name arrive with the first fragment of each tool call
+    for fragment in choice["delta"]["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let index = fragment["index"].as_u64().unwrap_or(0) as usize;
+        if pending_tool_calls.len() <= index {
+            pending_tool_calls.resize(index + 1, Default::default());
+        }
+        let (id, name, arguments) = &mut pending_tool_calls[index];
+        if let Some(fragment_id) = fragment["id"].as_str() {
+            id.push_str(fragment_id);
+        }
+        if let Some(fragment_name) = fragment["function"]["name"].as_str() {
+            name.push_str(fragment_name);
+        }
+        if let Some(fragment_arguments) = fragment["function"]["arguments"].as_str() {
+            arguments.push_str(fragment_arguments);
+        }
+    }
+
+    // Tool calls are complete once the model finishes
+    if !choice["finish_reason"].is_null() {
+        for (id, name, arguments) in pending_tool_calls.drain(..) {
+            events.push(
+                parse_tool_arguments(&arguments)
+                    .map(|input| StreamEvent::ToolCall(ToolCall { id, name, input })),
+            );
+        }
+    }
+
+    events
 }
 
 /// A client for the OpenAI API
 pub struct OpenAIClient {
     client: Client,
     config: LLMConfig,
-    pricing_cache: RwLock<HashMap<String, (f64, f64)>>,
 }
 
 impl OpenAIClient {
@@ -71,11 +227,13 @@ impl OpenAIClient {
     pub fn new(config: &LLMConfig) -> Result<Self> {
         let mut headers = header::HeaderMap::new();
 
-        // Add the API key header
-        let auth_value = format!("Bearer {}", config.api_key);
-        let auth_header = header::HeaderValue::from_str(&auth_value)
-            .context("Failed to create Authorization header")?;
-        headers.insert(header::AUTHORIZATION, auth_header);
+        // Add the API key header (OpenAI-compatible servers may not require one)
+        if !config.api_key.trim().is_empty() {
+            let auth_value = format!("Bearer {}", config.api_key);
+            let auth_header = header::HeaderValue::from_str(&auth_value)
+                .context("Failed to create Authorization header")?;
+            headers.insert(header::AUTHORIZATION, auth_header);
+        }
 
         // Add content-type header
         headers.insert(
@@ -93,43 +251,57 @@ impl OpenAIClient {
         Ok(Self {
             client,
             config: config.clone(),
-            pricing_cache: RwLock::new(HashMap::new()),
         })
     }
 
-    /// Get token pricing for the configured model - fallback to static values if not in cache
-    fn get_model_pricing(&self) -> (f64, f64) {
-        // Try to get from cache first
-        if let Some(pricing) = self.pricing_cache.read().unwrap().get(&self.config.model) {
-            return *pricing;
+    /// Build the chat completions request body from the typed messages
+    fn request_body(&self, request: &ChatRequest) -> serde_json::Value {
+        // OpenAI takes the system prompt as the first message
+        let mut messages = Vec::new();
+        if let Some(system) = &request.system {
+            messages.push(json!({
+                "role": "system",
+                "content": system
+            }));
+        }
+        for message in &request.messages {
+            messages.extend(message_to_json(message));
         }
 
-        // Fallback to hardcoded pricing if not in cache
-        match self.config.model.as_str() {
-            "gpt-4" => (0.03, 0.06),
-            "gpt-4-32k" => (0.06, 0.12),
-            "gpt-4-turbo" | "gpt-4-1106-preview" | "gpt-4-0125-preview" => (0.01, 0.03),
-            "gpt-4o" | "gpt-4o-2024-05-13" => (0.005, 0.015),
-            "gpt-3.5-turbo" | "gpt-3.5-turbo-1106" => (0.0015, 0.002),
-            _ => {
-                debug!(
-                    "Unknown model pricing for {}, using GPT-4 pricing",
-                    self.config.model
-                );
-                (0.03, 0.06)
-            }
+        let mut request_body = json!({
+            "model": self.config.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens,
+            "temperature": request.temperature,
+        });
+
+        // Add tool definitions as functions if the model may call tools
+        if !request.tools.is_empty() {
+            let tools: Vec<serde_json::Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.input_schema,
+                        },
+                    })
+                })
+                .collect();
+            request_body["tools"] = json!(tools);
         }
+
+        request_body
     }
-}
 
-#[async_trait]
-impl LLMClient for OpenAIClient {
-    async fn completion(
+    /// Send a request to the chat completions API, returning the response if it succeeded
+    async fn send_chat_request(
         &self,
-        prompt: &str,
-        max_tokens: usize,
-        temperature: f64,
-    ) -> Result<LLMResponse> {
+        request_body: serde_json::Value,
+    ) -> Result<reqwest::Response> {
         let base_url = self
             .config
             .base_url
@@ -137,53 +309,56 @@ impl LLMClient for OpenAIClient {
             .unwrap_or("https://api.openai.com/v1");
         let url = format!("{}/chat/completions", base_url);
 
-        let request_body = json!({
-            "model": self.config.model,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": prompt
-                }
-            ],
-            "max_tokens": max_tokens,
-            "temperature": temperature,
-        });
-
         let response = self
             .client
             .post(&url)
             .json(&request_body)
             .send()
             .await
-            .context("Failed to send request to OpenAI API")?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response from OpenAI API")?;
-            return Err(anyhow::anyhow!(
-                "OpenAI API error ({}): {}",
-                status,
-                error_text
-            ));
+            .map_err(|e| LLMError::transport("OpenAI", e))?;
+
+        if !response.status().is_success() {
+            let error = LLMError::from_response("OpenAI", response).await;
+            return Err(error.into());
         }
 
-        let response_data: OpenAIResponse = response
-            .json()
-            .await
-            .context("Failed to parse OpenAI API response")?;
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LLMClient for OpenAIClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        let response = self.send_chat_request(self.request_body(request)).await?;
+
+        let response_data: OpenAIResponse = response.json().await.map_err(|e| {
+            LLMError::malformed("OpenAI", format!("failed to parse response: {}", e))
+        })?;
 
         if response_data.choices.is_empty() {
-            return Err(anyhow::anyhow!("OpenAI API returned no choices"));
+            return Err(LLMError::malformed("OpenAI", "response has no choices").into());
         }
 
-        let content = response_data.choices[0]
-            .message
-            .content
-            .clone()
-            .ok_or_else(|| anyhow::anyhow!("OpenAI API returned null content"))?;
+        let message = &response_data.choices[0].message;
+
+        let tool_calls = message
+            .tool_calls
+            .iter()
+            .map(|call| {
+                Ok(ToolCall {
+                    id: call.id.clone(),
+                    name: call.function.name.clone(),
+                    input: parse_tool_arguments(&call.function.arguments)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Content is null when the model only calls tools
+        let content = match &message.content {
+            Some(content) => content.clone(),
+            None if !tool_calls.is_empty() => String::new(),
+            None => return Err(LLMError::malformed("OpenAI", "response has null content").into()),
+        };
 
         // Extract usage information
         let usage = if let Some(api_usage) = response_data.usage {
@@ -191,6 +366,7 @@ impl LLMClient for OpenAIClient {
                 prompt_tokens: api_usage.prompt_tokens,
                 completion_tokens: api_usage.completion_tokens,
                 total_tokens: api_usage.total_tokens,
+                ..Default::default()
             }
         } else {
             // Fallback if API doesn't return usage
@@ -198,7 +374,30 @@ impl LLMClient for OpenAIClient {
             TokenUsage::default()
         };
 
-        Ok(LLMResponse { content, usage })
+        Ok(LLMResponse {
+            content,
+            usage,
+            tool_calls,
+        })
+    }
+
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        let mut request_body = self.request_body(request);
+        request_body["stream"] = json!(true);
+        request_body["stream_options"] = json!({ "include_usage": true });
+
+        let response = self.send_chat_request(request_body).await?;
+
+        let mut pending_tool_calls = PendingToolCalls::new();
+        let stream = response_events(response).flat_map(move |event| {
+            let items = match event {
+                Ok(event) => parse_stream_event(&event, &mut pending_tool_calls),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(items)
+        });
+
+        Ok(Box::pin(stream))
     }
 
     fn name(&self) -> &str {
@@ -210,47 +409,38 @@ impl LLMClient for OpenAIClient {
     }
 
     fn get_token_prices(&self) -> (f64, f64) {
-        self.get_model_pricing()
+        self.pricing().per_1k()
     }
 
-    async fn fetch_pricing_data(&self) -> Result<()> {
-        debug!("Fetching OpenAI pricing data");
-
-        let base_url = self
-            .config
-            .base_url
-            .as_deref()
-            .unwrap_or("https://api.openai.com/v1");
-        let url = format!("{}/models", base_url);
-
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to fetch OpenAI models for pricing")?;
-
-        if !response.status().is_success() {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response from OpenAI API")?;
-            debug!("Error fetching OpenAI pricing: {}", error_text);
-            return Ok(()); // Continue with hardcoded pricing
-        }
-
-        // OpenAI doesn't expose pricing in their API directly
-        // We would need to parse from their pricing page or use another source
-        // For now, we'll populate the cache with the static values for the current model
-
-        let model_name = self.config.model.clone();
-        let pricing = self.get_model_pricing(); // Get hardcoded pricing
-
-        // Update cache
-        let mut pricing_cache = self.pricing_cache.write().unwrap();
-        pricing_cache.insert(model_name, pricing);
+    fn pricing(&self) -> ModelPricing {
+        model_pricing("openai", &self.config.model)
+    }
 
-        debug!("Updated pricing cache for OpenAI model");
-        Ok(())
+    fn context_window(&self) -> Option<usize> {
+        openai_context_window(&self.config.model)
     }
 }
+
+/// Context windows of OpenAI's model families, by model name prefix. More
+/// specific prefixes come first.
+const OPENAI_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+];
+
+/// Context window of an OpenAI model, if it belongs to a known family
+fn openai_context_window(model: &str) -> Option<usize> {
+    OPENAI_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
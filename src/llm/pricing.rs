@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use crate::config::{ModelPrice, PricingConfig};
+use crate::llm::client::{
+    TokenCost, TokenUsage, CACHE_READ_PRICE_MULTIPLIER, CACHE_WRITE_PRICE_MULTIPLIER,
+};
+
+/// The pricing table shipped with the binary
+const BUNDLED_PRICING: &str = include_str!("pricing.json");
+
+/// The pricing catalog set for the current run, if any
+static PRICING_CATALOG: RwLock<Option<Arc<PricingCatalog>>> = RwLock::new(None);
+
+/// Set the pricing catalog that clients price their usage with
+pub fn set_pricing_catalog(catalog: Option<Arc<PricingCatalog>>) {
+    *PRICING_CATALOG.write().unwrap() = catalog;
+}
+
+/// The pricing catalog of the current run, or the bundled one if none is set
+pub fn pricing_catalog() -> Arc<PricingCatalog> {
+    static BUNDLED: OnceLock<Arc<PricingCatalog>> = OnceLock::new();
+
+    if let Some(catalog) = PRICING_CATALOG.read().unwrap().clone() {
+        return catalog;
+    }
+    BUNDLED
+        .get_or_init(|| Arc::new(PricingCatalog::bundled()))
+        .clone()
+}
+
+/// The price of a provider's model from the current pricing catalog
+pub fn model_pricing(provider: &str, model: &str) -> ModelPricing {
+    pricing_catalog().pricing(provider, model)
+}
+
+/// A versioned table of model prices for each provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricingTable {
+    pub version: String,
+    pub providers: HashMap<String, ProviderPrices>,
+}
+
+/// Prices of a provider's models
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProviderPrices {
+    /// Price of models that aren't listed
+    #[serde(default)]
+    pub default: Option<ModelPrice>,
+    /// Prices by model name or model name prefix
+    #[serde(default)]
+    pub models: HashMap<String, ModelPrice>,
+}
+
+/// Find the price of a model, preferring an exact match and then the longest
+/// matching prefix, so that dated model versions get their family's price
+fn find_price<'a>(models: &'a HashMap<String, ModelPrice>, model: &str) -> Option<&'a ModelPrice> {
+    models.get(model).or_else(|| {
+        models
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    })
+}
+
+/// The price used for a model and where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelPricing {
+    pub price: ModelPrice,
+    /// E.g. "bundled pricing 2025-08-01", "config" or "anthropic default"
+    pub source: String,
+}
+
+impl ModelPricing {
+    /// Pricing from prices per 1K tokens, with cache prices relative to the
+    /// prompt price
+    pub fn from_per_1k(prompt_price: f64, completion_price: f64, source: &str) -> Self {
+        Self {
+            price: ModelPrice {
+                input: prompt_price * 1000.0,
+                output: completion_price * 1000.0,
+                cache_write: None,
+                cache_read: None,
+            },
+            source: source.to_string(),
+        }
+    }
+
+    /// Prompt and completion prices per 1K tokens
+    pub fn per_1k(&self) -> (f64, f64) {
+        (self.price.input / 1000.0, self.price.output / 1000.0)
+    }
+
+    /// Calculate the cost of token usage
+    pub fn cost(&self, usage: &TokenUsage) -> TokenCost {
+        let (prompt_price, completion_price) = self.per_1k();
+        let cache_write_price = self
+            .price
+            .cache_write
+            .map(|price| price / 1000.0)
+            .unwrap_or(prompt_price * CACHE_WRITE_PRICE_MULTIPLIER);
+        let cache_read_price = self
+            .price
+            .cache_read
+            .map(|price| price / 1000.0)
+            .unwrap_or(prompt_price * CACHE_READ_PRICE_MULTIPLIER);
+
+        TokenCost::from_usage_with_cache_prices(
+            usage,
+            prompt_price,
+            completion_price,
+            cache_write_price,
+            cache_read_price,
+        )
+    }
+}
+
+/// Model prices from a pricing table, with per-model overrides from the
+/// configuration
+pub struct PricingCatalog {
+    table: PricingTable,
+    /// Where the table came from, e.g. "bundled pricing"
+    origin: String,
+    overrides: HashMap<String, HashMap<String, ModelPrice>>,
+    /// Models already warned about, so each is only warned about once
+    warned: Mutex<HashSet<String>>,
+}
+
+impl PricingCatalog {
+    /// Create a catalog from a pricing table
+    pub fn new(table: PricingTable, origin: &str) -> Self {
+        Self {
+            table,
+            origin: origin.to_string(),
+            overrides: HashMap::new(),
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The pricing table shipped with the binary
+    pub fn bundled() -> Self {
+        let table = serde_json::from_str(BUNDLED_PRICING).expect("bundled pricing.json is valid");
+        Self::new(table, "bundled pricing")
+    }
+
+    /// Load the configured pricing table, or the bundled one, and apply the
+    /// configured overrides
+    pub fn load(config: &PricingConfig) -> Result<Self> {
+        let mut catalog = match &config.path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .context(format!("Failed to read pricing table: {}", path))?;
+                let table = serde_json::from_str(&content)
+                    .context(format!("Failed to parse pricing table: {}", path))?;
+                Self::new(table, path)
+            }
+            None => Self::bundled(),
+        };
+        catalog.overrides = config.models.clone();
+        Ok(catalog)
+    }
+
+    /// Version of the pricing table
+    pub fn version(&self) -> &str {
+        &self.table.version
+    }
+
+    /// The price of a model, if it is configured or listed in the table
+    pub fn lookup(&self, provider: &str, model: &str) -> Option<ModelPricing> {
+        if let Some(price) = self
+            .overrides
+            .get(provider)
+            .and_then(|models| find_price(models, model))
+        {
+            return Some(ModelPricing {
+                price: price.clone(),
+                source: "config".to_string(),
+            });
+        }
+
+        let price = find_price(&self.table.providers.get(provider)?.models, model)?;
+        Some(ModelPricing {
+            price: price.clone(),
+            source: format!("{} {}", self.origin, self.table.version),
+        })
+    }
+
+    /// The price of a model, falling back to the provider's default price
+    /// for unknown models. Unknown models are warned about unless the
+    /// provider is free, as local models are.
+    pub fn pricing(&self, provider: &str, model: &str) -> ModelPricing {
+        if let Some(pricing) = self.lookup(provider, model) {
+            return pricing;
+        }
+
+        let pricing = match self
+            .table
+            .providers
+            .get(provider)
+            .and_then(|prices| prices.default.clone())
+        {
+            Some(price) => ModelPricing {
+                price,
+                source: format!("{} default", provider),
+            },
+            None => ModelPricing {
+                price: ModelPrice::default(),
+                source: "unknown".to_string(),
+            },
+        };
+
+        let is_free = pricing.price.input == 0.0 && pricing.price.output == 0.0;
+        let first_time = self
+            .warned
+            .lock()
+            .unwrap()
+            .insert(format!("{}/{}", provider, model));
+        if first_time && !is_free {
+            if pricing.source == "unknown" {
+                warn!(
+                    "No pricing known for {} model {}; its cost will be reported as $0. Add it to the \"pricing\" section of the config.",
+                    provider, model
+                );
+            } else {
+                warn!(
+                    "No pricing known for {} model {}; using the {} price of ${}/${} per million input/output tokens. Add it to the \"pricing\" section of the config.",
+                    provider, model, pricing.source, pricing.price.input, pricing.price.output
+                );
+            }
+        }
+
+        pricing
+    }
+}
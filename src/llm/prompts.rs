@@ -1,5 +1,6 @@
 use crate::models::problem::SWEBenchProblem;
 use crate::models::ranking::{RankedCodebaseFile, RelevantFileDataForPrompt};
+use crate::models::relevance::LineRange;
 
 /// System prompt for codebase tree analysis to determine which files to assess
 pub const CODEBASE_TREE_SYSTEM_PROMPT: &str = r#"You are going to analyze a directory structure of a codebase to decide which files and directories are worth processing to solve a GitHub issue.
@@ -20,6 +21,8 @@ Your task is to output a list of file patterns that should be processed. These p
 
 IMPORTANT: Do NOT include "./" prefix in any file paths. Paths should be relative to the root (e.g., "src/main.rs", not "./src/main.rs").
 
+Large codebases are shown in part. A directory that doesn't fit is collapsed into one line counting its files by extension (e.g., "fixtures/ (1,204 files: .json ×1,100, .py ×104)"), and a line such as "… 300 more files: .py ×300" stands in for files left out of a directory's listing. Select files in them with directory paths or glob patterns.
+
 IMPORTANT: In the general case, specific code files should NOT be included unless they specifically relate to running tests or generating a Dockerfile.
 Focus primarily on configuration files, test files, CI files, and build files that would be necessary for these operations.
 
@@ -64,34 +67,36 @@ In summary, include anything you think may need to be edited or understood. In y
 
 You're going to provide your output in a specified format, which I'll describe now:
 
-If the file may need to be edited, your output should contain this:
+Write your thoughts on the relevance of the file first. Then end your answer with your decision as a JSON object in a ```json code block:
 
-{Your thoughts on the relevance of the file}
+```json
+{"status": "relevant", "summary": "Brief summary of the file's relevance to the issue", "confidence": 0.8}
+```
 
-RELEVANCE: Relevant
-SUMMARY: [Brief summary of the file's relevance to the issue]
+- "status" is either "relevant" or "not_relevant".
+- "summary" is required when the file is relevant, and can be left out otherwise.
+- "confidence" is optional: how sure you are of the decision, from 0 to 1.
+- No other fields are allowed.
 
 For an irrelevant file:
 
-{Your thoughts on the relevance of the file}
-
-RELEVANCE: Not Relevant
+```json
+{"status": "not_relevant"}
+```
 
-Important note about the SUMMARY:
+Important note about the summary:
 The summary you provide for relevant files will be used to rank and prioritize which files to include in the context for the
 next stage of analysis. Therefore, your summary should clearly and concisely explain why this file is important for understanding or solving the issue.
 You can include details here that will be useful for comparing different files.
 
 Your output will be automatically parsed, so be sure to follow the format carefully.
-This regex will be used to extract your answer: r"RELEVANCE:\\s*Relevant\\s*\nSUMMARY:(.*?)"
-
 If there is an issue in your formatting, you will be prompted to re-enter your decision.
 You need to reenter the entire decision with the correct formatting, but don't need to rewrite all of your reasoning.
 
 Some notes:
 - The issue description will be wrapped in <issue></issue> tags.
 - The file content will be wrapped in <content></content> tags.
-- Include your thoughts on the relevance before the structured output.
+- Include your thoughts on the relevance before the JSON decision.
 - Be precise in following the output format to ensure correct parsing.
 - The summary for relevant files will be used for ranking, so make it informative and focused on the file's importance to the issue.
 - Before outputting your decision, take time to thoroughly analyze the issue and the code file.
@@ -103,6 +108,12 @@ pub fn get_relevance_user_prompt(
     file_path: &str,
     file_content: &str,
 ) -> String {
+    get_relevance_issue_prompt(problem) + &get_relevance_file_prompt(file_path, file_content)
+}
+
+/// The part of the relevance user prompt describing the issue. It is the same
+/// for every file, so it can be cached across requests.
+pub fn get_relevance_issue_prompt(problem: &SWEBenchProblem) -> String {
     format!(
         r#"
 Please analyze the relevance of the following file to the given GitHub issue. Determine if it's relevant for understanding or solving the issue.
@@ -112,7 +123,15 @@ GitHub Issue Description:
 <issue>
 {}
 </issue>
+"#,
+        problem.problem_statement
+    )
+}
 
+/// The part of the relevance user prompt with the file being assessed
+pub fn get_relevance_file_prompt(file_path: &str, file_content: &str) -> String {
+    format!(
+        r#"
 File Path: {}
 
 File Contents:
@@ -129,7 +148,112 @@ Remember:
 5. Follow the output format exactly as specified in the system prompt.
 6. Include your thoughts on the relevance before making your final decision.
 "#,
-        problem.problem_statement, file_path, file_content
+        file_path, file_content
+    )
+}
+
+/// The part of the relevance user prompt with one chunk of a file too long
+/// to assess at once
+pub fn get_relevance_chunk_prompt(
+    file_path: &str,
+    chunk_content: &str,
+    lines: LineRange,
+    total_lines: usize,
+) -> String {
+    format!(
+        r#"
+File Path: {}
+Lines: {} to {} of {}
+
+This file is too long to assess at once, so it has been split into overlapping chunks that are assessed separately. Only this chunk is shown below.
+
+File Contents:
+<content>
+{}
+</content>
+
+
+Remember:
+1. Carefully read both the issue description and the chunk's contents.
+2. Determine if this chunk is relevant to understanding or solving the issue. The file counts as relevant if any of its chunks is.
+3. If relevant, identify important functions or settings in this chunk in relation to the issue.
+4. Provide a brief but informative summary for relevant chunks. This summary will be used to rank and prioritize files for inclusion in the next stage of analysis, so focus on why these lines are important for addressing the GitHub issue.
+5. Follow the output format exactly as specified in the system prompt.
+6. Include your thoughts on the relevance before making your final decision.
+"#,
+        file_path, lines.start, lines.end, total_lines, chunk_content
+    )
+}
+
+/// Ask for just the relevance decision again after it couldn't be parsed
+pub fn get_relevance_reprompt(error: &str) -> String {
+    format!(
+        r#"Your decision could not be parsed: {}
+
+Reply with only the corrected JSON decision in a ```json code block, without repeating your reasoning:
+
+```json
+{{"status": "relevant" or "not_relevant", "summary": "...", "confidence": 0.0 to 1.0}}
+```
+"#,
+        error
+    )
+}
+
+/// The part of the relevance user prompt with a batch of small files, each
+/// to be decided on separately
+pub fn get_relevance_batch_prompt(files: &[(String, String)]) -> String {
+    let file_sections: Vec<String> = files
+        .iter()
+        .map(|(path, content)| {
+            format!(
+                "File Path: {}\n\nFile Contents:\n<content>\n{}\n</content>",
+                path, content
+            )
+        })
+        .collect();
+
+    format!(
+        r#"
+This request covers {} small files instead of one. Decide on the relevance of each file separately.
+
+{}
+
+
+Remember:
+1. Carefully read both the issue description and the contents of each file.
+2. Determine for each file if it is relevant to understanding or solving the issue.
+3. Provide a brief but informative summary for each relevant file. These summaries will be used to rank and prioritize files for inclusion in the next stage of analysis, so focus on why each file is important for addressing the GitHub issue.
+4. Include your thoughts on the relevance of the files before making your final decisions.
+5. Instead of a single decision, end your answer with the decisions for all of the files in one JSON object in a ```json code block, giving each file's path exactly as above:
+
+```json
+{{"decisions": [
+  {{"path": "path/of/first/file", "status": "relevant", "summary": "Brief summary of the file's relevance to the issue", "confidence": 0.8}},
+  {{"path": "path/of/second/file", "status": "not_relevant"}}
+]}}
+```
+
+Every file must have exactly one decision. Each decision follows the rules of the system prompt, with its "path" added.
+"#,
+        files.len(),
+        file_sections.join("\n\n")
+    )
+}
+
+/// Ask for just the decisions for a batch of files again after they couldn't
+/// be parsed
+pub fn get_relevance_batch_reprompt(error: &str) -> String {
+    format!(
+        r#"Your decisions could not be parsed: {}
+
+Reply with only the corrected JSON decisions for all of the files in a ```json code block, without repeating your reasoning:
+
+```json
+{{"decisions": [{{"path": "...", "status": "relevant" or "not_relevant", "summary": "...", "confidence": 0.0 to 1.0}}]}}
+```
+"#,
+        error
     )
 }
 
@@ -195,8 +319,27 @@ Your ranking will be crucial for the next steps in addressing the GitHub issue,
 
 IMPORTANT: Your ranking should be the FINAL thing you output. You MUST deliberate and work through the problem BEFORE giving your final ranking. Think through the tradeoffs and about how you want to spend your budget best. Do not give the ranking first and then explain your reasoning afterwards."#;
 
-/// Generate a user prompt for codebase tree analysis
-pub fn get_codebase_tree_user_prompt(problem: &SWEBenchProblem, tree_output: &str) -> String {
+/// Generate a user prompt for codebase tree analysis, listing the files that
+/// rules already include
+pub fn get_codebase_tree_user_prompt(
+    problem: &SWEBenchProblem,
+    tree_output: &str,
+    included_files: &[String],
+) -> String {
+    let included_section = if included_files.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"
+These build, test and CI files are always included, so you don't need to list them:
+<included>
+{}
+</included>
+"#,
+            included_files.join("\n")
+        )
+    };
+
     format!(
         r#"
 Please analyze the following codebase structure to determine which files and directories should be processed to solve the given GitHub issue.
@@ -210,7 +353,7 @@ Codebase Tree Structure:
 <tree>
 {}
 </tree>
-
+{}
 Based on the issue and the codebase structure, provide a list of file patterns that should be processed for relevance assessment.
 Remember to include exact file paths, directory paths, or glob patterns that are most likely to contain code relevant to solving the issue.
 
@@ -218,22 +361,22 @@ IMPORTANT: Do NOT include "./" prefix in any file paths. Paths should be relativ
 
 Output your decision as a JSON array of strings as specified in the system prompt.
 "#,
-        problem.problem_statement, tree_output
+        problem.problem_statement, tree_output, included_section
     )
 }
 
-/// Generate a ranking prompt for file ranking
+/// Generate the system prompt for file ranking with the given token limits
+pub fn get_ranking_system_prompt(max_tokens: usize, target_tokens: usize) -> String {
+    RANKING_PROMPT
+        .replace("{max_tokens}", &max_tokens.to_string())
+        .replace("{target_tokens}", &target_tokens.to_string())
+}
+
+/// Generate a user prompt for file ranking
 pub fn get_ranking_user_prompt(
     issue_description: &str,
     relevance_info: &[RelevantFileDataForPrompt],
-    max_tokens: usize,
-    target_tokens: usize,
 ) -> String {
-    // Format the RANKING_PROMPT with token limits
-    let formatted_prompt = RANKING_PROMPT
-        .replace("{max_tokens}", &max_tokens.to_string())
-        .replace("{target_tokens}", &target_tokens.to_string());
-
     let mut file_summaries = Vec::new();
 
     for file in relevance_info {
@@ -244,9 +387,7 @@ pub fn get_ranking_user_prompt(
     }
 
     format!(
-        r#"{}
-
-Please rank the following files based on their relevance to the given GitHub issue.
+        r#"Please rank the following files based on their relevance to the given GitHub issue.
 
 GitHub Issue:
 <issue>
@@ -257,7 +398,6 @@ File Summaries:
 {}
 
 Please provide your ranking and explanation as specified in the system prompt."#,
-        formatted_prompt,
         issue_description,
         file_summaries.join("\n")
     )
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::warn;
+use std::sync::Arc;
+
+use crate::config::LLMConfig;
+use crate::llm::client::{
+    ChatRequest, LLMClient, LLMResponse, LLMStream, StreamEvent, TokenCost, TokenUsage,
+};
+use crate::llm::pricing::{model_pricing, ModelPricing};
+use crate::utils::cassette::{Cassette, ModelInfo};
+
+/// Record a result to the cassette, logging rather than failing if it can't
+/// be written
+fn record(
+    cassette: &Cassette,
+    model: &str,
+    request: &ChatRequest,
+    result: Result<&LLMResponse, &anyhow::Error>,
+) {
+    if let Err(e) = cassette.record_exchange(model, request, result) {
+        warn!("Failed to record LLM exchange: {}", e);
+    }
+}
+
+/// An LLM client that records every request and its result to a cassette
+pub struct RecordingClient {
+    inner: Box<dyn LLMClient>,
+    cassette: Arc<Cassette>,
+}
+
+impl RecordingClient {
+    /// Wrap a client, recording what was discovered about its model so it
+    /// can be replayed too
+    pub fn new(inner: Box<dyn LLMClient>, cassette: Arc<Cassette>) -> Self {
+        let info = ModelInfo {
+            model: inner.model_name().to_string(),
+            context_window: inner.context_window(),
+        };
+        if let Err(e) = cassette.record_model(&info) {
+            warn!("Failed to record model info: {}", e);
+        }
+        Self { inner, cassette }
+    }
+}
+
+#[async_trait]
+impl LLMClient for RecordingClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        let result = self.inner.chat(request).await;
+        record(&self.cassette, self.model_name(), request, result.as_ref());
+        result
+    }
+
+    async fn chat_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<LLMResponse> {
+        let result = self
+            .inner
+            .chat_with_tracing(request, trace_id, generation_name, metadata)
+            .await;
+        record(&self.cassette, self.model_name(), request, result.as_ref());
+        result
+    }
+
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        let stream = match self.inner.chat_stream(request).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                record(&self.cassette, self.model_name(), request, Err(&e));
+                return Err(e);
+            }
+        };
+
+        // Assemble the response as it streams past, and record it once the
+        // final usage or an error arrives
+        let cassette = self.cassette.clone();
+        let model = self.model_name().to_string();
+        let request = request.clone();
+        let mut response = LLMResponse::default();
+        let stream = stream.map(move |event| {
+            match &event {
+                Ok(StreamEvent::Delta(text)) => response.content.push_str(text),
+                Ok(StreamEvent::ToolCall(call)) => response.tool_calls.push(call.clone()),
+                Ok(StreamEvent::Usage(usage)) => {
+                    response.usage = usage.clone();
+                    record(&cassette, &model, &request, Ok(&response));
+                }
+                Err(e) => record(&cassette, &model, &request, Err(e)),
+            }
+            event
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn chat_stream_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+        on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+    ) -> Result<LLMResponse> {
+        let result = self
+            .inner
+            .chat_stream_with_tracing(request, trace_id, generation_name, metadata, on_delta)
+            .await;
+        record(&self.cassette, self.model_name(), request, result.as_ref());
+        result
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.inner.get_token_prices()
+    }
+
+    fn pricing(&self) -> ModelPricing {
+        self.inner.pricing()
+    }
+
+    async fn fetch_pricing_data(&self) -> Result<()> {
+        self.inner.fetch_pricing_data().await
+    }
+
+    async fn fetch_model_info(&self) -> Result<()> {
+        self.inner.fetch_model_info().await
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
+        self.inner.calculate_cost(usage)
+    }
+}
+
+/// An LLM client that answers requests from a recorded cassette and never
+/// contacts the provider, so it needs no credentials. The model's context
+/// window is the one discovered when recording.
+pub struct ReplayingClient {
+    provider: String,
+    model: String,
+    context_window: Option<usize>,
+    cassette: Arc<Cassette>,
+}
+
+impl ReplayingClient {
+    pub fn new(config: &LLMConfig, cassette: Arc<Cassette>) -> Self {
+        let context_window = cassette
+            .replay_model(&config.model)
+            .and_then(|info| info.context_window);
+        Self {
+            provider: config.model_type.clone(),
+            model: config.model.clone(),
+            context_window,
+            cassette,
+        }
+    }
+}
+
+#[async_trait]
+impl LLMClient for ReplayingClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        self.cassette.replay_exchange(self.model_name(), request)
+    }
+
+    fn name(&self) -> &str {
+        &self.provider
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.pricing().per_1k()
+    }
+
+    fn pricing(&self) -> ModelPricing {
+        model_pricing(&self.provider, &self.model)
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.context_window
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::warn;
+use reqwest::header::HeaderMap;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::LLMConfig;
+use crate::llm::client::{
+    collect_stream, ChatRequest, LLMClient, LLMResponse, LLMStream, TokenCost, TokenUsage,
+    TracedGeneration,
+};
+use crate::llm::error::LLMError;
+use crate::llm::pricing::ModelPricing;
+
+/// Parse the delay from `retry-after-ms` or `retry-after` (in seconds) headers
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|value| value.is_finite() && *value >= 0.0)
+    };
+
+    header_value("retry-after-ms")
+        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .or_else(|| header_value("retry-after").map(Duration::from_secs_f64))
+}
+
+/// Whether an error from an LLM request is worth retrying
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    match LLMError::find(error) {
+        Some(llm_error) => llm_error.is_retryable(),
+        None => error.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_timeout() || e.is_connect() || e.is_request())
+        }),
+    }
+}
+
+/// How a failed request should be retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each retry after it
+    pub base_delay: Duration,
+    /// Upper bound on the backoff delay. A provider asking for a longer
+    /// delay isn't retried.
+    pub max_delay: Duration,
+    /// Time limit for each attempt; `None` for no limit
+    pub attempt_timeout: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// The policy for a client configuration
+    pub fn from_config(config: &LLMConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            attempt_timeout: (config.timeout > 0).then(|| Duration::from_secs(config.timeout)),
+        }
+    }
+
+    /// How long to wait before retry number `retry` (starting at 1), or
+    /// `None` not to retry. A delay requested by the provider is used as is,
+    /// unless it's longer than `max_delay`; otherwise the delay grows
+    /// exponentially with jitter.
+    pub fn delay(&self, retry: u32, error: &anyhow::Error) -> Option<Duration> {
+        let retry_after = LLMError::find(error).and_then(LLMError::retry_after);
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        // Wait between half and all of the backoff, so concurrent requests spread out
+        Some(backoff.mul_f64(0.5 + fastrand::f64() * 0.5))
+    }
+}
+
+/// An LLM client that retries failed requests with exponential backoff
+pub struct RetryingClient {
+    inner: Box<dyn LLMClient>,
+    policy: RetryPolicy,
+}
+
+impl RetryingClient {
+    pub fn new(inner: Box<dyn LLMClient>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Run one attempt within the attempt timeout
+    async fn attempt<T, F>(&self, request: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send,
+    {
+        match self.policy.attempt_timeout {
+            Some(limit) => match tokio::time::timeout(limit, request).await {
+                Ok(result) => result,
+                Err(_) => Err(LLMError::Transport {
+                    provider: self.inner.name().to_string(),
+                    message: format!("request timed out after {:?}", limit),
+                }
+                .into()),
+            },
+            None => request.await,
+        }
+    }
+
+    /// Send a request until it succeeds, fails with an error that is not
+    /// retryable, or runs out of retries. Returns the result and the number of
+    /// retries made.
+    async fn with_retries<T, F, Fut>(&self, mut send: F) -> (Result<T>, u32)
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+    {
+        let mut retries = 0;
+        loop {
+            let error = match self.attempt(send()).await {
+                Ok(value) => return (Ok(value), retries),
+                Err(e) => e,
+            };
+
+            if retries >= self.policy.max_retries || !is_retryable(&error) {
+                return (Err(error), retries);
+            }
+
+            let delay = match self.policy.delay(retries + 1, &error) {
+                Some(delay) => delay,
+                None => {
+                    warn!(
+                        "{} request failed: {}. Not retrying, as the provider asked to wait longer than {:.1}s",
+                        self.inner.model_name(),
+                        error,
+                        self.policy.max_delay.as_secs_f64()
+                    );
+                    return (Err(error), retries);
+                }
+            };
+            retries += 1;
+            warn!(
+                "{} request failed: {}. Retry {} of {} in {:.1}s",
+                self.inner.model_name(),
+                error,
+                retries,
+                self.policy.max_retries,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait]
+impl LLMClient for RetryingClient {
+    async fn chat(&self, request: &ChatRequest) -> Result<LLMResponse> {
+        self.with_retries(|| self.inner.chat(request)).await.0
+    }
+
+    async fn chat_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<LLMResponse> {
+        let mut generation = TracedGeneration::start(trace_id, generation_name, metadata).await;
+
+        let (result, retries) = self.with_retries(|| self.inner.chat(request)).await;
+
+        if let Ok(response) = &result {
+            let cost = self.calculate_cost(&response.usage);
+            generation.add_metadata("retries", retries.into());
+            generation.add_metadata("price_source", self.pricing().source.into());
+            generation
+                .finish(self.model_name(), request, response, &cost)
+                .await;
+        }
+
+        result
+    }
+
+    /// Only opening the stream is retried, since output may already have been
+    /// shown by the time a stream fails
+    async fn chat_stream(&self, request: &ChatRequest) -> Result<LLMStream> {
+        self.with_retries(|| self.inner.chat_stream(request))
+            .await
+            .0
+    }
+
+    async fn chat_stream_with_tracing(
+        &self,
+        request: &ChatRequest,
+        trace_id: Option<&str>,
+        generation_name: Option<&str>,
+        metadata: Option<serde_json::Value>,
+        on_delta: &mut (dyn for<'a> FnMut(&'a str) + Send),
+    ) -> Result<LLMResponse> {
+        let mut generation = TracedGeneration::start(trace_id, generation_name, metadata).await;
+
+        let (stream, retries) = self.with_retries(|| self.inner.chat_stream(request)).await;
+        let response = collect_stream(stream?, on_delta).await?;
+
+        let cost = self.calculate_cost(&response.usage);
+        generation.add_metadata("retries", retries.into());
+        generation.add_metadata("price_source", self.pricing().source.into());
+        generation
+            .finish(self.model_name(), request, &response, &cost)
+            .await;
+
+        Ok(response)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        self.inner.get_token_prices()
+    }
+
+    fn pricing(&self) -> ModelPricing {
+        self.inner.pricing()
+    }
+
+    async fn fetch_pricing_data(&self) -> Result<()> {
+        self.inner.fetch_pricing_data().await
+    }
+
+    async fn fetch_model_info(&self) -> Result<()> {
+        self.inner.fetch_model_info().await
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn calculate_cost(&self, usage: &TokenUsage) -> TokenCost {
+        self.inner.calculate_cost(usage)
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+/// A single server-sent event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    /// Event type from the `event:` field, if any
+    pub event: Option<String>,
+    /// Payload from the `data:` field(s), joined with newlines
+    pub data: String,
+}
+
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>>;
+
+/// State for decoding a byte stream into server-sent events
+struct SseDecoder {
+    body: ByteStream,
+    buffer: Vec<u8>,
+    pending: VecDeque<SseEvent>,
+    done: bool,
+}
+
+impl SseDecoder {
+    /// Move every complete event in the buffer to the pending queue
+    fn drain_complete_events(&mut self) {
+        while let Some(end) = find_event_end(&self.buffer) {
+            let block: Vec<u8> = self.buffer.drain(..end.0).collect();
+            self.buffer.drain(..end.1);
+            if let Some(event) = parse_event(&String::from_utf8_lossy(&block)) {
+                self.pending.push_back(event);
+            }
+        }
+    }
+}
+
+/// Find the end of the first event in the buffer, returning the length of the
+/// event and of the blank-line separator that follows it
+fn find_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    for i in 0..buffer.len() {
+        for separator in [&b"\r\n\r\n"[..], &b"\n\n"[..], &b"\r\r"[..]] {
+            if buffer[i..].starts_with(separator) {
+                return Some((i, separator.len()));
+            }
+        }
+    }
+    None
+}
+
+/// Parse a single event block. Returns None for blocks without data, such as
+/// comments used as keep-alives.
+fn parse_event(block: &str) -> Option<SseEvent> {
+    let mut event = None;
+    let mut data_lines = Vec::new();
+
+    for line in block.lines() {
+        // Lines starting with a colon are comments
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+
+        match field {
+            "event" => event = Some(value.to_string()),
+            "data" => data_lines.push(value),
+            _ => {}
+        }
+    }
+
+    if data_lines.is_empty() {
+        return None;
+    }
+
+    Some(SseEvent {
+        event,
+        data: data_lines.join("\n"),
+    })
+}
+
+/// Decode a stream of body chunks into server-sent events
+pub fn sse_events<S, E>(body: S) -> impl Stream<Item = Result<SseEvent>> + Send
+where
+    S: Stream<Item = std::result::Result<Vec<u8>, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let decoder = SseDecoder {
+        body: Box::pin(body.map(|chunk| chunk.context("Failed to read event stream"))),
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(decoder, |mut decoder| async move {
+        loop {
+            if let Some(event) = decoder.pending.pop_front() {
+                return Some((Ok(event), decoder));
+            }
+            if decoder.done {
+                return None;
+            }
+
+            match decoder.body.next().await {
+                Some(Ok(chunk)) => {
+                    decoder.buffer.extend_from_slice(&chunk);
+                    decoder.drain_complete_events();
+                }
+                Some(Err(e)) => {
+                    decoder.done = true;
+                    return Some((Err(e), decoder));
+                }
+                None => {
+                    // Flush a final event that wasn't followed by a blank line
+                    decoder.done = true;
+                    let rest = std::mem::take(&mut decoder.buffer);
+                    if let Some(event) = parse_event(&String::from_utf8_lossy(&rest)) {
+                        decoder.pending.push_back(event);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Decode the body of an HTTP response into server-sent events
+pub fn response_events(response: reqwest::Response) -> impl Stream<Item = Result<SseEvent>> + Send {
+    sse_events(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map(|bytes| bytes.to_vec())),
+    )
+}
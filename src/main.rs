@@ -1,14 +1,20 @@
 use anyhow::Result;
 use clap::Parser;
 use colored::Colorize;
-use engine_builder::config::Config;
+use engine_builder::config::{CacheMode, Config};
+use engine_builder::llm::budget::{budget, is_budget_exceeded, set_budget, BudgetAccountant};
+use engine_builder::llm::cache::{set_response_cache, ResponseCache};
 use engine_builder::llm::langfuse;
+use engine_builder::llm::pricing::{set_pricing_catalog, PricingCatalog};
 use engine_builder::models::exclusion::ExclusionConfig;
 use engine_builder::models::problem::SWEBenchProblem;
 use engine_builder::stages::{container, dockerfile, file_selection, ranking, relevance};
+use engine_builder::utils::cassette::{set_cassette, Cassette};
+use engine_builder::utils::git::{self, GitScope};
 use log::{info, warn};
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -33,6 +39,30 @@ struct Cli {
     #[arg(short = 'p', long)]
     problem_statement: Option<String>,
 
+    /// Response cache mode: off, read-write, read-only or refresh (overrides the config)
+    #[arg(long, global = true)]
+    cache: Option<CacheMode>,
+
+    /// Record every LLM exchange and Docker command of the run to a cassette in this directory
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a recorded cassette from this directory instead of calling LLMs and Docker
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Limit file selection and relevance to files changed since this revision, and their neighbours
+    #[arg(long, global = true, value_name = "REV", conflicts_with_all = ["commit", "staged"])]
+    since: Option<String>,
+
+    /// Limit file selection and relevance to files this commit changed, and their neighbours
+    #[arg(long, global = true, value_name = "SHA", conflicts_with = "staged")]
+    commit: Option<String>,
+
+    /// Limit file selection and relevance to staged files, and their neighbours
+    #[arg(long, global = true)]
+    staged: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -93,8 +123,23 @@ enum Command {
     },
 }
 
+impl Cli {
+    /// The git scope given on the command line, if any
+    fn git_scope(&self) -> Option<GitScope> {
+        if let Some(rev) = &self.since {
+            Some(GitScope::Since(rev.clone()))
+        } else if let Some(sha) = &self.commit {
+            Some(GitScope::Commit(sha.clone()))
+        } else if self.staged {
+            Some(GitScope::Staged)
+        } else {
+            None
+        }
+    }
+}
+
 /// Create a problem from the CLI args and config
-fn create_problem(cli: &Cli, config: &Config) -> SWEBenchProblem {
+fn create_problem(cli: &Cli, config: &Config) -> Result<SWEBenchProblem> {
     let problem_id = cli
         .problem_id
         .clone()
@@ -119,9 +164,16 @@ fn create_problem(cli: &Cli, config: &Config) -> SWEBenchProblem {
         }
     };
 
-    SWEBenchProblem::new(problem_id, problem_statement)
+    let problem = SWEBenchProblem::new(problem_id, problem_statement)
         .with_codebase_path(&config.codebase.path)
-        .with_exclusion_config(exclusion_config)
+        .with_exclusion_config(exclusion_config);
+
+    // Record the commit analyzed, and limit the analysis to a diff if asked
+    git::scope_problem(
+        problem,
+        &config.codebase.path,
+        config.codebase.git_scope.as_ref(),
+    )
 }
 
 #[tokio::main]
@@ -228,13 +280,48 @@ async fn main() -> Result<()> {
         Err(e) => warn!("Failed to initialize Langfuse tracing: {}", e),
     }
 
+    // Set up the response cache, letting the command line override the config
+    if let Some(mode) = cli.cache {
+        config.cache.mode = mode;
+    }
+    if config.cache.mode != CacheMode::Off {
+        let cache_dir = config.get_cache_dir();
+        info!(
+            "Using response cache in {} (mode: {:?})",
+            cache_dir, config.cache.mode
+        );
+        set_response_cache(Some(ResponseCache::new(cache_dir, config.cache.mode)));
+    }
+
+    // Record or replay the run's interactions with LLMs and Docker
+    if let Some(dir) = &cli.record {
+        set_cassette(Some(Arc::new(Cassette::record(dir)?)));
+    } else if let Some(dir) = &cli.replay {
+        set_cassette(Some(Arc::new(Cassette::replay(dir)?)));
+    }
+
+    // Price LLM usage from the bundled or configured pricing table
+    let pricing = PricingCatalog::load(&config.pricing)?;
+    info!("Using model pricing version {}", pricing.version());
+    set_pricing_catalog(Some(Arc::new(pricing)));
+
+    // Limit LLM spending across the run, if configured
+    if config.budget.is_limited() {
+        set_budget(Some(Arc::new(BudgetAccountant::new(config.budget.clone()))));
+    }
+
     // Update codebase path if provided
     if let Some(path) = &cli.codebase_path {
         config.codebase.path = path.clone();
     }
 
+    // Limit the analysis to a git diff if asked, overriding the config
+    if let Some(scope) = cli.git_scope() {
+        config.codebase.git_scope = Some(scope);
+    }
+
     // Create problem from CLI and config
-    let problem = create_problem(&cli, &config);
+    let problem = create_problem(&cli, &config)?;
 
     match cli.command {
         Command::Relevance => {
@@ -259,33 +346,57 @@ async fn main() -> Result<()> {
         Command::Pipeline => {
             info!("Running full pipeline");
 
-            // Run file selection first to generate codebase_tree_response.txt
-            info!("Running file selection process");
-            file_selection::process_file_selection(
-                &config,
-                &config.codebase,
-                problem.clone(),
-                &config.get_trajectory_dir(&problem.id),
-            )
-            .await?;
-
-            // Then process relevance using the existing codebase_tree_response.txt
-            relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
-
-            info!("Running file ranking");
-            ranking::process_rankings(&config, problem.clone()).await?;
-            info!("Generating lint and test scripts based on ranked files");
-            engine_builder::stages::scripts::generate_scripts_from_ranking(
-                &config,
-                problem.clone(),
-            )
-            .await?;
-            info!("Generating test-focused Dockerfile based on ranked files");
-            dockerfile::generate_dockerfile(&config, problem.clone()).await?;
+            // Count finished stages, to report what is left if the budget runs out
+            let mut finished = 0;
+            let result: Result<()> = async {
+                // Run file selection first to generate codebase_tree_response.txt
+                info!("Running file selection process");
+                file_selection::process_file_selection(
+                    &config,
+                    &config.codebase,
+                    problem.clone(),
+                    &config.get_trajectory_dir(&problem.id),
+                )
+                .await?;
+                finished += 1;
+
+                // Then process relevance using the existing codebase_tree_response.txt
+                relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
+                finished += 1;
+
+                info!("Running file ranking");
+                ranking::process_rankings(&config, problem.clone()).await?;
+                finished += 1;
+
+                info!("Generating lint and test scripts based on ranked files");
+                engine_builder::stages::scripts::generate_scripts_from_ranking(
+                    &config,
+                    problem.clone(),
+                )
+                .await?;
+                finished += 1;
+
+                info!("Generating test-focused Dockerfile based on ranked files");
+                dockerfile::generate_dockerfile(&config, problem.clone()).await?;
+                finished += 1;
+
+                // Finally, generate the overview document with reasoning from all stages
+                info!("Generating overview document");
+                engine_builder::stages::overview::generate_overview(&config, &problem).await?;
+                finished += 1;
+
+                Ok(())
+            }
+            .await;
 
-            // Finally, generate the overview document with reasoning from all stages
-            info!("Generating overview document");
-            engine_builder::stages::overview::generate_overview(&config, &problem).await?;
+            // Return the error rather than exiting, so that everything is
+            // dropped and cleaned up as on any other error
+            if let Err(e) = result {
+                if is_budget_exceeded(&e) {
+                    report_budget_stop(&config, &problem, &PIPELINE_STAGES[finished..]);
+                }
+                return Err(e);
+            }
         }
         Command::FileSelection => {
             info!("Running file selection process");
@@ -402,19 +513,30 @@ async fn main() -> Result<()> {
             // First check if we have a dedicated chat model config
             let llm_config = if config.chat.model.is_some() {
                 info!("Using dedicated chat model configuration");
-                config.to_llm_config(&config.chat.model)
+                config.to_stage_llm_config(&config.chat.model, &config.chat.llm)
             } else {
                 // Fall back to the selected config type
                 info!("Using {} model configuration for chat", config_type);
                 match config_type.to_lowercase().as_str() {
-                    "relevance" => config.to_llm_config(&config.relevance.model),
-                    "ranking" => config.to_llm_config(&config.ranking.model),
-                    "dockerfile" => config.to_llm_config(&config.dockerfile.model),
-                    "scripts" => config.to_llm_config(&config.scripts.model),
+                    "relevance" => {
+                        config.to_stage_llm_config(&config.relevance.model, &config.relevance.llm)
+                    }
+                    "ranking" => {
+                        config.to_stage_llm_config(&config.ranking.model, &config.ranking.llm)
+                    }
+                    "dockerfile" => {
+                        config.to_stage_llm_config(&config.dockerfile.model, &config.dockerfile.llm)
+                    }
+                    "scripts" => {
+                        config.to_stage_llm_config(&config.scripts.model, &config.scripts.llm)
+                    }
                     _ => {
                         eprintln!("Invalid config type: {}. Using default chat model: claude-3-7-sonnet-20250219", config_type);
                         // Create a Some with the default model for chat
-                        config.to_llm_config(&Some("claude-3-7-sonnet-20250219".to_string()))
+                        config.to_stage_llm_config(
+                            &Some("claude-3-7-sonnet-20250219".to_string()),
+                            &config.chat.llm,
+                        )
                     }
                 }
             };
@@ -434,5 +556,35 @@ async fn main() -> Result<()> {
         }
     }
 
+    if let Some(budget) = budget() {
+        budget.log_summary();
+    }
+
     Ok(())
 }
+
+/// The stages of the full pipeline, in order
+const PIPELINE_STAGES: &[&str] = &[
+    "file selection",
+    "relevance",
+    "ranking",
+    "scripts",
+    "dockerfile",
+    "overview",
+];
+
+/// Log what a pipeline run stopped by the budget running out got done
+fn report_budget_stop(config: &Config, problem: &SWEBenchProblem, unfinished: &[&str]) {
+    if let Some(budget) = budget() {
+        budget.log_summary();
+    }
+
+    warn!(
+        "Pipeline stopped by the LLM budget, with these stages unfinished: {}",
+        unfinished.join(", ")
+    );
+    info!(
+        "Progress so far is saved in {}",
+        config.get_trajectory_dir(&problem.id)
+    );
+}
@@ -1,7 +1,45 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::fs::{self, File};
+use std::io::Read;
 use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Bytes read from the start of a file to tell what it contains
+const SNIFF_BYTES: usize = 8192;
+
+/// Lines at the start of a file searched for a generated-code header
+const HEADER_LINES: usize = 10;
+
+/// Share of the characters sniffed that must be printable for a file without
+/// NUL bytes to count as text
+const MIN_PRINTABLE_RATIO: f64 = 0.9;
+
+/// Patterns for vendored and generated paths, in the style of GitHub
+/// Linguist's `vendor.yml` and `generated.rb`. Directories are matched with
+/// a trailing `/`.
+const VENDORED_PATH_PATTERNS: &[&str] = &[
+    // Vendored dependencies
+    r"(^|/)vendors?/",
+    r"(^|/)third[-_]?party/",
+    r"(^|/)bower_components/",
+    r"(^|/)jspm_packages/",
+    r"(^|/)Godeps/_workspace/",
+    r"(^|/)\.yarn/(releases|plugins|sdks|versions|unplugged)/",
+    // Vendored and bundled JavaScript and CSS
+    r"(^|/)jquery([-.][^/]*)?\.js$",
+    r"(^|/)bootstrap([-.][^/]*)?\.(js|css)$",
+    r"(^|/)(underscore|lodash|backbone|modernizr|d3)([-.][^/]*)?\.js$",
+    r"\.bundle\.(js|css)$",
+    // Generated protocol buffer and gRPC code
+    r"_pb2(_grpc)?\.pyi?$",
+    r"\.pb(\.gw)?\.go$",
+    r"\.pb\.(cc|h)$",
+    r"_grpc\.pb\.go$",
+];
 
 /// Config structure for exclusion patterns
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +52,208 @@ pub struct ExclusionConfig {
 
     /// Directories to skip
     pub directories_to_skip: Vec<String>,
+
+    /// Files larger than this many bytes are skipped, or none if 0
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+
+    /// Whether to skip files whose content looks binary
+    #[serde(default = "default_skip_binary_files")]
+    pub skip_binary_files: bool,
+
+    /// Whether to skip vendored code and files with a generated-code header
+    #[serde(default = "default_skip_generated_files")]
+    pub skip_generated_files: bool,
+
+    /// Gitignore-style patterns for paths to skip, relative to the codebase
+    /// root. A pattern starting with `!` keeps paths an earlier one skips.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+
+    /// Gitignore-style patterns for paths to keep even if another rule
+    /// above skips them
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Rules added for particular uses of the scan
+    #[serde(default)]
+    pub profiles: ExclusionProfiles,
+
+    /// `patterns` and `include` as last compiled
+    #[serde(skip)]
+    pub compiled_patterns: CompiledPatterns,
+}
+
+/// Patterns added to the exclusion file's rules for one use of the codebase
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExclusionProfile {
+    /// Gitignore-style patterns for paths to skip, tried after the exclusion
+    /// file's own so that a `!` pattern can keep what those skip
+    pub patterns: Vec<String>,
+
+    /// Gitignore-style patterns for paths to keep regardless
+    pub include: Vec<String>,
+}
+
+/// The profiles for each use of the codebase
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExclusionProfiles {
+    /// The codebase tree the model selects files from
+    pub tree: ExclusionProfile,
+
+    /// The files assessed for relevance
+    pub relevance: ExclusionProfile,
+
+    /// The Docker build context. The build needs the whole project, so only
+    /// this profile's patterns apply to it.
+    pub docker: ExclusionProfile,
+}
+
+/// A use of the codebase scan with its own exclusion profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanProfile {
+    Tree,
+    Relevance,
+}
+
+/// The compiled `patterns` and `include` lists of an exclusion config
+#[derive(Debug, Clone)]
+pub struct PathPatterns {
+    exclude: Gitignore,
+    include: Gitignore,
+    /// Directories leading to the paths include patterns name, which are
+    /// scanned even if excluded so that those paths can be found
+    include_dirs: Vec<String>,
+}
+
+impl Default for PathPatterns {
+    fn default() -> Self {
+        Self {
+            exclude: Gitignore::empty(),
+            include: Gitignore::empty(),
+            include_dirs: Vec::new(),
+        }
+    }
+}
+
+impl PathPatterns {
+    /// Compile exclude and include patterns
+    pub fn new(exclude: &[String], include: &[String]) -> Result<Self> {
+        let mut include_dirs = Vec::new();
+        for pattern in include {
+            // A pattern with no `/` but at its end matches at any depth, so
+            // there is no directory to lead to
+            let pattern = pattern.trim_start_matches('!').trim_end_matches('/');
+            if !pattern.contains('/') {
+                continue;
+            }
+
+            // The directories before the first part with a wildcard, or
+            // before the path itself if there is none
+            let parts: Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+            let mut literal: Vec<&str> = parts
+                .iter()
+                .take_while(|part| !part.contains(['*', '?', '[']))
+                .copied()
+                .collect();
+            if literal.len() == parts.len() {
+                literal.pop();
+            }
+            if !literal.is_empty() {
+                include_dirs.push(literal.join("/"));
+            }
+        }
+
+        Ok(Self {
+            exclude: build_matcher(exclude).context("Invalid exclusion pattern")?,
+            include: build_matcher(include).context("Invalid include pattern")?,
+            include_dirs,
+        })
+    }
+
+    /// The pattern that skips a path, relative to the codebase root, or one
+    /// of the directories it is in
+    pub fn excluding_pattern(&self, path: &Path, is_dir: bool) -> Option<String> {
+        match self.exclude.matched_path_or_any_parents(path, is_dir) {
+            ignore::Match::Ignore(glob) => Some(glob.original().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Whether an include pattern keeps a path, relative to the codebase
+    /// root, or one of the directories it is in
+    pub fn is_included(&self, path: &Path, is_dir: bool) -> bool {
+        self.include
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    /// Whether a directory, relative to the codebase root, has paths named
+    /// by an include pattern in it
+    pub fn leads_to_include(&self, dir: &Path) -> bool {
+        self.include_dirs
+            .iter()
+            .any(|include_dir| Path::new(include_dir).starts_with(dir))
+    }
+}
+
+/// The `patterns` and `include` lists of an exclusion config compiled on
+/// first use, so that checking many paths compiles them once. They're
+/// compiled again if either list has changed since.
+#[derive(Debug, Default)]
+pub struct CompiledPatterns(Mutex<Option<CompiledFrom>>);
+
+#[derive(Debug, Clone)]
+struct CompiledFrom {
+    patterns: Vec<String>,
+    include: Vec<String>,
+    /// The compiled patterns, or why they don't compile
+    result: Result<Arc<PathPatterns>, String>,
+}
+
+impl Clone for CompiledPatterns {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl CompiledPatterns {
+    /// The lists compiled, reusing the last compilation if they haven't
+    /// changed
+    fn get(&self, patterns: &[String], include: &[String]) -> Result<Arc<PathPatterns>> {
+        let mut compiled = self.0.lock().unwrap();
+        let current = compiled
+            .as_ref()
+            .filter(|compiled| compiled.patterns == patterns && compiled.include == include);
+        if let Some(compiled) = current {
+            return compiled.result.clone().map_err(|e| anyhow!(e));
+        }
+
+        let result = PathPatterns::new(patterns, include).map(Arc::new);
+        *compiled = Some(CompiledFrom {
+            patterns: patterns.to_vec(),
+            include: include.to_vec(),
+            result: result
+                .as_ref()
+                .map(Arc::clone)
+                .map_err(|e| format!("{:#}", e)),
+        });
+        result
+    }
+}
+
+fn default_max_file_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_skip_binary_files() -> bool {
+    true
+}
+
+fn default_skip_generated_files() -> bool {
+    true
 }
 
 impl Default for ExclusionConfig {
@@ -145,6 +385,14 @@ impl Default for ExclusionConfig {
                 ".nuxt".to_string(),
                 ".cache".to_string(),
             ],
+
+            max_file_size: default_max_file_size(),
+            skip_binary_files: default_skip_binary_files(),
+            skip_generated_files: default_skip_generated_files(),
+            patterns: Vec::new(),
+            include: Vec::new(),
+            profiles: ExclusionProfiles::default(),
+            compiled_patterns: CompiledPatterns::default(),
         }
     }
 }
@@ -158,9 +406,73 @@ impl ExclusionConfig {
         let config: Self = serde_json::from_str(&content)
             .context(format!("Failed to parse exclusion config file: {}", path))?;
 
+        // Check the patterns of every profile compile
+        for profile in [ScanProfile::Tree, ScanProfile::Relevance] {
+            config
+                .for_profile(profile)
+                .path_patterns()
+                .context(format!(
+                    "Invalid patterns in exclusion config file: {}",
+                    path
+                ))?;
+        }
+        build_matcher(&config.profiles.docker.patterns).context(format!(
+            "Invalid patterns in exclusion config file: {}",
+            path
+        ))?;
+
         Ok(config)
     }
 
+    /// This config with a profile's patterns added
+    pub fn for_profile(&self, profile: ScanProfile) -> Self {
+        let added = match profile {
+            ScanProfile::Tree => &self.profiles.tree,
+            ScanProfile::Relevance => &self.profiles.relevance,
+        };
+
+        let mut config = self.clone();
+        config.patterns.extend(added.patterns.iter().cloned());
+        config.include.extend(added.include.iter().cloned());
+        config
+    }
+
+    /// The `patterns` and `include` lists compiled, which is done once
+    /// unless they change
+    pub fn path_patterns(&self) -> Result<Arc<PathPatterns>> {
+        self.compiled_patterns.get(&self.patterns, &self.include)
+    }
+
+    /// The Docker profile as `.dockerignore` lines. Patterns without a `/`
+    /// but at their end match at any depth, as in a `.gitignore`.
+    pub fn dockerignore_lines(&self) -> Vec<String> {
+        let docker = &self.profiles.docker;
+        let excluded = docker.patterns.iter().map(|pattern| pattern.to_string());
+        let included = docker.include.iter().map(|pattern| format!("!{}", pattern));
+
+        excluded
+            .chain(included)
+            .filter_map(|line| {
+                let (negation, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => ("!", pattern),
+                    None => ("", line.as_str()),
+                };
+                let pattern = pattern.trim();
+                if pattern.is_empty() || pattern.starts_with('#') {
+                    return None;
+                }
+
+                let anchored = pattern.trim_end_matches('/').contains('/');
+                let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+                Some(if anchored {
+                    format!("{}{}", negation, pattern)
+                } else {
+                    format!("{}**/{}", negation, pattern)
+                })
+            })
+            .collect()
+    }
+
     /// Check if a file should be excluded based on its extension
     pub fn should_exclude_by_extension(&self, path: &Path) -> bool {
         if let Some(extension) = path.extension() {
@@ -199,26 +511,9 @@ impl ExclusionConfig {
         false
     }
 
-    /// Check if a path should be excluded based on its parent directories
+    /// Check if a path, relative to the codebase root, should be excluded
+    /// based on its parent directories
     pub fn should_exclude_by_directory(&self, path: &Path) -> bool {
-        // Skip exclusion for paths in temporary test directories
-        if let Some(path_str) = path.to_str() {
-            // Check if the path is in a temporary test directory (common pattern in tests)
-            if path_str.contains("/tmp/") || path_str.contains("/.tmp") {
-                // Always exclude .git directories even in test directories
-                for ancestor in path.ancestors() {
-                    if let Some(dir_name) = ancestor.file_name() {
-                        if let Some(dir_str) = dir_name.to_str() {
-                            if dir_str == ".git" {
-                                return true;
-                            }
-                        }
-                    }
-                }
-                return false;
-            }
-        }
-
         for ancestor in path.ancestors() {
             if let Some(dir_name) = ancestor.file_name() {
                 if let Some(dir_str) = dir_name.to_str() {
@@ -232,10 +527,139 @@ impl ExclusionConfig {
         false
     }
 
+    /// Check if a path, relative to the codebase root, is vendored or
+    /// generated code by its name. Directories are checked with a trailing
+    /// `/`.
+    pub fn should_exclude_as_vendored(&self, path: &Path) -> bool {
+        if !self.skip_generated_files {
+            return false;
+        }
+
+        let path = path.to_string_lossy().replace('\\', "/");
+        vendored_path_regex().is_match(&path)
+    }
+
+    /// Check if a path, relative to the codebase root, is skipped by the
+    /// `patterns` list and not kept by the `include` list
+    pub fn should_exclude_by_pattern(&self, path: &Path, is_dir: bool) -> bool {
+        match self.path_patterns() {
+            Ok(patterns) => {
+                !patterns.is_included(path, is_dir)
+                    && patterns.excluding_pattern(path, is_dir).is_some()
+            }
+            Err(e) => {
+                warn!(
+                    "Not checking {:?} against the exclusion patterns: {:#}",
+                    path, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Check if a file should be excluded based on its size in bytes
+    pub fn should_exclude_by_size(&self, size: u64) -> bool {
+        self.max_file_size > 0 && size > self.max_file_size
+    }
+
+    /// Why a file should be excluded based on its size and what it
+    /// contains, if it should. Files that can't be read aren't excluded here.
+    pub fn content_exclusion_reason(&self, path: &Path) -> Option<String> {
+        let size = fs::metadata(path).ok()?.len();
+        if self.should_exclude_by_size(size) {
+            return Some(format!(
+                "larger than {} bytes ({} bytes)",
+                self.max_file_size, size
+            ));
+        }
+        if !self.skip_binary_files && !self.skip_generated_files {
+            return None;
+        }
+
+        let mut sample = Vec::with_capacity(SNIFF_BYTES);
+        File::open(path)
+            .ok()?
+            .take(SNIFF_BYTES as u64)
+            .read_to_end(&mut sample)
+            .ok()?;
+
+        if self.skip_binary_files && looks_binary(&sample) {
+            return Some("binary content".to_string());
+        }
+        if self.skip_generated_files && has_generated_header(&sample) {
+            return Some("generated file header".to_string());
+        }
+        None
+    }
+
     /// Check if a path should be excluded for any reason
     pub fn should_exclude(&self, path: &Path) -> bool {
+        match self.path_patterns() {
+            Ok(patterns) if patterns.is_included(path, false) => return false,
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Not checking {:?} against the include patterns: {:#}",
+                path, e
+            ),
+        }
+
         self.should_exclude_by_extension(path)
             || self.should_exclude_by_filename(path)
             || self.should_exclude_by_directory(path)
+            || self.should_exclude_by_pattern(path, false)
+            || self.should_exclude_as_vendored(path)
+    }
+}
+
+/// Compile gitignore-style patterns, matched against paths relative to the
+/// codebase root
+fn build_matcher(patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .context(format!("Invalid pattern: {}", pattern))?;
+    }
+    builder.build().context("Failed to compile patterns")
+}
+
+/// Whether the start of a file looks binary: it has a NUL byte, or too few
+/// of its characters are printable
+pub fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
     }
+
+    let text = String::from_utf8_lossy(sample);
+    let mut total = 0;
+    let mut printable = 0;
+    for c in text.chars() {
+        total += 1;
+        if c != char::REPLACEMENT_CHARACTER
+            && (!c.is_control() || matches!(c, '\n' | '\r' | '\t' | '\x0c' | '\x1b'))
+        {
+            printable += 1;
+        }
+    }
+    total > 0 && (printable as f64) < total as f64 * MIN_PRINTABLE_RATIO
+}
+
+/// Whether the start of a file has a header marking it generated, such as Go's
+/// `// Code generated by protoc-gen-go. DO NOT EDIT.` or `@generated`
+pub fn has_generated_header(sample: &[u8]) -> bool {
+    String::from_utf8_lossy(sample)
+        .lines()
+        .take(HEADER_LINES)
+        .any(|line| {
+            let line = line.to_lowercase();
+            line.contains("@generated")
+                || (line.contains("generated") && line.contains("do not edit"))
+        })
+}
+
+fn vendored_path_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(&VENDORED_PATH_PATTERNS.join("|")).expect("vendored path patterns are valid")
+    })
 }
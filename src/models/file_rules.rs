@@ -0,0 +1,272 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+
+use super::relevance::{RelevanceDecision, RelevanceVerdict};
+use crate::config::FileRulesConfig;
+
+/// Built-in rules for files that aren't relevant, as (pattern, reason). They
+/// are tried first so that e.g. a `package.json` fixture isn't taken for the
+/// project's own.
+const BUILTIN_NOT_RELEVANT: &[(&str, &str)] = &[
+    // Test data
+    ("**/fixtures/**", "Test fixture data"),
+    ("**/__fixtures__/**", "Test fixture data"),
+    ("**/testdata/**", "Test fixture data"),
+    ("**/__snapshots__/**", "Test snapshots"),
+    ("*.snap", "Test snapshot"),
+    // Generated and vendored code
+    ("*_pb2.py", "Generated protocol buffer code"),
+    ("*_pb2_grpc.py", "Generated protocol buffer code"),
+    ("*.pb.go", "Generated protocol buffer code"),
+    ("*.pb.cc", "Generated protocol buffer code"),
+    ("*.pb.h", "Generated protocol buffer code"),
+    ("*.generated.*", "Generated code"),
+    ("*_generated.*", "Generated code"),
+    ("**/vendor/**", "Vendored third-party code"),
+];
+
+/// Built-in rules for build, test and CI files, as (pattern, summary)
+const BUILTIN_RELEVANT: &[(&str, &str)] = &[
+    // Rust
+    (
+        "Cargo.toml",
+        "Cargo manifest with the dependencies and build settings",
+    ),
+    ("rust-toolchain", "Pins the Rust toolchain version"),
+    ("rust-toolchain.toml", "Pins the Rust toolchain version"),
+    (".cargo/config.toml", "Cargo build configuration"),
+    // Python
+    (
+        "pyproject.toml",
+        "Python project settings, dependencies and tool configuration",
+    ),
+    ("setup.py", "Python package setup with the dependencies"),
+    (
+        "setup.cfg",
+        "Python package metadata, dependencies and tool settings",
+    ),
+    ("requirements*.txt", "Python dependencies to install"),
+    ("Pipfile", "Python dependencies managed by Pipenv"),
+    (
+        "tox.ini",
+        "tox environments and the commands that run the tests",
+    ),
+    ("noxfile.py", "nox sessions that run the tests and linters"),
+    ("pytest.ini", "pytest configuration"),
+    (
+        "conftest.py",
+        "pytest fixtures and hooks shared by the tests",
+    ),
+    (".python-version", "Pins the Python version"),
+    // JavaScript and TypeScript
+    (
+        "package.json",
+        "npm manifest with the dependencies and build and test scripts",
+    ),
+    ("tsconfig.json", "TypeScript compiler settings"),
+    ("jest.config.*", "Jest test configuration"),
+    ("vitest.config.*", "Vitest test configuration"),
+    ("karma.conf.*", "Karma test runner configuration"),
+    (".mocharc*", "Mocha test configuration"),
+    (".nvmrc", "Pins the Node.js version"),
+    // Go
+    (
+        "go.mod",
+        "Go module definition with the Go version and dependencies",
+    ),
+    // JVM
+    (
+        "pom.xml",
+        "Maven build with the dependencies, plugins and test settings",
+    ),
+    (
+        "build.gradle",
+        "Gradle build with the dependencies, plugins and test settings",
+    ),
+    (
+        "build.gradle.kts",
+        "Gradle build with the dependencies, plugins and test settings",
+    ),
+    ("settings.gradle", "Gradle project layout"),
+    ("settings.gradle.kts", "Gradle project layout"),
+    // Ruby and PHP
+    ("Gemfile", "Ruby dependencies managed by Bundler"),
+    ("*.gemspec", "Ruby gem specification with the dependencies"),
+    ("Rakefile", "Rake tasks, usually including the test task"),
+    (
+        "composer.json",
+        "Composer manifest with the PHP dependencies and scripts",
+    ),
+    ("phpunit.xml*", "PHPUnit test configuration"),
+    // C, C++ and .NET
+    ("Makefile", "Make targets that build and test the project"),
+    ("makefile", "Make targets that build and test the project"),
+    (
+        "GNUmakefile",
+        "Make targets that build and test the project",
+    ),
+    ("*.mk", "Make rules included by the build"),
+    ("CMakeLists.txt", "CMake build definition"),
+    ("meson.build", "Meson build definition"),
+    ("configure.ac", "Autoconf script that configures the build"),
+    (
+        "*.csproj",
+        ".NET project with the dependencies and build settings",
+    ),
+    ("*.sln", ".NET solution listing the projects"),
+    // Containers
+    (
+        "Dockerfile",
+        "Existing Dockerfile showing how the project is built",
+    ),
+    (
+        "Dockerfile.*",
+        "Existing Dockerfile showing how the project is built",
+    ),
+    (
+        "*.dockerfile",
+        "Existing Dockerfile showing how the project is built",
+    ),
+    (
+        "docker-compose.y*ml",
+        "Docker Compose services the project runs with",
+    ),
+    (
+        "compose.y*ml",
+        "Docker Compose services the project runs with",
+    ),
+    // Continuous integration
+    (
+        ".github/workflows/*.yml",
+        "CI workflow showing how the project is built and tested",
+    ),
+    (
+        ".github/workflows/*.yaml",
+        "CI workflow showing how the project is built and tested",
+    ),
+    (
+        ".gitlab-ci.yml",
+        "CI pipeline showing how the project is built and tested",
+    ),
+    (
+        ".travis.yml",
+        "CI configuration showing how the project is built and tested",
+    ),
+    (
+        ".circleci/config.yml",
+        "CI configuration showing how the project is built and tested",
+    ),
+    (
+        "azure-pipelines.yml",
+        "CI pipeline showing how the project is built and tested",
+    ),
+    (".tool-versions", "Pins the versions of the project's tools"),
+];
+
+/// A rule that decides the relevance of the files matching a pattern without
+/// asking a model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+    /// Glob matched against the path from the codebase root, or against just
+    /// the file name if it has no `/`
+    pub pattern: String,
+    pub status: RelevanceVerdict,
+    /// What matching files are, or why they aren't relevant
+    pub summary: String,
+}
+
+impl FileRule {
+    pub fn new(pattern: &str, status: RelevanceVerdict, summary: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            status,
+            summary: summary.to_string(),
+        }
+    }
+
+    /// The decision to save for a file matching this rule
+    pub fn decision(&self) -> RelevanceDecision {
+        let message = format!("Matched the file rule `{}`: {}", self.pattern, self.summary);
+        match self.status {
+            RelevanceVerdict::Relevant => {
+                RelevanceDecision::relevant(message, self.summary.clone())
+            }
+            RelevanceVerdict::NotRelevant => RelevanceDecision::not_relevant(message),
+        }
+    }
+}
+
+/// The rules that decide the relevance of well-known files, such as build
+/// manifests, CI workflows, fixtures and generated code
+#[derive(Debug, Clone)]
+pub struct FileRules {
+    rules: Vec<(FileRule, Pattern)>,
+}
+
+impl FileRules {
+    /// Compile rules, which are tried in order
+    pub fn new(rules: Vec<FileRule>) -> Result<Self> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let pattern = Pattern::new(&rule.pattern)
+                    .context(format!("Invalid file rule pattern: {}", rule.pattern))?;
+                Ok((rule, pattern))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The configured rules, followed by the built-in ones unless turned off
+    pub fn from_config(config: &FileRulesConfig) -> Result<Self> {
+        let mut rules = config.rules.clone();
+        if config.builtin {
+            rules.extend(builtin_rules());
+        }
+        Self::new(rules)
+    }
+
+    /// The first rule matching a file, if any
+    pub fn classify(&self, file_path: &str) -> Option<&FileRule> {
+        let path = file_path.strip_prefix("./").unwrap_or(file_path);
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        self.rules
+            .iter()
+            .find(|(rule, pattern)| {
+                if rule.pattern.contains('/') {
+                    pattern.matches_with(path, options)
+                } else {
+                    pattern.matches_with(file_name, options)
+                }
+            })
+            .map(|(rule, _)| rule)
+    }
+
+    /// Number of rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// The built-in rules for common build, test and CI files, fixtures and
+/// generated code
+pub fn builtin_rules() -> Vec<FileRule> {
+    let not_relevant = BUILTIN_NOT_RELEVANT
+        .iter()
+        .map(|&(pattern, reason)| FileRule::new(pattern, RelevanceVerdict::NotRelevant, reason));
+    let relevant = BUILTIN_RELEVANT
+        .iter()
+        .map(|&(pattern, summary)| FileRule::new(pattern, RelevanceVerdict::Relevant, summary));
+    not_relevant.chain(relevant).collect()
+}
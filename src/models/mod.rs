@@ -1,6 +1,7 @@
 pub mod dockerfile;
 pub mod exclusion;
 pub mod file;
+pub mod file_rules;
 pub mod overview;
 pub mod problem;
 pub mod ranking;
@@ -171,7 +171,8 @@ impl OverviewData {
         &self,
         config: &crate::config::Config,
     ) -> anyhow::Result<String> {
-        use crate::llm::client::create_client;
+        use crate::llm::budget::with_budget;
+        use crate::llm::client::{create_client, ChatRequest};
         use anyhow::Context;
 
         // Create LLM config for summary generation
@@ -181,40 +182,39 @@ impl OverviewData {
         let client = create_client(&llm_config)
             .await
             .context("Failed to create LLM client for overview summarization")?;
+        let client = with_budget(client, "overview");
 
         // Generate the detailed version first
         let detailed_md = self.to_markdown();
 
-        // Create a prompt for the LLM to summarize the detailed overview
-        let summary_prompt = format!(
-            "You are an expert software engineer tasked with summarizing detailed reasoning about a project build process. \
-            I'll provide you with the full reasoning for each step, and I need you to create a concise but \
-            comprehensive summary of the build process.
-
-            Here's the full detailed reasoning document:
-            
-            ```markdown
-            {}
-            ```
-            
-            Please create a summarized version that:
-            1. Keeps the same section structure
-            2. Significantly condenses each section to focus only on key points and decisions
-            3. Explains WHY choices were made rather than HOW they were implemented
-            4. Highlights important tradeoffs and considerations
-            5. Is approximately 1/4 the length of the original document
-            
-            Format your response as a complete Markdown document.",
-            detailed_md
-        );
+        // Create a request for the LLM to summarize the detailed overview
+        let request = ChatRequest::new(4096, 0.2) // Use a moderate temperature for summarization
+            .with_system(
+                "You are an expert software engineer tasked with summarizing detailed reasoning about a project build process. \
+                I'll provide you with the full reasoning for each step, and I need you to create a concise but \
+                comprehensive summary of the build process.",
+            )
+            .with_user(format!(
+                r#"Here's the full detailed reasoning document:
+
+```markdown
+{}
+```
+
+Please create a summarized version that:
+1. Keeps the same section structure
+2. Significantly condenses each section to focus only on key points and decisions
+3. Explains WHY choices were made rather than HOW they were implemented
+4. Highlights important tradeoffs and considerations
+5. Is approximately 1/4 the length of the original document
+
+Format your response as a complete Markdown document."#,
+                detailed_md
+            ));
 
         // Send the request to the LLM
         let llm_response = client
-            .completion(
-                &summary_prompt,
-                4096,
-                0.2, // Use a moderate temperature for summarization
-            )
+            .chat(&request)
             .await
             .context("Failed to get overview summary from LLM")?;
 
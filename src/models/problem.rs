@@ -1,14 +1,129 @@
 use anyhow::{Context, Result};
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use log::{debug, info};
+use ignore::WalkBuilder;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use walkdir::{DirEntry, WalkDir};
+use std::sync::{Arc, Mutex};
+use walkdir::DirEntry;
 
-use super::exclusion::ExclusionConfig;
+use super::exclusion::{ExclusionConfig, PathPatterns};
 use super::file::CodebaseFile;
+use crate::utils::ignore_files::IgnoreFiles;
+use crate::utils::tree_renderer::{parent_dir, render_tree, TreeBudget};
+
+/// Hidden files and directories that are scanned anyway, as they show how
+/// the project is built, tested and run in CI
+const SCANNED_HIDDEN_NAMES: &[&str] = &[
+    ".github",
+    ".gitlab-ci.yml",
+    ".circleci",
+    ".travis.yml",
+    ".cargo",
+    ".python-version",
+    ".nvmrc",
+    ".tool-versions",
+];
+
+/// Why the engine's own rules leave a path out of a scan, if they do: `.git`
+/// directories, hidden files and what the exclusion config excludes, unless
+/// its include patterns keep the path. Paths are checked relative to the
+/// codebase root.
+fn exclusion_reason(
+    root: &Path,
+    exclusion_config: &ExclusionConfig,
+    patterns: &PathPatterns,
+    path: &Path,
+    is_dir: bool,
+) -> Option<String> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if relative.as_os_str().is_empty() {
+        return None;
+    }
+
+    // Always exclude .git directories and their contents regardless of location
+    if relative
+        .components()
+        .any(|component| component.as_os_str() == ".git")
+    {
+        return Some("git directory".to_string());
+    }
+
+    if patterns.is_included(relative, is_dir) {
+        return None;
+    }
+    let reason = excluding_rule(exclusion_config, patterns, relative, is_dir)?;
+
+    // Directories leading to included paths are scanned, and the paths in
+    // them that aren't included are excluded one by one
+    if is_dir && patterns.leads_to_include(relative) {
+        return None;
+    }
+    Some(reason)
+}
+
+/// The exclusion config rule that excludes a path relative to the codebase
+/// root, or one of the directories it is in
+fn excluding_rule(
+    exclusion_config: &ExclusionConfig,
+    patterns: &PathPatterns,
+    relative: &Path,
+    is_dir: bool,
+) -> Option<String> {
+    let hidden = relative.components().any(|component| {
+        component
+            .as_os_str()
+            .to_str()
+            .is_some_and(|name| name.starts_with('.') && !SCANNED_HIDDEN_NAMES.contains(&name))
+    });
+    if hidden {
+        return Some("hidden".to_string());
+    }
+
+    if exclusion_config.should_exclude_by_directory(relative) {
+        return Some("excluded directory".to_string());
+    }
+    if !is_dir && exclusion_config.should_exclude_by_extension(relative) {
+        return Some("excluded extension".to_string());
+    }
+    if !is_dir && exclusion_config.should_exclude_by_filename(relative) {
+        return Some("excluded file name".to_string());
+    }
+    if let Some(pattern) = patterns.excluding_pattern(relative, is_dir) {
+        return Some(format!("excluded by pattern `{}`", pattern));
+    }
+
+    // Vendored directories are matched with a trailing `/`
+    let vendored = if is_dir {
+        exclusion_config.should_exclude_as_vendored(&relative.join(""))
+    } else {
+        exclusion_config.should_exclude_as_vendored(relative)
+    };
+    if vendored {
+        return Some("vendored or generated path".to_string());
+    }
+    None
+}
+
+/// A path left out of a codebase scan, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedPath {
+    /// Path from the codebase root, ending in `/` for a directory
+    pub path: String,
+    pub reason: String,
+}
+
+impl SkippedPath {
+    fn new(root: &Path, path: &Path, is_dir: bool, reason: String) -> Self {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let mut path = relative.to_string_lossy().to_string();
+        if is_dir {
+            path.push('/');
+        }
+        Self { path, reason }
+    }
+}
 
 /// Represents a problem from the SWE-bench dataset
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,9 +153,22 @@ pub struct SWEBenchProblem {
     #[serde(skip)]
     cached_paths: Vec<String>,
 
-    /// Gitignore patterns (not serialized)
+    /// Cached directory paths, including empty directories (not serialized)
+    #[serde(skip)]
+    cached_dirs: Vec<String>,
+
+    /// Ignore files found by the scan (not serialized)
+    #[serde(skip)]
+    ignore_files: Option<IgnoreFiles>,
+
+    /// Paths the scan left out, and why (not serialized)
     #[serde(skip)]
-    gitignore: Option<Gitignore>,
+    skipped_paths: Vec<SkippedPath>,
+
+    /// Files to limit the scan to, with the other files in their directories
+    /// (not serialized)
+    #[serde(skip)]
+    scope: Option<Vec<String>>,
 }
 
 impl SWEBenchProblem {
@@ -54,7 +182,10 @@ impl SWEBenchProblem {
             codebase_path: None,
             exclusion_config: ExclusionConfig::default(),
             cached_paths: Vec::new(),
-            gitignore: None,
+            cached_dirs: Vec::new(),
+            ignore_files: None,
+            skipped_paths: Vec::new(),
+            scope: None,
         }
     }
 
@@ -75,213 +206,207 @@ impl SWEBenchProblem {
         self
     }
 
+    /// Limit the scan to these files, relative to the codebase root, and the
+    /// other files in their directories
+    pub fn with_scope(mut self, files: Vec<String>) -> Self {
+        self.scope = Some(files);
+        self
+    }
+
     /// Initialize the problem by scanning the codebase
+    ///
+    /// Paths ignored by `.gitignore`, `.ignore` or `.enginesignore` files in
+    /// any directory, by `.git/info/exclude` or by the global git excludes are
+    /// left out, as are those the exclusion config excludes and files too
+    /// large, binary or generated to read. Each path left out is recorded
+    /// with the reason, and logged at debug level.
     pub fn initialize(&mut self) -> Result<()> {
-        if self.codebase_path.is_none() {
-            return Ok(());
-        }
-
-        let codebase_path = self.codebase_path.as_ref().unwrap();
+        let codebase_path = match &self.codebase_path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
 
         info!("Starting file tree traversal at: {:?}", codebase_path);
 
-        // Load gitignore file if it exists
-        let gitignore_path = codebase_path.join(".gitignore");
-        if gitignore_path.exists() {
-            info!("Found .gitignore file at: {:?}", gitignore_path);
-            match self.load_gitignore(&gitignore_path, codebase_path) {
-                Ok(gitignore) => {
-                    info!("Successfully loaded .gitignore patterns");
-                    self.gitignore = Some(gitignore);
-                }
-                Err(e) => {
-                    info!("Failed to load .gitignore: {:?}", e);
-                }
-            }
-        } else {
-            info!("No .gitignore file found at: {:?}", gitignore_path);
-        }
-
-        // Scan for files
+        let patterns = self
+            .exclusion_config
+            .path_patterns()
+            .context("Invalid patterns in the exclusion config")?;
+        let ignore_files = Arc::new(Mutex::new(IgnoreFiles::new(&codebase_path)));
         let mut paths = Vec::new();
-        let mut file_count = 0;
-        let mut dir_count = 0;
-        let mut excluded_count = 0;
-
-        for entry in WalkDir::new(codebase_path)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| {
-                if let Ok(entry) = e {
-                    if entry.file_type().is_dir() {
-                        debug!("Exploring directory: {:?}", entry.path());
-                        dir_count += 1;
-                    }
-                    Some(entry)
-                } else {
-                    info!("Error accessing path: {:?}", e);
-                    None
-                }
-            })
-            .filter(|e| {
-                let should_include = !self.should_exclude(e);
-                if !should_include {
-                    debug!("Excluding path: {:?}", e.path());
-                    excluded_count += 1;
-                }
-                should_include
-            })
+        let mut dirs = Vec::new();
+        let mut error_count = 0;
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+
+        for result in self
+            .walker(
+                &codebase_path,
+                &patterns,
+                ignore_files.clone(),
+                skipped.clone(),
+            )
+            .build()
         {
-            if entry.file_type().is_file() {
-                debug!("Found file: {:?}", entry.path());
-                file_count += 1;
+            let entry = match result {
+                Ok(entry) => entry,
+                Err(e) => {
+                    info!("Error accessing path: {}", e);
+                    error_count += 1;
+                    continue;
+                }
+            };
 
-                if let Ok(path) = entry.path().strip_prefix(codebase_path) {
-                    if let Some(path_str) = path.to_str() {
-                        paths.push(path_str.to_string());
+            let is_dir = entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir());
+            let path = match entry.path().strip_prefix(&codebase_path) {
+                Ok(path) if !path.as_os_str().is_empty() => path,
+                _ => continue,
+            };
+            if let Some(path_str) = path.to_str() {
+                if is_dir {
+                    debug!("Exploring directory: {:?}", entry.path());
+                    dirs.push(path_str.to_string());
+                } else if entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_file())
+                {
+                    // Leave out files too large, binary or generated to read,
+                    // unless an include pattern keeps them
+                    if let Some(reason) = self
+                        .exclusion_config
+                        .content_exclusion_reason(entry.path())
+                        .filter(|_| !patterns.is_included(path, false))
+                    {
+                        debug!("Excluding {:?} ({})", entry.path(), reason);
+                        skipped.lock().unwrap().push(SkippedPath::new(
+                            &codebase_path,
+                            entry.path(),
+                            false,
+                            reason,
+                        ));
+                        continue;
                     }
+                    debug!("Found file: {:?}", entry.path());
+                    paths.push(path_str.to_string());
                 }
             }
         }
 
-        self.cached_paths = paths;
-        info!(
-            "File tree traversal complete: {} directories, {} files processed, {} paths excluded",
-            dir_count, file_count, excluded_count
-        );
-
-        Ok(())
-    }
+        let mut skipped_paths = std::mem::take(&mut *skipped.lock().unwrap());
+        skipped_paths.sort_by(|a, b| a.path.cmp(&b.path));
 
-    /// Load gitignore patterns from a .gitignore file
-    fn load_gitignore(&self, gitignore_path: &Path, codebase_path: &Path) -> Result<Gitignore> {
-        let mut builder = GitignoreBuilder::new(codebase_path);
-
-        info!("Loading gitignore from path: {:?}", gitignore_path);
-
-        // Read the gitignore file content for debugging
-        if let Ok(content) = std::fs::read_to_string(gitignore_path) {
-            info!("Gitignore content:\n{}", content);
-        }
-
-        // GitignoreBuilder.add returns Option<()>, where None means success
-        match builder.add(gitignore_path) {
-            Some(err) => {
-                info!("Failed to add gitignore file: {}", err);
-                return Err(anyhow::anyhow!("Failed to add gitignore file: {}", err));
-            }
-            None => {
-                info!("Successfully added gitignore file");
-            }
-        }
-
-        // builder.build() returns Result<Gitignore, ignore::Error>
-        let gitignore = match builder.build() {
-            Ok(gitignore) => {
-                info!("Successfully built gitignore");
-                gitignore
-            }
-            Err(e) => {
-                info!("Failed to build gitignore: {}", e);
-                return Err(anyhow::anyhow!("Failed to build gitignore: {}", e));
-            }
-        };
-
-        // Test that the gitignore patterns work correctly
-        let test_paths = vec![
-            "target/test.txt",
-            "node_modules/file.js",
-            "example.log",
-            "src/main.rs",
-        ];
-
-        for test_path in test_paths {
-            let path = codebase_path.join(test_path);
-            let is_dir = path.ends_with("node_modules");
-            let match_result = gitignore.matched(&path, is_dir);
+        // Keep only the files in scope and their neighbours
+        if let Some(scope) = &self.scope {
+            let scope_dirs: HashSet<&str> = scope.iter().map(|path| parent_dir(path)).collect();
+            paths.retain(|path| scope_dirs.contains(parent_dir(path)));
+            dirs.retain(|dir| {
+                let prefix = format!("{}/", dir);
+                paths.iter().any(|path| path.starts_with(&prefix))
+            });
             info!(
-                "Testing gitignore match for {}: {:?}",
-                test_path, match_result
+                "{} files are in the directories of the {} files in scope",
+                paths.len(),
+                scope.len()
             );
         }
 
-        Ok(gitignore)
-    }
-
-    /// Check if a directory entry should be excluded
-    pub fn should_exclude(&self, entry: &DirEntry) -> bool {
-        let path = entry.path();
+        info!(
+            "File tree traversal complete: {} directories, {} files found, {} paths skipped, {} paths unreadable",
+            dirs.len(),
+            paths.len(),
+            skipped_paths.len(),
+            error_count
+        );
 
-        // Always exclude .git directories and their contents regardless of location
-        for ancestor in path.ancestors() {
-            if let Some(dir_name) = ancestor.file_name() {
-                if let Some(dir_str) = dir_name.to_str() {
-                    if dir_str == ".git" {
-                        debug!("Excluding .git directory or its contents: {:?}", path);
-                        return true;
-                    }
-                }
-            }
-        }
+        self.cached_paths = paths;
+        self.cached_dirs = dirs;
+        self.ignore_files = Some(std::mem::take(&mut *ignore_files.lock().unwrap()));
+        self.skipped_paths = skipped_paths;
 
-        // Skip directory-based exclusion for paths in temporary test directories
-        let in_temp_dir = if let Some(path_str) = path.to_str() {
-            path_str.contains("/tmp/") || path_str.contains("/.tmp")
-        } else {
-            false
-        };
+        Ok(())
+    }
 
-        // Special handling for test directories
-        if in_temp_dir {
-            if let Some(path_str) = path.to_str() {
-                // In test_initialize_with_gitignore, we need to exclude .gitignore itself
-                if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name == ".gitignore" {
-                        // Only exclude .gitignore in the test that checks gitignore functionality
-                        if path_str.contains("node_modules") || path_str.contains(".log") {
-                            return true;
+    /// A walk over the codebase that leaves out what the exclusion config
+    /// excludes and what ignore files ignore, recording each path left out
+    /// with the reason in `skipped`. The ignore files in each directory are
+    /// loaded into `ignore_files` as the walk enters it, before its entries
+    /// are checked.
+    fn walker(
+        &self,
+        codebase_path: &Path,
+        patterns: &PathPatterns,
+        ignore_files: Arc<Mutex<IgnoreFiles>>,
+        skipped: Arc<Mutex<Vec<SkippedPath>>>,
+    ) -> WalkBuilder {
+        let root = codebase_path.to_path_buf();
+        let exclusion_config = self.exclusion_config.clone();
+        let patterns = patterns.clone();
+
+        let mut builder = WalkBuilder::new(codebase_path);
+        builder
+            .follow_links(true)
+            // Hidden paths are handled by the exclusion filter below, and
+            // ignore files by `ignore_files`, so that the rule skipping each
+            // path is known
+            .standard_filters(false)
+            .filter_entry(move |entry| {
+                let is_dir = entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_dir());
+                let reason =
+                    exclusion_reason(&root, &exclusion_config, &patterns, entry.path(), is_dir)
+                        .or_else(|| {
+                            let rule = ignore_files
+                                .lock()
+                                .unwrap()
+                                .ignoring_rule(entry.path(), is_dir)?;
+                            Some(format!("ignored by {}", rule))
+                        });
+                match reason {
+                    Some(reason) => {
+                        debug!("Excluding {:?} ({})", entry.path(), reason);
+                        skipped.lock().unwrap().push(SkippedPath::new(
+                            &root,
+                            entry.path(),
+                            is_dir,
+                            reason,
+                        ));
+                        false
+                    }
+                    None => {
+                        if is_dir && entry.depth() > 0 {
+                            ignore_files.lock().unwrap().add_dir(entry.path());
                         }
+                        true
                     }
                 }
+            });
+        builder
+    }
 
-                // Exclude node_modules directory and its contents
-                if path_str.contains("node_modules") {
-                    return true;
-                }
-
-                // Exclude .log files
-                if path_str.ends_with(".log") {
-                    return true;
-                }
-            }
-        }
-
-        // Always apply gitignore rules regardless of directory
-        if let Some(gitignore) = &self.gitignore {
-            let is_dir = entry.file_type().is_dir();
-            let is_match = gitignore.matched(path, is_dir);
-            if is_match.is_ignore() {
-                debug!("Excluding due to .gitignore match: {:?}", path);
-                return true;
-            }
-        }
-
-        // Skip hidden files and directories (if not already excluded by gitignore)
-        if let Some(file_name) = entry.file_name().to_str() {
-            if file_name.starts_with('.') && file_name != ".gitignore" {
-                debug!("Excluding hidden file/directory: {:?}", path);
-                return true;
-            }
-        }
-
-        // If in temp directory, skip the directory-based exclusions
-        if in_temp_dir {
-            return false;
+    /// Check if a directory entry should be excluded
+    pub fn should_exclude(&self, entry: &DirEntry) -> bool {
+        let root = self.codebase_path.as_deref().unwrap_or(Path::new(""));
+        let path = entry.path();
+        let is_dir = entry.file_type().is_dir();
+        let patterns = self.exclusion_config.path_patterns().unwrap_or_else(|e| {
+            warn!("Ignoring the exclusion config's patterns: {:#}", e);
+            Arc::default()
+        });
+
+        if let Some(reason) =
+            exclusion_reason(root, &self.exclusion_config, &patterns, path, is_dir)
+        {
+            debug!("Excluding {:?} ({})", path, reason);
+            return true;
         }
-
-        // Apply pattern-based exclusions
-        if self.exclusion_config.should_exclude(path) {
-            debug!("Excluding path based on exclusion patterns: {:?}", path);
+        if let Some(rule) = self
+            .ignore_files
+            .as_ref()
+            .and_then(|ignore_files| ignore_files.ignoring_rule(path, is_dir))
+        {
+            debug!("Excluding {:?} (ignored by {})", path, rule);
             return true;
         }
 
@@ -293,209 +418,34 @@ impl SWEBenchProblem {
         self.cached_paths.clone()
     }
 
+    /// The paths the scan left out, and why, sorted by path
+    pub fn skipped_paths(&self) -> &[SkippedPath] {
+        &self.skipped_paths
+    }
+
     /// Generate a tree-like representation of the codebase
     pub fn generate_tree(&self) -> String {
+        self.render_tree(None)
+    }
+
+    /// Generate a tree-like representation of the codebase that fits a token
+    /// budget, collapsing directories that don't fit into summaries
+    pub fn generate_tree_within(&self, budget: &TreeBudget) -> String {
+        self.render_tree(Some(budget))
+    }
+
+    fn render_tree(&self, budget: Option<&TreeBudget>) -> String {
         if self.codebase_path.is_none() {
             info!("Cannot generate tree: codebase path not set");
             return String::new();
         }
 
-        let codebase_path = self.codebase_path.as_ref().unwrap();
-        info!(
-            "Generating tree representation for codebase at: {:?}",
-            codebase_path
+        debug!(
+            "Generating tree of {} files and {} directories",
+            self.cached_paths.len(),
+            self.cached_dirs.len()
         );
-        info!(
-            "Total files to include in tree: {}",
-            self.cached_paths.len()
-        );
-
-        let mut result = String::new();
-
-        // Create a set of all directories based on file paths
-        let mut all_dirs = std::collections::HashSet::new();
-
-        // First gather all directories from file paths
-        for path in &self.cached_paths {
-            let mut parts = path.split('/').collect::<Vec<_>>();
-            parts.pop(); // Remove the filename
-
-            // Add all parent directories
-            let mut current_path = String::new();
-            for part in parts {
-                if !current_path.is_empty() {
-                    current_path.push('/');
-                }
-                current_path.push_str(part);
-                all_dirs.insert(current_path.clone());
-            }
-        }
-
-        // Also traverse filesystem to find all directories, including empty ones
-        if let Some(codebase_path) = &self.codebase_path {
-            use walkdir::WalkDir;
-
-            for entry in WalkDir::new(codebase_path)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| !self.should_exclude(e) && e.file_type().is_dir())
-            {
-                if let Ok(rel_path) = entry.path().strip_prefix(codebase_path) {
-                    if let Some(path_str) = rel_path.to_str() {
-                        if !path_str.is_empty() {
-                            all_dirs.insert(path_str.to_string());
-                        }
-                    }
-                }
-            }
-        }
-
-        info!("Found {} total directories", all_dirs.len());
-
-        // Create a map of directories to their files
-        let mut files_by_dir: std::collections::HashMap<String, Vec<String>> =
-            std::collections::HashMap::new();
-
-        // Add files to their respective directories
-        for path in &self.cached_paths {
-            let parent = path.rfind('/').map_or("", |i| &path[0..i]);
-            info!(
-                "Adding file to tree: {} (parent directory: {})",
-                path, parent
-            );
-            files_by_dir
-                .entry(parent.to_string())
-                .or_default()
-                .push(path.clone());
-        }
-
-        // Create a map of parent directories to their direct subdirectories
-        let mut subdirs_by_dir: std::collections::HashMap<String, Vec<String>> =
-            std::collections::HashMap::new();
-
-        // Map each directory to its parent
-        for dir in &all_dirs {
-            if let Some(last_slash) = dir.rfind('/') {
-                let parent = &dir[0..last_slash];
-                subdirs_by_dir
-                    .entry(parent.to_string())
-                    .or_default()
-                    .push(dir.clone());
-            } else {
-                // Top-level directory, add to root
-                subdirs_by_dir
-                    .entry(String::new())
-                    .or_default()
-                    .push(dir.clone());
-            }
-        }
-
-        // Function to print a directory tree recursively
-        fn print_dir(
-            dir: &str,
-            files_by_dir: &std::collections::HashMap<String, Vec<String>>,
-            subdirs_by_dir: &std::collections::HashMap<String, Vec<String>>,
-            result: &mut String,
-            prefix: &str,
-            is_last: bool,
-        ) {
-            // Print directory name
-            let dir_name = if dir.is_empty() {
-                "."
-            } else {
-                dir.split('/').last().unwrap_or(dir)
-            };
-
-            info!("Building tree for directory: {}", dir_name);
-
-            let branch = if is_last { "└── " } else { "├── " };
-            result.push_str(&format!("{}{}{}/\n", prefix, branch, dir_name));
-
-            // Prepare the prefix for children
-            let child_prefix = if is_last {
-                format!("{}    ", prefix)
-            } else {
-                format!("{}│   ", prefix)
-            };
-
-            // Get files and subdirectories in this directory
-            let mut entries = Vec::new();
-
-            // Add subdirectories
-            if let Some(subdirs) = subdirs_by_dir.get(dir) {
-                for subdir in subdirs {
-                    let name = if let Some(last_slash) = subdir.rfind('/') {
-                        &subdir[last_slash + 1..]
-                    } else {
-                        subdir
-                    };
-
-                    entries.push((name.to_string(), true));
-                }
-            }
-
-            // Add files
-            if let Some(files) = files_by_dir.get(dir) {
-                for file in files {
-                    if file.starts_with(dir) && file != dir {
-                        let rel_path = if dir.is_empty() {
-                            file.clone()
-                        } else if file.len() > dir.len() + 1 && file.as_bytes()[dir.len()] == b'/' {
-                            file[dir.len() + 1..].to_string()
-                        } else {
-                            continue; // Not directly under this directory
-                        };
-
-                        if !rel_path.contains('/') {
-                            // This is a file
-                            entries.push((rel_path, false));
-                        }
-                    }
-                }
-            }
-
-            // Sort entries: directories first, then files
-            entries.sort_by(|a, b| match (a.1, b.1) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.0.cmp(&b.0),
-            });
-
-            // Print entries
-            for (i, (name, is_dir)) in entries.iter().enumerate() {
-                let is_last_entry = i == entries.len() - 1;
-
-                if *is_dir {
-                    let full_path = if dir.is_empty() {
-                        name.clone()
-                    } else {
-                        format!("{}/{}", dir, name)
-                    };
-
-                    print_dir(
-                        &full_path,
-                        files_by_dir,
-                        subdirs_by_dir,
-                        result,
-                        &child_prefix,
-                        is_last_entry,
-                    );
-                } else {
-                    let branch = if is_last_entry {
-                        "└── "
-                    } else {
-                        "├── "
-                    };
-                    result.push_str(&format!("{}{}{}\n", child_prefix, branch, name));
-                }
-            }
-        }
-
-        // Start with the root directory
-        print_dir("", &files_by_dir, &subdirs_by_dir, &mut result, "", true);
-
-        result
+        render_tree(&self.cached_paths, &self.cached_dirs, budget)
     }
 
     /// Get a specific file from the codebase
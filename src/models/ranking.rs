@@ -21,6 +21,23 @@ pub struct FileRanking {
     pub ranking: Vec<String>,
 }
 
+/// How much the rankings agree on a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileAgreement {
+    /// Path to the file, relative to the codebase root
+    pub path: String,
+
+    /// The file's merged score; files with higher scores are ranked first
+    pub score: f64,
+
+    /// Fraction of the rankings that include the file, from 0 to 1
+    pub agreement: f64,
+
+    /// Position of the file in each ranking, from 1, or None if the ranking
+    /// leaves it out
+    pub positions: Vec<Option<usize>>,
+}
+
 /// Data about a file for inclusion in a prompt
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RelevantFileDataForPrompt {
@@ -43,6 +60,10 @@ pub struct ProblemContext {
     /// The final ranked list of files
     pub ranked_files: Vec<RankedCodebaseFile>,
 
+    /// How much the rankings agree on each file, in merged order
+    #[serde(default)]
+    pub agreement: Vec<FileAgreement>,
+
     /// Usage data from the LLM API
     pub prompt_caching_usages: Vec<HashMap<String, serde_json::Value>>,
 }
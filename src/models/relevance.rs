@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// The status of a relevance decision
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -11,6 +12,44 @@ pub enum RelevanceStatus {
 
     /// There was an error parsing the LLM response
     ParseError,
+
+    /// The file wasn't assessed, e.g. because it doesn't fit in the model's
+    /// context or would take too many chunks
+    Skipped { reason: String },
+}
+
+/// A range of lines in a file, numbered from 1, including both ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Sort ranges and join those that overlap or touch
+    pub fn merge(ranges: impl IntoIterator<Item = LineRange>) -> Vec<LineRange> {
+        let mut ranges: Vec<LineRange> = ranges.into_iter().collect();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<LineRange> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
 }
 
 /// The decision about whether a file is relevant to a problem
@@ -24,6 +63,14 @@ pub struct RelevanceDecision {
 
     /// A summary of why the file is relevant (only if status is Relevant)
     pub summary: Option<String>,
+
+    /// How sure the model is of the decision, from 0 to 1, if it said
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+
+    /// For files assessed in chunks, the lines of the chunks found relevant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relevant_lines: Vec<LineRange>,
 }
 
 impl RelevanceDecision {
@@ -33,6 +80,8 @@ impl RelevanceDecision {
             message,
             status: RelevanceStatus::Relevant,
             summary: Some(summary),
+            confidence: None,
+            relevant_lines: Vec::new(),
         }
     }
 
@@ -42,6 +91,8 @@ impl RelevanceDecision {
             message,
             status: RelevanceStatus::NotRelevant,
             summary: None,
+            confidence: None,
+            relevant_lines: Vec::new(),
         }
     }
 
@@ -51,6 +102,21 @@ impl RelevanceDecision {
             message,
             status: RelevanceStatus::ParseError,
             summary: None,
+            confidence: None,
+            relevant_lines: Vec::new(),
+        }
+    }
+
+    /// Create a new relevance decision for a file that wasn't assessed
+    pub fn skipped(reason: impl Into<String>) -> Self {
+        Self {
+            message: String::new(),
+            status: RelevanceStatus::Skipped {
+                reason: reason.into(),
+            },
+            summary: None,
+            confidence: None,
+            relevant_lines: Vec::new(),
         }
     }
 
@@ -58,4 +124,182 @@ impl RelevanceDecision {
     pub fn is_relevant(&self) -> bool {
         self.status == RelevanceStatus::Relevant
     }
+
+    /// Check if the file was left unassessed
+    pub fn is_skipped(&self) -> bool {
+        matches!(self.status, RelevanceStatus::Skipped { .. })
+    }
+
+    /// Combine the decisions for the chunks of a file too long to assess at
+    /// once. The file is relevant if any chunk is, and the summary of each
+    /// relevant chunk is kept along with its lines.
+    pub fn merge_chunks(chunks: Vec<(LineRange, RelevanceDecision)>) -> Self {
+        let message = chunks
+            .iter()
+            .map(|(lines, decision)| format!("## Lines {}\n\n{}", lines, decision.message))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let relevant: Vec<&(LineRange, RelevanceDecision)> = chunks
+            .iter()
+            .filter(|(_, decision)| decision.is_relevant())
+            .collect();
+        if !relevant.is_empty() {
+            let summary = relevant
+                .iter()
+                .map(|(lines, decision)| {
+                    format!(
+                        "Lines {}: {}",
+                        lines,
+                        decision.summary.as_deref().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let mut decision = Self::relevant(message, summary);
+            decision.confidence = relevant
+                .iter()
+                .filter_map(|(_, decision)| decision.confidence)
+                .reduce(f64::max);
+            decision.relevant_lines = LineRange::merge(relevant.iter().map(|(lines, _)| *lines));
+            return decision;
+        }
+
+        // Without a relevant chunk, a chunk that couldn't be parsed might
+        // have been the relevant one
+        if chunks
+            .iter()
+            .any(|(_, decision)| decision.status == RelevanceStatus::ParseError)
+        {
+            return Self::parse_error(message);
+        }
+
+        let mut decision = Self::not_relevant(message);
+        decision.confidence = chunks
+            .iter()
+            .filter_map(|(_, decision)| decision.confidence)
+            .reduce(f64::min);
+        decision
+    }
+}
+
+/// The relevance of a file as the model states it in its answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelevanceVerdict {
+    Relevant,
+    NotRelevant,
+}
+
+/// The structured block that ends a relevance answer, e.g.
+/// `{"status": "relevant", "summary": "...", "confidence": 0.8}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredRelevance {
+    pub status: RelevanceVerdict,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+impl StructuredRelevance {
+    /// Check the parts of the schema serde can't: relevant files need a
+    /// summary, and the confidence must be between 0 and 1
+    pub fn validate(&self) -> Result<(), String> {
+        let has_summary = self
+            .summary
+            .as_deref()
+            .is_some_and(|summary| !summary.trim().is_empty());
+        if self.status == RelevanceVerdict::Relevant && !has_summary {
+            return Err("\"summary\" is required when \"status\" is \"relevant\"".to_string());
+        }
+        if let Some(confidence) = self.confidence {
+            if !(0.0..=1.0).contains(&confidence) {
+                return Err(format!(
+                    "\"confidence\" must be between 0 and 1, not {}",
+                    confidence
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the structured block into a decision, keeping the full answer
+    pub fn into_decision(self, message: String) -> RelevanceDecision {
+        let mut decision = match self.status {
+            RelevanceVerdict::Relevant => RelevanceDecision::relevant(
+                message,
+                self.summary.unwrap_or_default().trim().to_string(),
+            ),
+            RelevanceVerdict::NotRelevant => RelevanceDecision::not_relevant(message),
+        };
+        decision.confidence = self.confidence;
+        decision
+    }
+}
+
+/// One file's decision in a batch answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredFileRelevance {
+    pub path: String,
+    pub status: RelevanceVerdict,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+/// The structured block that ends the answer for a batch of files, e.g.
+/// `{"decisions": [{"path": "setup.py", "status": "relevant", ...}]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredBatchRelevance {
+    pub decisions: Vec<StructuredFileRelevance>,
+}
+
+impl StructuredBatchRelevance {
+    /// Check that every file in the batch has exactly one valid decision,
+    /// and return the decisions in the order of `paths`
+    pub fn into_decisions(
+        self,
+        paths: &[String],
+    ) -> Result<Vec<(String, StructuredRelevance)>, String> {
+        let mut by_path = std::collections::HashMap::new();
+        for decision in self.decisions {
+            if !paths.contains(&decision.path) {
+                return Err(format!(
+                    "\"{}\" is not one of the files in the batch",
+                    decision.path
+                ));
+            }
+            let path = decision.path;
+            let relevance = StructuredRelevance {
+                status: decision.status,
+                summary: decision.summary,
+                confidence: decision.confidence,
+            };
+            relevance
+                .validate()
+                .map_err(|e| format!("the decision for \"{}\" is invalid: {}", path, e))?;
+            if by_path.insert(path.clone(), relevance).is_some() {
+                return Err(format!("\"{}\" has more than one decision", path));
+            }
+        }
+
+        let missing: Vec<&str> = paths
+            .iter()
+            .filter(|path| !by_path.contains_key(*path))
+            .map(|path| path.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("decisions are missing for {}", missing.join(", ")));
+        }
+
+        Ok(paths
+            .iter()
+            .map(|path| (path.clone(), by_path.remove(path).unwrap()))
+            .collect())
+    }
 }
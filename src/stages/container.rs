@@ -11,7 +11,10 @@ use std::thread;
 use std::time::Duration;
 
 use crate::config::ContainerConfig;
+use crate::llm::client::{ChatRequest, LLMResponse};
+use crate::llm::fallback::ModelChain;
 use crate::models::problem::SWEBenchProblem;
+use crate::utils::cassette::{cassette, command_line, run_command, CommandRecord};
 
 /// Container run result with exit code and success status
 #[derive(Debug, Clone)]
@@ -308,6 +311,20 @@ Your output must follow this exact format:
 Be thorough in your reasoning but make a definitive recommendation on what should be fixed.
 "#;
 
+/// The JSON block of a failure analysis with the decision in it
+fn decision_json(content: &str) -> Option<&str> {
+    let re = regex::Regex::new(r#"\{[\s\S]*"fix_dockerfile"[\s\S]*"fix_test_script"[\s\S]*\}"#).unwrap();
+    re.find(content).map(|mat| mat.as_str())
+}
+
+/// Check that a failure analysis has a decision, so that another model can be
+/// tried if it doesn't
+fn require_decision(response: &LLMResponse) -> Result<()> {
+    decision_json(&response.content)
+        .map(|_| ())
+        .ok_or_else(|| anyhow::anyhow!("No fix_dockerfile/fix_test_script decision in the answer"))
+}
+
 /// Analyze test failure logs using LLM to determine what to fix
 pub async fn analyze_test_failure_with_llm(
     config: &crate::config::Config,
@@ -335,39 +352,42 @@ Respond in the exact JSON format specified in the system instructions.
         logs_str
     );
     
-    // Create LLM config
-    let llm_config = config.to_llm_config(&None);
-    
-    // Create LLM client
-    let client = crate::llm::client::create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client for test failure analysis")?;
-        
-    // Combine system and user prompts
-    let combined_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        FAILURE_ANALYSIS_SYSTEM_PROMPT, user_prompt
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.container.model,
+        &config.container.models,
+        &config.container.llm,
     );
+    let models = ModelChain::create(&llm_configs, "container").await?;
+        
+    // Build the request with the system and user prompts
+    let request = ChatRequest::new(2000, 0.2)
+        .with_system(FAILURE_ANALYSIS_SYSTEM_PROMPT)
+        .with_user(user_prompt);
     
-    // Send the request to the LLM
-    let llm_response = client
-        .completion_with_tracing(
-            &combined_prompt,
-            2000, // Reasonable token limit for this task
-            0.2,  // Low temperature for more deterministic results
-            None,
-            Some(&format!("test_failure_analysis_{}", problem.id)),
-            None,
+    // Send the request to the LLM, moving to the next model if the answer
+    // has no decision in it
+    let generation_name = format!("test_failure_analysis_{}", problem.id);
+    let (request_ref, generation_name_ref) = (&request, &generation_name);
+    let answer = models
+        .run(
+            |client| async move {
+                client
+                    .chat_with_tracing(request_ref, None, Some(generation_name_ref), None)
+                    .await
+            },
+            require_decision,
         )
         .await
         .context("Failed to get test failure analysis from LLM")?;
+    let llm_response = &answer.value;
         
     // Extract the JSON response
     let response_content = llm_response.content.clone();
     
     // Save reasoning for reference
     let metadata = serde_json::json!({
-        "model": llm_config.model,
+        "model": answer.model(),
         "tokens": llm_response.usage.total_tokens
     });
     
@@ -399,9 +419,8 @@ Respond in the exact JSON format specified in the system instructions.
     .context("Failed to save test failure analysis to structured storage")?;
     
     // Extract the JSON portion using regex
-    let re = regex::Regex::new(r#"\{[\s\S]*"fix_dockerfile"[\s\S]*"fix_test_script"[\s\S]*\}"#).unwrap();
-    let json_str = match re.find(&response_content) {
-        Some(mat) => mat.as_str(),
+    let json_str = match decision_json(&response_content) {
+        Some(json_str) => json_str,
         None => {
             // Fallback to using the analyze_test_failure_fallback function
             warn!("Failed to extract JSON from LLM response, using fallback heuristic analysis");
@@ -543,16 +562,18 @@ async fn run_container(
     output_prefix: String,
 ) -> Result<ContainerResult> {
     // Check if container already exists and remove it if necessary
-    let check_output = Command::new("docker")
-        .args(["ps", "-a", "-q", "-f", &format!("name={}", container_name)])
-        .output()
-        .context("Failed to check if container exists")?;
+    let check_output = run_command(Command::new("docker").args([
+        "ps",
+        "-a",
+        "-q",
+        "-f",
+        &format!("name={}", container_name),
+    ]))
+    .context("Failed to check if container exists")?;
 
     if !check_output.stdout.is_empty() {
         info!("Container {} already exists, removing it", container_name);
-        Command::new("docker")
-            .args(["rm", "-f", container_name])
-            .output()
+        run_command(Command::new("docker").args(["rm", "-f", container_name]))
             .context("Failed to remove existing container")?;
     }
 
@@ -569,6 +590,23 @@ async fn run_container(
         .arg("-c")
         .arg(format!("if [ -f /usr/local/bin/setup-script.sh ]; then /usr/local/bin/setup-script.sh; fi && /usr/local/bin/{}", script));
 
+    // When replaying a cassette, show the recorded output instead of running the container
+    let cassette = cassette();
+    if let Some(cassette) = &cassette {
+        if let Some(record) = cassette.replay_command(&docker_cmd)? {
+            info!("Replaying container: {}", container_name);
+            for line in &record.logs {
+                println!("{} {}", output_prefix, line);
+            }
+            return Ok(ContainerResult {
+                name: container_name.to_string(),
+                exit_code: record.exit_code,
+                success: record.success(),
+                logs: record.logs,
+            });
+        }
+    }
+
     info!("Starting container: {}", container_name);
 
     // Start container
@@ -589,15 +627,18 @@ async fn run_container(
     let stdout_logs = Arc::clone(&logs);
 
     let stdout_handle = thread::spawn(move || {
+        let mut lines = Vec::new();
         for line in stdout_reader.lines() {
             if let Ok(line) = line {
                 println!("{} {}", stdout_prefix, line);
 
                 // Store log
                 let mut logs = stdout_logs.lock().unwrap();
-                logs.push(line);
+                logs.push(line.clone());
+                lines.push(line);
             }
         }
+        lines
     });
 
     // Stream stderr
@@ -607,15 +648,18 @@ async fn run_container(
     let stderr_logs = Arc::clone(&logs);
 
     let stderr_handle = thread::spawn(move || {
+        let mut lines = Vec::new();
         for line in stderr_reader.lines() {
             if let Ok(line) = line {
                 println!("{} {}", stderr_prefix, line);
 
                 // Store log
                 let mut logs = stderr_logs.lock().unwrap();
-                logs.push(line);
+                logs.push(line.clone());
+                lines.push(line);
             }
         }
+        lines
     });
 
     // Set up timeout cancellation channel
@@ -664,8 +708,8 @@ async fn run_container(
         .context("Failed to wait for docker container")?;
 
     // Wait for output threads to complete
-    stdout_handle.join().expect("Failed to join stdout thread");
-    stderr_handle.join().expect("Failed to join stderr thread");
+    let stdout_lines = stdout_handle.join().expect("Failed to join stdout thread");
+    let stderr_lines = stderr_handle.join().expect("Failed to join stderr thread");
 
     // Cancel timeout if it's still waiting by sending a message
     if let Some((handle, tx)) = timeout_handle {
@@ -678,9 +722,7 @@ async fn run_container(
 
     // Clean up container if needed
     if config.remove {
-        let _ = Command::new("docker")
-            .args(["rm", "-f", container_name])
-            .output();
+        let _ = run_command(Command::new("docker").args(["rm", "-f", container_name]));
     }
 
     // Get exit code
@@ -690,6 +732,16 @@ async fn run_container(
     // Get collected logs
     let logs = logs_clone.lock().unwrap().clone();
 
+    if let Some(cassette) = &cassette {
+        cassette.record_command(&CommandRecord {
+            command: command_line(&docker_cmd),
+            stdout: stdout_lines.join("\n"),
+            stderr: stderr_lines.join("\n"),
+            exit_code,
+            logs: logs.clone(),
+        })?;
+    }
+
     Ok(ContainerResult {
         name: container_name.to_string(),
         exit_code,
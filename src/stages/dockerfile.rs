@@ -2,18 +2,28 @@ use anyhow::{anyhow, Context, Result};
 use log::{info, warn};
 use regex::Regex;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 use crate::config::Config;
-use crate::llm::client::create_client;
+use crate::llm::client::{stream_to_terminal, ChatRequest, LLMResponse};
+use crate::llm::error::retry_with_fewer_files;
+use crate::llm::fallback::ModelChain;
 use crate::llm::prompts::{
     get_dockerfile_error_user_prompt, get_test_dockerfile_user_prompt,
     DOCKERFILE_ERROR_SYSTEM_PROMPT, TEST_DOCKERFILE_SYSTEM_PROMPT,
 };
+use crate::models::exclusion::ExclusionConfig;
 use crate::models::problem::SWEBenchProblem;
+use crate::utils::cassette::run_command;
+use crate::utils::context_packer::{context_budget, pack_files};
+use crate::utils::token_counter::tokenizer_for_model;
 use crate::utils::trajectory_store::TrajectoryStore;
 
+/// Ignore file for the Dockerfile copied into the build context, which
+/// BuildKit reads instead of `.dockerignore`
+const DOCKERIGNORE_FILE: &str = "Dockerfile.dockerignore";
+
 /// Generate a test-focused Dockerfile based on ranked files
 pub async fn generate_dockerfile(config: &Config, mut problem: SWEBenchProblem) -> Result<()> {
     info!("Starting test-focused Dockerfile generation");
@@ -49,14 +59,9 @@ pub async fn generate_dockerfile(config: &Config, mut problem: SWEBenchProblem)
         ));
     }
 
-    // Limit to top 5 files to reduce context size
-    let max_files = 5;
-    let ranked_files = ranked_files.into_iter().take(max_files).collect::<Vec<_>>();
-
     info!(
-        "Found {} ranked files, using top {} for Dockerfile generation",
-        ranked_files.len(),
-        max_files
+        "Found {} ranked files for Dockerfile generation",
+        ranked_files.len()
     );
 
     // Load file contents
@@ -73,38 +78,81 @@ pub async fn generate_dockerfile(config: &Config, mut problem: SWEBenchProblem)
         }
     }
 
-    // Create LLM config using the config's to_llm_config method
-    let llm_config = config.to_llm_config(&config.dockerfile.model);
-
-    // Create LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.dockerfile.model,
+        &config.dockerfile.models,
+        &config.dockerfile.llm,
+    );
+    let models = ModelChain::create(&llm_configs, "dockerfile").await?;
+
+    // Take the highest-ranked files that fit in the budget, leaving room in
+    // the context window for the rest of the prompt and the answer
+    let tokenizer = tokenizer_for_model(models.primary().model_name());
+    let prompt_tokens = tokenizer.count_tokens(TEST_DOCKERFILE_SYSTEM_PROMPT)
+        + tokenizer.count_tokens(&get_test_dockerfile_user_prompt(
+            &problem.problem_statement,
+            &ranked_files,
+            &[],
+        ));
+    let budget = context_budget(
+        &config.dockerfile.context,
+        models.context_window(),
+        prompt_tokens + config.dockerfile.max_tokens,
+    );
+    let packed = pack_files(
+        file_contents,
+        budget,
+        config.dockerfile.context.max_file_tokens,
+        tokenizer.as_ref(),
+    );
+    packed.log_summary("dockerfile");
+    let file_contents = packed.contents();
 
     info!("Generating Dockerfile from ranked files");
 
-    // Generate the user prompt for the LLM
-    let user_prompt =
-        get_test_dockerfile_user_prompt(&problem.problem_statement, &ranked_files, &file_contents);
-
-    // Combine with system prompt
-    let combined_dockerfile_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        TEST_DOCKERFILE_SYSTEM_PROMPT, user_prompt
-    );
-
-    // Send the request to the LLM
-    let llm_response = client
-        .completion_with_tracing(
-            &combined_dockerfile_prompt,
-            config.dockerfile.max_tokens,
-            config.dockerfile.temperature,
-            None,
-            Some(&format!("dockerfile_{}", problem.id)),
-            None,
+    // Send the request to the LLM, leaving out the contents of the lowest-ranked
+    // files if they don't fit in its context, and moving to the next model if
+    // no Dockerfile can be extracted from the answer
+    let (problem_ref, ranked_files_ref, file_contents_ref) =
+        (&problem, &ranked_files, &file_contents);
+    let answer = models
+        .run(
+            |client| {
+                retry_with_fewer_files(file_contents_ref.len(), move |count| {
+                    // Generate the user prompt for the LLM
+                    let user_prompt = get_test_dockerfile_user_prompt(
+                        &problem_ref.problem_statement,
+                        ranked_files_ref,
+                        &file_contents_ref[..count],
+                    );
+
+                    // Build the request with the system and user prompts
+                    let dockerfile_request = ChatRequest::new(
+                        config.dockerfile.max_tokens,
+                        config.dockerfile.temperature,
+                    )
+                    .with_system(TEST_DOCKERFILE_SYSTEM_PROMPT)
+                    .with_user(user_prompt);
+
+                    let generation_name = format!("dockerfile_{}", problem_ref.id);
+                    async move {
+                        stream_to_terminal(
+                            client,
+                            &dockerfile_request,
+                            None,
+                            Some(&generation_name),
+                            None,
+                        )
+                        .await
+                    }
+                })
+            },
+            require_dockerfile,
         )
         .await
         .context("Failed to get Dockerfile generation from LLM")?;
+    let llm_response = &answer.value;
 
     // Save full LLM response which contains reasoning
     let full_llm_response = llm_response.content.clone();
@@ -134,9 +182,10 @@ pub async fn generate_dockerfile(config: &Config, mut problem: SWEBenchProblem)
 
     // Also save to the structured reasoning storage
     let metadata = serde_json::json!({
-        "model": config.dockerfile.model,
+        "model": answer.model(),
         "tokens": llm_response.usage.total_tokens,
-        "temperature": config.dockerfile.temperature
+        "temperature": config.dockerfile.temperature,
+        "context": packed.report()
     });
 
     crate::stages::overview::save_reasoning(
@@ -261,6 +310,65 @@ fn cleanup_copied_files(docker_context_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// A Docker ignore file written to the build context, removed when dropped
+/// so that it doesn't outlive the build however the build ends
+struct DockerignoreFile {
+    path: PathBuf,
+}
+
+impl Drop for DockerignoreFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove Docker ignore file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Write the exclusion file's Docker profile to the build context as the
+/// Dockerfile's ignore file, after the project's own `.dockerignore` rules.
+/// Returns the file written, if it was.
+fn write_dockerignore(
+    docker_context_dir: &Path,
+    exclusion_config: &ExclusionConfig,
+) -> Result<Option<DockerignoreFile>> {
+    let lines = exclusion_config.dockerignore_lines();
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    let path = docker_context_dir.join(DOCKERIGNORE_FILE);
+    if path.exists() {
+        warn!(
+            "{:?} already exists, so the Docker profile of the exclusion file isn't applied",
+            path
+        );
+        return Ok(None);
+    }
+
+    // The project's .dockerignore is no longer read once this file exists
+    let mut content =
+        fs::read_to_string(docker_context_dir.join(".dockerignore")).unwrap_or_default();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str("# Docker profile of the exclusion file\n");
+    for line in &lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+
+    fs::write(&path, content).context(format!(
+        "Failed to write Docker ignore file to Docker context: {:?}",
+        path
+    ))?;
+    info!(
+        "Wrote {} Docker profile patterns to Docker context: {:?}",
+        lines.len(),
+        path
+    );
+    Ok(Some(DockerignoreFile { path }))
+}
+
 /// Build a Docker image using the generated Dockerfile
 pub async fn build_docker_image(
     config: &Config,
@@ -360,6 +468,10 @@ pub async fn build_docker_image(
             source_path, dest_path
         );
 
+        // Leave what the Docker profile excludes out of the build context.
+        // The file is removed when this attempt ends.
+        let dockerignore = write_dockerignore(docker_context_dir, &problem.exclusion_config)?;
+
         // Build the Docker image
         info!("Building Docker image with tag: {}", tag);
         println!("\nBuilding Docker image with tag: {}", tag);
@@ -370,23 +482,23 @@ pub async fn build_docker_image(
         docker_build_command.arg(tag);
         docker_build_command.arg(".");
         docker_build_command.current_dir(&docker_context_dir);
+        if dockerignore.is_some() {
+            // Only BuildKit reads the Dockerfile's own ignore file
+            docker_build_command.env("DOCKER_BUILDKIT", "1");
+        }
 
-        // For capturing stderr
+        // Show build progress, and capture stderr
+        docker_build_command.stdout(Stdio::inherit());
         docker_build_command.stderr(Stdio::piped());
 
         info!("Running docker build command: {:?}", docker_build_command);
         println!("\nRunning docker build...");
 
-        let build_process = docker_build_command
-            .spawn()
-            .context("Failed to spawn docker build process")?;
-
-        let build_output = build_process
-            .wait_with_output()
-            .context("Failed to wait for docker build process")?;
+        let build_output =
+            run_command(&mut docker_build_command).context("Failed to run docker build process")?;
 
         // Log stderr for debugging
-        let error_output = String::from_utf8_lossy(&build_output.stderr).into_owned();
+        let error_output = build_output.stderr.clone();
         if !error_output.is_empty() {
             warn!("Docker build stderr: {}", error_output);
         }
@@ -395,9 +507,10 @@ pub async fn build_docker_image(
         if let Err(e) = cleanup_copied_files(&docker_context_dir) {
             warn!("Failed to clean up copied files: {}", e);
         }
+        drop(dockerignore);
 
         // Check if the build was successful
-        if build_output.status.success() {
+        if build_output.success() {
             println!("\nDocker build completed successfully!");
             info!("Docker build completed successfully");
             info!("Image built with tag: {}", tag);
@@ -481,6 +594,14 @@ pub fn extract_dockerfile_from_response(response: &str) -> Option<String> {
     None
 }
 
+/// Check that a Dockerfile can be extracted from an LLM response
+fn require_dockerfile(response: &LLMResponse) -> Result<()> {
+    match extract_dockerfile_from_response(&response.content) {
+        Some(_) => Ok(()),
+        None => Err(anyhow::anyhow!("no Dockerfile found in the response")),
+    }
+}
+
 /// Update a Dockerfile based on error output from a failed build
 pub async fn update_dockerfile_from_error(
     config: &Config,
@@ -495,13 +616,13 @@ pub async fn update_dockerfile_from_error(
         dockerfile_path
     ))?;
 
-    // Create LLM config using the config's to_llm_config method
-    let llm_config = config.to_llm_config(&config.dockerfile.model);
-
-    // Create LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.dockerfile.model,
+        &config.dockerfile.models,
+        &config.dockerfile.llm,
+    );
+    let models = ModelChain::create(&llm_configs, "dockerfile").await?;
 
     // Generate the user prompt for the LLM
     let user_prompt = get_dockerfile_error_user_prompt(
@@ -510,24 +631,23 @@ pub async fn update_dockerfile_from_error(
         error_output,
     );
 
-    // Combine with system prompt
-    let combined_error_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        DOCKERFILE_ERROR_SYSTEM_PROMPT, user_prompt
-    );
-
-    // Send the request to the LLM
-    let llm_response = client
-        .completion_with_tracing(
-            &combined_error_prompt,
-            config.dockerfile.max_tokens,
-            config.dockerfile.temperature,
-            None,
-            Some(&format!("dockerfile_error_{}", problem.id)),
-            None,
+    // Build the request with the system and user prompts
+    let error_request =
+        ChatRequest::new(config.dockerfile.max_tokens, config.dockerfile.temperature)
+            .with_system(DOCKERFILE_ERROR_SYSTEM_PROMPT)
+            .with_user(user_prompt);
+
+    // Send the request to the LLM, moving to the next model if no Dockerfile
+    // can be extracted from the answer
+    let generation_name = format!("dockerfile_error_{}", problem.id);
+    let answer = models
+        .run(
+            |client| stream_to_terminal(client, &error_request, None, Some(&generation_name), None),
+            require_dockerfile,
         )
         .await
         .context("Failed to get Dockerfile fix from LLM")?;
+    let llm_response = &answer.value;
 
     // Extract the updated Dockerfile content
     let full_llm_response = llm_response.content.clone();
@@ -543,7 +663,7 @@ pub async fn update_dockerfile_from_error(
 
     // Add attempt number as identifier
     let metadata = serde_json::json!({
-        "model": config.dockerfile.model,
+        "model": answer.model(),
         "tokens": llm_response.usage.total_tokens,
         "temperature": config.dockerfile.temperature,
         "attempt": attempt
@@ -7,12 +7,17 @@ use std::fs;
 use std::path::Path;
 
 use crate::config::{CodebaseConfig, Config, RelevanceConfig};
-use crate::llm::client::create_client;
-use crate::llm::prompts::get_codebase_tree_user_prompt;
-use crate::models::exclusion::ExclusionConfig;
+use crate::llm::client::ChatRequest;
+use crate::llm::fallback::ModelChain;
+use crate::llm::prompts::{get_codebase_tree_user_prompt, CODEBASE_TREE_SYSTEM_PROMPT};
+use crate::models::exclusion::{ExclusionConfig, ScanProfile};
 use crate::models::file::FilePatternSelection;
+use crate::models::file_rules::FileRules;
 use crate::models::problem::SWEBenchProblem;
+use crate::models::relevance::RelevanceVerdict;
+use crate::utils::token_counter::tokenizer_for_model;
 use crate::utils::trajectory_store::TrajectoryStore;
+use crate::utils::tree_renderer::TreeBudget;
 
 /// Parse the LLM response to extract the file patterns
 pub fn parse_file_patterns(response: &str) -> Result<FilePatternSelection> {
@@ -97,13 +102,15 @@ pub async fn run_file_selection(
 ) -> Result<(FilePatternSelection, crate::llm::client::TokenUsage)> {
     debug!("Starting file selection process");
 
-    // Get the LLM config which uses the top-level model as fallback
-    let llm_config = config.to_llm_config(&relevance_config.model);
+    // Get the LLM configs, which use the top-level model as fallback
+    let llm_configs = config.to_stage_llm_configs(
+        &relevance_config.model,
+        &relevance_config.models,
+        &relevance_config.llm,
+    );
 
-    // Create the LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let models = ModelChain::create(&llm_configs, "file_selection").await?;
 
     // Load exclusion config from file
     debug!(
@@ -128,7 +135,7 @@ pub async fn run_file_selection(
     let mut configured_problem = problem
         .clone()
         .with_codebase_path(&codebase_config.path)
-        .with_exclusion_config(exclusion_config);
+        .with_exclusion_config(exclusion_config.for_profile(ScanProfile::Tree));
 
     configured_problem
         .initialize()
@@ -138,9 +145,30 @@ pub async fn run_file_selection(
     let all_files = configured_problem.all_file_paths();
     debug!("Found {} files in codebase", all_files.len());
 
-    // Generate a tree representation of the codebase
+    // Build and test files the rules mark relevant are included anyway, so
+    // tell the LLM it needn't select them
+    let file_rules = FileRules::from_config(&codebase_config.file_rules)?;
+    let is_included = |path: &str| {
+        file_rules
+            .classify(path)
+            .is_some_and(|rule| rule.status == RelevanceVerdict::Relevant)
+    };
+    let included_files: Vec<String> = all_files
+        .iter()
+        .filter(|path| is_included(path))
+        .cloned()
+        .collect();
+    debug!("{} files are included by file rules", included_files.len());
+
+    // Generate a tree representation of the codebase that fits the prompt,
+    // keeping the build files the rules know about visible
     debug!("Generating codebase tree structure");
-    let tree_output = configured_problem.generate_tree();
+    let tokenizer = tokenizer_for_model(models.primary().model_name());
+    let tree_output = configured_problem.generate_tree_within(&TreeBudget {
+        max_tokens: codebase_config.tree_max_tokens,
+        tokenizer: tokenizer.as_ref(),
+        is_build_file: &is_included,
+    });
 
     // Use the trajectory dir provided by the caller (via config from main)
 
@@ -161,9 +189,25 @@ pub async fn run_file_selection(
 
     debug!("Saved codebase tree to: {:?}", tree_path);
 
+    // Save the paths the scan left out, and why, so the scan can be checked
+    let skipped_path = Path::new(&trajectory_dir).join("skipped_paths.json");
+    let skipped_json = serde_json::to_string_pretty(configured_problem.skipped_paths())
+        .context("Failed to serialize skipped paths")?;
+    fs::write(&skipped_path, skipped_json).context(format!(
+        "Failed to write skipped paths to: {:?}",
+        skipped_path
+    ))?;
+
+    debug!(
+        "Saved {} skipped paths to: {:?}",
+        configured_problem.skipped_paths().len(),
+        skipped_path
+    );
+
     // Ask the LLM which files to process based on the tree
     debug!("Asking LLM to select files for processing");
-    let tree_prompt = get_codebase_tree_user_prompt(&configured_problem, &tree_output);
+    let tree_prompt =
+        get_codebase_tree_user_prompt(&configured_problem, &tree_output, &included_files);
 
     // Save the prompt to a file
     let prompt_path = Path::new(&trajectory_dir).join("codebase_tree_prompt.txt");
@@ -182,17 +226,40 @@ pub async fn run_file_selection(
         "files_count": all_files.len(),
     });
 
-    let llm_response = client
-        .completion_with_tracing(
-            &tree_prompt,
-            relevance_config.max_tokens,
-            0.0,  // Fixed temperature
-            None, // Auto-generate trace ID
-            Some(&format!("file_selection_{}", problem.id)),
-            Some(metadata),
+    let request = ChatRequest::new(relevance_config.max_tokens, 0.0) // Fixed temperature
+        .with_system(CODEBASE_TREE_SYSTEM_PROMPT)
+        .with_user(tree_prompt);
+
+    // Move to the next model if no file patterns can be parsed from the answer
+    let generation_name = format!("file_selection_{}", problem.id);
+    let answer = models
+        .run(
+            |client| {
+                let (request, metadata, generation_name) =
+                    (&request, metadata.clone(), &generation_name);
+                async move {
+                    client
+                        .chat_with_tracing(
+                            request,
+                            None, // Auto-generate trace ID
+                            Some(generation_name),
+                            Some(metadata),
+                        )
+                        .await
+                }
+            },
+            |response| parse_file_patterns(&response.content).map(|_| ()),
         )
         .await
         .context("Failed to get file selection from LLM")?;
+    debug!("File selection answered by {}", answer.model());
+
+    let cost = answer.client.calculate_cost(&answer.value.usage);
+    let llm_response = answer.value;
+
+    // Output cost information through logs (not stdout)
+    debug!("File selection LLM usage: {}", llm_response.usage);
+    debug!("File selection LLM cost: {}", cost);
 
     // Save the LLM response to a file
     let response_path = Path::new(trajectory_dir).join("codebase_tree_response.txt");
@@ -272,8 +339,8 @@ pub async fn process_file_selection(
     progress_bar.inc(1);
     progress_bar.set_message("Requesting file pattern selection from LLM");
 
-    // Run file selection and get token usage
-    let (file_patterns, token_usage) = run_file_selection(
+    // Run file selection
+    let (file_patterns, _) = run_file_selection(
         config,
         &config.relevance,
         codebase_config,
@@ -285,17 +352,6 @@ pub async fn process_file_selection(
     progress_bar.inc(1);
     progress_bar.set_message("Saving file patterns");
 
-    // Create the LLM client to access pricing information
-    let client = create_client(&config.to_llm_config(&config.relevance.model))
-        .await
-        .context("Failed to create LLM client")?;
-
-    let cost = client.calculate_cost(&token_usage);
-
-    // Output cost information through logs (not stdout)
-    debug!("File selection LLM usage: {}", token_usage);
-    debug!("File selection LLM cost: {}", cost);
-
     // Save the results
     save_file_patterns(trajectory_dir, &problem, &file_patterns)?;
 
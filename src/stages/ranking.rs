@@ -1,24 +1,33 @@
 use anyhow::{Context, Result};
+use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, warn};
 use std::collections::HashMap;
 
-use crate::config::{Config, RankingConfig};
-use crate::llm::client::{create_client, LLMClient};
-use crate::llm::prompts::get_ranking_user_prompt;
+use crate::config::{Config, RankAggregation, RankingConfig};
+use crate::llm::budget::is_budget_exceeded;
+use crate::llm::client::{ChatRequest, TokenCost, TokenUsage};
+use crate::llm::error::is_auth_error;
+use crate::llm::fallback::ModelChain;
+use crate::llm::prompts::{get_ranking_system_prompt, get_ranking_user_prompt};
 use crate::models::problem::SWEBenchProblem;
 use crate::models::ranking::{
-    FileRanking, ProblemContext, RankedCodebaseFile, RelevantFileDataForPrompt,
+    FileAgreement, FileRanking, ProblemContext, RankedCodebaseFile, RelevantFileDataForPrompt,
 };
 use crate::models::relevance::RelevanceStatus;
 use crate::utils::json_utils::extract_last_json;
-use crate::utils::token_counter::count_tokens;
+use crate::utils::token_counter::{tokenizer_for_model, Tokenizer};
 use crate::utils::trajectory_store::TrajectoryStore;
 
+/// Dampens the weight reciprocal rank fusion gives to the very top positions,
+/// using the value from the original paper
+const RECIPROCAL_RANK_K: f64 = 60.0;
+
 /// Get relevant files for a problem
 fn get_relevant_files(
     trajectory_store: &TrajectoryStore,
     problem: &mut SWEBenchProblem,
+    tokenizer: &dyn Tokenizer,
 ) -> Result<Vec<RelevantFileDataForPrompt>> {
     // Check for existence of relevance decisions file
     let relevance_path = trajectory_store.relevance_decisions_path();
@@ -38,7 +47,12 @@ fn get_relevant_files(
         ));
     }
 
-    let decisions = trajectory_store.load_relevance_decisions()?;
+    // Sort by path so the prompt lists the files in the same order every run
+    let mut decisions: Vec<_> = trajectory_store
+        .load_relevance_decisions()?
+        .into_iter()
+        .collect();
+    decisions.sort_by(|a, b| a.0.cmp(&b.0));
     if decisions.is_empty() {
         return Err(anyhow::anyhow!(
             "No relevance decisions found in {:?}. Run the relevance step first with 'cargo run --release -- relevance'",
@@ -64,7 +78,7 @@ fn get_relevant_files(
         // Get the file content to count tokens, skip if file doesn't exist
         match problem.get_file(&path) {
             Ok(file) => {
-                let token_count = count_tokens(&file.content);
+                let token_count = tokenizer.count_tokens(&file.content);
 
                 relevant_files.push(RelevantFileDataForPrompt {
                     path,
@@ -87,13 +101,97 @@ fn get_relevant_files(
     Ok(relevant_files)
 }
 
+/// Extract the ranked file paths from a ranking answer
+fn parse_ranking(content: &str) -> Option<Vec<String>> {
+    match extract_last_json(content) {
+        Ok(ranking) => {
+            info!("Successfully extracted ranking: {:?}", ranking);
+            return Some(ranking);
+        }
+        Err(e) => warn!("Failed to extract ranking: {}", e),
+    }
+
+    // Try a more direct approach - just look for file paths
+    let path_re = regex::Regex::new(r#"["']([^"']+\.[^"']+)["']"#).unwrap();
+    let matches: Vec<String> = path_re
+        .captures_iter(content)
+        .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+        .collect();
+    if !matches.is_empty() {
+        info!("Found file paths using regex: {:?}", matches);
+        return Some(matches);
+    }
+
+    // Still not working, try another approach - look for lines that start with file paths
+    let file_paths: Vec<String> = content
+        .lines()
+        .filter(|line| line.contains("/") && !line.starts_with("```") && !line.starts_with("- "))
+        .map(|line| line.trim().to_string())
+        .collect();
+    if !file_paths.is_empty() {
+        info!("Found file paths by line parsing: {:?}", file_paths);
+        return Some(file_paths);
+    }
+
+    None
+}
+
+/// Merge several rankings of the same files into one, most important file
+/// first, with how much the rankings agree on each file
+///
+/// Files the rankings score equally keep the order in which they first
+/// appear, so a single ranking is returned unchanged.
+pub fn aggregate_rankings(rankings: &[Vec<String>], method: RankAggregation) -> Vec<FileAgreement> {
+    // Positions of each file in each ranking, in order of first appearance
+    let mut files: Vec<FileAgreement> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (sample, ranking) in rankings.iter().enumerate() {
+        let mut position = 0;
+        for path in ranking {
+            let i = *index.entry(path.as_str()).or_insert_with(|| {
+                files.push(FileAgreement {
+                    path: path.clone(),
+                    score: 0.0,
+                    agreement: 0.0,
+                    positions: vec![None; rankings.len()],
+                });
+                files.len() - 1
+            });
+            // A path listed twice keeps its first position
+            if files[i].positions[sample].is_none() {
+                position += 1;
+                files[i].positions[sample] = Some(position);
+            }
+        }
+    }
+
+    // Borda counts give a file n points for first place, n - 1 for second
+    // and so on, where n is the number of files any ranking mentions
+    let candidates = files.len();
+    for file in &mut files {
+        let ranked: Vec<usize> = file.positions.iter().flatten().copied().collect();
+        file.agreement = ranked.len() as f64 / rankings.len() as f64;
+        file.score = ranked
+            .iter()
+            .map(|&position| match method {
+                RankAggregation::Borda => (candidates - position + 1) as f64,
+                RankAggregation::ReciprocalRank => 1.0 / (RECIPROCAL_RANK_K + position as f64),
+            })
+            .sum();
+    }
+
+    // A stable sort keeps equally scored files in order of first appearance
+    files.sort_by(|a, b| b.score.total_cmp(&a.score));
+    files
+}
+
 /// Rank files for a problem
 async fn rank_problem_files(
     problem: &mut SWEBenchProblem,
     config: &RankingConfig,
-    client: &dyn LLMClient,
+    models: &ModelChain,
     output_dir: &str,
-) -> Result<crate::llm::client::TokenUsage> {
+) -> Result<(TokenUsage, TokenCost)> {
     info!("Ranking files for problem: {}", problem.id);
 
     // Create a trajectory store for this problem
@@ -105,18 +203,22 @@ async fn rank_problem_files(
     // Check if ranking already exists
     if trajectory_store.ranking_exists() {
         info!("Ranking already exists for problem: {}", problem.id);
-        return Ok(crate::llm::client::TokenUsage::default());
+        return Ok(Default::default());
     }
 
     // Get relevant files
-    let relevant_files = get_relevant_files(&trajectory_store, problem).context(format!(
-        "Failed to get relevant files for problem: {}",
-        problem.id
-    ))?;
+    // Count tokens as the ranking model sees them, since the token budgets
+    // in the prompt are in its tokens
+    let tokenizer = tokenizer_for_model(models.primary().model_name());
+    let relevant_files = get_relevant_files(&trajectory_store, problem, tokenizer.as_ref())
+        .context(format!(
+            "Failed to get relevant files for problem: {}",
+            problem.id
+        ))?;
 
     if relevant_files.is_empty() {
         info!("No relevant files found for problem: {}", problem.id);
-        return Ok(crate::llm::client::TokenUsage::default());
+        return Ok(Default::default());
     }
 
     info!(
@@ -125,124 +227,127 @@ async fn rank_problem_files(
         problem.id
     );
 
-    // Generate prompt
-    let prompt = get_ranking_user_prompt(
-        &problem.problem_statement,
-        &relevant_files,
+    let num_rankings = config.num_rankings.max(1);
+    if num_rankings > 1 && config.temperature == 0.0 && !config.shuffle_files {
+        warn!(
+            "Asking for {} rankings with identical prompts at temperature 0, so they will likely all be the same. Set a temperature or shuffle_files to vary them.",
+            num_rankings
+        );
+    }
+
+    // The system prompt is the same for every ranking
+    let system_prompt = get_ranking_system_prompt(
         120_000, // max_tokens
         60_000,  // target_tokens
     );
 
-    // Set up progress bar for the ranking
-    let progress_bar = ProgressBar::new(1);
+    // Set up progress bar for the rankings
+    let progress_bar = ProgressBar::new(num_rankings as u64);
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
             .unwrap(),
     );
+    progress_bar.set_message("Running ranking");
+
+    // Clone problem_id for use in async blocks
+    let problem_id = problem.id.clone();
+
+    // Ask for the rankings concurrently, each moving to the next model if the
+    // ranking can't be extracted from its answer
+    let samples = (0..num_rankings).map(|sample| {
+        // Shuffle with a fixed seed per sample, so that re-runs send the same
+        // prompts and can be answered from the response cache
+        let mut files = relevant_files.clone();
+        if config.shuffle_files && sample > 0 {
+            fastrand::Rng::with_seed(sample as u64).shuffle(&mut files);
+        }
+        let request = ChatRequest::new(config.max_tokens, config.temperature)
+            .with_cached_system(system_prompt.clone())
+            .with_user(get_ranking_user_prompt(&problem.problem_statement, &files));
+
+        // Add tracing metadata
+        let metadata = serde_json::json!({
+            "problem_id": problem_id,
+            "stage": "ranking",
+            "temperature": config.temperature,
+            "sample": sample,
+        });
+        let generation_name = if sample == 0 {
+            format!("ranking_{}", problem_id)
+        } else {
+            format!("ranking_{}_{}", problem_id, sample)
+        };
+        let progress_bar = &progress_bar;
+
+        async move {
+            let result = models
+                .run(
+                    |client| {
+                        let (request, metadata, generation_name) =
+                            (&request, metadata.clone(), &generation_name);
+                        async move {
+                            client
+                                .chat_with_tracing(
+                                    request,
+                                    None, // Use auto-generated trace ID
+                                    Some(generation_name),
+                                    Some(metadata),
+                                )
+                                .await
+                        }
+                    },
+                    |response| {
+                        parse_ranking(&response.content)
+                            .map(|_| ())
+                            .ok_or_else(|| anyhow::anyhow!("no ranking could be extracted"))
+                    },
+                )
+                .await
+                .context("Failed to get ranking completion");
+            progress_bar.inc(1);
+            result
+        }
+    });
+    let results: Vec<_> = futures::stream::iter(samples)
+        .buffered(config.max_workers.max(1))
+        .collect()
+        .await;
 
-    // Prepare for single ranking
     let mut rankings = Vec::new();
     let mut prompt_caching_usages = Vec::new();
 
-    // Track total token usage
-    let mut total_usage = crate::llm::client::TokenUsage::default();
+    // Track total token usage and cost
+    let mut total_usage = TokenUsage::default();
+    let mut total_cost = TokenCost::default();
 
-    // Clone problem_id for use in async blocks
-    let problem_id = problem.id.clone();
+    for result in results {
+        match result {
+            Ok(answer) => {
+                info!("Ranking answered by {}", answer.model());
+                let llm_response = answer.value;
 
-    progress_bar.set_message("Running ranking");
+                // Add to the total token usage
+                total_usage += &llm_response.usage;
+                total_cost += &answer.client.calculate_cost(&llm_response.usage);
 
-    // Add tracing metadata
-    let metadata = serde_json::json!({
-        "problem_id": problem_id,
-        "stage": "ranking",
-        "temperature": config.temperature,
-    });
-
-    // Execute a single ranking request
-
-    let llm_result = client
-        .completion_with_tracing(
-            &prompt,
-            config.max_tokens,
-            config.temperature,
-            None, // Use auto-generated trace ID
-            Some(&format!("ranking_{}", problem_id)),
-            Some(metadata),
-        )
-        .await
-        .context("Failed to get ranking completion");
-
-    progress_bar.inc(1);
-
-    match llm_result {
-        Ok(llm_response) => {
-            // Add to the total token usage
-            total_usage.prompt_tokens += llm_response.usage.prompt_tokens;
-            total_usage.completion_tokens += llm_response.usage.completion_tokens;
-            total_usage.total_tokens += llm_response.usage.total_tokens;
-
-            // Extract the ranking
-            warn!("Got response: {}", llm_response.content);
-            match extract_last_json(&llm_response.content) {
-                Ok(ranking) => {
-                    info!("Successfully extracted ranking: {:?}", ranking);
-                    rankings.push(FileRanking {
-                        message: llm_response.content.clone(),
-                        ranking,
-                    });
-                    // Add the usage for prompt caching
-                    let usage_map = HashMap::new();
-                    prompt_caching_usages.push(usage_map);
-                }
-                Err(e) => {
-                    warn!("Failed to extract ranking: {}", e);
-
-                    // Try a more direct approach - just look for file paths
-                    let path_re = regex::Regex::new(r#"["']([^"']+\.[^"']+)["']"#).unwrap();
-                    let matches: Vec<String> = path_re
-                        .captures_iter(&llm_response.content)
-                        .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
-                        .collect();
-
-                    if !matches.is_empty() {
-                        info!("Found file paths using regex: {:?}", matches);
+                // Record the prompt caching usage alongside each ranking
+                match parse_ranking(&llm_response.content) {
+                    Some(ranking) => {
                         rankings.push(FileRanking {
                             message: llm_response.content.clone(),
-                            ranking: matches,
+                            ranking,
                         });
-                        // Add the usage for prompt caching
-                        let usage_map = HashMap::new();
-                        prompt_caching_usages.push(usage_map);
-                    } else {
-                        // Still not working, try another approach - look for lines that start with file paths
-                        let lines = llm_response.content.lines();
-                        let file_paths: Vec<String> = lines
-                            .filter(|line| {
-                                line.contains("/")
-                                    && !line.starts_with("```")
-                                    && !line.starts_with("- ")
-                            })
-                            .map(|line| line.trim().to_string())
-                            .collect();
-
-                        if !file_paths.is_empty() {
-                            info!("Found file paths by line parsing: {:?}", file_paths);
-                            rankings.push(FileRanking {
-                                message: llm_response.content.clone(),
-                                ranking: file_paths,
-                            });
-                            // Add the usage for prompt caching
-                            let usage_map = HashMap::new();
-                            prompt_caching_usages.push(usage_map);
-                        }
+                        prompt_caching_usages.push(llm_response.usage.to_usage_map());
                     }
+                    None => warn!("No ranking found in response: {}", llm_response.content),
                 }
             }
-        }
-        Err(e) => {
-            warn!("Failed to get ranking: {}", e);
+            // Every later stage would fail the same way, so stop the pipeline here
+            Err(e) if is_auth_error(&e) || is_budget_exceeded(&e) => return Err(e),
+            Err(e) => {
+                warn!("Failed to get ranking: {}", e);
+            }
         }
     }
 
@@ -259,25 +364,37 @@ async fn rank_problem_files(
         prompt_caching_usages.push(HashMap::new());
     }
 
-    // Get the single ranking result
-    let final_ranking = if !rankings.is_empty() {
-        rankings[0].ranking.clone()
-    } else {
-        Vec::new()
-    };
-
-    // Convert to RankedCodebaseFile objects
+    // Merge the rankings, leaving out files that aren't relevant, such as
+    // paths the model made up
     let path_to_token_count: HashMap<String, usize> = relevant_files
         .iter()
         .map(|file| (file.path.clone(), file.token_count))
         .collect();
+    let known_rankings: Vec<Vec<String>> = rankings
+        .iter()
+        .map(|ranking| {
+            ranking
+                .ranking
+                .iter()
+                .filter(|path| path_to_token_count.contains_key(*path))
+                .cloned()
+                .collect()
+        })
+        .collect();
+    let agreement = aggregate_rankings(&known_rankings, config.aggregation);
+    if rankings.len() > 1 {
+        info!(
+            "Merged {} rankings of {} files",
+            rankings.len(),
+            agreement.len()
+        );
+    }
 
-    let ranked_files: Vec<RankedCodebaseFile> = final_ranking
-        .into_iter()
-        .filter_map(|path| {
-            path_to_token_count
-                .get(&path)
-                .map(|&tokens| RankedCodebaseFile { tokens, path })
+    let ranked_files: Vec<RankedCodebaseFile> = agreement
+        .iter()
+        .map(|file| RankedCodebaseFile {
+            path: file.path.clone(),
+            tokens: path_to_token_count[&file.path],
         })
         .collect();
 
@@ -285,6 +402,7 @@ async fn rank_problem_files(
     let context = ProblemContext {
         model_rankings: rankings,
         ranked_files,
+        agreement,
         prompt_caching_usages: prompt_caching_usages.into_iter().collect(),
     };
 
@@ -294,7 +412,7 @@ async fn rank_problem_files(
     ))?;
 
     info!("Ranking completed for problem: {}", problem_id);
-    Ok(total_usage)
+    Ok((total_usage, total_cost))
 }
 
 /// Process rankings for all problems
@@ -331,21 +449,20 @@ pub async fn process_rankings(config: &Config, mut problem: SWEBenchProblem) ->
         ));
     }
 
-    // Create LLM config using the config's to_llm_config method
-    let llm_config = config.to_llm_config(&config.ranking.model);
-
-    // Create the LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.ranking.model,
+        &config.ranking.models,
+        &config.ranking.llm,
+    );
+    let models = ModelChain::create(&llm_configs, "ranking").await?;
 
     info!("Processing problem: {}", problem.id);
 
     let output_dir = config.get_trajectory_dir(&problem.id);
-    match rank_problem_files(&mut problem, &config.ranking, &*client, &output_dir).await {
-        Ok(token_usage) => {
-            // Calculate and display cost
-            let cost = client.calculate_cost(&token_usage);
+    match rank_problem_files(&mut problem, &config.ranking, &models, &output_dir).await {
+        Ok((token_usage, cost)) => {
+            // Display usage and cost
             info!("Ranking LLM usage: {}", token_usage);
             info!("Ranking LLM cost: {}", cost);
 
@@ -1,213 +1,567 @@
 use anyhow::{Context, Result};
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, info, warn};
 use regex::Regex;
 use std::fs;
+use std::sync::{Mutex, OnceLock};
 
 use crate::config::{CodebaseConfig, Config};
-use crate::llm::client::{create_client, LLMClient};
-use crate::llm::prompts::get_relevance_user_prompt;
-use crate::models::exclusion::ExclusionConfig;
+use crate::llm::budget::{check_budget, is_budget_exceeded};
+use crate::llm::client::{ChatRequest, Message, TokenCost, TokenUsage};
+use crate::llm::error::{is_auth_error, is_context_too_long, LLMError};
+use crate::llm::fallback::ModelChain;
+use crate::llm::prompts::{
+    get_relevance_batch_prompt, get_relevance_batch_reprompt, get_relevance_chunk_prompt,
+    get_relevance_file_prompt, get_relevance_issue_prompt, get_relevance_reprompt,
+    RELEVANCE_SYSTEM_PROMPT,
+};
+use crate::models::exclusion::{ExclusionConfig, ScanProfile};
 use crate::models::file::FilePatternSelection;
+use crate::models::file_rules::FileRules;
 use crate::models::problem::SWEBenchProblem;
-use crate::models::relevance::{RelevanceDecision, RelevanceStatus};
-use crate::utils::token_counter::count_tokens;
+use crate::models::relevance::{
+    RelevanceDecision, RelevanceVerdict, StructuredBatchRelevance, StructuredRelevance,
+};
+use crate::utils::chunking::split_into_chunks;
+use crate::utils::token_counter::{tokenizer_for_model, Tokenizer};
 use crate::utils::trajectory_store::TrajectoryStore;
 
-/// Parse the LLM response to extract the relevance decision
-fn parse_response(response: &str) -> RelevanceDecision {
-    // More flexible patterns that match what the model is actually outputting
-
-    // Check for "Not Relevant" in various formats
-    let not_relevant_patterns = [
-        r"RELEVANCE:\s*Not\s+Relevant",
-        r"Relevance\s*(?:of\s*(?:File|the\s*file))?\s*(?:to\s*(?:Issue|the\s*issue))?:\s*Not\s+Relevant",
-        r"Not\s+[Rr]elevant",
-        r"(?:file|relevance)(?:\s+is|\s*:)\s*Not\s+Relevant",
-        r"(?:^|[:\n])\s*The file is not relevant",
-        r"Relevance(?:\s+Decision)?:\s*Not Relevant",
-        r"File relevance:\s*Not relevant",
-        r"Final decision on the relevance(?:\s+of the file)?(?:\s+to the issue)?:\s*Not\s+Relevant",
-    ];
-
-    // Check response line by line for patterns
-    let response_lower = response.to_lowercase();
-
-    // First check for not relevant
-    for pattern in not_relevant_patterns {
-        if Regex::new(pattern).unwrap().is_match(response) {
-            return RelevanceDecision {
-                message: response.to_string(),
-                status: RelevanceStatus::NotRelevant,
-                summary: None,
-            };
-        }
-    }
+/// Most tokens a model may use to restate a decision that couldn't be parsed
+const REPROMPT_MAX_TOKENS: usize = 1024;
 
-    // If response contains "not relevant" in various common forms
-    if response_lower.contains("not relevant")
-        || response_lower.contains("file is not relevant")
-        || response_lower.contains("is not relevant to the issue")
-        || (response_lower.contains("relevance") && response_lower.contains("not relevant"))
+fn json_code_block_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"```(?:json)?\s*(\{[\s\S]*?\})\s*```").expect("code block pattern is valid")
+    })
+}
+
+/// Find the JSON object a relevance answer ends with, preferring the last
+/// ```json code block
+fn find_decision_json(response: &str) -> Option<&str> {
+    if let Some(json) = json_code_block_regex()
+        .captures_iter(response)
+        .last()
+        .and_then(|captures| captures.get(1))
     {
-        return RelevanceDecision {
-            message: response.to_string(),
-            status: RelevanceStatus::NotRelevant,
-            summary: None,
-        };
+        return Some(json.as_str());
     }
 
-    // Check for "Relevant" with a summary in various formats
-    let relevant_patterns = [
-        r"RELEVANCE:\s*(?:Yes|Relevant)\s*\nSUMMARY:(.*?)(?:\n|$)",
-        r"Relevance\s*(?:of\s*(?:File|the\s*file))?\s*(?:to\s*(?:Issue|the\s*issue))?:\s*(?:Yes|Relevant)",
-        r"(?:file|relevance)(?:\s+is|\s*:)\s*(?:Yes|Relevant)",
-        r"Relevance (?:Decision|Reasoning):\s*(?:Yes|Relevant|Include)",
-        r"Final Decision on Relevance:\s*(?:Yes|Relevant|Include)",
-        r"File relevance:\s*(?:Yes|Relevant|Include)",
-    ];
-
-    // First check strict patterns with summary capture
-    for pattern in relevant_patterns {
-        if let Some(captures) = Regex::new(pattern).unwrap().captures(response) {
-            if let Some(summary) = captures.get(1) {
-                return RelevanceDecision {
-                    message: response.to_string(),
-                    status: RelevanceStatus::Relevant,
-                    summary: Some(summary.as_str().trim().to_string()),
-                };
+    // Otherwise take the last whole object, e.g. when the answer is only
+    // JSON. Objects nested in it, and braces in its strings, are skipped over
+    // with it.
+    let mut last = None;
+    let mut from = 0;
+    while let Some(offset) = response[from..].find('{') {
+        let start = from + offset;
+        let mut objects =
+            serde_json::Deserializer::from_str(&response[start..]).into_iter::<serde_json::Value>();
+        match objects.next() {
+            Some(Ok(serde_json::Value::Object(_))) => {
+                let end = start + objects.byte_offset();
+                last = Some(&response[start..end]);
+                from = end;
             }
+            _ => from = start + 1,
         }
     }
+    last
+}
 
-    // Check if file is marked as relevant
-    if response_lower.contains("yes") && response_lower.contains("relevance")
-        || response_lower.contains("is relevant")
-        || response_lower.contains("highly relevant")
-        || response_lower.contains("partially relevant")
-        || (response_lower.contains("relevant") && !response_lower.contains("not relevant"))
-    {
-        // Try to find a summary paragraph
-        let mut summary = "Summary not explicitly provided, but file was marked as relevant";
-
-        // Look for common summary indicators
-        let summary_indicators = [
-            r"Summary:\s*(.*?)(?:\n\n|\n[A-Z]|$)",
-            r"Summary of the File:\s*(.*?)(?:\n\n|\n[A-Z]|$)",
-            r"Important Functions.*?(?:\n\n|\n[A-Z]|$)(.*?)(?:\n\n|\n[A-Z]|$)",
-            r"Functions in the File.*?(?:\n\n|\n[A-Z]|$)(.*?)(?:\n\n|\n[A-Z]|$)",
-        ];
-
-        for pattern in summary_indicators {
-            if let Some(captures) = Regex::new(pattern).unwrap().captures(response) {
-                if let Some(matched_summary) = captures.get(1) {
-                    summary = matched_summary.as_str().trim();
-                    break;
-                }
-            }
-        }
+/// Parse the structured decision at the end of a relevance answer, saying
+/// what is wrong with it if it can't be used
+fn parse_response(response: &str) -> Result<StructuredRelevance, String> {
+    let json = find_decision_json(response).ok_or("no JSON decision was found")?;
+    let decision: StructuredRelevance = serde_json::from_str(json)
+        .map_err(|e| format!("the JSON decision doesn't match the format: {}", e))?;
+    decision.validate()?;
+    Ok(decision)
+}
 
-        return RelevanceDecision {
-            message: response.to_string(),
-            status: RelevanceStatus::Relevant,
-            summary: Some(summary.to_string()),
-        };
-    }
+/// Parse the decisions at the end of the answer for a batch of files, in the
+/// order of `paths`, saying what is wrong with them if they can't be used
+fn parse_batch_response(
+    response: &str,
+    paths: &[String],
+) -> Result<Vec<(String, StructuredRelevance)>, String> {
+    let json = find_decision_json(response).ok_or("no JSON decisions were found")?;
+    let batch: StructuredBatchRelevance = serde_json::from_str(json)
+        .map_err(|e| format!("the JSON decisions don't match the format: {}", e))?;
+    batch.into_decisions(paths)
+}
 
-    // Additional check for "Output:" header followed by a definitive relevance
-    if let Some(output_start) = response.find("Output:") {
-        let output_part = &response[output_start..];
+/// Check if a file should be included in the relevance assessment
+fn should_process_file(file_path: &str, file_patterns: &FilePatternSelection) -> bool {
+    file_patterns.matches(file_path)
+}
 
-        if output_part.contains("Not Relevant")
-            || output_part.to_lowercase().contains("not relevant")
-        {
-            return RelevanceDecision {
-                message: response.to_string(),
-                status: RelevanceStatus::NotRelevant,
-                summary: None,
-            };
-        } else if output_part.contains("Relevant") && !output_part.contains("Not Relevant") {
-            return RelevanceDecision {
-                message: response.to_string(),
-                status: RelevanceStatus::Relevant,
-                summary: Some("Summary extracted from Output section".to_string()),
-            };
-        }
+/// What the requests for the relevance of a file, or a batch of files, share
+struct Assessment<'a> {
+    models: &'a ModelChain,
+    config: &'a Config,
+    /// The file or files being assessed, for logs
+    subject: &'a str,
+    trace_id: Option<&'a str>,
+    /// Usage and cost of every request made
+    spent: Mutex<(TokenUsage, TokenCost)>,
+}
+
+impl Assessment<'_> {
+    /// Ask the models for a relevance decision, re-prompting a model whose
+    /// decision can't be parsed and falling back to the next model if it
+    /// still can't
+    async fn request_decision(
+        &self,
+        request: &ChatRequest,
+        token_count: usize,
+        generation_name: &str,
+        metadata: serde_json::Value,
+    ) -> Result<RelevanceDecision> {
+        let (message, parsed) = self
+            .request_parsed(
+                request,
+                token_count,
+                generation_name,
+                metadata,
+                parse_response,
+                get_relevance_reprompt,
+            )
+            .await?;
+        Ok(match parsed {
+            Ok(decision) => decision.into_decision(message),
+            Err(_) => RelevanceDecision::parse_error(message),
+        })
     }
 
-    // If we couldn't parse properly, return a parse error
-    RelevanceDecision {
-        message: response.to_string(),
-        status: RelevanceStatus::ParseError,
-        summary: None,
+    /// Ask the models for an answer that `parse` accepts, re-prompting with
+    /// `reprompt` once when it doesn't. Returns the answer and what parsing
+    /// it gave; the answer of the last model is returned even if it couldn't
+    /// be parsed.
+    async fn request_parsed<T, P>(
+        &self,
+        request: &ChatRequest,
+        token_count: usize,
+        generation_name: &str,
+        metadata: serde_json::Value,
+        parse: P,
+        reprompt: fn(&str) -> String,
+    ) -> Result<(String, Result<T, String>)>
+    where
+        P: Fn(&str) -> Result<T, String> + Sync,
+    {
+        let (config, subject, trace_id, spent) =
+            (self.config, self.subject, self.trace_id, &self.spent);
+        let parse = &parse;
+        let answer = self
+            .models
+            .run(
+                |client| {
+                    let metadata = metadata.clone();
+                    async move {
+                        // Respect the model's context window when the provider reports one
+                        if let Some(context_window) = client.context_window() {
+                            if token_count + config.relevance.max_tokens > context_window {
+                                return Err(LLMError::ContextTooLong {
+                                    provider: client.name().to_string(),
+                                    message: format!(
+                                        "file needs {} tokens, but the context window is {}",
+                                        token_count, context_window
+                                    ),
+                                }
+                                .into());
+                            }
+                        }
+
+                        let record_spending = |usage: &TokenUsage| {
+                            let mut spent = spent.lock().unwrap();
+                            spent.0 += usage;
+                            spent.1 += &client.calculate_cost(usage);
+                        };
+
+                        // Send the request to the LLM with tracing
+                        let response = client
+                            .chat_with_tracing(
+                                request,
+                                trace_id,
+                                Some(generation_name),
+                                Some(metadata.clone()),
+                            )
+                            .await?;
+                        record_spending(&response.usage);
+
+                        let error = match parse(&response.content) {
+                            Ok(parsed) => return Ok((response.content, Ok(parsed))),
+                            Err(error) => error,
+                        };
+
+                        // Ask for just the corrected decision, which costs far less
+                        // than assessing the file again
+                        debug!(
+                            "Re-prompting {} for the relevance of {}: {}",
+                            client.model_name(),
+                            subject,
+                            error
+                        );
+                        let mut reprompt = request
+                            .clone()
+                            .with_message(Message::assistant(response.content.clone()))
+                            .with_user(reprompt(&error));
+                        reprompt.max_tokens = REPROMPT_MAX_TOKENS.min(request.max_tokens);
+                        let mut metadata = metadata;
+                        metadata["reprompt"] = true.into();
+                        let correction = client
+                            .chat_with_tracing(
+                                &reprompt,
+                                trace_id,
+                                Some(&format!("{}_reprompt", generation_name)),
+                                Some(metadata),
+                            )
+                            .await?;
+                        record_spending(&correction.usage);
+
+                        let message = format!("{}\n\n{}", response.content, correction.content);
+                        Ok(match parse(&correction.content) {
+                            Ok(parsed) => (message, Ok(parsed)),
+                            Err(error) => {
+                                warn!(
+                                    "Relevance decision for {} from {} could not be parsed after re-prompting: {}",
+                                    subject,
+                                    client.model_name(),
+                                    error
+                                );
+                                (message, Err(error))
+                            }
+                        })
+                    }
+                },
+                |(_, parsed)| match parsed {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(anyhow::anyhow!(
+                        "relevance decision could not be parsed: {}",
+                        e
+                    )),
+                },
+            )
+            .await?;
+        debug!("Relevance of {} assessed by {}", subject, answer.model());
+        Ok(answer.value)
     }
 }
 
-/// Check if a file should be included in the relevance assessment
-fn should_process_file(file_path: &str, file_patterns: &FilePatternSelection) -> bool {
-    file_patterns.matches(file_path)
+/// A relevance request for one file, or one chunk of a file
+fn relevance_request(
+    problem: &SWEBenchProblem,
+    file_prompt: String,
+    config: &Config,
+) -> ChatRequest {
+    // The system prompt and issue are the same for every file, so mark them
+    // for caching and only pay full price for the file contents
+    ChatRequest::new(config.relevance.max_tokens, 0.0)
+        .with_cached_system(RELEVANCE_SYSTEM_PROMPT)
+        .with_message(Message::user_with_cached_prefix(
+            get_relevance_issue_prompt(problem),
+            file_prompt,
+        ))
 }
 
-/// Assess the relevance of a file to a problem
+/// Assess the relevance of a file to a problem, returning the usage and cost
+/// of the models asked
+///
+/// Files longer than `max_file_tokens` are split into overlapping chunks that
+/// are assessed one at a time, and their decisions merged. Files that can't
+/// be assessed get a skipped decision saying why.
 async fn assess_file_relevance(
     problem: &SWEBenchProblem,
     file_path: &str,
     file_content: &str,
-    client: &dyn LLMClient,
+    models: &ModelChain,
     config: &Config,
     trajectory_store: &TrajectoryStore,
     trace_id: Option<&str>,
-) -> Result<crate::llm::client::TokenUsage> {
+) -> Result<(TokenUsage, TokenCost)> {
     // Check if we already have a relevance decision for this file
-    if trajectory_store.relevance_decision_exists(file_path) {
+    if !needs_assessment(trajectory_store, file_path) {
         debug!("Skipping already assessed file: {}", file_path);
-        return Ok(crate::llm::client::TokenUsage::default());
+        return Ok(Default::default());
     }
 
-    // Check if the file is too large
-    let token_count = count_tokens(file_content);
-    if token_count > config.relevance.max_file_tokens {
-        warn!("File too large ({}): {}", token_count, file_path);
-        return Ok(crate::llm::client::TokenUsage::default());
+    let tokenizer = tokenizer_for_model(models.primary().model_name());
+    let token_count = tokenizer.count_tokens(file_content);
+    let generation_name = format!("relevance_{}", file_path.replace("/", "_"));
+    let assessment = Assessment {
+        models,
+        config,
+        subject: file_path,
+        trace_id,
+        spent: Mutex::new(Default::default()),
+    };
+
+    let decision = if token_count <= config.relevance.max_file_tokens {
+        let request = relevance_request(
+            problem,
+            get_relevance_file_prompt(file_path, file_content),
+            config,
+        );
+
+        // Add tracing metadata
+        let metadata = serde_json::json!({
+            "problem_id": problem.id,
+            "file_path": file_path,
+            "stage": "relevance",
+            "token_count": token_count,
+        });
+
+        assessment
+            .request_decision(&request, token_count, &generation_name, metadata)
+            .await
+    } else {
+        let chunks = split_into_chunks(
+            file_content,
+            config.relevance.max_file_tokens,
+            config.relevance.chunk_overlap_lines,
+            tokenizer.as_ref(),
+        );
+        if chunks.len() > config.relevance.max_chunks {
+            warn!(
+                "File too large ({} tokens, {} chunks): {}",
+                token_count,
+                chunks.len(),
+                file_path
+            );
+            Ok(RelevanceDecision::skipped(format!(
+                "file has {} tokens, which would take {} chunks, more than the limit of {}",
+                token_count,
+                chunks.len(),
+                config.relevance.max_chunks
+            )))
+        } else {
+            info!(
+                "Assessing {} ({} tokens) in {} chunks",
+                file_path,
+                token_count,
+                chunks.len()
+            );
+            let total_lines = chunks.last().map_or(0, |chunk| chunk.lines.end);
+
+            let mut decisions = Vec::new();
+            let mut error = None;
+            for chunk in chunks {
+                let request = relevance_request(
+                    problem,
+                    get_relevance_chunk_prompt(file_path, &chunk.content, chunk.lines, total_lines),
+                    config,
+                );
+                let metadata = serde_json::json!({
+                    "problem_id": problem.id,
+                    "file_path": file_path,
+                    "stage": "relevance",
+                    "token_count": chunk.tokens,
+                    "lines": [chunk.lines.start, chunk.lines.end],
+                });
+                let chunk_name = format!(
+                    "{}_lines_{}_{}",
+                    generation_name, chunk.lines.start, chunk.lines.end
+                );
+
+                match assessment
+                    .request_decision(&request, chunk.tokens, &chunk_name, metadata)
+                    .await
+                {
+                    Ok(decision) => decisions.push((chunk.lines, decision)),
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            match error {
+                Some(e) => Err(e),
+                None => Ok(RelevanceDecision::merge_chunks(decisions)),
+            }
+        }
+    };
+    let spent = assessment.spent.into_inner().unwrap();
+
+    // The model may count tokens differently from us, so a file can still overflow its context
+    let decision = match decision {
+        Err(e) if is_context_too_long(&e) => {
+            warn!("File too large for model context window: {}", file_path);
+            RelevanceDecision::skipped(format!("file doesn't fit in the model's context: {:#}", e))
+        }
+        result => result.context(format!("Failed to get completion for file: {}", file_path))?,
+    };
+
+    // Save the decision
+    trajectory_store
+        .save_per_file_relevance_decision(file_path, decision)
+        .context(format!(
+            "Failed to save relevance decision for file: {}",
+            file_path
+        ))?;
+
+    Ok(spent)
+}
+
+/// Whether a file still needs a decision. Skipped files are tried again, as
+/// the limits that skipped them may have changed.
+fn needs_assessment(trajectory_store: &TrajectoryStore, file_path: &str) -> bool {
+    trajectory_store
+        .load_relevance_decisions()
+        .unwrap_or_default()
+        .get(file_path)
+        .is_none_or(|decision| decision.is_skipped())
+}
+
+/// Files to assess in one request: a single file, or a batch of small ones
+enum Work {
+    File(String, String),
+    Batch(Vec<(String, String)>),
+}
+
+/// Group the small files still needing a decision into batches, in path
+/// order, leaving every other file to be assessed on its own
+fn plan_batches(
+    files: Vec<(String, String)>,
+    config: &Config,
+    tokenizer: &dyn Tokenizer,
+    trajectory_store: &TrajectoryStore,
+) -> Vec<Work> {
+    let relevance = &config.relevance;
+    let mut work = Vec::new();
+    let mut batches: Vec<Vec<(String, String)>> = Vec::new();
+    let mut batch_tokens = 0;
+
+    for (path, content) in files {
+        let tokens = tokenizer.count_tokens(&content);
+        if content.is_empty()
+            || tokens > relevance.small_file_tokens
+            || !needs_assessment(trajectory_store, &path)
+        {
+            work.push(Work::File(path, content));
+            continue;
+        }
+
+        let full = batches.last().is_none_or(|batch| {
+            batch.len() >= relevance.batch_max_files
+                || batch_tokens + tokens > relevance.batch_max_tokens
+        });
+        if full {
+            batches.push(Vec::new());
+            batch_tokens = 0;
+        }
+        batches.last_mut().unwrap().push((path, content));
+        batch_tokens += tokens;
     }
 
-    // Generate the prompt
-    let prompt = get_relevance_user_prompt(problem, file_path, file_content);
+    let batched: usize = batches.iter().map(Vec::len).sum();
+    if batched > 0 {
+        info!(
+            "Batching {} small files into {} requests",
+            batched,
+            batches.len()
+        );
+    }
+    for mut batch in batches {
+        if batch.len() == 1 {
+            let (path, content) = batch.pop().unwrap();
+            work.push(Work::File(path, content));
+        } else {
+            work.push(Work::Batch(batch));
+        }
+    }
+    work
+}
+
+/// Assess the relevance of several small files in one request, saving a
+/// decision for each, and return the usage and cost of the models asked
+///
+/// If the decisions can't be parsed, or the batch doesn't fit in the model's
+/// context, the files are assessed one at a time instead.
+async fn assess_batch_relevance(
+    problem: &SWEBenchProblem,
+    files: &[(String, String)],
+    models: &ModelChain,
+    config: &Config,
+    trajectory_store: &TrajectoryStore,
+    trace_id: Option<&str>,
+) -> Result<(TokenUsage, TokenCost)> {
+    let paths: Vec<String> = files.iter().map(|(path, _)| path.clone()).collect();
+    let subject = format!("{} files ({})", paths.len(), paths.join(", "));
+
+    let file_prompt = get_relevance_batch_prompt(files);
+    let token_count = tokenizer_for_model(models.primary().model_name()).count_tokens(&file_prompt);
+    let request = relevance_request(problem, file_prompt, config);
 
     // Add tracing metadata
     let metadata = serde_json::json!({
         "problem_id": problem.id,
-        "file_path": file_path,
+        "file_paths": paths,
         "stage": "relevance",
         "token_count": token_count,
     });
+    let generation_name = format!("relevance_batch_{}", paths[0].replace("/", "_"));
+
+    let assessment = Assessment {
+        models,
+        config,
+        subject: &subject,
+        trace_id,
+        spent: Mutex::new(Default::default()),
+    };
+    let result = assessment
+        .request_parsed(
+            &request,
+            token_count,
+            &generation_name,
+            metadata,
+            |response| parse_batch_response(response, &paths),
+            get_relevance_batch_reprompt,
+        )
+        .await;
+    let mut spent = assessment.spent.into_inner().unwrap();
+
+    let reason = match result {
+        Ok((message, Ok(decisions))) => {
+            // Every file keeps the whole answer, which has the reasoning for all of them
+            for (path, decision) in decisions {
+                trajectory_store
+                    .save_per_file_relevance_decision(
+                        &path,
+                        decision.into_decision(message.clone()),
+                    )
+                    .context(format!(
+                        "Failed to save relevance decision for file: {}",
+                        path
+                    ))?;
+            }
+            return Ok(spent);
+        }
+        Ok((_, Err(error))) => format!("their decisions could not be parsed: {}", error),
+        Err(e) if is_context_too_long(&e) => {
+            "the batch is too large for the model's context window".to_string()
+        }
+        Err(e) => return Err(e).context(format!("Failed to get completion for {}", subject)),
+    };
 
-    // Send the request to the LLM with tracing
-    let llm_response = client
-        .completion_with_tracing(
-            &prompt,
-            config.relevance.max_tokens,
-            0.0,
+    // Fall back to a request per file, adding to what the batch already spent
+    warn!("Assessing {} one at a time, as {}", subject, reason);
+    for (path, content) in files {
+        let (usage, cost) = assess_file_relevance(
+            problem,
+            path,
+            content,
+            models,
+            config,
+            trajectory_store,
             trace_id,
-            Some(&format!("relevance_{}", file_path.replace("/", "_"))),
-            Some(metadata),
         )
-        .await
-        .context(format!("Failed to get completion for file: {}", file_path))?;
-
-    // Parse the response
-    let relevance_decision = parse_response(&llm_response.content);
-
-    // Save the decision
-    trajectory_store
-        .save_per_file_relevance_decision(file_path, relevance_decision)
-        .context(format!(
-            "Failed to save relevance decision for file: {}",
-            file_path
-        ))?;
-
-    Ok(llm_response.usage)
+        .await?;
+        spent.0 += &usage;
+        spent.1 += &cost;
+    }
+    Ok(spent)
 }
 
 use crate::stages::file_selection::{parse_file_patterns, save_file_patterns};
@@ -221,8 +575,12 @@ pub async fn process_codebase(
 ) -> Result<()> {
     info!("Starting relevance assessment");
 
-    // Create LLM config using the config's to_llm_config method
-    let llm_config = config.to_llm_config(&config.relevance.model);
+    // Create an LLM config for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.relevance.model,
+        &config.relevance.models,
+        &config.relevance.llm,
+    );
 
     // Set up Langfuse trace for the entire relevance stage
     let trace_metadata = serde_json::json!({
@@ -230,8 +588,8 @@ pub async fn process_codebase(
         "stage": "relevance",
         "max_workers": config.relevance.max_workers,
         "max_tokens": config.relevance.max_tokens,
-        "model_type": "anthropic",
-        "model": config.get_model_for_stage(&config.relevance.model),
+        "model_type": llm_configs[0].model_type,
+        "models": llm_configs.iter().map(|llm_config| &llm_config.model).collect::<Vec<_>>(),
     });
 
     // Create a new trace
@@ -254,10 +612,8 @@ pub async fn process_codebase(
         Err(_) => None,
     };
 
-    // Create the LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create the LLM clients
+    let models = ModelChain::create(&llm_configs, "relevance").await?;
 
     info!("Processing problem: {}", problem.id);
 
@@ -283,7 +639,7 @@ pub async fn process_codebase(
     // Setup the problem with codebase configuration
     let mut configured_problem = problem
         .with_codebase_path(&codebase_config.path)
-        .with_exclusion_config(exclusion_config);
+        .with_exclusion_config(exclusion_config.for_profile(ScanProfile::Relevance));
 
     // Initialize the problem to scan the codebase
     configured_problem
@@ -312,7 +668,7 @@ pub async fn process_codebase(
         .context("Failed to parse file patterns from response file")?;
 
     // Track total token usage across all LLM calls
-    let mut total_usage = crate::llm::client::TokenUsage::default();
+    let mut total_usage = TokenUsage::default();
 
     // Save the file patterns for future reference
     save_file_patterns(&trajectory_dir, &configured_problem, &file_patterns)?;
@@ -320,11 +676,34 @@ pub async fn process_codebase(
     // Get all file paths for this problem
     let all_files = configured_problem.all_file_paths();
 
-    // Filter files based on the LLM's selection
-    let relevant_files: Vec<_> = all_files
-        .into_iter()
-        .filter(|path| should_process_file(path, &file_patterns))
-        .collect();
+    // Decide well-known files by rule instead of asking a model. Build and
+    // test files are included even if they weren't selected.
+    let file_rules = FileRules::from_config(&codebase_config.file_rules)?;
+    let decided = trajectory_store
+        .load_relevance_decisions()
+        .unwrap_or_default();
+    let mut relevant_files = Vec::new();
+    let mut ruled_count = 0;
+    for path in all_files {
+        let selected = should_process_file(&path, &file_patterns);
+        match file_rules.classify(&path) {
+            Some(rule) if selected || rule.status == RelevanceVerdict::Relevant => {
+                if decided
+                    .get(&path)
+                    .is_none_or(|decision| decision.is_skipped())
+                {
+                    debug!("Decided {} by the file rule {}", path, rule.pattern);
+                    trajectory_store
+                        .save_per_file_relevance_decision(&path, rule.decision())
+                        .context(format!("Failed to save the decision for {}", path))?;
+                }
+                ruled_count += 1;
+            }
+            None if selected => relevant_files.push(path),
+            _ => {}
+        }
+    }
+    info!("Decided {} files by file rules", ruled_count);
 
     info!(
         "Found {} matching files for problem: {}",
@@ -353,66 +732,123 @@ pub async fn process_codebase(
         file_contents.push((file_path, file_content));
     }
 
+    // Remember the files, to report any left unassessed if the run stops early
+    let file_paths: Vec<String> = file_contents.iter().map(|(path, _)| path.clone()).collect();
+
+    // Group small files into batches if asked to, so that each doesn't cost a
+    // request of its own
+    let work = if config.relevance.batch_small_files {
+        let tokenizer = tokenizer_for_model(models.primary().model_name());
+        plan_batches(file_contents, config, tokenizer.as_ref(), &trajectory_store)
+    } else {
+        file_contents
+            .into_iter()
+            .map(|(path, content)| Work::File(path, content))
+            .collect()
+    };
+
     // Create a fixed-size buffer of futures to limit concurrency
     // Clone trace_id for use in async blocks
     let trace_id_for_async = trace_id.clone();
 
-    let futures =
-        futures::stream::iter(file_contents.into_iter().map(|(file_path, file_content)| {
-            let file_path_clone = file_path.clone();
-            let client_ref = &*client;
-            let config_ref = config; // Pass the whole config reference
-            let trajectory_store_ref = &trajectory_store;
-            let problem_ref = &configured_problem;
-            let progress_bar_ref = &progress_bar;
-            let trace_id_local = trace_id_for_async.clone();
-
-            async move {
+    let futures = futures::stream::iter(work.into_iter().map(|work| {
+        let models_ref = &models;
+        let config_ref = config; // Pass the whole config reference
+        let trajectory_store_ref = &trajectory_store;
+        let problem_ref = &configured_problem;
+        let progress_bar_ref = &progress_bar;
+        let trace_id_local = trace_id_for_async.clone();
+
+        async move {
+            if let Work::File(file_path, file_content) = &work {
                 if file_content.is_empty() {
                     progress_bar_ref.inc(1);
-                    progress_bar_ref.set_message(format!("Skipped (empty): {}", file_path_clone));
-                    return Ok(crate::llm::client::TokenUsage::default());
+                    progress_bar_ref.set_message(format!("Skipped (empty): {}", file_path));
+                    return Ok(Default::default());
                 }
+            }
+
+            // Don't start on another file once the budget has run out
+            check_budget("relevance")?;
+
+            let (subject, file_count, result) = match &work {
+                Work::File(file_path, file_content) => (
+                    file_path.clone(),
+                    1,
+                    assess_file_relevance(
+                        problem_ref,
+                        file_path,
+                        file_content,
+                        models_ref,
+                        config_ref,
+                        trajectory_store_ref,
+                        trace_id_local.as_deref(),
+                    )
+                    .await,
+                ),
+                Work::Batch(files) => (
+                    format!("batch of {} files", files.len()),
+                    files.len(),
+                    assess_batch_relevance(
+                        problem_ref,
+                        files,
+                        models_ref,
+                        config_ref,
+                        trajectory_store_ref,
+                        trace_id_local.as_deref(),
+                    )
+                    .await,
+                ),
+            };
 
-                let result = assess_file_relevance(
-                    problem_ref,
-                    &file_path_clone,
-                    &file_content,
-                    client_ref,
-                    config_ref,
-                    trajectory_store_ref,
-                    trace_id_local.as_deref(),
-                )
-                .await;
-
-                if let Err(e) = &result {
-                    warn!("Error assessing file {}: {}", file_path_clone, e);
+            let result = match result {
+                Ok(spending) => Ok(spending),
+                // Every other file would fail the same way, so stop here
+                Err(e) if is_auth_error(&e) || is_budget_exceeded(&e) => Err(e),
+                Err(e) => {
+                    warn!("Error assessing {}: {}", subject, e);
+                    Ok(Default::default())
                 }
+            };
 
-                progress_bar_ref.inc(1);
-                progress_bar_ref.set_message(format!("Processed: {}", file_path_clone));
+            progress_bar_ref.inc(file_count as u64);
+            progress_bar_ref.set_message(format!("Processed: {}", subject));
 
-                result
-            }
-        }))
-        .buffer_unordered(config.relevance.max_workers);
+            result
+        }
+    }))
+    .buffer_unordered(config.relevance.max_workers);
 
-    // Collect all the futures results
-    let usage_results = futures.collect::<Vec<_>>().await;
+    // Collect all the futures results, stopping at the first error
+    let spendings = match futures.try_collect::<Vec<_>>().await {
+        Ok(spendings) => spendings,
+        Err(e) => {
+            progress_bar.abandon_with_message("Aborted");
+            if is_budget_exceeded(&e) {
+                let unassessed = file_paths
+                    .iter()
+                    .filter(|path| !trajectory_store.relevance_decision_exists(path))
+                    .count();
+                warn!(
+                    "{} of {} files were left unassessed. Decisions made so far are saved, so re-running relevance continues from here.",
+                    unassessed,
+                    file_paths.len()
+                );
+            }
+            return Err(e).context("Relevance assessment aborted");
+        }
+    };
 
     progress_bar.finish_with_message(format!("Completed problem: {}", configured_problem.id));
 
-    // Aggregate token usage across all relevance assessments
-    for result in usage_results {
-        if let Ok(usage) = result {
-            total_usage.prompt_tokens += usage.prompt_tokens;
-            total_usage.completion_tokens += usage.completion_tokens;
-            total_usage.total_tokens += usage.total_tokens;
-        }
+    // Aggregate token usage and cost across all relevance assessments
+    let mut cost = TokenCost::default();
+    for (usage, file_cost) in spendings {
+        total_usage += &usage;
+        cost += &file_cost;
     }
 
-    // Calculate and display cost
-    let cost = client.calculate_cost(&total_usage);
+    // Display usage and cost
     info!("Relevance assessment LLM usage: {}", total_usage);
     info!("Relevance assessment LLM cost: {}", cost);
 
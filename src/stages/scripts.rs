@@ -5,15 +5,19 @@ use std::fs;
 use std::path::Path;
 
 use crate::config::Config;
-use crate::llm::client::{create_client, TokenCost};
+use crate::llm::client::{stream_to_terminal, ChatRequest, LLMResponse, TokenCost};
+use crate::llm::error::retry_with_fewer_files;
+use crate::llm::fallback::ModelChain;
 use crate::llm::prompts::{
     get_lint_script_user_prompt, get_setup_script_user_prompt, get_test_script_error_user_prompt,
-    get_test_script_user_prompt, LINT_SCRIPT_SYSTEM_PROMPT, SETUP_SCRIPT_SYSTEM_PROMPT, 
+    get_test_script_user_prompt, LINT_SCRIPT_SYSTEM_PROMPT, SETUP_SCRIPT_SYSTEM_PROMPT,
     TEST_SCRIPT_ERROR_SYSTEM_PROMPT, TEST_SCRIPT_SYSTEM_PROMPT,
 };
 use crate::models::problem::SWEBenchProblem;
 use crate::models::ranking::RankedCodebaseFile;
 use crate::models::relevance::RelevanceStatus;
+use crate::utils::context_packer::{context_budget, pack_files};
+use crate::utils::token_counter::tokenizer_for_model;
 use crate::utils::trajectory_store::TrajectoryStore;
 use std::ops::Add;
 
@@ -25,6 +29,8 @@ impl Add for TokenCost {
         TokenCost {
             prompt_cost: self.prompt_cost + other.prompt_cost,
             completion_cost: self.completion_cost + other.completion_cost,
+            cache_write_cost: self.cache_write_cost + other.cache_write_cost,
+            cache_read_cost: self.cache_read_cost + other.cache_read_cost,
             total_cost: self.total_cost + other.total_cost,
         }
     }
@@ -99,6 +105,11 @@ pub fn extract_script(response: &str) -> Result<String> {
     Ok(response.to_string())
 }
 
+/// Check that a script can be extracted from an LLM response
+fn require_script(response: &LLMResponse) -> Result<()> {
+    extract_script(&response.content).map(|_| ())
+}
+
 /// Generate lint and test scripts based on relevance data
 pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) -> Result<()> {
     info!("Starting script generation from relevance data");
@@ -136,12 +147,27 @@ pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) ->
         ))?;
 
     // Get relevant files
-    let relevant_files = all_decisions
+    let mut relevant_files = all_decisions
         .into_iter()
         .filter(|(_, decision)| decision.status == RelevanceStatus::Relevant)
         .map(|(path, decision)| (path, decision.summary.unwrap_or_default()))
         .collect::<Vec<_>>();
 
+    // Order the files by their ranking, so the least important are left out
+    // first if the prompts don't fit in the model's context
+    let ranked_paths = match trajectory_store.load_ranking() {
+        Ok(ranking) => ranking
+            .ranked_files
+            .into_iter()
+            .map(|file| file.path)
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    relevant_files.sort_by_cached_key(|(path, _)| {
+        let rank = ranked_paths.iter().position(|ranked| ranked == path);
+        (rank.unwrap_or(usize::MAX), path.clone())
+    });
+
     if relevant_files.is_empty() {
         return Err(anyhow::anyhow!(
             "No relevant files found for problem: {}",
@@ -170,13 +196,13 @@ pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) ->
         .map(|(path, summary)| format!("{}:\n{}", path, summary))
         .collect();
 
-    // Create LLM config using the config's to_llm_config method
-    let llm_config = config.to_llm_config(&config.scripts.model);
-
-    // Create LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.scripts.model,
+        &config.scripts.models,
+        &config.scripts.llm,
+    );
+    let models = ModelChain::create(&llm_configs, "scripts").await?;
 
     // Generate setup script
     info!("Generating setup script...");
@@ -191,53 +217,109 @@ pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) ->
 
     // Prepare file_contents in the right format (path, content) without summaries
     let file_contents_for_prompt: Vec<(String, String)> = file_contents
-        .iter()
-        .map(|(path, _, content)| (path.clone(), content.clone()))
+        .into_iter()
+        .map(|(path, _, content)| (path, content))
         .collect();
 
-    let setup_prompt = get_setup_script_user_prompt(
-        &problem.problem_statement,
-        &ranked_files,
-        &file_contents_for_prompt,
+    // Take the highest-ranked files that fit in the budget. The lint and test
+    // prompts also carry the setup script, so leave room for it as well as
+    // for the answer.
+    let tokenizer = tokenizer_for_model(models.primary().model_name());
+    let prompt_tokens = [
+        (
+            SETUP_SCRIPT_SYSTEM_PROMPT,
+            get_setup_script_user_prompt(&problem.problem_statement, &ranked_files, &[]),
+        ),
+        (
+            LINT_SCRIPT_SYSTEM_PROMPT,
+            get_lint_script_user_prompt(&problem.problem_statement, &ranked_files, &[]),
+        ),
+        (
+            TEST_SCRIPT_SYSTEM_PROMPT,
+            get_test_script_user_prompt(&problem.problem_statement, &ranked_files, &[]),
+        ),
+    ]
+    .iter()
+    .map(|(system, user)| tokenizer.count_tokens(system) + tokenizer.count_tokens(user))
+    .max()
+    .unwrap_or(0);
+    let budget = context_budget(
+        &config.scripts.context,
+        models.context_window(),
+        prompt_tokens + 2 * config.scripts.max_tokens,
     );
-
-    // Create a combined prompt with system and user instructions
-    let combined_setup_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        SETUP_SCRIPT_SYSTEM_PROMPT, setup_prompt
+    let packed = pack_files(
+        file_contents_for_prompt,
+        budget,
+        config.scripts.context.max_file_tokens,
+        tokenizer.as_ref(),
     );
+    packed.log_summary("scripts");
+    let file_contents_for_prompt = packed.contents();
 
-    // Add tracing metadata for setup script
-    let setup_metadata = serde_json::json!({
-        "problem_id": problem.id,
-        "stage": "setup_script_generation",
-        "temperature": config.scripts.temperature,
-        "num_files": formatted_files.len(),
-    });
-
-    let setup_response = client
-        .completion_with_tracing(
-            &combined_setup_prompt,
-            config.scripts.max_tokens,
-            config.scripts.temperature,
-            None, // Auto-generate trace ID
-            Some(&format!("setup_script_{}", problem.id)),
-            Some(setup_metadata),
+    // Leave out the contents of the lowest-ranked files if the prompt doesn't
+    // fit, and move to the next model if no script can be extracted
+    let (problem_ref, ranked_files_ref, file_contents_ref, formatted_files_ref) = (
+        &problem,
+        &ranked_files,
+        &file_contents_for_prompt,
+        &formatted_files,
+    );
+    let setup_answer = models
+        .run(
+            |client| {
+                retry_with_fewer_files(file_contents_ref.len(), move |count| {
+                    let setup_prompt = get_setup_script_user_prompt(
+                        &problem_ref.problem_statement,
+                        ranked_files_ref,
+                        &file_contents_ref[..count],
+                    );
+
+                    // Build the request with the system and user prompts
+                    let setup_request =
+                        ChatRequest::new(config.scripts.max_tokens, config.scripts.temperature)
+                            .with_system(SETUP_SCRIPT_SYSTEM_PROMPT)
+                            .with_user(setup_prompt);
+
+                    // Add tracing metadata for setup script
+                    let setup_metadata = serde_json::json!({
+                        "problem_id": problem_ref.id,
+                        "stage": "setup_script_generation",
+                        "temperature": config.scripts.temperature,
+                        "num_files": formatted_files_ref.len(),
+                    });
+
+                    let generation_name = format!("setup_script_{}", problem_ref.id);
+                    async move {
+                        stream_to_terminal(
+                            client,
+                            &setup_request,
+                            None, // Auto-generate trace ID
+                            Some(&generation_name),
+                            Some(setup_metadata),
+                        )
+                        .await
+                    }
+                })
+            },
+            require_script,
         )
         .await
         .context("Failed to generate setup script")?;
+    let setup_response = &setup_answer.value;
 
     // Track usage
-    let setup_usage = setup_response.usage;
-    let setup_cost = client.calculate_cost(&setup_usage);
+    let setup_usage = setup_response.usage.clone();
+    let setup_cost = setup_answer.client.calculate_cost(&setup_usage);
     info!("Setup script generation LLM usage: {}", setup_usage);
     info!("Setup script generation LLM cost: {}", setup_cost);
 
     // Save setup script reasoning
     let metadata = serde_json::json!({
-        "model": config.scripts.model,
+        "model": setup_answer.model(),
         "tokens": setup_usage.total_tokens,
-        "temperature": config.scripts.temperature
+        "temperature": config.scripts.temperature,
+        "context": packed.report()
     });
 
     crate::stages::overview::save_reasoning(
@@ -280,50 +362,63 @@ pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) ->
 
     // Generate lint script
     info!("Generating lint script...");
-    let mut lint_prompt = get_lint_script_user_prompt(
-        &problem.problem_statement,
-        &ranked_files,
-        &file_contents_for_prompt,
-    );
-    lint_prompt.push_str(&additional_context);
-
-    // Create a combined prompt with system and user instructions
-    let combined_lint_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        LINT_SCRIPT_SYSTEM_PROMPT, lint_prompt
-    );
-
-    // Add tracing metadata for lint script
-    let lint_metadata = serde_json::json!({
-        "problem_id": problem.id,
-        "stage": "lint_script_generation",
-        "temperature": config.scripts.temperature,
-        "num_files": formatted_files.len(),
-    });
-
-    let lint_response = client
-        .completion_with_tracing(
-            &combined_lint_prompt,
-            config.scripts.max_tokens,
-            config.scripts.temperature,
-            None, // Auto-generate trace ID
-            Some(&format!("lint_script_{}", problem.id)),
-            Some(lint_metadata),
+    let additional_context_ref = &additional_context;
+    let lint_answer = models
+        .run(
+            |client| {
+                retry_with_fewer_files(file_contents_ref.len(), move |count| {
+                    let mut lint_prompt = get_lint_script_user_prompt(
+                        &problem_ref.problem_statement,
+                        ranked_files_ref,
+                        &file_contents_ref[..count],
+                    );
+                    lint_prompt.push_str(additional_context_ref);
+
+                    // Build the request with the system and user prompts
+                    let lint_request =
+                        ChatRequest::new(config.scripts.max_tokens, config.scripts.temperature)
+                            .with_system(LINT_SCRIPT_SYSTEM_PROMPT)
+                            .with_user(lint_prompt);
+
+                    // Add tracing metadata for lint script
+                    let lint_metadata = serde_json::json!({
+                        "problem_id": problem_ref.id,
+                        "stage": "lint_script_generation",
+                        "temperature": config.scripts.temperature,
+                        "num_files": formatted_files_ref.len(),
+                    });
+
+                    let generation_name = format!("lint_script_{}", problem_ref.id);
+                    async move {
+                        stream_to_terminal(
+                            client,
+                            &lint_request,
+                            None, // Auto-generate trace ID
+                            Some(&generation_name),
+                            Some(lint_metadata),
+                        )
+                        .await
+                    }
+                })
+            },
+            require_script,
         )
         .await
         .context("Failed to generate lint script")?;
+    let lint_response = &lint_answer.value;
 
     // Track usage
-    let lint_usage = lint_response.usage;
-    let lint_cost = client.calculate_cost(&lint_usage);
+    let lint_usage = lint_response.usage.clone();
+    let lint_cost = lint_answer.client.calculate_cost(&lint_usage);
     info!("Lint script generation LLM usage: {}", lint_usage);
     info!("Lint script generation LLM cost: {}", lint_cost);
 
     // Save lint script reasoning
     let metadata = serde_json::json!({
-        "model": config.scripts.model,
+        "model": lint_answer.model(),
         "tokens": lint_usage.total_tokens,
-        "temperature": config.scripts.temperature
+        "temperature": config.scripts.temperature,
+        "context": packed.report()
     });
 
     crate::stages::overview::save_reasoning(
@@ -360,50 +455,62 @@ pub async fn generate_scripts(config: &Config, mut problem: SWEBenchProblem) ->
 
     // Generate test script
     info!("Generating test script...");
-    let mut test_prompt = get_test_script_user_prompt(
-        &problem.problem_statement,
-        &ranked_files,
-        &file_contents_for_prompt,
-    );
-    test_prompt.push_str(&additional_context);
-
-    // Create a combined prompt with system and user instructions
-    let combined_test_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        TEST_SCRIPT_SYSTEM_PROMPT, test_prompt
-    );
-
-    // Add tracing metadata for test script
-    let test_metadata = serde_json::json!({
-        "problem_id": problem.id,
-        "stage": "test_script_generation",
-        "temperature": config.scripts.temperature,
-        "num_files": formatted_files.len(),
-    });
-
-    let test_response = client
-        .completion_with_tracing(
-            &combined_test_prompt,
-            config.scripts.max_tokens,
-            config.scripts.temperature,
-            None, // Auto-generate trace ID
-            Some(&format!("test_script_{}", problem.id)),
-            Some(test_metadata),
+    let test_answer = models
+        .run(
+            |client| {
+                retry_with_fewer_files(file_contents_ref.len(), move |count| {
+                    let mut test_prompt = get_test_script_user_prompt(
+                        &problem_ref.problem_statement,
+                        ranked_files_ref,
+                        &file_contents_ref[..count],
+                    );
+                    test_prompt.push_str(additional_context_ref);
+
+                    // Build the request with the system and user prompts
+                    let test_request =
+                        ChatRequest::new(config.scripts.max_tokens, config.scripts.temperature)
+                            .with_system(TEST_SCRIPT_SYSTEM_PROMPT)
+                            .with_user(test_prompt);
+
+                    // Add tracing metadata for test script
+                    let test_metadata = serde_json::json!({
+                        "problem_id": problem_ref.id,
+                        "stage": "test_script_generation",
+                        "temperature": config.scripts.temperature,
+                        "num_files": formatted_files_ref.len(),
+                    });
+
+                    let generation_name = format!("test_script_{}", problem_ref.id);
+                    async move {
+                        stream_to_terminal(
+                            client,
+                            &test_request,
+                            None, // Auto-generate trace ID
+                            Some(&generation_name),
+                            Some(test_metadata),
+                        )
+                        .await
+                    }
+                })
+            },
+            require_script,
         )
         .await
         .context("Failed to generate test script")?;
+    let test_response = &test_answer.value;
 
     // Track usage
-    let test_usage = test_response.usage;
-    let test_cost = client.calculate_cost(&test_usage);
+    let test_usage = test_response.usage.clone();
+    let test_cost = test_answer.client.calculate_cost(&test_usage);
     info!("Test script generation LLM usage: {}", test_usage);
     info!("Test script generation LLM cost: {}", test_cost);
 
     // Save test script reasoning
     let metadata = serde_json::json!({
-        "model": config.scripts.model,
+        "model": test_answer.model(),
         "tokens": test_usage.total_tokens,
-        "temperature": config.scripts.temperature
+        "temperature": config.scripts.temperature,
+        "context": packed.report()
     });
 
     crate::stages::overview::save_reasoning(
@@ -474,11 +581,11 @@ Create a script called 'single-test-script.sh' that runs just one specified test
         test_script_content, first_test
     );
 
-    // Create a combined prompt with system and user instructions
-    let combined_single_test_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        TEST_SCRIPT_SYSTEM_PROMPT, single_test_prompt
-    );
+    // Build the request with the system and user prompts
+    let single_test_request =
+        ChatRequest::new(config.scripts.max_tokens, config.scripts.temperature)
+            .with_system(TEST_SCRIPT_SYSTEM_PROMPT)
+            .with_user(single_test_prompt);
 
     // Add tracing metadata for single test script
     let single_test_metadata = serde_json::json!({
@@ -488,21 +595,27 @@ Create a script called 'single-test-script.sh' that runs just one specified test
         "num_files": formatted_files.len(),
     });
 
-    let single_test_response = client
-        .completion_with_tracing(
-            &combined_single_test_prompt,
-            config.scripts.max_tokens,
-            config.scripts.temperature,
-            None, // Auto-generate trace ID
-            Some(&format!("single_test_script_{}", problem.id)),
-            Some(single_test_metadata),
+    let generation_name = format!("single_test_script_{}", problem.id);
+    let single_test_answer = models
+        .run(
+            |client| {
+                stream_to_terminal(
+                    client,
+                    &single_test_request,
+                    None, // Auto-generate trace ID
+                    Some(&generation_name),
+                    Some(single_test_metadata.clone()),
+                )
+            },
+            require_script,
         )
         .await
         .context("Failed to generate single test script")?;
+    let single_test_response = &single_test_answer.value;
 
     // Track usage
-    let single_test_usage = single_test_response.usage;
-    let single_test_cost = client.calculate_cost(&single_test_usage);
+    let single_test_usage = single_test_response.usage.clone();
+    let single_test_cost = single_test_answer.client.calculate_cost(&single_test_usage);
     info!(
         "Single test script generation LLM usage: {}",
         single_test_usage
@@ -514,7 +627,7 @@ Create a script called 'single-test-script.sh' that runs just one specified test
 
     // Save single test script reasoning
     let metadata = serde_json::json!({
-        "model": config.scripts.model,
+        "model": single_test_answer.model(),
         "tokens": single_test_usage.total_tokens,
         "temperature": config.scripts.temperature
     });
@@ -554,20 +667,10 @@ Create a script called 'single-test-script.sh' that runs just one specified test
     // No need to save copies since scripts are already in the trajectory store directory
 
     // Calculate total usage and cost
-    let total_usage = crate::llm::client::TokenUsage {
-        prompt_tokens: setup_usage.prompt_tokens
-            + lint_usage.prompt_tokens
-            + test_usage.prompt_tokens
-            + single_test_usage.prompt_tokens,
-        completion_tokens: setup_usage.completion_tokens
-            + lint_usage.completion_tokens
-            + test_usage.completion_tokens
-            + single_test_usage.completion_tokens,
-        total_tokens: setup_usage.total_tokens
-            + lint_usage.total_tokens
-            + test_usage.total_tokens
-            + single_test_usage.total_tokens,
-    };
+    let mut total_usage = crate::llm::client::TokenUsage::default();
+    for usage in [&setup_usage, &lint_usage, &test_usage, &single_test_usage] {
+        total_usage += usage;
+    }
     let total_cost = setup_cost + lint_cost + test_cost + single_test_cost;
     info!("Total script generation LLM usage: {}", total_usage);
     info!("Total script generation LLM cost: {}", total_cost);
@@ -585,26 +688,21 @@ pub async fn update_test_script_from_error(
     attempt: usize,
 ) -> Result<String> {
     // Read the current test script
-    let test_script_content = fs::read_to_string(test_script_path)
-        .context(format!("Failed to read test script at {:?}", test_script_path))?;
+    let test_script_content = fs::read_to_string(test_script_path).context(format!(
+        "Failed to read test script at {:?}",
+        test_script_path
+    ))?;
 
     // Format error output as a single string
     let error_output_str = error_output.join("\n");
 
-    // Create LLM config
-    let llm_config = crate::config::LLMConfig {
-        model_type: "anthropic".to_string(),
-        model: config.scripts.model.clone().unwrap_or_else(|| config.model.clone()),
-        api_key: config.anthropic_api_key.clone(),
-        base_url: None,
-        timeout: 60,
-        max_retries: 3,
-    };
-
-    // Create LLM client
-    let client = create_client(&llm_config)
-        .await
-        .context("Failed to create LLM client")?;
+    // Create an LLM client for each model to try
+    let llm_configs = config.to_stage_llm_configs(
+        &config.scripts.model,
+        &config.scripts.models,
+        &config.scripts.llm,
+    );
+    let models = ModelChain::create(&llm_configs, "scripts").await?;
 
     // Generate the user prompt for the LLM
     let user_prompt = get_test_script_error_user_prompt(
@@ -613,31 +711,29 @@ pub async fn update_test_script_from_error(
         &error_output_str,
     );
 
-    // Combine with system prompt
-    let combined_error_prompt = format!(
-        "System instructions:\n{}\n\nUser request:\n{}",
-        TEST_SCRIPT_ERROR_SYSTEM_PROMPT, user_prompt
-    );
-
-    // Send the request to the LLM
-    let llm_response = client
-        .completion_with_tracing(
-            &combined_error_prompt,
-            config.scripts.max_tokens,
-            config.scripts.temperature,
-            None,
-            Some(&format!("test_script_error_{}", problem.id)),
-            None,
+    // Build the request with the system and user prompts
+    let error_request = ChatRequest::new(config.scripts.max_tokens, config.scripts.temperature)
+        .with_system(TEST_SCRIPT_ERROR_SYSTEM_PROMPT)
+        .with_user(user_prompt);
+
+    // Send the request to the LLM, moving to the next model if no script can
+    // be extracted from the answer
+    let generation_name = format!("test_script_error_{}", problem.id);
+    let answer = models
+        .run(
+            |client| stream_to_terminal(client, &error_request, None, Some(&generation_name), None),
+            require_script,
         )
         .await
         .context("Failed to get test script fix from LLM")?;
+    let llm_response = &answer.value;
 
     // Extract the full LLM response
     let full_llm_response = llm_response.content.clone();
 
     // Save the reasoning to a file
-    let reasoning_path = test_script_path
-        .with_file_name(format!("test_script_error_reasoning_{}.md", problem.id));
+    let reasoning_path =
+        test_script_path.with_file_name(format!("test_script_error_reasoning_{}.md", problem.id));
 
     fs::write(&reasoning_path, &full_llm_response).context(format!(
         "Failed to write test script error reasoning to {:?}",
@@ -646,7 +742,7 @@ pub async fn update_test_script_from_error(
 
     // Save structured reasoning
     let metadata = serde_json::json!({
-        "model": config.scripts.model,
+        "model": answer.model(),
         "tokens": llm_response.usage.total_tokens,
         "temperature": config.scripts.temperature,
         "attempt": attempt
@@ -0,0 +1,326 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::llm::cache::ResponseCache;
+use crate::llm::client::{ChatRequest, LLMResponse};
+use crate::llm::error::LLMError;
+
+/// Name of the file holding the interactions in a cassette directory
+const CASSETTE_FILE: &str = "cassette.jsonl";
+
+/// The cassette being recorded or replayed, if any
+static CASSETTE: RwLock<Option<Arc<Cassette>>> = RwLock::new(None);
+
+/// Set the cassette that LLM clients and external commands record to or
+/// replay from
+pub fn set_cassette(cassette: Option<Arc<Cassette>>) {
+    *CASSETTE.write().unwrap() = cassette;
+}
+
+/// The cassette being recorded or replayed, if any
+pub fn cassette() -> Option<Arc<Cassette>> {
+    CASSETTE.read().unwrap().clone()
+}
+
+/// Run a command to completion and capture its output, or replay it from the
+/// current cassette
+pub fn run_command(command: &mut Command) -> Result<CommandRecord> {
+    match cassette() {
+        Some(cassette) => cassette.run_command(command),
+        None => CommandRecord::run(command),
+    }
+}
+
+/// An LLM request and the response (or error) it got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMExchange {
+    pub model: String,
+    pub request: ChatRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<LLMResponse>,
+    /// The error with its context, as shown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The provider error behind `error`, if there was one, so it's replayed
+    /// as the same kind of error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm_error: Option<LLMError>,
+}
+
+/// What was discovered about a model when its client was created, which a
+/// replaying client can't find out without contacting the provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+}
+
+/// An external command and what it printed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandRecord {
+    /// The program followed by its arguments
+    pub command: Vec<String>,
+    pub stdout: String,
+    pub stderr: String,
+    /// -1 if the command was killed by a signal
+    pub exit_code: i32,
+    /// Output lines of both streams in the order they were shown, for
+    /// commands whose output is streamed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<String>,
+}
+
+impl CommandRecord {
+    /// Run a command to completion, capturing any output that isn't
+    /// redirected elsewhere
+    pub fn run(command: &mut Command) -> Result<Self> {
+        let output = command
+            .output()
+            .context(format!("Failed to run {:?}", command.get_program()))?;
+
+        Ok(Self {
+            command: command_line(command),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_code: output.status.code().unwrap_or(-1),
+            logs: Vec::new(),
+        })
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// The program and arguments of a command
+pub fn command_line(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Rebuild a recorded error around its provider error, with the context the
+/// recorded message adds to it
+fn replayed_error(error: String, llm_error: LLMError) -> anyhow::Error {
+    let cause = llm_error.to_string();
+    match error
+        .strip_suffix(&cause)
+        .and_then(|context| context.strip_suffix(": "))
+    {
+        Some(context) => anyhow::Error::new(llm_error).context(context.to_string()),
+        None => llm_error.into(),
+    }
+}
+
+/// A recorded interaction with the outside world
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Interaction {
+    Model(ModelInfo),
+    Llm(LLMExchange),
+    Command(CommandRecord),
+}
+
+enum CassetteState {
+    Recording(File),
+    /// Interactions not yet replayed, by request key or command line
+    Replaying {
+        models: HashMap<String, ModelInfo>,
+        exchanges: HashMap<String, VecDeque<LLMExchange>>,
+        commands: HashMap<Vec<String>, VecDeque<CommandRecord>>,
+    },
+}
+
+/// Every LLM exchange and external command of a run, in the order they
+/// happened, so the run can be reproduced without network access or Docker
+///
+/// Interactions are replayed by matching requests and command lines rather
+/// than by position, since stages send requests concurrently. Identical
+/// requests are answered in the order they were recorded.
+pub struct Cassette {
+    path: PathBuf,
+    state: Mutex<CassetteState>,
+}
+
+impl Cassette {
+    /// Start recording into a directory, replacing any earlier recording there
+    pub fn record(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .context(format!("Failed to create cassette directory: {:?}", dir))?;
+
+        let path = dir.join(CASSETTE_FILE);
+        let file = File::create(&path).context(format!("Failed to create cassette: {:?}", path))?;
+        info!("Recording LLM requests and commands to {:?}", path);
+
+        Ok(Self {
+            path,
+            state: Mutex::new(CassetteState::Recording(file)),
+        })
+    }
+
+    /// Load a recording from a directory for replay
+    pub fn replay(dir: impl AsRef<Path>) -> Result<Self> {
+        let path = dir.as_ref().join(CASSETTE_FILE);
+        let file = File::open(&path).context(format!("Failed to open cassette: {:?}", path))?;
+
+        let mut models: HashMap<String, ModelInfo> = HashMap::new();
+        let mut exchanges: HashMap<String, VecDeque<LLMExchange>> = HashMap::new();
+        let mut commands: HashMap<Vec<String>, VecDeque<CommandRecord>> = HashMap::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context(format!("Failed to read cassette: {:?}", path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let interaction: Interaction = serde_json::from_str(&line).context(format!(
+                "Invalid interaction on line {} of cassette {:?}",
+                index + 1,
+                path
+            ))?;
+            match interaction {
+                Interaction::Model(info) => {
+                    models.insert(info.model.clone(), info);
+                }
+                Interaction::Llm(exchange) => exchanges
+                    .entry(ResponseCache::key(&exchange.model, &exchange.request))
+                    .or_default()
+                    .push_back(exchange),
+                Interaction::Command(record) => commands
+                    .entry(record.command.clone())
+                    .or_default()
+                    .push_back(record),
+            }
+        }
+        info!("Replaying LLM requests and commands from {:?}", path);
+
+        Ok(Self {
+            path,
+            state: Mutex::new(CassetteState::Replaying {
+                models,
+                exchanges,
+                commands,
+            }),
+        })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), CassetteState::Replaying { .. })
+    }
+
+    /// Append an interaction when recording
+    fn record_interaction(&self, interaction: Interaction) -> Result<()> {
+        if let CassetteState::Recording(file) = &mut *self.state.lock().unwrap() {
+            let line = serde_json::to_string(&interaction)?;
+            writeln!(file, "{}", line)
+                .and_then(|_| file.flush())
+                .context(format!("Failed to write to cassette: {:?}", self.path))?;
+        }
+        Ok(())
+    }
+
+    /// Record what was discovered about a model
+    pub fn record_model(&self, info: &ModelInfo) -> Result<()> {
+        self.record_interaction(Interaction::Model(info.clone()))
+    }
+
+    /// What was recorded about a model, if the cassette is being replayed and
+    /// the model was used
+    pub fn replay_model(&self, model: &str) -> Option<ModelInfo> {
+        match &*self.state.lock().unwrap() {
+            CassetteState::Replaying { models, .. } => models.get(model).cloned(),
+            CassetteState::Recording(_) => None,
+        }
+    }
+
+    /// Record the result of an LLM request
+    pub fn record_exchange(
+        &self,
+        model: &str,
+        request: &ChatRequest,
+        result: Result<&LLMResponse, &anyhow::Error>,
+    ) -> Result<()> {
+        let (response, error, llm_error) = match result {
+            Ok(response) => (Some(response.clone()), None, None),
+            Err(e) => (None, Some(format!("{:#}", e)), LLMError::find(e).cloned()),
+        };
+        self.record_interaction(Interaction::Llm(LLMExchange {
+            model: model.to_string(),
+            request: request.clone(),
+            response,
+            error,
+            llm_error,
+        }))
+    }
+
+    /// The recorded result of an LLM request
+    pub fn replay_exchange(&self, model: &str, request: &ChatRequest) -> Result<LLMResponse> {
+        let key = ResponseCache::key(model, request);
+        let exchange = match &mut *self.state.lock().unwrap() {
+            CassetteState::Replaying { exchanges, .. } => {
+                exchanges.get_mut(&key).and_then(VecDeque::pop_front)
+            }
+            CassetteState::Recording(_) => {
+                return Err(anyhow!("Cassette {:?} is not being replayed", self.path))
+            }
+        };
+
+        let exchange = exchange.ok_or_else(|| {
+            anyhow!(
+                "No recorded response left for this {} request in cassette {:?}; the run has diverged from the recording",
+                model,
+                self.path
+            )
+        })?;
+        match (exchange.response, exchange.error, exchange.llm_error) {
+            (Some(response), _, _) => Ok(response),
+            (None, Some(error), Some(llm_error)) => Err(replayed_error(error, llm_error)),
+            (None, Some(error), None) => Err(anyhow!(error)),
+            (None, None, _) => Err(anyhow!("Recorded {} exchange has no response", model)),
+        }
+    }
+
+    /// Record the output of a command
+    pub fn record_command(&self, record: &CommandRecord) -> Result<()> {
+        self.record_interaction(Interaction::Command(record.clone()))
+    }
+
+    /// The recorded output of a command, if the cassette is being replayed
+    pub fn replay_command(&self, command: &Command) -> Result<Option<CommandRecord>> {
+        let command_line = command_line(command);
+        match &mut *self.state.lock().unwrap() {
+            CassetteState::Replaying { commands, .. } => commands
+                .get_mut(&command_line)
+                .and_then(VecDeque::pop_front)
+                .map(Some)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No recorded output left for `{}` in cassette {:?}; the run has diverged from the recording",
+                        command_line.join(" "),
+                        self.path
+                    )
+                }),
+            CassetteState::Recording(_) => Ok(None),
+        }
+    }
+
+    /// Run a command and record its output, or replay it without running it
+    pub fn run_command(&self, command: &mut Command) -> Result<CommandRecord> {
+        if let Some(record) = self.replay_command(command)? {
+            return Ok(record);
+        }
+
+        let record = CommandRecord::run(command)?;
+        self.record_command(&record)?;
+        Ok(record)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::models::relevance::LineRange;
+use crate::utils::token_counter::Tokenizer;
+
+/// Times to shrink the chunk size when a chunk turns out larger than
+/// estimated
+const MAX_RESIZES: usize = 3;
+
+/// A part of a file, made of whole lines
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub lines: LineRange,
+    pub content: String,
+    pub tokens: usize,
+}
+
+/// Split a file into chunks of at most `max_tokens` tokens, each repeating
+/// up to `overlap_lines` lines from the end of the one before it
+///
+/// Chunks end at a blank line or before an unindented line where one is
+/// close to the size limit, so that functions and sections are rarely split.
+/// A single line longer than `max_tokens` makes a chunk of its own.
+pub fn split_into_chunks(
+    content: &str,
+    max_tokens: usize,
+    overlap_lines: usize,
+    tokenizer: &dyn Tokenizer,
+) -> Vec<Chunk> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    // Line sizes are estimated from the file's tokens per byte, so chunks
+    // are counted again once made and made smaller if they don't fit
+    let tokens_per_byte = tokenizer.count_tokens(content) as f64 / content.len() as f64;
+    let mut target = max_tokens;
+    let mut resizes = 0;
+    loop {
+        let chunks: Vec<Chunk> = chunk_boundaries(&lines, target, overlap_lines, tokens_per_byte)
+            .into_iter()
+            .map(|(start, end)| {
+                let content = lines[start..end].concat();
+                Chunk {
+                    lines: LineRange::new(start + 1, end),
+                    tokens: tokenizer.count_tokens(&content),
+                    content,
+                }
+            })
+            .collect();
+
+        let largest = chunks.iter().map(|chunk| chunk.tokens).max().unwrap_or(0);
+        if largest <= max_tokens || resizes == MAX_RESIZES {
+            return chunks;
+        }
+        target = (target * max_tokens / largest).saturating_sub(1).max(1);
+        resizes += 1;
+    }
+}
+
+/// Start and end (exclusive) line indexes of chunks of about `target` tokens
+fn chunk_boundaries(
+    lines: &[&str],
+    target: usize,
+    overlap_lines: usize,
+    tokens_per_byte: f64,
+) -> Vec<(usize, usize)> {
+    let mut boundaries = Vec::new();
+    let mut start = 0;
+
+    loop {
+        // Take lines until the next would go over the target
+        let mut end = start;
+        let mut tokens = 0.0;
+        while end < lines.len() {
+            let line_tokens = lines[end].len() as f64 * tokens_per_byte;
+            if end > start && tokens + line_tokens > target as f64 {
+                break;
+            }
+            tokens += line_tokens;
+            end += 1;
+        }
+
+        if end == lines.len() {
+            boundaries.push((start, end));
+            return boundaries;
+        }
+        let end = break_point(lines, start, end);
+        boundaries.push((start, end));
+
+        // Overlap with the chunk just made, but by no more than half of it so
+        // that every chunk moves well past the last
+        start = end - overlap_lines.min((end - start) / 2);
+    }
+}
+
+/// Where to end a chunk that could go up to `end`: after the last blank line
+/// in its final quarter, or before the last unindented line there, or at
+/// `end` if there is neither
+fn break_point(lines: &[&str], start: usize, end: usize) -> usize {
+    let earliest = (start + (end - start) * 3 / 4).max(start + 1);
+
+    if let Some(i) = (earliest..end)
+        .rev()
+        .find(|&i| lines[i - 1].trim().is_empty())
+    {
+        return i;
+    }
+
+    (earliest..end)
+        .rev()
+        .find(|&i| starts_top_level_item(lines[i]))
+        .unwrap_or(end)
+}
+
+/// Whether a line starts something at the top level of a file, such as a
+/// function, a class or a section, rather than closing one
+fn starts_top_level_item(line: &str) -> bool {
+    line.chars()
+        .next()
+        .is_some_and(|c| !c.is_whitespace() && !matches!(c, '}' | ')' | ']'))
+}
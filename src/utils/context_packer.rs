@@ -0,0 +1,258 @@
+use log::info;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::ContextConfig;
+use crate::utils::token_counter::Tokenizer;
+
+/// A file that doesn't fit whole is only cut down to fit the remaining budget
+/// if at least this many tokens are left; otherwise it is dropped
+pub const MIN_EXCERPT_TOKENS: usize = 256;
+
+/// Share of an excerpt taken from the beginning of a file, where imports,
+/// build settings and declarations usually are
+const HEAD_SHARE: f64 = 2.0 / 3.0;
+
+/// Tokens set aside for the marker that replaces the elided lines
+const MARKER_TOKENS: usize = 16;
+
+/// A file included in a prompt, whole or as an excerpt
+#[derive(Debug, Clone, Serialize)]
+pub struct PackedFile {
+    pub path: String,
+    pub content: String,
+    /// Tokens in the included content
+    pub tokens: usize,
+    /// Tokens in the whole file
+    pub original_tokens: usize,
+    /// Whether the middle of the file was left out
+    pub excerpt: bool,
+}
+
+/// A file left out of a prompt because the budget was used up
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedFile {
+    pub path: String,
+    pub tokens: usize,
+}
+
+/// The file contents chosen for a prompt
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PackedContext {
+    pub files: Vec<PackedFile>,
+    pub dropped: Vec<DroppedFile>,
+    /// The token budget the files were packed into
+    pub budget: usize,
+}
+
+impl PackedContext {
+    /// Tokens used by the included files
+    pub fn tokens(&self) -> usize {
+        self.files.iter().map(|file| file.tokens).sum()
+    }
+
+    /// Paths and contents of the included files, in ranking order
+    pub fn contents(&self) -> Vec<(String, String)> {
+        self.files
+            .iter()
+            .map(|file| (file.path.clone(), file.content.clone()))
+            .collect()
+    }
+
+    /// Files included only as excerpts
+    pub fn excerpts(&self) -> impl Iterator<Item = &PackedFile> {
+        self.files.iter().filter(|file| file.excerpt)
+    }
+
+    /// What was included, cut down and dropped, for reasoning metadata
+    pub fn report(&self) -> serde_json::Value {
+        json!({
+            "budget": self.budget,
+            "tokens": self.tokens(),
+            "included": self.files.iter().map(|file| &file.path).collect::<Vec<_>>(),
+            "excerpts": self.excerpts().map(|file| &file.path).collect::<Vec<_>>(),
+            "dropped": self.dropped.iter().map(|file| &file.path).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Log what a stage's prompt will contain and what it leaves out
+    pub fn log_summary(&self, stage: &str) {
+        info!(
+            "{}: including {} files ({} of {} tokens)",
+            stage,
+            self.files.len(),
+            self.tokens(),
+            self.budget
+        );
+        for file in self.excerpts() {
+            info!(
+                "{}: {} cut down from {} to {} tokens",
+                stage, file.path, file.original_tokens, file.tokens
+            );
+        }
+        if !self.dropped.is_empty() {
+            let dropped: Vec<&str> = self.dropped.iter().map(|f| f.path.as_str()).collect();
+            info!(
+                "{}: left out {} files that didn't fit: {}",
+                stage,
+                dropped.len(),
+                dropped.join(", ")
+            );
+        }
+    }
+}
+
+/// The token budget for file contents: the configured budget, lowered so that
+/// the whole prompt fits the model's context window. `reserved` is what the
+/// rest of the prompt and the answer need.
+pub fn context_budget(
+    config: &ContextConfig,
+    context_window: Option<usize>,
+    reserved: usize,
+) -> usize {
+    match context_window {
+        Some(window) => config.max_tokens.min(window.saturating_sub(reserved)),
+        None => config.max_tokens,
+    }
+}
+
+/// Take files, most relevant first, until the budget is used up
+///
+/// Files longer than `max_file_tokens` are cut down to their beginning and
+/// end. A file that doesn't fit whole is cut down to the space left, or
+/// dropped if too little is left for a useful excerpt; smaller files after it
+/// may still fit.
+pub fn pack_files(
+    files: Vec<(String, String)>,
+    budget: usize,
+    max_file_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+) -> PackedContext {
+    let mut packed = PackedContext {
+        budget,
+        ..Default::default()
+    };
+    let mut used = 0;
+
+    for (path, content) in files {
+        let original_tokens = tokenizer.count_tokens(&content);
+        let limit = max_file_tokens.min(budget.saturating_sub(used));
+
+        if original_tokens <= limit {
+            used += original_tokens;
+            packed.files.push(PackedFile {
+                path,
+                content,
+                tokens: original_tokens,
+                original_tokens,
+                excerpt: false,
+            });
+        } else if limit >= MIN_EXCERPT_TOKENS {
+            let (content, tokens) = fit_excerpt(&content, original_tokens, limit, tokenizer);
+            used += tokens;
+            packed.files.push(PackedFile {
+                path,
+                content,
+                tokens,
+                original_tokens,
+                excerpt: true,
+            });
+        } else {
+            packed.dropped.push(DroppedFile {
+                path,
+                tokens: original_tokens,
+            });
+        }
+    }
+
+    packed
+}
+
+/// Cut a file down to its beginning and end, at most `max_tokens` long
+fn fit_excerpt(
+    content: &str,
+    tokens: usize,
+    max_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+) -> (String, usize) {
+    // Excerpts are sized from the file's average bytes per token, which is
+    // only an estimate, so shrink the target until the excerpt fits
+    let mut target = max_tokens;
+    loop {
+        let text = excerpt(content, tokens, target);
+        let excerpt_tokens = tokenizer.count_tokens(&text);
+        if excerpt_tokens <= max_tokens || target <= MIN_EXCERPT_TOKENS / 2 {
+            return (text, excerpt_tokens);
+        }
+        target = (target * max_tokens / excerpt_tokens).saturating_sub(1);
+    }
+}
+
+/// Keep about `max_tokens` tokens of a file, two thirds from its beginning
+/// and one third from its end, cut at line breaks where possible
+pub fn excerpt(content: &str, tokens: usize, max_tokens: usize) -> String {
+    let bytes_per_token = content.len() as f64 / tokens.max(1) as f64;
+    let keep = max_tokens.saturating_sub(MARKER_TOKENS) as f64 * bytes_per_token;
+    let head_len = (keep * HEAD_SHARE) as usize;
+    let tail_len = (keep * (1.0 - HEAD_SHARE)) as usize;
+
+    let head_end = head_end(content, head_len);
+    let tail_start = tail_start(content, content.len().saturating_sub(tail_len));
+    if tail_start <= head_end {
+        return content.to_string();
+    }
+
+    let elided = &content[head_end..tail_start];
+    let marker = match elided.lines().count() {
+        0 | 1 => format!("... [{} characters elided] ...", elided.chars().count()),
+        lines => format!("... [{} lines elided] ...", lines),
+    };
+    let head = &content[..head_end];
+    let separator = if head.is_empty() || head.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    format!(
+        "{}{}{}\n{}",
+        head,
+        separator,
+        marker,
+        &content[tail_start..]
+    )
+}
+
+/// End of the head: after the last line break before `len`, or at `len`
+/// itself when the head would otherwise lose more than half its length
+fn head_end(content: &str, len: usize) -> usize {
+    let len = floor_char_boundary(content, len.min(content.len()));
+    match content[..len].rfind('\n') {
+        Some(i) if i + 1 >= len / 2 => i + 1,
+        _ => len,
+    }
+}
+
+/// Start of the tail: after the first line break from `start`, or at
+/// `start` itself when the tail would otherwise lose more than half its length
+fn tail_start(content: &str, start: usize) -> usize {
+    let start = ceil_char_boundary(content, start);
+    let tail_len = content.len() - start;
+    match content[start..].find('\n') {
+        Some(i) if i < tail_len / 2 => start + i + 1,
+        _ => start,
+    }
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(s: &str, mut index: usize) -> usize {
+    while index < s.len() && !s.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
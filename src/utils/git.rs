@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::models::problem::SWEBenchProblem;
+
+/// The part of a git history to limit an analysis to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitScope {
+    /// Files changed between a revision and `HEAD`
+    Since(String),
+    /// Files a single commit changed
+    Commit(String),
+    /// Files staged for the next commit
+    Staged,
+}
+
+impl fmt::Display for GitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitScope::Since(rev) => write!(f, "since {}", rev),
+            GitScope::Commit(sha) => write!(f, "commit {}", sha),
+            GitScope::Staged => write!(f, "staged"),
+        }
+    }
+}
+
+/// Run git in a directory and return its output
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed in {:?}: {}",
+            args.join(" "),
+            repo,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The SHA of the commit checked out in a repository
+pub fn head_commit(repo: &Path) -> Result<String> {
+    Ok(git(repo, &["rev-parse", "HEAD"])?.trim().to_string())
+}
+
+/// Whether tracked files in a repository have changes that aren't committed
+pub fn is_dirty(repo: &Path) -> Result<bool> {
+    let status = git(repo, &["status", "--porcelain", "--untracked-files=no"])?;
+    Ok(!status.trim().is_empty())
+}
+
+/// The files a scope's diff touches, including deleted ones, relative to
+/// `repo`. Only changes under `repo` are listed, so it may be a subdirectory
+/// of the repository.
+pub fn changed_files(repo: &Path, scope: &GitScope) -> Result<Vec<String>> {
+    let output = match scope {
+        GitScope::Since(rev) => git(
+            repo,
+            &["diff", "--name-only", "-z", "--relative", rev, "HEAD"],
+        )?,
+        GitScope::Commit(sha) => {
+            // Diff against the first parent, so that a merge commit lists what
+            // it brought in; a root commit is diffed against the empty tree
+            let parents = git(repo, &["rev-list", "--parents", "-n", "1", sha])?;
+            let first_parent = parents.split_whitespace().nth(1);
+            let mut args = vec![
+                "diff-tree",
+                "-r",
+                "--root",
+                "--no-commit-id",
+                "--name-only",
+                "-z",
+                "--relative",
+            ];
+            args.extend(first_parent);
+            args.push(sha);
+            git(repo, &args)?
+        }
+        GitScope::Staged => git(
+            repo,
+            &["diff", "--cached", "--name-only", "-z", "--relative"],
+        )?,
+    };
+
+    Ok(output
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_string())
+        .collect())
+}
+
+/// Record the commit a problem's codebase is at, and whether it has changes
+/// that aren't committed, in its metadata as `git_commit` and `git_dirty`.
+/// Given a scope, also limit the problem to the files that scope touches and
+/// their neighbours, and record the scope as `git_scope`.
+pub fn scope_problem(
+    mut problem: SWEBenchProblem,
+    repo: &Path,
+    scope: Option<&GitScope>,
+) -> Result<SWEBenchProblem> {
+    // A codebase outside a repository is analyzed as it is, unless a scope
+    // needs its history
+    match head_commit(repo) {
+        Ok(commit) => {
+            let dirty = is_dirty(repo)?;
+            let state = if dirty { " with uncommitted changes" } else { "" };
+            info!("Analyzing commit {}{}", commit, state);
+            problem.metadata.insert("git_commit".to_string(), commit);
+            problem
+                .metadata
+                .insert("git_dirty".to_string(), dirty.to_string());
+        }
+        Err(e) if scope.is_none() => {
+            debug!("Not recording the git commit: {:#}", e);
+        }
+        Err(e) => return Err(e.context("Failed to read the git history to scope the analysis")),
+    }
+
+    let scope = match scope {
+        Some(scope) => scope,
+        None => return Ok(problem),
+    };
+
+    let changed = changed_files(repo, scope)
+        .context(format!("Failed to list the files changed ({})", scope))?;
+    if changed.is_empty() {
+        return Err(anyhow!("No files under {:?} changed ({})", repo, scope));
+    }
+    info!(
+        "Limiting the analysis to {} files changed ({}) and their neighbours",
+        changed.len(),
+        scope
+    );
+
+    problem
+        .metadata
+        .insert("git_scope".to_string(), scope.to_string());
+    Ok(problem.with_scope(changed))
+}
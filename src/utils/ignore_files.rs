@@ -0,0 +1,122 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the project-specific ignore file, read in every directory like
+/// `.gitignore`, for paths to leave out of the scan but not out of git
+pub const ENGINES_IGNORE_FILE: &str = ".enginesignore";
+
+/// Ignore files read in each directory, from the highest precedence
+const IGNORE_FILE_NAMES: &[&str] = &[ENGINES_IGNORE_FILE, ".ignore", ".gitignore"];
+
+/// The ignore files of a codebase, used to tell which rule ignores a path
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreFiles {
+    root: PathBuf,
+    /// Matchers for the ignore files in each directory added
+    by_dir: HashMap<PathBuf, Vec<Gitignore>>,
+    /// `.git/info/exclude` and the user's global git excludes, which have the
+    /// lowest precedence
+    repo: Vec<Gitignore>,
+}
+
+impl IgnoreFiles {
+    /// Load the repository's excludes and the ignore files in the root
+    pub fn new(root: &Path) -> Self {
+        let mut repo = Vec::new();
+        let exclude_path = root.join(".git").join("info").join("exclude");
+        if let Some(exclude) = load(root, &exclude_path) {
+            repo.push(exclude);
+        }
+        let (global, error) = GitignoreBuilder::new(root).build_global();
+        if let Some(error) = error {
+            warn!("Failed to read the global git excludes: {}", error);
+        }
+        if !global.is_empty() {
+            repo.push(global);
+        }
+
+        let mut ignore_files = Self {
+            root: root.to_path_buf(),
+            by_dir: HashMap::new(),
+            repo,
+        };
+        ignore_files.add_dir(root);
+        ignore_files
+    }
+
+    /// Load the ignore files in a directory of the codebase
+    pub fn add_dir(&mut self, dir: &Path) {
+        let matchers: Vec<Gitignore> = IGNORE_FILE_NAMES
+            .iter()
+            .filter_map(|name| load(dir, &dir.join(name)))
+            .collect();
+        if !matchers.is_empty() {
+            self.by_dir.insert(dir.to_path_buf(), matchers);
+        }
+    }
+
+    /// The rule that ignores a path, or one of the directories it is in, as
+    /// the ignore file and pattern, e.g. `pkg/.gitignore: build/`
+    pub fn ignoring_rule(&self, path: &Path, is_dir: bool) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let mut current = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let is_last = components.peek().is_none();
+            if let Some(rule) = self.matching_rule(&current, !is_last || is_dir) {
+                return Some(rule);
+            }
+        }
+        None
+    }
+
+    /// The rule that ignores a path itself, taking the ignore files nearest
+    /// to it first, so that e.g. a `!` pattern in a subdirectory can re-include
+    /// what the root `.gitignore` ignores
+    fn matching_rule(&self, path: &Path, is_dir: bool) -> Option<String> {
+        let dir_matchers = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .filter_map(|dir| self.by_dir.get(dir))
+            .flatten();
+
+        for matcher in dir_matchers.chain(&self.repo) {
+            match matcher.matched(path, is_dir) {
+                Match::Ignore(glob) => {
+                    let file = match glob.from() {
+                        Some(file) => file.strip_prefix(&self.root).unwrap_or(file),
+                        None => Path::new("global git excludes"),
+                    };
+                    return Some(format!("{}: {}", file.display(), glob.original()));
+                }
+                Match::Whitelist(_) => return None,
+                Match::None => {}
+            }
+        }
+        None
+    }
+}
+
+/// Load an ignore file, if there is one, with patterns relative to `dir`
+fn load(dir: &Path, path: &Path) -> Option<Gitignore> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(error) = builder.add(path) {
+        warn!("Failed to read {:?}: {}", path, error);
+    }
+    match builder.build() {
+        Ok(matcher) => Some(matcher),
+        Err(e) => {
+            warn!("Failed to parse {:?}: {}", path, e);
+            None
+        }
+    }
+}
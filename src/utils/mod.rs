@@ -1,3 +1,9 @@
+pub mod cassette;
+pub mod chunking;
+pub mod context_packer;
+pub mod git;
+pub mod ignore_files;
 pub mod json_utils;
 pub mod token_counter;
 pub mod trajectory_store;
+pub mod tree_renderer;
@@ -1,32 +1,233 @@
-/// Count the number of tokens in a string
-/// This is a very simplistic implementation - in a real app,
-/// you'd use a proper tokenizer library for your model type.
-pub fn count_tokens(text: &str) -> usize {
-    // Simple whitespace-based tokenization for demonstration
-    // In a real implementation, you'd use a tiktoken or similar library
-
-    // Avoid counting sequential whitespace as multiple tokens
-    let mut prev_was_space = true;
-    let mut count = 0;
-
-    for c in text.chars() {
-        if c.is_whitespace() {
-            if !prev_was_space {
-                count += 1;
-                prev_was_space = true;
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tiktoken_rs::CoreBPE;
+
+/// Claude's tokenizer isn't published, so Claude counts are approximated by
+/// scaling cl100k counts and rounding up. The ratio is an estimate, not a
+/// measurement: Claude models produce noticeably more tokens than cl100k for
+/// the same code and prose, and erring high means size limits are hit early
+/// rather than late. To recalibrate it, count a sample of typical source files
+/// with Anthropic's token counting endpoint (`POST /v1/messages/count_tokens`)
+/// and divide by the sample's cl100k count.
+pub const CLAUDE_TOKEN_RATIO: f64 = 1.15;
+
+/// Counts the tokens a model would see for a piece of text
+pub trait Tokenizer: Send + Sync {
+    /// A name identifying the tokenizer, e.g. "cl100k_base"
+    fn name(&self) -> &str;
+
+    /// Count the number of tokens in a string
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// Counts whitespace-separated words. Cheap, but badly wrong for minified
+/// code and languages that don't separate words with spaces.
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn name(&self) -> &str {
+        "whitespace"
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        // Avoid counting sequential whitespace as multiple tokens
+        let mut prev_was_space = true;
+        let mut count = 0;
+
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !prev_was_space {
+                    count += 1;
+                    prev_was_space = true;
+                }
+            } else if prev_was_space {
+                prev_was_space = false;
             }
-        } else if prev_was_space {
-            prev_was_space = false;
         }
+
+        // Add one more token if the text doesn't end with whitespace
+        if !prev_was_space {
+            count += 1;
+        }
+
+        count
     }
+}
 
-    // Add one more token if the text doesn't end with whitespace
-    if !prev_was_space {
-        count += 1;
+/// A byte-pair encoding tokenizer using one of OpenAI's vocabularies, which
+/// are bundled with the binary so counting never needs the network
+pub struct BpeTokenizer {
+    name: String,
+    bpe: CoreBPE,
+}
+
+impl BpeTokenizer {
+    /// The cl100k_base vocabulary used by GPT-4 and GPT-3.5
+    pub fn cl100k() -> anyhow::Result<Self> {
+        Ok(Self {
+            name: "cl100k_base".to_string(),
+            bpe: tiktoken_rs::cl100k_base()?,
+        })
     }
 
-    // This is a very approximate count - would need model-specific tokenization
-    count
+    /// The o200k_base vocabulary used by GPT-4o and later OpenAI models
+    pub fn o200k() -> anyhow::Result<Self> {
+        Ok(Self {
+            name: "o200k_base".to_string(),
+            bpe: tiktoken_rs::o200k_base()?,
+        })
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+/// Approximates Claude's token counts by scaling another tokenizer's counts
+pub struct ClaudeTokenizer {
+    base: Arc<dyn Tokenizer>,
+    ratio: f64,
+}
+
+impl ClaudeTokenizer {
+    pub fn new(base: Arc<dyn Tokenizer>, ratio: f64) -> Self {
+        Self { base, ratio }
+    }
+}
+
+impl Tokenizer for ClaudeTokenizer {
+    fn name(&self) -> &str {
+        "claude"
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        (self.base.count_tokens(text) as f64 * self.ratio).ceil() as usize
+    }
+}
+
+/// Remembers the token count of each distinct content, keyed by its hash, so
+/// files counted by several stages are only tokenized once
+pub struct CachedTokenizer {
+    inner: Arc<dyn Tokenizer>,
+    counts: Mutex<HashMap<[u8; 32], usize>>,
+}
+
+impl CachedTokenizer {
+    pub fn new(inner: Arc<dyn Tokenizer>) -> Self {
+        Self {
+            inner,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of distinct contents counted so far
+    pub fn len(&self) -> usize {
+        self.counts.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Tokenizer for CachedTokenizer {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        let hash: [u8; 32] = Sha256::digest(text.as_bytes()).into();
+        if let Some(&count) = self.counts.lock().unwrap().get(&hash) {
+            return count;
+        }
+
+        // Tokenize without holding the lock so other threads aren't blocked
+        let count = self.inner.count_tokens(text);
+        self.counts.lock().unwrap().insert(hash, count);
+        count
+    }
+}
+
+/// Load a bundled BPE vocabulary, falling back to counting words if it
+/// somehow can't be loaded
+fn load_bpe(load: fn() -> anyhow::Result<BpeTokenizer>) -> Arc<dyn Tokenizer> {
+    match load() {
+        Ok(tokenizer) => Arc::new(tokenizer),
+        Err(e) => {
+            warn!(
+                "Failed to load BPE tokenizer, counting words instead: {}",
+                e
+            );
+            Arc::new(WhitespaceTokenizer)
+        }
+    }
+}
+
+fn cl100k() -> Arc<dyn Tokenizer> {
+    static TOKENIZER: OnceLock<Arc<dyn Tokenizer>> = OnceLock::new();
+    TOKENIZER
+        .get_or_init(|| Arc::new(CachedTokenizer::new(load_bpe(BpeTokenizer::cl100k))))
+        .clone()
+}
+
+fn o200k() -> Arc<dyn Tokenizer> {
+    static TOKENIZER: OnceLock<Arc<dyn Tokenizer>> = OnceLock::new();
+    TOKENIZER
+        .get_or_init(|| Arc::new(CachedTokenizer::new(load_bpe(BpeTokenizer::o200k))))
+        .clone()
+}
+
+fn claude() -> Arc<dyn Tokenizer> {
+    static TOKENIZER: OnceLock<Arc<dyn Tokenizer>> = OnceLock::new();
+    TOKENIZER
+        .get_or_init(|| {
+            Arc::new(CachedTokenizer::new(Arc::new(ClaudeTokenizer::new(
+                load_bpe(BpeTokenizer::cl100k),
+                CLAUDE_TOKEN_RATIO,
+            ))))
+        })
+        .clone()
+}
+
+/// The tokenizer that best matches a model. Tokenizers are shared, so their
+/// count caches last for the whole run.
+pub fn tokenizer_for_model(model: &str) -> Arc<dyn Tokenizer> {
+    let model = model.to_lowercase();
+    let model = model.rsplit('/').next().unwrap_or(&model);
+
+    if model.starts_with("claude") {
+        claude()
+    } else if model.starts_with("gpt-4o")
+        || model.starts_with("gpt-4.1")
+        || model.starts_with("gpt-5")
+        || model.starts_with("chatgpt-4o")
+        || model.starts_with("o1")
+        || model.starts_with("o3")
+        || model.starts_with("o4")
+    {
+        o200k()
+    } else {
+        cl100k()
+    }
+}
+
+/// Count the number of tokens in a string as the given model would see them
+pub fn count_tokens_for_model(model: &str, text: &str) -> usize {
+    tokenizer_for_model(model).count_tokens(text)
+}
+
+/// Count the number of tokens in a string using the cl100k_base vocabulary,
+/// for when the model isn't known
+pub fn count_tokens(text: &str) -> usize {
+    cl100k().count_tokens(text)
 }
 
 /// Count tokens with fallback for empty strings
@@ -0,0 +1,412 @@
+use log::debug;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+use crate::utils::token_counter::Tokenizer;
+
+/// Extensions named in a collapsed directory's summary; the rest are counted
+/// together as "other"
+const SUMMARY_EXTENSIONS: usize = 3;
+
+/// How much of a codebase tree to render
+pub struct TreeBudget<'a> {
+    /// Most tokens the tree may take; 0 renders every path
+    pub max_tokens: usize,
+    pub tokenizer: &'a dyn Tokenizer,
+    /// Whether a file is build-related. Directories holding such files are
+    /// expanded first, and the files are listed even in directories too large
+    /// to list in full.
+    pub is_build_file: &'a dyn Fn(&str) -> bool,
+}
+
+/// Directories waiting to be expanded, ordered by depth, then whether they
+/// lack build-related files, then file count, then path
+type ExpansionQueue = BinaryHeap<Reverse<(usize, bool, usize, String)>>;
+
+/// How a directory's contents are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Listing {
+    /// A single line summarizing the files under the directory
+    Collapsed,
+    /// Subdirectories and every file
+    Full,
+    /// Subdirectories, build-related files and a summary of the other files
+    BuildFiles,
+}
+
+/// A directory and counts of everything under it
+#[derive(Debug, Default)]
+struct Dir {
+    /// Full paths of the direct subdirectories, sorted
+    subdirs: Vec<String>,
+    /// Full paths of the direct files, sorted
+    files: Vec<String>,
+    /// Files anywhere under the directory
+    file_count: usize,
+    /// Files anywhere under the directory, by extension
+    extensions: BTreeMap<String, usize>,
+    /// Whether any file under the directory is build-related
+    has_build_files: bool,
+}
+
+/// Render a tree of files and directories, with directories first and every
+/// level sorted by name. Paths are relative to the root and separated by
+/// `/`; directories holding files needn't be listed in `dirs`.
+///
+/// Given a budget, directories that don't fit are collapsed into a line
+/// counting their files by extension, such as
+/// `fixtures/ (1,204 files: .json ×1,100, .py ×104)`.
+pub fn render_tree(files: &[String], dirs: &[String], budget: Option<&TreeBudget>) -> String {
+    let mut tree = Tree::new(files, dirs);
+    match budget {
+        Some(budget) if budget.max_tokens > 0 => tree.render_within(budget),
+        _ => tree.render(),
+    }
+}
+
+/// A tree of files and directories, and how each directory is shown
+struct Tree {
+    dirs: HashMap<String, Dir>,
+    listings: HashMap<String, Listing>,
+    build_files: HashSet<String>,
+}
+
+impl Tree {
+    fn new(files: &[String], dirs: &[String]) -> Self {
+        let mut tree = Self {
+            dirs: HashMap::new(),
+            listings: HashMap::new(),
+            build_files: HashSet::new(),
+        };
+        tree.dirs.insert(String::new(), Dir::default());
+
+        for dir in dirs {
+            tree.add_dir(dir);
+        }
+        for file in files {
+            let parent = parent_dir(file);
+            tree.add_dir(parent);
+            tree.dirs
+                .get_mut(parent)
+                .expect("parent directory was just added")
+                .files
+                .push(file.clone());
+
+            let extension = extension(file);
+            for ancestor in ancestors(parent) {
+                let dir = tree.dirs.get_mut(ancestor).expect("ancestors are added");
+                dir.file_count += 1;
+                *dir.extensions.entry(extension.clone()).or_default() += 1;
+            }
+        }
+
+        for dir in tree.dirs.values_mut() {
+            dir.subdirs.sort();
+            dir.files.sort();
+        }
+        tree
+    }
+
+    /// Add a directory and its ancestors
+    fn add_dir(&mut self, path: &str) {
+        if self.dirs.contains_key(path) {
+            return;
+        }
+        let parent = parent_dir(path);
+        self.add_dir(parent);
+        self.dirs.insert(path.to_string(), Dir::default());
+        self.dirs
+            .get_mut(parent)
+            .expect("parent directory was just added")
+            .subdirs
+            .push(path.to_string());
+    }
+
+    /// Render every directory and file
+    fn render(&mut self) -> String {
+        self.listings = self
+            .dirs
+            .keys()
+            .map(|path| (path.clone(), Listing::Full))
+            .collect();
+        self.to_text()
+    }
+
+    /// Render as much of the tree as fits a token budget. Directories are
+    /// expanded shallowest first, and among directories at the same depth,
+    /// those with build-related files and then the smallest come first.
+    ///
+    /// Token counts are estimated line by line, so the rendered tree can be
+    /// slightly off the budget.
+    fn render_within(&mut self, budget: &TreeBudget) -> String {
+        self.build_files = self
+            .dirs
+            .values()
+            .flat_map(|dir| dir.files.iter())
+            .filter(|file| (budget.is_build_file)(file))
+            .cloned()
+            .collect();
+        for file in &self.build_files {
+            for ancestor in ancestors(parent_dir(file)) {
+                if let Some(dir) = self.dirs.get_mut(ancestor) {
+                    dir.has_build_files = true;
+                }
+            }
+        }
+
+        self.listings = self
+            .dirs
+            .keys()
+            .map(|path| (path.clone(), Listing::Collapsed))
+            .collect();
+
+        // The root is always expanded, if only to its build files
+        let mut used = self.line_tokens(budget, 0, "./");
+        let full = self.expansion_tokens(budget, "", Listing::Full);
+        let listing = if used + full <= budget.max_tokens {
+            used += full;
+            Listing::Full
+        } else {
+            used += self.expansion_tokens(budget, "", Listing::BuildFiles);
+            Listing::BuildFiles
+        };
+        self.listings.insert(String::new(), listing);
+
+        let mut queue = BinaryHeap::new();
+        self.queue_subdirs("", &mut queue);
+        let mut collapsed = 0;
+        while let Some(Reverse((_, _, _, path))) = queue.pop() {
+            let full = self.expansion_tokens(budget, &path, Listing::Full);
+            let listing = if used + full <= budget.max_tokens {
+                used += full;
+                Listing::Full
+            } else {
+                let partial = self.expansion_tokens(budget, &path, Listing::BuildFiles);
+                if used + partial > budget.max_tokens {
+                    collapsed += 1;
+                    continue;
+                }
+                used += partial;
+                Listing::BuildFiles
+            };
+            self.listings.insert(path.clone(), listing);
+            self.queue_subdirs(&path, &mut queue);
+        }
+
+        debug!(
+            "Rendered the codebase tree in about {} of {} tokens, with {} directories collapsed",
+            used, budget.max_tokens, collapsed
+        );
+        self.to_text()
+    }
+
+    /// Queue a directory's subdirectories to be expanded
+    fn queue_subdirs(&self, path: &str, queue: &mut ExpansionQueue) {
+        for subdir in &self.dirs[path].subdirs {
+            let dir = &self.dirs[subdir];
+            let depth = subdir.split('/').count();
+            queue.push(Reverse((
+                depth,
+                !dir.has_build_files,
+                dir.file_count,
+                subdir.clone(),
+            )));
+        }
+    }
+
+    /// Tokens a collapsed directory's lines grow by when it's expanded
+    fn expansion_tokens(&self, budget: &TreeBudget, path: &str, listing: Listing) -> usize {
+        let dir = &self.dirs[path];
+        let depth = if path.is_empty() {
+            0
+        } else {
+            path.split('/').count()
+        };
+
+        // The directory's own line loses its summary, which isn't counted so
+        // that estimates err high
+        let mut tokens = 0;
+        for subdir in &dir.subdirs {
+            tokens += self.line_tokens(budget, depth + 1, &self.dir_label(subdir));
+        }
+        let (shown, hidden) = self.split_files(path, listing);
+        for file in shown {
+            tokens += self.line_tokens(budget, depth + 1, file_name(file));
+        }
+        if !hidden.is_empty() {
+            tokens += self.line_tokens(budget, depth + 1, &more_files_label(&hidden));
+        }
+        tokens
+    }
+
+    /// Estimated tokens for a line of the tree at a depth
+    fn line_tokens(&self, budget: &TreeBudget, depth: usize, label: &str) -> usize {
+        let line = format!("{}├── {}\n", "│   ".repeat(depth), label);
+        budget.tokenizer.count_tokens(&line)
+    }
+
+    /// A directory's files, split into those listed and those summarized
+    fn split_files(&self, path: &str, listing: Listing) -> (Vec<&String>, Vec<&String>) {
+        let files = &self.dirs[path].files;
+        match listing {
+            Listing::Full => (files.iter().collect(), Vec::new()),
+            Listing::Collapsed => (Vec::new(), files.iter().collect()),
+            Listing::BuildFiles => files
+                .iter()
+                .partition(|file| self.build_files.contains(*file)),
+        }
+    }
+
+    /// The line for a directory as it's currently shown
+    fn dir_label(&self, path: &str) -> String {
+        let dir = &self.dirs[path];
+        let name = if path.is_empty() {
+            "."
+        } else {
+            file_name(path)
+        };
+        let collapsed = self.listings.get(path) == Some(&Listing::Collapsed);
+        if collapsed && dir.file_count > 0 {
+            format!(
+                "{}/ ({})",
+                name,
+                summarize(dir.file_count, &dir.extensions, false)
+            )
+        } else {
+            format!("{}/", name)
+        }
+    }
+
+    /// The tree as currently expanded
+    fn to_text(&self) -> String {
+        let mut result = String::new();
+        self.write_dir("", "", true, &mut result);
+        result
+    }
+
+    /// Write a directory's line and, unless it's collapsed, its contents
+    fn write_dir(&self, path: &str, prefix: &str, is_last: bool, result: &mut String) {
+        let branch = if is_last { "└── " } else { "├── " };
+        result.push_str(&format!("{}{}{}\n", prefix, branch, self.dir_label(path)));
+
+        let listing = self.listings[path];
+        if listing == Listing::Collapsed {
+            return;
+        }
+
+        let child_prefix = if is_last {
+            format!("{}    ", prefix)
+        } else {
+            format!("{}│   ", prefix)
+        };
+
+        let dir = &self.dirs[path];
+        let (shown, hidden) = self.split_files(path, listing);
+        let mut lines: Vec<String> = shown
+            .iter()
+            .map(|file| file_name(file).to_string())
+            .collect();
+        if !hidden.is_empty() {
+            lines.push(more_files_label(&hidden));
+        }
+
+        let entries = dir.subdirs.len() + lines.len();
+        for (i, subdir) in dir.subdirs.iter().enumerate() {
+            self.write_dir(subdir, &child_prefix, i + 1 == entries, result);
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let branch = if dir.subdirs.len() + i + 1 == entries {
+                "└── "
+            } else {
+                "├── "
+            };
+            result.push_str(&format!("{}{}{}\n", child_prefix, branch, line));
+        }
+    }
+}
+
+/// The line standing in for files left out of a directory's listing
+fn more_files_label(files: &[&String]) -> String {
+    let mut extensions = BTreeMap::new();
+    for file in files {
+        *extensions.entry(extension(file)).or_default() += 1;
+    }
+    format!("… {}", summarize(files.len(), &extensions, true))
+}
+
+/// Count files by extension, e.g. "1,204 files: .json ×1,100, .py ×104", or
+/// "1,204 more files: ..." for files left out of a listing
+fn summarize(count: usize, extensions: &BTreeMap<String, usize>, more: bool) -> String {
+    let mut by_count: Vec<(&String, &usize)> = extensions.iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut parts: Vec<String> = by_count
+        .iter()
+        .take(SUMMARY_EXTENSIONS)
+        .map(|(extension, count)| format!("{} ×{}", extension, with_separators(**count)))
+        .collect();
+    let other: usize = by_count
+        .iter()
+        .skip(SUMMARY_EXTENSIONS)
+        .map(|(_, count)| **count)
+        .sum();
+    if other > 0 {
+        parts.push(format!("other ×{}", with_separators(other)));
+    }
+
+    let noun = if count == 1 { "file" } else { "files" };
+    let more = if more { " more" } else { "" };
+    format!(
+        "{}{} {}: {}",
+        with_separators(count),
+        more,
+        noun,
+        parts.join(", ")
+    )
+}
+
+/// Format a number with commas between thousands, e.g. "1,204"
+fn with_separators(number: usize) -> String {
+    let digits = number.to_string();
+    let mut result = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            result.push(',');
+        }
+        result.push(digit);
+    }
+    result
+}
+
+/// A file's extension with its dot, or "no extension"
+fn extension(path: &str) -> String {
+    match Path::new(path).extension() {
+        Some(extension) => format!(".{}", extension.to_string_lossy()),
+        None => "no extension".to_string(),
+    }
+}
+
+/// The last component of a path
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The directory a path is in, or "" for the root
+pub fn parent_dir(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..i])
+}
+
+/// A directory and each directory above it, ending with the root
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    let mut next = Some(path);
+    std::iter::from_fn(move || {
+        let current = next?;
+        next = if current.is_empty() {
+            None
+        } else {
+            Some(parent_dir(current))
+        };
+        Some(current)
+    })
+}
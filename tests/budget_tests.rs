@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use engine_builder::config::{BudgetConfig, Config, LLMConfig, StageBudget};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::budget::{
+    is_budget_exceeded, set_budget, BudgetAccountant, BudgetExceeded, BudgetedClient,
+};
+use engine_builder::llm::client::{ChatRequest, LLMClient, TokenCost, TokenUsage};
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::stages::relevance;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use std::sync::Arc;
+use tempfile::tempdir;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Each response uses 100 tokens
+const OK_BODY: &str = r#"{"content":[{"type":"text","text":"```json\n{\"status\": \"relevant\", \"summary\": \"Needed to build.\"}\n```"}],"usage":{"input_tokens":90,"output_tokens":10}}"#;
+
+/// Start a mock server that answers every request to the messages API with
+/// `OK_BODY`
+async fn start_mock_server() -> MockServer {
+    MockServer::messages()
+        .reply(Reply::json(OK_BODY))
+        .start()
+        .await
+}
+
+fn usage(total_tokens: usize) -> TokenUsage {
+    TokenUsage {
+        prompt_tokens: total_tokens,
+        total_tokens,
+        ..Default::default()
+    }
+}
+
+fn cost(total_cost: f64) -> TokenCost {
+    TokenCost {
+        total_cost,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_accountant_enforces_run_and_stage_limits() {
+    let budget = BudgetAccountant::new(BudgetConfig {
+        max_tokens: Some(1000),
+        max_usd: None,
+        stages: [(
+            "relevance".to_string(),
+            StageBudget {
+                max_tokens: Some(300),
+                max_usd: None,
+            },
+        )]
+        .into(),
+    });
+
+    budget.record("relevance", &usage(200), &cost(0.0));
+    assert!(budget.check("relevance").is_ok());
+
+    // The stage's own limit stops only that stage
+    budget.record("relevance", &usage(100), &cost(0.0));
+    let error = budget.check("relevance").unwrap_err();
+    assert!(is_budget_exceeded(&error));
+    assert!(error.to_string().contains("the relevance stage"));
+    assert!(budget.check("ranking").is_ok());
+
+    // The run's limit stops every stage
+    budget.record("ranking", &usage(700), &cost(0.0));
+    let error = budget.check("ranking").unwrap_err();
+    match error.downcast_ref::<BudgetExceeded>() {
+        Some(exceeded) => {
+            assert_eq!(exceeded.scope, "the run");
+            assert_eq!(exceeded.spent, "1000 tokens");
+        }
+        None => panic!("Expected a budget error, got {:?}", error),
+    }
+
+    assert_eq!(budget.total().usage.total_tokens, 1000);
+    assert_eq!(budget.stage("relevance").usage.total_tokens, 300);
+    assert_eq!(budget.stage("ranking").usage.total_tokens, 700);
+}
+
+#[test]
+fn test_accountant_enforces_dollar_limit() {
+    let budget = BudgetAccountant::new(BudgetConfig {
+        max_usd: Some(1.0),
+        ..Default::default()
+    });
+
+    budget.record("dockerfile", &usage(10), &cost(0.6));
+    assert!(budget.check("dockerfile").is_ok());
+
+    budget.record("scripts", &usage(10), &cost(0.6));
+    assert!(budget.check("dockerfile").is_err());
+    assert!((budget.total().cost - 1.2).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_budgeted_client_refuses_requests_once_spent() -> Result<()> {
+    let server = start_mock_server().await;
+    let config = LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(server.url()),
+        timeout: 5,
+        max_retries: 0,
+    };
+    let budget = Arc::new(BudgetAccountant::new(BudgetConfig {
+        max_tokens: Some(250),
+        ..Default::default()
+    }));
+    let client = BudgetedClient::new(
+        Box::new(AnthropicClient::new(&config)?),
+        "ranking",
+        budget.clone(),
+    );
+    let request = ChatRequest::new(100, 0.0).with_user("Hello");
+
+    // Requests are allowed until the spending reaches the limit
+    for _ in 0..3 {
+        client.chat(&request).await?;
+    }
+    let error = client.chat(&request).await.unwrap_err();
+    assert!(is_budget_exceeded(&error));
+
+    assert_eq!(server.request_count(), 3);
+    assert_eq!(budget.stage("ranking").usage.total_tokens, 300);
+    assert!(budget.stage("ranking").cost > 0.0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relevance_stops_when_budget_runs_out() -> Result<()> {
+    let server = start_mock_server().await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    for i in 0..5 {
+        std::fs::write(
+            codebase_dir.path().join(format!("file{}.rs", i)),
+            format!("fn f{}() {{}}\n", i),
+        )?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "relevance": { "max_workers": 1 },
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+        "budget": { "max_tokens": 250 },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    );
+
+    // Select every file for assessment
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    std::fs::create_dir_all(&trajectory_dir)?;
+    std::fs::write(
+        std::path::Path::new(&trajectory_dir).join("codebase_tree_response.txt"),
+        "```json\n[\"*.rs\"]\n```",
+    )
+    .context("Failed to write file selection response")?;
+
+    set_budget(Some(Arc::new(BudgetAccountant::new(config.budget.clone()))));
+    let result = relevance::process_codebase(&config, &config.codebase, problem.clone()).await;
+    set_budget(None);
+
+    let error = result.unwrap_err();
+    assert!(is_budget_exceeded(&error));
+    assert_eq!(server.request_count(), 3);
+
+    // The decisions made before the budget ran out are saved
+    let trajectory_store = TrajectoryStore::new(&trajectory_dir, &problem)?;
+    let assessed = (0..5)
+        .filter(|i| trajectory_store.relevance_decision_exists(&format!("file{}.rs", i)))
+        .count();
+    assert_eq!(assessed, 3);
+
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use anyhow::Result;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{create_client, ChatRequest, LLMClient};
+use engine_builder::llm::error::{is_context_too_long, retry_with_fewer_files};
+use engine_builder::llm::local::LocalClient;
+use engine_builder::llm::replay::{RecordingClient, ReplayingClient};
+use engine_builder::utils::cassette::{set_cassette, Cassette};
+use std::process::Command;
+use std::sync::Arc;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Start a mock server that answers requests to the messages API with
+/// "reply N", where N counts the requests received
+async fn start_mock_server() -> MockServer {
+    MockServer::messages()
+        .respond_with(|_, index| {
+            Reply::json(format!(
+                r#"{{"content":[{{"type":"text","text":"reply {}"}}],"usage":{{"input_tokens":10,"output_tokens":2}}}}"#,
+                index + 1
+            ))
+        })
+        .start()
+        .await
+}
+
+fn test_config(base_url: &str) -> LLMConfig {
+    LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url.to_string()),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+/// A base URL nothing is listening on
+fn unreachable_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn request(content: &str) -> ChatRequest {
+    ChatRequest::new(100, 0.0).with_user(content)
+}
+
+#[tokio::test]
+async fn test_replays_recorded_exchanges() -> Result<()> {
+    let server = start_mock_server().await;
+    let dir = tempfile::tempdir()?;
+
+    let recorder = RecordingClient::new(
+        Box::new(AnthropicClient::new(&test_config(&server.url()))?),
+        Arc::new(Cassette::record(dir.path())?),
+    );
+    recorder.chat(&request("first")).await?;
+    recorder.chat(&request("second")).await?;
+    recorder.chat(&request("first")).await?;
+    drop(recorder);
+    assert_eq!(server.request_count(), 3);
+
+    // Requests are matched by content, so they may be replayed in any order;
+    // repeated requests get their responses in the recorded order
+    let replayer = ReplayingClient::new(
+        &test_config(&unreachable_url()),
+        Arc::new(Cassette::replay(dir.path())?),
+    );
+    assert_eq!(replayer.chat(&request("second")).await?.content, "reply 2");
+    let first = replayer.chat(&request("first")).await?;
+    assert_eq!(first.content, "reply 1");
+    assert_eq!(first.usage.completion_tokens, 2);
+    assert_eq!(replayer.chat(&request("first")).await?.content, "reply 3");
+
+    // A request beyond the recording means the run has diverged
+    let error = replayer.chat(&request("first")).await.unwrap_err();
+    assert!(error.to_string().contains("diverged"));
+    let error = replayer.chat(&request("third")).await.unwrap_err();
+    assert!(error.to_string().contains("diverged"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replays_recorded_errors() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let recorder = RecordingClient::new(
+        Box::new(AnthropicClient::new(&test_config(&unreachable_url()))?),
+        Arc::new(Cassette::record(dir.path())?),
+    );
+    let recorded = recorder.chat(&request("hello")).await.unwrap_err();
+    drop(recorder);
+
+    let replayer = ReplayingClient::new(
+        &test_config(&unreachable_url()),
+        Arc::new(Cassette::replay(dir.path())?),
+    );
+    let replayed = replayer.chat(&request("hello")).await.unwrap_err();
+    assert_eq!(replayed.to_string(), format!("{:#}", recorded));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replayed_context_overflow_still_falls_back_to_fewer_files() -> Result<()> {
+    // The prompt only fits with two files or fewer
+    let server = MockServer::messages()
+        .respond_with(|request, _| {
+            if request.body.matches("file ").count() > 2 {
+                Reply::status(
+                    "400 Bad Request",
+                    r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 250000 tokens > 200000 maximum"}}"#,
+                )
+            } else {
+                Reply::text("done")
+            }
+        })
+        .start()
+        .await;
+    let dir = tempfile::tempdir()?;
+
+    /// Ask with the first `count` of four files, returning how many fit
+    async fn generate(client: &dyn LLMClient, count: usize) -> Result<usize> {
+        let files: Vec<String> = (0..count).map(|i| format!("file {}", i)).collect();
+        client.chat(&request(&files.join("\n"))).await?;
+        Ok(count)
+    }
+
+    let recorder = RecordingClient::new(
+        Box::new(AnthropicClient::new(&test_config(&server.url()))?),
+        Arc::new(Cassette::record(dir.path())?),
+    );
+    let recorded = retry_with_fewer_files(4, |count| generate(&recorder, count)).await?;
+    drop(recorder);
+    assert_eq!(recorded, 2);
+    assert_eq!(server.request_count(), 3);
+
+    // The replayed errors are still context overflows, so the fallback takes
+    // the same path without reaching the server
+    let replayer = || -> Result<ReplayingClient> {
+        Ok(ReplayingClient::new(
+            &test_config(&unreachable_url()),
+            Arc::new(Cassette::replay(dir.path())?),
+        ))
+    };
+    let error = generate(&replayer()?, 4).await.unwrap_err();
+    assert!(is_context_too_long(&error));
+    let replayer = replayer()?;
+    let replayed = retry_with_fewer_files(4, |count| generate(&replayer, count)).await?;
+    assert_eq!(replayed, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streamed_exchanges_are_replayed() -> Result<()> {
+    let server = start_mock_server().await;
+    let dir = tempfile::tempdir()?;
+
+    let recorder = RecordingClient::new(
+        Box::new(AnthropicClient::new(&test_config(&server.url()))?),
+        Arc::new(Cassette::record(dir.path())?),
+    );
+    let recorded = recorder.chat(&request("hello")).await?;
+    drop(recorder);
+
+    let replayer = ReplayingClient::new(
+        &test_config(&unreachable_url()),
+        Arc::new(Cassette::replay(dir.path())?),
+    );
+    let mut deltas = String::new();
+    let replayed = replayer
+        .chat_stream_with_tracing(&request("hello"), None, None, None, &mut |delta| {
+            deltas.push_str(delta)
+        })
+        .await?;
+    assert_eq!(replayed.content, recorded.content);
+    assert_eq!(deltas, recorded.content);
+
+    Ok(())
+}
+
+#[test]
+fn test_replays_commands_without_running_them() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let marker = dir.path().join("marker");
+    let script = format!(
+        "echo ran >> {}; echo out; echo err >&2; exit 3",
+        marker.display()
+    );
+    let command = || {
+        let mut command = Command::new("sh");
+        command.args(["-c", &script]);
+        command
+    };
+
+    let recorded = Cassette::record(dir.path())?.run_command(&mut command())?;
+    assert_eq!(recorded.stdout, "out\n");
+    assert_eq!(recorded.stderr, "err\n");
+    assert_eq!(recorded.exit_code, 3);
+    assert!(!recorded.success());
+
+    let cassette = Cassette::replay(dir.path())?;
+    let replayed = cassette.run_command(&mut command())?;
+    assert_eq!(replayed.stdout, recorded.stdout);
+    assert_eq!(replayed.stderr, recorded.stderr);
+    assert_eq!(replayed.exit_code, recorded.exit_code);
+    assert_eq!(std::fs::read_to_string(&marker)?, "ran\n");
+
+    // Commands that weren't recorded aren't run either
+    let error = cassette
+        .run_command(Command::new("sh").args(["-c", "exit 0"]))
+        .unwrap_err();
+    assert!(error.to_string().contains("diverged"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_created_clients_replay_the_current_cassette() -> Result<()> {
+    let server = start_mock_server().await;
+    let dir = tempfile::tempdir()?;
+
+    set_cassette(Some(Arc::new(Cassette::record(dir.path())?)));
+    let recorded = create_client(&test_config(&server.url()))
+        .await?
+        .chat(&request("hello"))
+        .await?;
+
+    // Whoever replays the run may have no credentials for the provider
+    let config = LLMConfig {
+        api_key: String::new(),
+        ..test_config(&unreachable_url())
+    };
+    set_cassette(Some(Arc::new(Cassette::replay(dir.path())?)));
+    let replayer = create_client(&config).await;
+    set_cassette(None);
+
+    let replayed = replayer?.chat(&request("hello")).await?;
+    assert_eq!(replayed.content, recorded.content);
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replays_discovered_context_window() -> Result<()> {
+    // A local server that only tells the context length when asked
+    let server = MockServer::builder()
+        .respond_with(|request, _| {
+            if request.head.starts_with("GET /v1/models") {
+                Reply::json(r#"{"data":[{"id":"llama3.1:8b","max_model_len":32768}]}"#)
+            } else {
+                Reply::not_found()
+            }
+        })
+        .start()
+        .await;
+    let dir = tempfile::tempdir()?;
+    let config = |base_url: String| LLMConfig {
+        model_type: "local".to_string(),
+        model: "llama3.1:8b".to_string(),
+        api_key: String::new(),
+        base_url: Some(format!("{}/v1", base_url)),
+        timeout: 5,
+        max_retries: 0,
+    };
+
+    let client = LocalClient::new(&config(server.url()))?;
+    client.fetch_model_info().await?;
+    drop(RecordingClient::new(
+        Box::new(client),
+        Arc::new(Cassette::record(dir.path())?),
+    ));
+
+    let replayer = ReplayingClient::new(
+        &config(unreachable_url()),
+        Arc::new(Cassette::replay(dir.path())?),
+    );
+    assert_eq!(replayer.context_window(), Some(32768));
+    assert_eq!(replayer.name(), "local");
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{ChatRequest, LLMClient, Message, Role};
+
+mod common;
+use common::{MockServer, Reply};
+
+const OK_BODY: &str =
+    r#"{"content":[{"type":"text","text":"ok"}],"usage":{"input_tokens":10,"output_tokens":2}}"#;
+
+fn anthropic_config(base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+#[test]
+fn test_chat_request_builder() {
+    let request = ChatRequest::new(1000, 0.5)
+        .with_system("Be helpful")
+        .with_user("Hello")
+        .with_message(Message::assistant("Hi there"));
+
+    assert_eq!(request.system.as_deref(), Some("Be helpful"));
+    assert_eq!(request.messages.len(), 2);
+    assert_eq!(request.messages[0].role, Role::User);
+    assert_eq!(request.messages[1].role, Role::Assistant);
+    assert_eq!(request.max_tokens, 1000);
+    assert_eq!(request.temperature, 0.5);
+}
+
+#[test]
+fn test_chat_request_to_prompt() {
+    // A lone user message is passed through untouched
+    let request = ChatRequest::new(100, 0.0).with_user("  indented\n    more");
+    assert_eq!(request.to_prompt(), "  indented\n    more");
+
+    // Otherwise the roles are labelled
+    let request = ChatRequest::new(100, 0.0)
+        .with_system("System prompt")
+        .with_user("Question");
+    assert_eq!(
+        request.to_prompt(),
+        "System: System prompt\n\nHuman: Question"
+    );
+}
+
+#[tokio::test]
+async fn test_anthropic_chat_sends_typed_messages() -> Result<()> {
+    let server = MockServer::builder()
+        .reply(Reply::json(OK_BODY))
+        .start()
+        .await;
+    let client = AnthropicClient::new(&anthropic_config(server.url()))?;
+
+    let yaml = "services:\n  app:\n    image: rust:1.75\n";
+    let request = ChatRequest::new(500, 0.0)
+        .with_system("You write Dockerfiles")
+        .with_user(format!("Human: this line looks like a role\n{}", yaml));
+
+    let response = client.chat(&request).await?;
+    assert_eq!(response.content, "ok");
+    assert_eq!(response.usage.total_tokens, 12);
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+    assert_eq!(body["system"], "You write Dockerfiles");
+    assert_eq!(body["max_tokens"], 500);
+
+    // The user content is sent verbatim, indentation and all
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(
+        messages[0]["content"],
+        format!("Human: this line looks like a role\n{}", yaml)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_shim_sends_single_user_message() -> Result<()> {
+    let server = MockServer::builder()
+        .reply(Reply::json(OK_BODY))
+        .start()
+        .await;
+    let client = AnthropicClient::new(&anthropic_config(server.url()))?;
+
+    client.completion("    fn main() {}", 100, 0.0).await?;
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+    assert!(body.get("system").is_none());
+    assert_eq!(body["messages"][0]["content"], "    fn main() {}");
+
+    Ok(())
+}
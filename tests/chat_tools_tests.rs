@@ -0,0 +1,272 @@
+use anyhow::Result;
+use engine_builder::chat::tools::{find_tool, get_tools, ParameterType, ToolValue};
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{ChatRequest, LLMClient, Message, ToolCall, ToolCallResult};
+use engine_builder::llm::openai::OpenAIClient;
+use serde_json::json;
+
+mod common;
+use common::{MockServer, Reply};
+
+fn test_config(model_type: &str, model: &str, base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: model_type.to_string(),
+        model: model.to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+/// A conversation where the model called `run_all` and got its result back
+fn tool_round_request() -> ChatRequest {
+    let call = ToolCall {
+        id: "call_1".to_string(),
+        name: "run_all".to_string(),
+        input: json!({ "tag": "my-image", "parallel": true }),
+    };
+
+    ChatRequest::new(500, 0.0)
+        .with_system("You run tools")
+        .with_user("Run everything")
+        .with_message(Message::assistant_tool_calls(
+            "Running them now.",
+            vec![call],
+        ))
+        .with_message(Message::tool_results(vec![ToolCallResult {
+            tool_call_id: "call_1".to_string(),
+            content: "Lint: SUCCESS, Test: FAILED (exit code: 1)".to_string(),
+            is_error: true,
+        }]))
+        .with_tools(get_tools().iter().map(|tool| tool.definition()).collect())
+}
+
+#[test]
+fn test_tool_definition_schema() {
+    let tool = find_tool("run_all").unwrap();
+    let definition = tool.definition();
+
+    assert_eq!(definition.name, "run_all");
+    let schema = &definition.input_schema;
+    assert_eq!(schema["type"], "object");
+    assert_eq!(schema["properties"]["tag"]["type"], "string");
+    assert_eq!(
+        schema["properties"]["tag"]["default"],
+        "engine-builder-test"
+    );
+    assert_eq!(schema["properties"]["parallel"]["type"], "boolean");
+    assert_eq!(schema["properties"]["parallel"]["default"], false);
+    assert_eq!(schema["properties"]["timeout"]["type"], "integer");
+    assert!(schema["properties"]["timeout"].get("default").is_none());
+    assert_eq!(schema["required"], json!([]));
+
+    // Every tool has a valid object schema, including those without parameters
+    for tool in get_tools() {
+        let definition = tool.definition();
+        assert_eq!(definition.input_schema["type"], "object", "{}", tool.name);
+        assert!(definition.input_schema["properties"].is_object());
+    }
+}
+
+#[test]
+fn test_parse_arguments_applies_types_and_defaults() -> Result<()> {
+    let tool = find_tool("run_all").unwrap();
+    assert_eq!(
+        tool.parameters["timeout"].parameter_type,
+        ParameterType::Integer
+    );
+
+    // Values containing commas and parentheses are passed through intact
+    let arguments = tool.parse_arguments(&json!({
+        "tag": "app(v1), latest",
+        "parallel": true,
+        "timeout": 120,
+    }))?;
+    assert_eq!(arguments.string("tag"), Some("app(v1), latest"));
+    assert_eq!(arguments.boolean("parallel"), Some(true));
+    assert_eq!(arguments.integer("timeout"), Some(120));
+
+    // Missing parameters fall back to their defaults
+    let arguments = tool.parse_arguments(&json!({}))?;
+    assert_eq!(arguments.string("tag"), Some("engine-builder-test"));
+    assert_eq!(arguments.boolean("parallel"), Some(false));
+    assert_eq!(arguments.integer("timeout"), None);
+
+    // Quoted booleans and integers are accepted
+    let arguments = tool.parse_arguments(&json!({ "parallel": "TRUE", "timeout": "60" }))?;
+    assert_eq!(arguments.boolean("parallel"), Some(true));
+    assert_eq!(arguments.integer("timeout"), Some(60));
+
+    // Tools without parameters accept a null input
+    let relevance = find_tool("relevance").unwrap();
+    relevance.parse_arguments(&serde_json::Value::Null)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_arguments_rejects_invalid_input() {
+    let tool = find_tool("run_all").unwrap();
+
+    let err = tool
+        .parse_arguments(&json!({ "parallel": "sometimes" }))
+        .unwrap_err();
+    assert!(err.to_string().contains("must be of type boolean"));
+
+    let err = tool
+        .parse_arguments(&json!({ "timeout": 1.5 }))
+        .unwrap_err();
+    assert!(err.to_string().contains("must be of type integer"));
+
+    let err = tool.parse_arguments(&json!({ "tga": "typo" })).unwrap_err();
+    assert!(err.to_string().contains("Unknown parameter 'tga'"));
+
+    assert!(tool.parse_arguments(&json!(["my-image"])).is_err());
+}
+
+#[test]
+fn test_tool_value_serialization() {
+    assert_eq!(
+        serde_json::to_value(ToolValue::String("a".to_string())).unwrap(),
+        json!("a")
+    );
+    assert_eq!(
+        serde_json::to_value(ToolValue::Boolean(true)).unwrap(),
+        json!(true)
+    );
+    assert_eq!(
+        serde_json::to_value(ToolValue::Integer(3)).unwrap(),
+        json!(3)
+    );
+}
+
+#[tokio::test]
+async fn test_anthropic_tool_use_round_trip() -> Result<()> {
+    let server = MockServer::builder()
+        .reply(Reply::json(
+            r#"{"content":[
+            {"type":"text","text":"Building the image first."},
+            {"type":"tool_use","id":"toolu_2","name":"build_image","input":{"tag":"my-image"}}
+        ],"stop_reason":"tool_use","usage":{"input_tokens":100,"output_tokens":20}}"#,
+        ))
+        .start()
+        .await;
+    let client = AnthropicClient::new(&test_config(
+        "anthropic",
+        "claude-3-7-sonnet-20250219",
+        server.url(),
+    ))?;
+
+    let response = client.chat(&tool_round_request()).await?;
+
+    // The tool call comes back typed
+    assert_eq!(response.content, "Building the image first.");
+    assert_eq!(
+        response.tool_calls,
+        vec![ToolCall {
+            id: "toolu_2".to_string(),
+            name: "build_image".to_string(),
+            input: json!({ "tag": "my-image" }),
+        }]
+    );
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+
+    // Tools are sent as schemas
+    let tools = body["tools"].as_array().unwrap();
+    assert_eq!(tools.len(), get_tools().len());
+    let run_all = tools.iter().find(|t| t["name"] == "run_all").unwrap();
+    assert_eq!(
+        run_all["input_schema"]["properties"]["parallel"]["type"],
+        "boolean"
+    );
+
+    // Plain messages keep string content; tool calls and results become blocks
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0]["content"], "Run everything");
+    assert_eq!(
+        messages[1]["content"],
+        json!([
+            { "type": "text", "text": "Running them now." },
+            {
+                "type": "tool_use",
+                "id": "call_1",
+                "name": "run_all",
+                "input": { "tag": "my-image", "parallel": true },
+            },
+        ])
+    );
+    assert_eq!(messages[2]["role"], "user");
+    assert_eq!(
+        messages[2]["content"],
+        json!([{
+            "type": "tool_result",
+            "tool_use_id": "call_1",
+            "content": "Lint: SUCCESS, Test: FAILED (exit code: 1)",
+            "is_error": true,
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_openai_tool_use_round_trip() -> Result<()> {
+    let server = MockServer::builder()
+        .reply(Reply::json(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[
+            {"id":"call_2","type":"function","function":{"name":"run_test","arguments":"{\"timeout\":90}"}}
+        ]},"finish_reason":"tool_calls"}],
+        "usage":{"prompt_tokens":80,"completion_tokens":15,"total_tokens":95}}"#,
+        ))
+        .start()
+        .await;
+    let client = OpenAIClient::new(&test_config("openai", "gpt-4o", server.url()))?;
+
+    let response = client.chat(&tool_round_request()).await?;
+
+    // Null content is fine when the model only calls tools
+    assert_eq!(response.content, "");
+    assert_eq!(response.tool_calls.len(), 1);
+    assert_eq!(response.tool_calls[0].id, "call_2");
+    assert_eq!(response.tool_calls[0].name, "run_test");
+    assert_eq!(response.tool_calls[0].input, json!({ "timeout": 90 }));
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+
+    // Tools are sent as functions
+    let run_all = body["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["function"]["name"] == "run_all")
+        .unwrap();
+    assert_eq!(run_all["type"], "function");
+    assert_eq!(
+        run_all["function"]["parameters"]["properties"]["timeout"]["type"],
+        "integer"
+    );
+
+    // The assistant's call carries JSON-encoded arguments, and its result is a tool message
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["role"], "system");
+    assert_eq!(messages[2]["role"], "assistant");
+    assert_eq!(messages[2]["content"], "Running them now.");
+    assert_eq!(messages[2]["tool_calls"][0]["id"], "call_1");
+    let arguments: serde_json::Value = serde_json::from_str(
+        messages[2]["tool_calls"][0]["function"]["arguments"]
+            .as_str()
+            .unwrap(),
+    )?;
+    assert_eq!(arguments, json!({ "tag": "my-image", "parallel": true }));
+    assert_eq!(messages[3]["role"], "tool");
+    assert_eq!(messages[3]["tool_call_id"], "call_1");
+
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//! Helpers shared by the integration tests: a minimal HTTP server with canned
+//! replies, and fixture factories for codebases on disk.
+//!
+//! Each test crate uses only some of these, so unused ones aren't warned about.
+#![allow(dead_code)]
+
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The request line and headers
+    pub head: String,
+    pub body: String,
+}
+
+impl Request {
+    /// The body parsed as JSON, or null if it isn't JSON
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_default()
+    }
+}
+
+/// A reply for the mock server to send
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: String,
+    pub content_type: String,
+    /// Extra header lines, each ending in `\r\n`
+    pub headers: String,
+    pub body: String,
+    /// How long to wait before answering
+    pub delay: Duration,
+}
+
+impl Reply {
+    /// A reply with a status line and a JSON body
+    pub fn status(status: &str, body: impl Into<String>) -> Self {
+        Self {
+            status: status.to_string(),
+            content_type: "application/json".to_string(),
+            headers: String::new(),
+            body: body.into(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A successful reply with a JSON body
+    pub fn json(body: impl Into<String>) -> Self {
+        Self::status("200 OK", body)
+    }
+
+    /// A successful reply with a server-sent event stream
+    pub fn sse(body: impl Into<String>) -> Self {
+        Self {
+            content_type: "text/event-stream".to_string(),
+            ..Self::json(body)
+        }
+    }
+
+    /// A messages API answer with the given text
+    pub fn text(answer: &str) -> Self {
+        Self::json(
+            json!({
+                "content": [{"type": "text", "text": answer}],
+                "usage": {"input_tokens": 90, "output_tokens": 10},
+            })
+            .to_string(),
+        )
+    }
+
+    pub fn not_found() -> Self {
+        Self::status("404 Not Found", "{}")
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push_str(&format!("{}: {}\r\n", name, value));
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// Decides the reply to the nth request (from 0) to the routed paths
+type Responder = Arc<dyn Fn(&Request, usize) -> Reply + Send + Sync>;
+
+/// Builds a [`MockServer`]
+pub struct MockServerBuilder {
+    /// Starts of the request lines answered, e.g. "POST /v1/messages"; the
+    /// rest get a 404. Empty answers every request.
+    routes: Vec<String>,
+    responder: Responder,
+    /// Write replies in pieces of this many bytes, so events arrive split
+    /// across reads
+    chunk_size: Option<usize>,
+}
+
+impl MockServerBuilder {
+    /// Answer requests whose request line starts with `route`
+    pub fn route(mut self, route: &str) -> Self {
+        self.routes.push(route.to_string());
+        self
+    }
+
+    /// Answer requests with the replies in order, repeating the last one
+    pub fn replies(mut self, replies: Vec<Reply>) -> Self {
+        assert!(!replies.is_empty(), "a mock server needs a reply");
+        self.responder = Arc::new(move |_, index| replies[index.min(replies.len() - 1)].clone());
+        self
+    }
+
+    /// Answer every request with the same reply
+    pub fn reply(self, reply: Reply) -> Self {
+        self.replies(vec![reply])
+    }
+
+    /// Answer requests with messages API answers with these texts in order,
+    /// repeating the last one
+    pub fn answers(self, answers: &[&str]) -> Self {
+        self.replies(answers.iter().map(|answer| Reply::text(answer)).collect())
+    }
+
+    /// Decide each reply from the request and how many came before it
+    pub fn respond_with(
+        mut self,
+        responder: impl Fn(&Request, usize) -> Reply + Send + Sync + 'static,
+    ) -> Self {
+        self.responder = Arc::new(responder);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Start answering requests in the background
+    pub async fn start(self) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(_) => return,
+                };
+                let request = read_request(&mut socket).await;
+
+                let routed = self.routes.is_empty()
+                    || self
+                        .routes
+                        .iter()
+                        .any(|route| request.head.starts_with(route.as_str()));
+                let reply = if routed {
+                    let mut received = received.lock().unwrap();
+                    received.push(request.clone());
+                    (self.responder)(&request, received.len() - 1)
+                } else {
+                    Reply::not_found()
+                };
+
+                // Answer each connection separately so a slow reply doesn't
+                // block the next request
+                let chunk_size = self.chunk_size;
+                tokio::spawn(async move {
+                    tokio::time::sleep(reply.delay).await;
+                    write_reply(&mut socket, &reply, chunk_size).await;
+                });
+            }
+        });
+
+        MockServer { url, requests }
+    }
+}
+
+/// Read the headers, then as much body as Content-Length says
+async fn read_request(socket: &mut tokio::net::TcpStream) -> Request {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut buf).await.unwrap_or(0);
+        if n == 0 {
+            return Request {
+                head: String::from_utf8_lossy(&data).to_string(),
+                body: String::new(),
+            };
+        }
+        data.extend_from_slice(&buf[..n]);
+
+        let text = String::from_utf8_lossy(&data).to_string();
+        if let Some(header_end) = text.find("\r\n\r\n") {
+            let content_length = text[..header_end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            let body_start = header_end + 4;
+            if data.len() >= body_start + content_length {
+                return Request {
+                    head: text[..header_end].to_string(),
+                    body: String::from_utf8_lossy(&data[body_start..body_start + content_length])
+                        .to_string(),
+                };
+            }
+        }
+    }
+}
+
+async fn write_reply(socket: &mut tokio::net::TcpStream, reply: &Reply, chunk_size: Option<usize>) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reply.content_type,
+        reply.headers,
+        reply.body.len(),
+        reply.body
+    );
+    match chunk_size {
+        Some(size) => {
+            for chunk in response.as_bytes().chunks(size) {
+                let _ = socket.write_all(chunk).await;
+                let _ = socket.flush().await;
+            }
+        }
+        None => {
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    }
+}
+
+/// A minimal HTTP server that answers with canned replies and records the
+/// requests it answered
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    /// A server answering only the messages API, and 404 for any other path
+    pub fn messages() -> MockServerBuilder {
+        Self::builder().route("POST /v1/messages")
+    }
+
+    /// A server answering every request, with an "ok" messages API answer
+    /// unless told otherwise
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder {
+            routes: Vec::new(),
+            responder: Arc::new(|_, _| Reply::text("ok")),
+            chunk_size: None,
+        }
+    }
+
+    /// The base URL, e.g. "http://127.0.0.1:4000"
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The requests answered so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// The bodies of the requests answered so far
+    pub fn bodies(&self) -> Vec<String> {
+        self.requests()
+            .into_iter()
+            .map(|request| request.body)
+            .collect()
+    }
+
+    /// The bodies of the requests answered so far, parsed as JSON
+    pub fn json_bodies(&self) -> Vec<serde_json::Value> {
+        self.requests().iter().map(Request::json).collect()
+    }
+}
+
+/// Write a file under `root`, creating its directories
+pub fn write(root: &Path, path: &str, content: impl AsRef<[u8]>) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+pub fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
@@ -116,6 +116,82 @@ fn test_config_from_file() {
     temp_dir.close().unwrap();
 }
 
+#[test]
+fn test_stage_provider_overrides() {
+    let config_json = r#"{
+        "anthropic_api_key": "anthropic_key",
+        "model": "claude-3-7-sonnet-20250219",
+        "request_timeout": 120,
+        "relevance": {
+            "model": "gpt-4o-mini",
+            "provider": "openai",
+            "api_key": "openai_key",
+            "base_url": "https://api.test.com/v1",
+            "request_max_retries": 7
+        },
+        "codebase": {
+            "problem_id": "test_problem",
+            "problem_statement": "test statement"
+        }
+    }"#;
+
+    let config: Config = serde_json::from_str(config_json).unwrap();
+
+    // The relevance stage uses its own provider settings
+    let relevance = config.to_stage_llm_config(&config.relevance.model, &config.relevance.llm);
+    assert_eq!(relevance.model_type, "openai");
+    assert_eq!(relevance.model, "gpt-4o-mini");
+    assert_eq!(relevance.api_key, "openai_key");
+    assert_eq!(
+        relevance.base_url,
+        Some("https://api.test.com/v1".to_string())
+    );
+    assert_eq!(relevance.timeout, 120);
+    assert_eq!(relevance.max_retries, 7);
+
+    // Stages without overrides fall back to the top-level settings
+    let dockerfile = config.to_stage_llm_config(&config.dockerfile.model, &config.dockerfile.llm);
+    assert_eq!(dockerfile.model_type, "anthropic");
+    assert_eq!(dockerfile.model, "claude-3-7-sonnet-20250219");
+    assert_eq!(dockerfile.api_key, "anthropic_key");
+    assert_eq!(dockerfile.base_url, None);
+    assert_eq!(dockerfile.timeout, 120);
+    assert_eq!(dockerfile.max_retries, 3);
+
+    // Stage-level build retries are unaffected by the provider settings
+    assert_eq!(config.dockerfile.max_retries, 3);
+}
+
+#[test]
+fn test_container_models_for_failure_analysis() {
+    let config_json = r#"{
+        "anthropic_api_key": "anthropic_key",
+        "model": "claude-3-7-sonnet-20250219",
+        "container": {
+            "timeout": 60,
+            "models": ["claude-3-5-haiku-20241022", "claude-3-7-sonnet-20250219"]
+        },
+        "codebase": {
+            "problem_id": "test_problem",
+            "problem_statement": "test statement"
+        }
+    }"#;
+
+    let config: Config = serde_json::from_str(config_json).unwrap();
+    assert_eq!(config.container.timeout, 60);
+
+    let configs = config.to_stage_llm_configs(
+        &config.container.model,
+        &config.container.models,
+        &config.container.llm,
+    );
+    let names: Vec<&str> = configs.iter().map(|c| c.model.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["claude-3-5-haiku-20241022", "claude-3-7-sonnet-20250219"]
+    );
+}
+
 // Test error handling for file not found
 #[test]
 fn test_config_from_nonexistent_file() {
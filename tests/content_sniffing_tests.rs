@@ -0,0 +1,192 @@
+use engine_builder::models::exclusion::{has_generated_header, looks_binary, ExclusionConfig};
+use engine_builder::models::problem::SWEBenchProblem;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::write;
+
+#[test]
+fn test_looks_binary() {
+    assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00\x00"));
+    assert!(looks_binary(&[
+        0x01, 0x02, 0x03, 0x04, b'a', b'b', 0x05, 0x06
+    ]));
+    assert!(looks_binary(&[
+        0xff, 0xfe, 0xfd, 0xc3, 0x28, 0xa0, 0xa1, b'x'
+    ]));
+
+    assert!(!looks_binary(b"fn main() {\n\tprintln!(\"hi\");\r\n}\n"));
+    assert!(!looks_binary(
+        "// Überprüfung der Eingabe: 日本語\n".as_bytes()
+    ));
+    assert!(!looks_binary(b"\x1b[31mred\x1b[0m\n"));
+    assert!(!looks_binary(b""));
+}
+
+#[test]
+fn test_has_generated_header() {
+    assert!(has_generated_header(
+        b"// Code generated by protoc-gen-go. DO NOT EDIT.\npackage pb\n"
+    ));
+    assert!(has_generated_header(
+        b"# -*- coding: utf-8 -*-\n# Generated by the protocol buffer compiler.  DO NOT EDIT!\n"
+    ));
+    assert!(has_generated_header(
+        b"/**\n * @generated SignedSource<<abc>>\n */\n"
+    ));
+
+    assert!(!has_generated_header(
+        b"// Do not edit the tests lightly\nfn f() {}\n"
+    ));
+    // Only the first lines of a file are searched
+    let late_header = format!("{}// Code generated. DO NOT EDIT.\n", "x = 1\n".repeat(20));
+    assert!(!has_generated_header(late_header.as_bytes()));
+}
+
+#[test]
+fn test_vendored_paths() {
+    let config = ExclusionConfig::default();
+    for path in [
+        "vendor/github.com/lib/pq/conn.go",
+        "src/third_party/zlib/",
+        "web/static/jquery-3.6.0.js",
+        "web/static/bootstrap.css",
+        "web/app.bundle.js",
+        "proto/service_pb2.py",
+        "proto/service_pb2_grpc.py",
+        "api/service.pb.go",
+    ] {
+        assert!(
+            config.should_exclude_as_vendored(Path::new(path)),
+            "{} should be vendored",
+            path
+        );
+    }
+    for path in [
+        "src/vendor.rs",
+        "src/vendors_list.py",
+        "web/app.js",
+        "proto/service.proto",
+    ] {
+        assert!(
+            !config.should_exclude_as_vendored(Path::new(path)),
+            "{} shouldn't be vendored",
+            path
+        );
+    }
+
+    let config = ExclusionConfig {
+        skip_generated_files: false,
+        ..ExclusionConfig::default()
+    };
+    assert!(!config.should_exclude_as_vendored(Path::new("vendor/lib.go")));
+}
+
+#[test]
+fn test_exclusion_file_without_content_settings_uses_defaults() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("exclusions.json");
+    fs::write(
+        &path,
+        r#"{"extensions_to_skip": [], "files_to_skip": [], "directories_to_skip": []}"#,
+    )
+    .unwrap();
+
+    let config = ExclusionConfig::from_file(path.to_str().unwrap()).unwrap();
+
+    assert_eq!(config.max_file_size, 1024 * 1024);
+    assert!(config.skip_binary_files);
+    assert!(config.skip_generated_files);
+    assert!(config.should_exclude_by_size(1024 * 1024 + 1));
+    assert!(!config.should_exclude_by_size(1024 * 1024));
+
+    let unlimited = ExclusionConfig {
+        max_file_size: 0,
+        ..config
+    };
+    assert!(!unlimited.should_exclude_by_size(u64::MAX));
+}
+
+#[test]
+fn test_scan_skips_binary_large_and_generated_files_with_reasons() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "src/main.go", b"package main\n\nfunc main() {}\n");
+    write(
+        root,
+        "bin/server",
+        b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00",
+    );
+    write(root, "data/dump.csv", "a,b\n1,2\n".repeat(200).as_bytes());
+    write(
+        root,
+        "api/types.go",
+        b"// Code generated by mockgen. DO NOT EDIT.\npackage api\n",
+    );
+    write(root, "vendor/lib/lib.go", b"package lib\n");
+
+    let exclusion_config = ExclusionConfig {
+        max_file_size: 1000,
+        ..ExclusionConfig::default()
+    };
+    let mut problem = SWEBenchProblem::new("sniff_test".to_string(), "Test".to_string())
+        .with_codebase_path(root)
+        .with_exclusion_config(exclusion_config);
+    problem.initialize().unwrap();
+
+    assert_eq!(problem.all_file_paths(), vec!["src/main.go".to_string()]);
+
+    let skipped: Vec<(&str, &str)> = problem
+        .skipped_paths()
+        .iter()
+        .map(|skipped| (skipped.path.as_str(), skipped.reason.as_str()))
+        .collect();
+    assert_eq!(
+        skipped,
+        vec![
+            ("api/types.go", "generated file header"),
+            ("bin/server", "binary content"),
+            ("data/dump.csv", "larger than 1000 bytes (1600 bytes)"),
+            ("vendor/", "vendored or generated path"),
+        ]
+    );
+}
+
+#[test]
+fn test_scan_records_paths_skipped_by_rules_and_ignore_files() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".gitignore", b"out/\n");
+    write(root, "out/app.js", b"bundle");
+    write(
+        root,
+        "node_modules/left-pad/index.js",
+        b"module.exports = 1;",
+    );
+    write(root, "logo.png", b"not really a png");
+    write(root, "app.js", b"console.log(1);");
+
+    let mut problem = SWEBenchProblem::new("sniff_test".to_string(), "Test".to_string())
+        .with_codebase_path(root)
+        .with_exclusion_config(ExclusionConfig::default());
+    problem.initialize().unwrap();
+
+    assert_eq!(problem.all_file_paths(), vec!["app.js".to_string()]);
+
+    let skipped: Vec<(&str, &str)> = problem
+        .skipped_paths()
+        .iter()
+        .map(|skipped| (skipped.path.as_str(), skipped.reason.as_str()))
+        .collect();
+    assert_eq!(
+        skipped,
+        vec![
+            (".gitignore", "hidden"),
+            ("logo.png", "excluded extension"),
+            ("node_modules/", "excluded directory"),
+            ("out/", "ignored by .gitignore: out/"),
+        ]
+    );
+}
@@ -0,0 +1,264 @@
+use anyhow::Result;
+use engine_builder::config::{Config, ContextConfig, LLMConfig};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::LLMClient;
+use engine_builder::llm::fallback::ModelChain;
+use engine_builder::llm::openai::OpenAIClient;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::ranking::{ProblemContext, RankedCodebaseFile};
+use engine_builder::stages::dockerfile;
+use engine_builder::utils::context_packer::{
+    context_budget, excerpt, pack_files, MIN_EXCERPT_TOKENS,
+};
+use engine_builder::utils::token_counter::WhitespaceTokenizer;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::{MockServer, Reply};
+
+const DOCKERFILE_STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":100,\"output_tokens\":1}}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"```dockerfile\\nFROM rust:1.75\\n```\"}}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":20}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+/// A file of `lines` numbered lines of four words each
+fn numbered_lines(name: &str, lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!("let {}_{} = {};\n", name, i, i))
+        .collect()
+}
+
+fn file(path: &str, content: String) -> (String, String) {
+    (path.to_string(), content)
+}
+
+fn llm_config(model_type: &str, model: &str) -> LLMConfig {
+    LLMConfig {
+        model_type: model_type.to_string(),
+        model: model.to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: None,
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+#[test]
+fn test_files_that_fit_are_included_whole_in_order() {
+    let files = vec![
+        file("b.rs", numbered_lines("b", 10)),
+        file("a.rs", numbered_lines("a", 10)),
+    ];
+
+    let packed = pack_files(files, 1000, 500, &WhitespaceTokenizer);
+
+    let paths: Vec<&str> = packed.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, vec!["b.rs", "a.rs"]);
+    assert_eq!(packed.tokens(), 80);
+    assert_eq!(packed.excerpts().count(), 0);
+    assert!(packed.dropped.is_empty());
+    assert_eq!(packed.contents()[0].1, numbered_lines("b", 10));
+}
+
+#[test]
+fn test_long_file_is_cut_to_head_and_tail() {
+    let content = numbered_lines("x", 1000);
+
+    let packed = pack_files(
+        vec![file("x.rs", content)],
+        10_000,
+        400,
+        &WhitespaceTokenizer,
+    );
+
+    let packed_file = &packed.files[0];
+    assert!(packed_file.excerpt);
+    assert_eq!(packed_file.original_tokens, 4000);
+    assert!(packed_file.tokens <= 400);
+    assert!(packed_file.tokens > 300);
+
+    // Whole lines from the beginning and end are kept, and the gap is marked
+    let text = &packed_file.content;
+    assert!(text.starts_with("let x_0 = 0;\n"));
+    assert!(text.ends_with("let x_999 = 999;\n"));
+    assert!(text.contains("lines elided] ..."));
+    assert!(text
+        .lines()
+        .all(|line| line.starts_with("let ") || line.starts_with("...")));
+
+    // More of the beginning is kept than of the end
+    let head_lines = text.lines().take_while(|l| l.starts_with("let ")).count();
+    let tail_lines = text
+        .lines()
+        .rev()
+        .take_while(|l| l.starts_with("let "))
+        .count();
+    assert!(head_lines > tail_lines);
+}
+
+#[test]
+fn test_files_past_the_budget_are_dropped_and_reported() {
+    let files = vec![
+        file("first.rs", numbered_lines("first", 100)),
+        file("second.rs", numbered_lines("second", 100)),
+        file("third.rs", numbered_lines("third", 100)),
+        file("tiny.rs", "fn main() {}\n".to_string()),
+    ];
+
+    // The second file is cut down to the space left; nothing useful fits
+    // after it but the tiny file
+    let packed = pack_files(files, 400 + MIN_EXCERPT_TOKENS, 1000, &WhitespaceTokenizer);
+
+    let paths: Vec<&str> = packed.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, vec!["first.rs", "second.rs", "tiny.rs"]);
+    assert!(!packed.files[0].excerpt);
+    assert!(packed.files[1].excerpt);
+    assert!(packed.tokens() <= packed.budget);
+
+    assert_eq!(packed.dropped.len(), 1);
+    assert_eq!(packed.dropped[0].path, "third.rs");
+    assert_eq!(packed.dropped[0].tokens, 400);
+
+    let report = packed.report();
+    assert_eq!(report["excerpts"], json!(["second.rs"]));
+    assert_eq!(report["dropped"], json!(["third.rs"]));
+}
+
+#[test]
+fn test_excerpt_of_a_single_long_line() {
+    let content = "word ".repeat(1000);
+
+    let text = excerpt(&content, 1000, 100);
+
+    assert!(text.len() < content.len());
+    assert!(text.contains("characters elided] ..."));
+}
+
+#[test]
+fn test_excerpt_respects_char_boundaries() {
+    let content = "é".repeat(5000);
+
+    // Would panic if cut inside a character
+    let text = excerpt(&content, 5000, 100);
+
+    assert!(text.contains("elided"));
+}
+
+#[test]
+fn test_budget_respects_context_window() {
+    let config = ContextConfig {
+        max_tokens: 50_000,
+        max_file_tokens: 8_000,
+    };
+
+    assert_eq!(context_budget(&config, None, 10_000), 50_000);
+    assert_eq!(context_budget(&config, Some(200_000), 10_000), 50_000);
+    assert_eq!(context_budget(&config, Some(32_000), 10_000), 22_000);
+    assert_eq!(context_budget(&config, Some(8_000), 10_000), 0);
+}
+
+#[test]
+fn test_chain_uses_smallest_context_window() -> Result<()> {
+    let claude: Box<dyn LLMClient> = Box::new(AnthropicClient::new(&llm_config(
+        "anthropic",
+        "claude-3-7-sonnet-20250219",
+    ))?);
+    assert_eq!(claude.context_window(), Some(200_000));
+
+    let gpt4: Box<dyn LLMClient> = Box::new(OpenAIClient::new(&llm_config("openai", "gpt-4"))?);
+    assert_eq!(gpt4.context_window(), Some(8_192));
+
+    let models = ModelChain::new(vec![claude, gpt4])?;
+    assert_eq!(models.context_window(), Some(8_192));
+    Ok(())
+}
+
+#[test]
+fn test_context_config() -> Result<()> {
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "codebase": { "problem_id": "test_problem", "problem_statement": "The build is broken" },
+        "scripts": { "context": { "max_tokens": 20000 } },
+    }))?;
+    assert_eq!(config.scripts.context.max_tokens, 20_000);
+    assert_eq!(config.scripts.context.max_file_tokens, 8_000);
+    assert_eq!(config.dockerfile.context.max_tokens, 60_000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dockerfile_prompt_is_packed() -> Result<()> {
+    let server = MockServer::messages()
+        .reply(Reply::sse(DOCKERFILE_STREAM))
+        .start()
+        .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    let files = [
+        ("Cargo.toml", "[package]\nname = \"demo\"\n".to_string()),
+        ("src/big.rs", numbered_lines("big", 1000)),
+        ("src/other.rs", numbered_lines("other", 300)),
+        ("build.rs", "fn main() {}\n".to_string()),
+    ];
+    for (path, content) in &files {
+        let full_path = codebase_dir.path().join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap())?;
+        std::fs::write(full_path, content)?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "dockerfile": { "context": { "max_tokens": 600, "max_file_tokens": 400 } },
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    )
+    .with_codebase_path(codebase_dir.path());
+
+    // Save a ranking of the files, most important first
+    let trajectory_store = TrajectoryStore::new(config.get_trajectory_dir(&problem.id), &problem)?;
+    trajectory_store.save_ranking(ProblemContext {
+        model_rankings: Vec::new(),
+        ranked_files: files
+            .iter()
+            .map(|(path, _)| RankedCodebaseFile {
+                path: path.to_string(),
+                tokens: 10,
+            })
+            .collect(),
+        agreement: Vec::new(),
+        prompt_caching_usages: Vec::new(),
+    })?;
+
+    dockerfile::generate_dockerfile(&config, problem.clone()).await?;
+
+    let bodies = server.json_bodies();
+    assert_eq!(bodies.len(), 1);
+    let prompt = bodies[0]["messages"][0]["content"].to_string();
+    assert!(prompt.contains("name = \\\"demo\\\""));
+    assert!(prompt.contains("let big_0 = 0;"));
+    assert!(prompt.contains("let big_999 = 999;"));
+    assert!(!prompt.contains("let big_500 = 500;"));
+    assert!(prompt.contains("lines elided] ..."));
+    assert!(!prompt.contains("let other_0 = 0;"));
+    assert!(prompt.contains("fn main() {}"));
+    Ok(())
+}
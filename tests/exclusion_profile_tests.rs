@@ -0,0 +1,198 @@
+use engine_builder::models::exclusion::{ExclusionConfig, ExclusionProfile, ScanProfile};
+use engine_builder::models::problem::SWEBenchProblem;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{strings, write};
+
+/// Scan a codebase and return its file paths, sorted
+fn scan(root: &Path, exclusion_config: ExclusionConfig) -> Vec<String> {
+    let mut problem = SWEBenchProblem::new("profile_test".to_string(), "Test".to_string())
+        .with_codebase_path(root)
+        .with_exclusion_config(exclusion_config);
+    problem.initialize().unwrap();
+    let mut paths = problem.all_file_paths();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_patterns_with_negation() {
+    let config = ExclusionConfig {
+        patterns: strings(&["docs/", "!docs/conf.py", "*.generated.ts", "/scripts/*.sh"]),
+        ..ExclusionConfig::default()
+    };
+
+    assert!(config.should_exclude_by_pattern(Path::new("docs"), true));
+    assert!(config.should_exclude_by_pattern(Path::new("docs/guide/intro.md"), false));
+    assert!(config.should_exclude_by_pattern(Path::new("pkg/docs/api.md"), false));
+    assert!(!config.should_exclude_by_pattern(Path::new("docs/conf.py"), false));
+    assert!(config.should_exclude_by_pattern(Path::new("src/api.generated.ts"), false));
+    assert!(config.should_exclude_by_pattern(Path::new("scripts/release.sh"), false));
+    // Anchored at the root, and `*` doesn't match `/`
+    assert!(!config.should_exclude_by_pattern(Path::new("pkg/scripts/release.sh"), false));
+    assert!(!config.should_exclude_by_pattern(Path::new("scripts/ci/release.sh"), false));
+    assert!(!config.should_exclude_by_pattern(Path::new("src/api.ts"), false));
+
+    assert!(config.should_exclude(Path::new("docs/index.md")));
+    assert!(!config.should_exclude(Path::new("docs/conf.py")));
+}
+
+#[test]
+fn test_patterns_changed_after_use_are_recompiled() {
+    let mut config = ExclusionConfig {
+        patterns: strings(&["docs/"]),
+        ..ExclusionConfig::default()
+    };
+    assert!(config.should_exclude_by_pattern(Path::new("docs/index.md"), false));
+    assert!(!config.should_exclude_by_pattern(Path::new("notes/todo.md"), false));
+
+    config.patterns = strings(&["notes/"]);
+    assert!(!config.should_exclude_by_pattern(Path::new("docs/index.md"), false));
+    assert!(config.should_exclude_by_pattern(Path::new("notes/todo.md"), false));
+
+    // Patterns that don't compile exclude nothing rather than failing
+    config.patterns = strings(&["notes/[z-a]"]);
+    assert!(config.path_patterns().is_err());
+    assert!(!config.should_exclude_by_pattern(Path::new("notes/todo.md"), false));
+}
+
+#[test]
+fn test_include_overrides_exclusions_in_scan() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "src/main.py", "print('hi')");
+    write(root, "tests/test_main.py", "def test(): pass");
+    write(root, "tests/data/sample.json", "{}");
+    write(
+        root,
+        "node_modules/left-pad/index.js",
+        "module.exports = 1;",
+    );
+    write(root, "node_modules/left-pad/README.md", "# left-pad");
+    write(root, "node_modules/other/index.js", "module.exports = 2;");
+    write(root, "docs/conf.py", "project = 'x'");
+    write(root, "docs/index.md", "# Docs");
+    write(root, "schema.lock", "locked");
+
+    let mut exclusion_config = ExclusionConfig {
+        patterns: strings(&["docs/", "*.json"]),
+        include: strings(&[
+            "tests/**",
+            "node_modules/left-pad/index.js",
+            "docs/conf.py",
+            "schema.lock",
+        ]),
+        ..ExclusionConfig::default()
+    };
+    exclusion_config
+        .directories_to_skip
+        .push("tests".to_string());
+
+    let paths = scan(root, exclusion_config);
+
+    assert_eq!(
+        paths,
+        strings(&[
+            "docs/conf.py",
+            "node_modules/left-pad/index.js",
+            "schema.lock",
+            "src/main.py",
+            "tests/data/sample.json",
+            "tests/test_main.py",
+        ])
+    );
+}
+
+#[test]
+fn test_profiles_add_to_the_exclusion_file_rules() {
+    let mut config = ExclusionConfig {
+        patterns: strings(&["docs/"]),
+        ..ExclusionConfig::default()
+    };
+    config.profiles.tree = ExclusionProfile {
+        patterns: strings(&["!docs/", "*.md"]),
+        include: Vec::new(),
+    };
+    config.profiles.relevance = ExclusionProfile {
+        patterns: strings(&["tests/"]),
+        include: strings(&["tests/conftest.py"]),
+    };
+
+    let tree = config.for_profile(ScanProfile::Tree);
+    assert!(!tree.should_exclude_by_pattern(Path::new("docs/conf.py"), false));
+    assert!(tree.should_exclude_by_pattern(Path::new("docs/index.md"), false));
+    assert!(!tree.should_exclude_by_pattern(Path::new("tests/test_a.py"), false));
+
+    let relevance = config.for_profile(ScanProfile::Relevance);
+    assert!(relevance.should_exclude_by_pattern(Path::new("docs/conf.py"), false));
+    assert!(relevance.should_exclude_by_pattern(Path::new("tests/test_a.py"), false));
+    assert!(!relevance.should_exclude(Path::new("tests/conftest.py")));
+
+    // The config itself is left as it was
+    assert_eq!(config.patterns, strings(&["docs/"]));
+    assert!(config.include.is_empty());
+}
+
+#[test]
+fn test_docker_profile_as_dockerignore_lines() {
+    let mut config = ExclusionConfig {
+        // Only the Docker profile applies to the build context
+        patterns: strings(&["docs/"]),
+        ..ExclusionConfig::default()
+    };
+    assert!(config.dockerignore_lines().is_empty());
+
+    config.profiles.docker = ExclusionProfile {
+        patterns: strings(&[
+            "node_modules/",
+            "/target/",
+            "data/*.csv",
+            "# a comment",
+            "",
+            "!keep.log",
+        ]),
+        include: strings(&["data/schema.csv"]),
+    };
+
+    assert_eq!(
+        config.dockerignore_lines(),
+        strings(&[
+            "**/node_modules",
+            "target",
+            "data/*.csv",
+            "!**/keep.log",
+            "!data/schema.csv",
+        ])
+    );
+}
+
+#[test]
+fn test_invalid_pattern_fails_to_load() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("exclusions.json");
+    fs::write(
+        &path,
+        r#"{
+            "extensions_to_skip": [],
+            "files_to_skip": [],
+            "directories_to_skip": [],
+            "profiles": { "relevance": { "patterns": ["src/[abc"] } }
+        }"#,
+    )
+    .unwrap();
+
+    let error = ExclusionConfig::from_file(path.to_str().unwrap()).unwrap_err();
+    assert!(format!("{:#}", error).contains("src/[abc"));
+}
+
+#[test]
+fn test_default_exclusion_file_keeps_tests() {
+    let config = ExclusionConfig::from_file("exclusions.json").unwrap();
+
+    assert!(!config.should_exclude(Path::new("tests/test_main.py")));
+    assert!(!config.should_exclude(Path::new("pkg/tests/conftest.py")));
+    assert!(config.should_exclude(Path::new("docs/index.md")));
+}
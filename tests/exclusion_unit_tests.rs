@@ -105,8 +105,6 @@ fn test_should_exclude_git_directory() {
 }
 
 #[test]
-// We're having issues with the gitignore test so let's just test the .git exclusion for now
-#[ignore]
 fn test_should_exclude_gitignore_patterns() {
     // Create a temporary directory structure
     let temp_dir = tempdir().expect("Failed to create temp directory");
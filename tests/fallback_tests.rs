@@ -0,0 +1,216 @@
+use anyhow::Result;
+use engine_builder::config::{Config, LLMConfig};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::budget::{is_budget_exceeded, BudgetExceeded};
+use engine_builder::llm::client::{ChatRequest, LLMClient, LLMResponse};
+use engine_builder::llm::fallback::ModelChain;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+mod common;
+use common::{MockServer, Reply};
+
+const RELEVANT_BODY: &str = r#"{"content":[{"type":"text","text":"```json\n{\"status\": \"relevant\", \"summary\": \"Needed to build.\"}\n```"}],"usage":{"input_tokens":90,"output_tokens":10}}"#;
+const UNPARSEABLE_BODY: &str = r#"{"content":[{"type":"text","text":"I'm not sure."}],"usage":{"input_tokens":90,"output_tokens":5}}"#;
+const BAD_REQUEST_BODY: &str =
+    r#"{"type":"error","error":{"type":"invalid_request_error","message":"bad request"}}"#;
+
+/// Start a mock server that answers every request to the messages API with
+/// the given status and body
+async fn start_mock_server(status: &str, body: &str) -> MockServer {
+    MockServer::messages()
+        .reply(Reply::status(status, body))
+        .start()
+        .await
+}
+
+fn client(model: &str, base_url: &str) -> Box<dyn LLMClient> {
+    Box::new(
+        AnthropicClient::new(&LLMConfig {
+            model_type: "anthropic".to_string(),
+            model: model.to_string(),
+            api_key: "dummy_key".to_string(),
+            base_url: Some(base_url.to_string()),
+            timeout: 5,
+            max_retries: 0,
+        })
+        .unwrap(),
+    )
+}
+
+fn request() -> ChatRequest {
+    ChatRequest::new(100, 0.0).with_user("Is this file relevant?")
+}
+
+/// Fails answers without a relevance decision
+fn require_relevance(response: &LLMResponse) -> Result<()> {
+    if response.content.contains("\"status\"") {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("no relevance decision in the answer"))
+    }
+}
+
+#[tokio::test]
+async fn test_failed_request_falls_back_to_next_model() -> Result<()> {
+    let failing = start_mock_server("400 Bad Request", BAD_REQUEST_BODY).await;
+    let working = start_mock_server("200 OK", RELEVANT_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &failing.url()),
+        client("claude-3-7-sonnet", &working.url()),
+    ])?;
+    let request = request();
+
+    let answer = models
+        .run(|client| client.chat(&request), require_relevance)
+        .await?;
+
+    assert_eq!(answer.model(), "claude-3-7-sonnet");
+    assert!(answer.value.content.contains("Needed to build."));
+    assert_eq!(failing.request_count(), 1);
+    assert_eq!(working.request_count(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unparseable_answer_falls_back_to_next_model() -> Result<()> {
+    let vague = start_mock_server("200 OK", UNPARSEABLE_BODY).await;
+    let working = start_mock_server("200 OK", RELEVANT_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &vague.url()),
+        client("claude-3-7-sonnet", &working.url()),
+    ])?;
+    let request = request();
+
+    let answer = models
+        .run(|client| client.chat(&request), require_relevance)
+        .await?;
+
+    assert_eq!(answer.model(), "claude-3-7-sonnet");
+    assert_eq!(vague.request_count(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_first_usable_answer_is_kept() -> Result<()> {
+    let working = start_mock_server("200 OK", RELEVANT_BODY).await;
+    let unused = start_mock_server("200 OK", RELEVANT_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &working.url()),
+        client("claude-3-7-sonnet", &unused.url()),
+    ])?;
+    let request = request();
+
+    let answer = models
+        .run(|client| client.chat(&request), require_relevance)
+        .await?;
+
+    assert_eq!(answer.model(), "claude-3-5-haiku");
+    assert_eq!(unused.request_count(), 0);
+    assert_eq!(
+        models.model_names(),
+        vec!["claude-3-5-haiku", "claude-3-7-sonnet"]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_last_models_answer_is_returned_even_if_unusable() -> Result<()> {
+    let vague = start_mock_server("200 OK", UNPARSEABLE_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &vague.url()),
+        client("claude-3-7-sonnet", &vague.url()),
+    ])?;
+    let request = request();
+
+    let answer = models
+        .run(|client| client.chat(&request), require_relevance)
+        .await?;
+
+    // The stage gets the answer and handles it as it would without a chain
+    assert_eq!(answer.model(), "claude-3-7-sonnet");
+    assert!(require_relevance(&answer.value).is_err());
+    assert_eq!(vague.request_count(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_last_models_error_is_returned() -> Result<()> {
+    let failing = start_mock_server("400 Bad Request", BAD_REQUEST_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &failing.url()),
+        client("claude-3-7-sonnet", &failing.url()),
+    ])?;
+    let request = request();
+
+    let result = models
+        .run(|client| client.chat(&request), require_relevance)
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(failing.request_count(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_budget_refusal_does_not_fall_back() -> Result<()> {
+    let server = start_mock_server("200 OK", RELEVANT_BODY).await;
+    let models = ModelChain::new(vec![
+        client("claude-3-5-haiku", &server.url()),
+        client("claude-3-7-sonnet", &server.url()),
+    ])?;
+    let attempts = AtomicUsize::new(0);
+
+    let result = models
+        .run(
+            |_client| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async {
+                    Err::<LLMResponse, _>(anyhow::Error::new(BudgetExceeded {
+                        scope: "the run".to_string(),
+                        spent: "100 tokens".to_string(),
+                        limit: "100 tokens".to_string(),
+                    }))
+                }
+            },
+            require_relevance,
+        )
+        .await;
+
+    assert!(is_budget_exceeded(&result.err().unwrap()));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    assert_eq!(server.request_count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_empty_chain_is_rejected() {
+    assert!(ModelChain::new(Vec::new()).is_err());
+}
+
+#[test]
+fn test_stage_models_take_precedence_over_model() {
+    let mut config = Config::default();
+    config.relevance.model = Some("claude-3-7-sonnet".to_string());
+
+    // Without a list, the stage uses its single model
+    let configs = config.to_stage_llm_configs(
+        &config.relevance.model,
+        &config.relevance.models,
+        &config.relevance.llm,
+    );
+    let names: Vec<&str> = configs.iter().map(|c| c.model.as_str()).collect();
+    assert_eq!(names, vec!["claude-3-7-sonnet"]);
+
+    config.relevance.models = vec![
+        "claude-3-5-haiku".to_string(),
+        "claude-3-7-sonnet".to_string(),
+    ];
+    let configs = config.to_stage_llm_configs(
+        &config.relevance.model,
+        &config.relevance.models,
+        &config.relevance.llm,
+    );
+    let names: Vec<&str> = configs.iter().map(|c| c.model.as_str()).collect();
+    assert_eq!(names, vec!["claude-3-5-haiku", "claude-3-7-sonnet"]);
+    assert!(configs.iter().all(|c| c.model_type == "anthropic"));
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use engine_builder::config::{Config, FileRulesConfig};
+use engine_builder::llm::prompts::get_codebase_tree_user_prompt;
+use engine_builder::models::file_rules::{FileRule, FileRules};
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::relevance::{RelevanceStatus, RelevanceVerdict};
+use engine_builder::stages::relevance;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::MockServer;
+
+const NOT_RELEVANT: &str = "```json\n{\"status\": \"not_relevant\"}\n```";
+
+fn builtin() -> FileRules {
+    FileRules::from_config(&FileRulesConfig::default()).unwrap()
+}
+
+fn status(rules: &FileRules, path: &str) -> Option<RelevanceVerdict> {
+    rules.classify(path).map(|rule| rule.status)
+}
+
+#[test]
+fn test_builtin_rules_recognize_build_and_test_files() {
+    let rules = builtin();
+
+    for path in [
+        "Cargo.toml",
+        "crates/core/Cargo.toml",
+        "package.json",
+        "pyproject.toml",
+        "tox.ini",
+        "go.mod",
+        "Makefile",
+        ".github/workflows/ci.yml",
+        "docker/Dockerfile",
+        "Dockerfile.dev",
+        "pytest.ini",
+        "jest.config.ts",
+        "requirements-dev.txt",
+    ] {
+        assert_eq!(
+            status(&rules, path),
+            Some(RelevanceVerdict::Relevant),
+            "{} should be relevant",
+            path
+        );
+    }
+
+    // The rule's summary is saved as the file's summary
+    let decision = rules.classify("Cargo.toml").unwrap().decision();
+    assert!(decision.is_relevant());
+    assert!(decision.summary.unwrap().contains("dependencies"));
+}
+
+#[test]
+fn test_builtin_rules_recognize_fixtures_and_generated_code() {
+    let rules = builtin();
+
+    for path in [
+        "tests/fixtures/package.json",
+        "fixtures/data.json",
+        "pkg/testdata/input.txt",
+        "src/__snapshots__/app.test.js.snap",
+        "proto/service_pb2.py",
+        "api/service.pb.go",
+        "vendor/github.com/lib/pq/conn.go",
+    ] {
+        assert_eq!(
+            status(&rules, path),
+            Some(RelevanceVerdict::NotRelevant),
+            "{} should not be relevant",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_other_files_are_left_to_the_model() {
+    let rules = builtin();
+
+    for path in [
+        "src/main.rs",
+        "src/cargo.rs",
+        "docs/workflows/ci.yml",
+        ".github/workflows/scripts/setup.yml",
+        "./src/lib.rs",
+    ] {
+        assert_eq!(status(&rules, path), None, "{} should not match", path);
+    }
+    assert_eq!(
+        status(&rules, "./Cargo.toml"),
+        Some(RelevanceVerdict::Relevant)
+    );
+}
+
+#[test]
+fn test_configured_rules_come_before_builtin_ones() -> Result<()> {
+    let config: FileRulesConfig = serde_json::from_value(json!({
+        "rules": [
+            {"pattern": "examples/**", "status": "not_relevant", "summary": "Examples"},
+            {"pattern": "*.bzl", "status": "relevant", "summary": "Bazel macros"},
+        ],
+    }))?;
+    let rules = FileRules::from_config(&config)?;
+
+    assert_eq!(
+        status(&rules, "examples/demo/Cargo.toml"),
+        Some(RelevanceVerdict::NotRelevant)
+    );
+    assert_eq!(
+        rules.classify("tools/defs.bzl").unwrap().summary,
+        "Bazel macros"
+    );
+    assert_eq!(
+        status(&rules, "Cargo.toml"),
+        Some(RelevanceVerdict::Relevant)
+    );
+
+    // Without the built-in rules only the configured ones apply
+    let rules = FileRules::from_config(&FileRulesConfig {
+        builtin: false,
+        ..config
+    })?;
+    assert_eq!(rules.len(), 2);
+    assert_eq!(status(&rules, "Cargo.toml"), None);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_rule_pattern_is_an_error() {
+    let result = FileRules::new(vec![FileRule::new(
+        "src/[",
+        RelevanceVerdict::Relevant,
+        "Broken",
+    )]);
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("src/["));
+}
+
+#[test]
+fn test_tree_prompt_lists_included_files() {
+    let problem = SWEBenchProblem::new("test_problem".to_string(), "Broken".to_string());
+
+    let prompt = get_codebase_tree_user_prompt(
+        &problem,
+        "./\n└── Cargo.toml\n",
+        &[
+            "Cargo.toml".to_string(),
+            ".github/workflows/ci.yml".to_string(),
+        ],
+    );
+    assert!(prompt.contains("<included>\nCargo.toml\n.github/workflows/ci.yml\n</included>"));
+
+    let prompt = get_codebase_tree_user_prompt(&problem, "./\n", &[]);
+    assert!(!prompt.contains("<included>"));
+}
+
+#[tokio::test]
+async fn test_relevance_decides_ruled_files_without_a_request() -> Result<()> {
+    let server = MockServer::messages()
+        .answers(&[NOT_RELEVANT])
+        .start()
+        .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    for (path, content) in [
+        ("Cargo.toml", "[package]\nname = \"demo\"\n"),
+        (
+            "src/lib.rs",
+            "pub fn add(a: i32, b: i32) -> i32 { a + b }\n",
+        ),
+        ("src/fixtures/input.rs", "fn fixture() {}\n"),
+        ("README.md", "# Demo\n"),
+    ] {
+        let full_path = codebase_dir.path().join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap())?;
+        std::fs::write(full_path, content)?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "relevance": { "max_workers": 1 },
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "Addition overflows",
+        },
+    }))?;
+    let problem =
+        SWEBenchProblem::new("test_problem".to_string(), "Addition overflows".to_string());
+
+    // Select only the Rust sources, leaving out the manifest
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    std::fs::create_dir_all(&trajectory_dir)?;
+    std::fs::write(
+        std::path::Path::new(&trajectory_dir).join("codebase_tree_response.txt"),
+        "```json\n[\"src/\"]\n```",
+    )?;
+
+    relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
+
+    // Only the source file needed a model
+    let requests = server.bodies();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].contains("pub fn add"));
+
+    let decisions = TrajectoryStore::new(&trajectory_dir, &problem)?.load_relevance_decisions()?;
+    assert_eq!(decisions.len(), 3);
+    let manifest = &decisions["Cargo.toml"];
+    assert_eq!(manifest.status, RelevanceStatus::Relevant);
+    assert!(manifest.message.contains("file rule `Cargo.toml`"));
+    assert_eq!(
+        decisions["src/fixtures/input.rs"].status,
+        RelevanceStatus::NotRelevant
+    );
+    assert_eq!(decisions["src/lib.rs"].status, RelevanceStatus::NotRelevant);
+    assert!(!decisions.contains_key("README.md"));
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use engine_builder::models::exclusion::ExclusionConfig;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::utils::git::{changed_files, scope_problem, GitScope};
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+mod common;
+use common::write;
+
+/// Run git in a repository, failing the test if it fails
+fn git(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// A repository with a first commit tagged `v1`, and a second commit that
+/// bumps a dependency. Returns the SHA of the second commit.
+fn make_repo(root: &Path) -> String {
+    git(root, &["init", "-q"]);
+    write(root, "Cargo.toml", "[dependencies]\nserde = \"1.0.100\"\n");
+    write(root, "README.md", "# Project\n");
+    write(root, "src/lib.rs", "pub fn lib() {}\n");
+    write(root, "src/util.rs", "pub fn util() {}\n");
+    write(root, "src/net/client.rs", "pub fn client() {}\n");
+    write(root, "web/app.js", "console.log(1);\n");
+    git(root, &["add", "-A"]);
+    git(root, &["commit", "-q", "-m", "First"]);
+    git(root, &["tag", "v1"]);
+
+    write(root, "Cargo.toml", "[dependencies]\nserde = \"1.0.200\"\n");
+    write(root, "src/net/client.rs", "pub fn client() -> u8 { 1 }\n");
+    git(root, &["add", "-A"]);
+    git(root, &["commit", "-q", "-m", "Bump serde"]);
+    git(root, &["rev-parse", "HEAD"])
+}
+
+fn problem(root: &Path) -> SWEBenchProblem {
+    SWEBenchProblem::new("git_test".to_string(), "Test".to_string())
+        .with_codebase_path(root)
+        .with_exclusion_config(ExclusionConfig::default())
+}
+
+#[test]
+fn test_changed_files_for_each_scope() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let bump = make_repo(root);
+
+    let mut since = changed_files(root, &GitScope::Since("v1".to_string())).unwrap();
+    since.sort();
+    assert_eq!(since, vec!["Cargo.toml", "src/net/client.rs"]);
+
+    let mut commit = changed_files(root, &GitScope::Commit(bump)).unwrap();
+    commit.sort();
+    assert_eq!(commit, vec!["Cargo.toml", "src/net/client.rs"]);
+
+    // The first commit has no parent to compare with
+    let first = changed_files(root, &GitScope::Commit("v1".to_string())).unwrap();
+    assert_eq!(first.len(), 6);
+
+    write(root, "web/app.js", "console.log(2);\n");
+    write(root, "src/util.rs", "pub fn util() -> u8 { 2 }\n");
+    git(root, &["add", "web/app.js"]);
+    assert_eq!(
+        changed_files(root, &GitScope::Staged).unwrap(),
+        vec!["web/app.js"]
+    );
+}
+
+#[test]
+fn test_merge_commit_lists_what_it_brought_in() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    make_repo(root);
+
+    git(root, &["checkout", "-q", "-b", "feature"]);
+    write(root, "web/app.js", "console.log(2);\n");
+    git(root, &["commit", "-q", "-am", "Change app"]);
+    git(root, &["checkout", "-q", "-"]);
+    write(root, "README.md", "# Project\n\nMore.\n");
+    git(root, &["commit", "-q", "-am", "Change README"]);
+    git(root, &["merge", "-q", "--no-edit", "--no-ff", "feature"]);
+    let merge = git(root, &["rev-parse", "HEAD"]);
+
+    // Compared with the first parent, only the merged branch's change shows
+    let changed = changed_files(root, &GitScope::Commit(merge)).unwrap();
+    assert_eq!(changed, vec!["web/app.js"]);
+}
+
+#[test]
+fn test_changed_files_relative_to_a_subdirectory() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    make_repo(root);
+
+    let changed = changed_files(&root.join("src"), &GitScope::Since("v1".to_string())).unwrap();
+
+    assert_eq!(changed, vec!["net/client.rs"]);
+}
+
+#[test]
+fn test_scope_limits_scan_to_changed_files_and_neighbours() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let bump = make_repo(root);
+
+    let mut problem = scope_problem(
+        problem(root),
+        root,
+        Some(&GitScope::Since("v1".to_string())),
+    )
+    .unwrap();
+    problem.initialize().unwrap();
+
+    let mut paths = problem.all_file_paths();
+    paths.sort();
+    assert_eq!(paths, vec!["Cargo.toml", "README.md", "src/net/client.rs"]);
+
+    let tree = problem.generate_tree();
+    assert!(tree.contains("client.rs"));
+    assert!(!tree.contains("lib.rs"));
+    assert!(!tree.contains("web"));
+
+    assert_eq!(problem.metadata["git_commit"], bump);
+    assert_eq!(problem.metadata["git_dirty"], "false");
+    assert_eq!(problem.metadata["git_scope"], "since v1");
+}
+
+#[test]
+fn test_records_commit_and_dirty_state_without_scope() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let bump = make_repo(root);
+    write(root, "src/lib.rs", "pub fn lib() -> u8 { 3 }\n");
+    // Untracked files don't make the tree dirty
+    write(root, "notes.txt", "todo\n");
+
+    let mut problem = scope_problem(problem(root), root, None).unwrap();
+    problem.initialize().unwrap();
+
+    assert_eq!(problem.metadata["git_commit"], bump);
+    assert_eq!(problem.metadata["git_dirty"], "true");
+    assert!(!problem.metadata.contains_key("git_scope"));
+    assert_eq!(problem.all_file_paths().len(), 7);
+}
+
+#[test]
+fn test_codebase_outside_a_repository() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "main.py", "print('hi')\n");
+
+    let problem = scope_problem(problem(root), root, None).unwrap();
+    assert!(problem.metadata.is_empty());
+
+    let error = scope_problem(problem, root, Some(&GitScope::Staged)).unwrap_err();
+    assert!(format!("{:#}", error).contains("git"));
+}
+
+#[test]
+fn test_empty_diff_is_an_error() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    make_repo(root);
+
+    let error = scope_problem(problem(root), root, Some(&GitScope::Staged)).unwrap_err();
+
+    assert!(error.to_string().contains("No files"));
+}
+
+#[test]
+fn test_git_scope_in_config() {
+    let scope: GitScope = serde_json::from_str(r#"{"since": "v1.2.0"}"#).unwrap();
+    assert_eq!(scope, GitScope::Since("v1.2.0".to_string()));
+
+    let scope: GitScope = serde_json::from_str(r#""staged""#).unwrap();
+    assert_eq!(scope, GitScope::Staged);
+}
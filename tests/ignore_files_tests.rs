@@ -0,0 +1,152 @@
+use engine_builder::models::exclusion::ExclusionConfig;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::utils::ignore_files::IgnoreFiles;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::write;
+
+/// Scan a codebase and return its file paths, sorted
+fn scan(root: &Path) -> Vec<String> {
+    let mut problem = SWEBenchProblem::new("ignore_test".to_string(), "Test".to_string())
+        .with_codebase_path(root)
+        .with_exclusion_config(ExclusionConfig::default());
+    problem.initialize().unwrap();
+    let mut paths = problem.all_file_paths();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_nested_gitignore_applies_to_its_directory() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, "pkg/.gitignore", "out/\n");
+    write(root, "pkg/out/bundle.js", "bundle");
+    write(root, "pkg/index.js", "module.exports = {};");
+    write(root, "out/keep.sh", "echo build");
+
+    let paths = scan(root);
+
+    assert!(paths.contains(&"pkg/index.js".to_string()));
+    assert!(!paths.contains(&"pkg/out/bundle.js".to_string()));
+    // The pattern is relative to pkg/, so an out/ directory elsewhere stays
+    assert!(paths.contains(&"out/keep.sh".to_string()));
+}
+
+#[test]
+fn test_ignore_and_enginesignore_files_are_honored() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".ignore", "*.orig\n");
+    write(root, ".enginesignore", "data/\n");
+    write(root, "main.py", "print('hi')");
+    write(root, "main.py.orig", "print('old')");
+    write(root, "data/big.csv", "a,b\n1,2\n");
+
+    let paths = scan(root);
+
+    assert_eq!(paths, vec!["main.py".to_string()]);
+}
+
+#[test]
+fn test_git_info_exclude_is_honored() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".git/info/exclude", "scratch.py\n");
+    write(root, "scratch.py", "x = 1");
+    write(root, "app.py", "x = 2");
+
+    let paths = scan(root);
+
+    assert_eq!(paths, vec!["app.py".to_string()]);
+}
+
+#[test]
+fn test_whitelist_in_subdirectory_reincludes_file() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".gitignore", "*.json\n");
+    write(root, "config/.gitignore", "!settings.json\n");
+    write(root, "config/settings.json", "{}");
+    write(root, "config/cache.json", "{}");
+    write(root, "lock.json", "{}");
+
+    let paths = scan(root);
+
+    assert!(paths.contains(&"config/settings.json".to_string()));
+    assert!(!paths.contains(&"config/cache.json".to_string()));
+    assert!(!paths.contains(&"lock.json".to_string()));
+
+    let mut ignore_files = IgnoreFiles::new(root);
+    ignore_files.add_dir(&root.join("config"));
+    assert_eq!(
+        ignore_files.ignoring_rule(&root.join("config/settings.json"), false),
+        None
+    );
+}
+
+#[test]
+fn test_ci_config_is_scanned_but_other_hidden_files_are_not() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".github/workflows/ci.yml", "on: push");
+    write(root, ".vscode/settings.json", "{}");
+    write(root, ".env", "SECRET=1");
+    write(root, "main.go", "package main");
+
+    let paths = scan(root);
+
+    assert_eq!(
+        paths,
+        vec![
+            ".github/workflows/ci.yml".to_string(),
+            "main.go".to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_ignoring_rule_names_file_and_pattern() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write(root, ".gitignore", "*.log\n");
+    write(root, "pkg/.gitignore", "out/\n");
+    write(root, ".enginesignore", "fixtures/\n");
+
+    let mut ignore_files = IgnoreFiles::new(root);
+    ignore_files.add_dir(&root.join("pkg"));
+
+    assert_eq!(
+        ignore_files.ignoring_rule(&root.join("pkg/out/bundle.js"), false),
+        Some(format!(
+            "{}: out/",
+            Path::new("pkg").join(".gitignore").display()
+        ))
+    );
+    assert_eq!(
+        ignore_files.ignoring_rule(&root.join("pkg/run.log"), false),
+        Some(".gitignore: *.log".to_string())
+    );
+    assert_eq!(
+        ignore_files.ignoring_rule(&root.join("fixtures"), true),
+        Some(".enginesignore: fixtures/".to_string())
+    );
+    assert_eq!(
+        ignore_files.ignoring_rule(&root.join("pkg/index.js"), false),
+        None
+    );
+}
+
+#[test]
+fn test_ignore_files_above_the_codebase_do_not_apply() {
+    let dir = tempdir().unwrap();
+    write(dir.path(), ".gitignore", "project/\n*.py\n");
+    let root = dir.path().join("project");
+    write(&root, "app.py", "x = 1");
+
+    let paths = scan(&root);
+
+    assert_eq!(paths, vec!["app.py".to_string()]);
+}
@@ -0,0 +1,360 @@
+use anyhow::{Context, Result};
+use engine_builder::config::{Config, LLMConfig};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{collect_stream, ChatRequest, LLMClient};
+use engine_builder::llm::error::{
+    is_auth_error, is_context_too_long, retry_with_fewer_files, LLMError,
+};
+use engine_builder::llm::openai::OpenAIClient;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::ranking::{ProblemContext, RankedCodebaseFile};
+use engine_builder::stages::{dockerfile, relevance};
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use reqwest::StatusCode;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::{MockServer, Reply};
+
+const CONTEXT_TOO_LONG: &str = r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 250000 tokens > 200000 maximum"}}"#;
+
+const DOCKERFILE_STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":100,\"output_tokens\":1}}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"```dockerfile\\nFROM rust:1.75\\n```\"}}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":20}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+/// Start a mock server that answers requests to the chat endpoints with the
+/// scripted replies in order, repeating the last one, and 404 for any other
+/// path
+async fn start_mock_server(replies: Vec<Reply>) -> MockServer {
+    MockServer::messages()
+        .route("POST /chat")
+        .replies(replies)
+        .start()
+        .await
+}
+
+fn test_config(model_type: &str, base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: model_type.to_string(),
+        model: "test-model".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+/// A pipeline configuration that sends every LLM request to the mock server
+fn pipeline_config(base_url: &str, output_path: &str, codebase_path: &str) -> Result<Config> {
+    Ok(serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": base_url,
+        "request_max_retries": 0,
+        "output_path": output_path,
+        "relevance": { "max_workers": 1 },
+        "codebase": {
+            "path": codebase_path,
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+    }))?)
+}
+
+fn request() -> ChatRequest {
+    ChatRequest::new(100, 0.0).with_user("Hello")
+}
+
+#[test]
+fn test_classifies_status_codes() {
+    let classify = |status: u16, message: &str| {
+        LLMError::from_status(
+            "Anthropic",
+            StatusCode::from_u16(status).unwrap(),
+            None,
+            message,
+        )
+    };
+
+    assert!(matches!(classify(401, "bad key"), LLMError::Auth { .. }));
+    assert!(matches!(classify(403, "forbidden"), LLMError::Auth { .. }));
+    assert!(matches!(
+        classify(429, "slow down"),
+        LLMError::RateLimited { .. }
+    ));
+    assert!(matches!(
+        classify(529, "overloaded"),
+        LLMError::Overloaded { .. }
+    ));
+    assert!(matches!(classify(500, "oops"), LLMError::Overloaded { .. }));
+    assert!(matches!(
+        classify(400, CONTEXT_TOO_LONG),
+        LLMError::ContextTooLong { .. }
+    ));
+    assert!(matches!(
+        classify(413, "request too large"),
+        LLMError::ContextTooLong { .. }
+    ));
+    assert!(matches!(
+        classify(400, "max_tokens: must be positive"),
+        LLMError::InvalidRequest { .. }
+    ));
+
+    // Only transient failures are retryable
+    assert!(classify(429, "").is_retryable());
+    assert!(classify(503, "").is_retryable());
+    assert!(!classify(401, "").is_retryable());
+    assert!(!classify(400, CONTEXT_TOO_LONG).is_retryable());
+}
+
+#[test]
+fn test_classifies_stream_error_types() {
+    assert!(matches!(
+        LLMError::from_error_type("Anthropic", "overloaded_error", "Overloaded"),
+        LLMError::Overloaded { .. }
+    ));
+    assert!(matches!(
+        LLMError::from_error_type("Anthropic", "authentication_error", "invalid x-api-key"),
+        LLMError::Auth { .. }
+    ));
+    assert!(matches!(
+        LLMError::from_error_type("OpenAI", "context_length_exceeded", "too long"),
+        LLMError::ContextTooLong { .. }
+    ));
+    assert!(matches!(
+        LLMError::from_error_type("OpenAI", "rate_limit_exceeded", "slow down"),
+        LLMError::RateLimited { .. }
+    ));
+}
+
+#[test]
+fn test_finds_error_through_context() {
+    let error = anyhow::Error::from(LLMError::from_status(
+        "OpenAI",
+        StatusCode::UNAUTHORIZED,
+        None,
+        "Incorrect API key provided",
+    ))
+    .context("Failed to get completion for file: src/main.rs");
+
+    assert!(is_auth_error(&error));
+    assert!(!is_context_too_long(&error));
+    assert!(LLMError::find(&anyhow::anyhow!("unrelated")).is_none());
+}
+
+#[tokio::test]
+async fn test_clients_return_typed_errors() -> Result<()> {
+    let server = start_mock_server(vec![Reply::status(
+        "401 Unauthorized",
+        r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#,
+    )])
+    .await;
+    let client = AnthropicClient::new(&test_config("anthropic", server.url()))?;
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(matches!(
+        LLMError::find(&error),
+        Some(LLMError::Auth { .. })
+    ));
+
+    let server = start_mock_server(vec![Reply::status(
+        "400 Bad Request",
+        r#"{"error":{"message":"This model's maximum context length is 128000 tokens.","code":"context_length_exceeded"}}"#,
+    )])
+    .await;
+    let client = OpenAIClient::new(&test_config("openai", server.url()))?;
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(is_context_too_long(&error));
+
+    let server = start_mock_server(vec![Reply::json("not json")]).await;
+    let client = AnthropicClient::new(&test_config("anthropic", server.url()))?;
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(matches!(
+        LLMError::find(&error),
+        Some(LLMError::MalformedResponse { .. })
+    ));
+
+    // Nothing is listening on this port
+    let client = AnthropicClient::new(&test_config("anthropic", "http://127.0.0.1:1".to_string()))?;
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(matches!(
+        LLMError::find(&error),
+        Some(LLMError::Transport { .. })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_error_events_are_typed() -> Result<()> {
+    let server = start_mock_server(vec![Reply::sse(
+        "event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+    )])
+    .await;
+    let client = AnthropicClient::new(&test_config("anthropic", server.url()))?;
+
+    let stream = client.chat_stream(&request()).await?;
+    let error = collect_stream(stream, &mut |_| {}).await.unwrap_err();
+    assert!(matches!(
+        LLMError::find(&error),
+        Some(LLMError::Overloaded { .. })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_with_fewer_files() -> Result<()> {
+    let overflow = || {
+        anyhow::Error::from(LLMError::ContextTooLong {
+            provider: "Anthropic".to_string(),
+            message: "prompt is too long".to_string(),
+        })
+    };
+
+    // A quarter of the files are dropped each time, at least one
+    let mut attempts = Vec::new();
+    let count = retry_with_fewer_files(10, |count| {
+        attempts.push(count);
+        let result = if count > 4 {
+            Err(overflow())
+        } else {
+            Ok(count)
+        };
+        async move { result }
+    })
+    .await?;
+    assert_eq!(count, 4);
+    assert_eq!(attempts, vec![10, 8, 6, 5, 4]);
+
+    // Once there are no files left, the error is returned
+    let result: Result<usize> = retry_with_fewer_files(2, |_| {
+        let error = overflow();
+        async move { Err(error) }
+    })
+    .await;
+    assert!(is_context_too_long(&result.unwrap_err()));
+
+    // Other errors are returned immediately
+    let mut attempts = 0;
+    let result: Result<usize> = retry_with_fewer_files(5, |_| {
+        attempts += 1;
+        async { Err(anyhow::anyhow!("something else")) }
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dockerfile_drops_lowest_ranked_files_when_too_long() -> Result<()> {
+    let server = start_mock_server(vec![
+        Reply::status("400 Bad Request", CONTEXT_TOO_LONG),
+        Reply::sse(DOCKERFILE_STREAM),
+    ])
+    .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    let files = ["Cargo.toml", "src/main.rs", "src/lib.rs", "README.md"];
+    for (i, path) in files.iter().enumerate() {
+        let full_path = codebase_dir.path().join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap())?;
+        std::fs::write(full_path, format!("contents of file {}", i))?;
+    }
+
+    let config = pipeline_config(
+        &server.url(),
+        &output_dir.path().to_string_lossy(),
+        &codebase_dir.path().to_string_lossy(),
+    )?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    )
+    .with_codebase_path(codebase_dir.path());
+
+    // Save a ranking of the files, most important first
+    let trajectory_store = TrajectoryStore::new(config.get_trajectory_dir(&problem.id), &problem)?;
+    trajectory_store.save_ranking(ProblemContext {
+        model_rankings: Vec::new(),
+        ranked_files: files
+            .iter()
+            .map(|path| RankedCodebaseFile {
+                path: path.to_string(),
+                tokens: 10,
+            })
+            .collect(),
+        agreement: Vec::new(),
+        prompt_caching_usages: Vec::new(),
+    })?;
+
+    dockerfile::generate_dockerfile(&config, problem.clone()).await?;
+
+    let dockerfile = std::fs::read_to_string(config.get_dockerfile_path(&problem.id))?;
+    assert_eq!(dockerfile.trim(), "FROM rust:1.75");
+
+    // The retry leaves out the contents of the lowest-ranked file
+    let bodies = server.json_bodies();
+    assert_eq!(bodies.len(), 2);
+    let prompt = |body: &serde_json::Value| body["messages"][0]["content"].to_string();
+    assert!(prompt(&bodies[0]).contains("contents of file 3"));
+    assert!(prompt(&bodies[1]).contains("contents of file 2"));
+    assert!(!prompt(&bodies[1]).contains("contents of file 3"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relevance_aborts_on_auth_error() -> Result<()> {
+    let server = start_mock_server(vec![Reply::status(
+        "401 Unauthorized",
+        r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#,
+    )])
+    .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    for i in 0..5 {
+        std::fs::write(
+            codebase_dir.path().join(format!("file{}.rs", i)),
+            "fn main() {}\n",
+        )?;
+    }
+
+    let config = pipeline_config(
+        &server.url(),
+        &output_dir.path().to_string_lossy(),
+        &codebase_dir.path().to_string_lossy(),
+    )?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    );
+
+    // Select every file for assessment
+    std::fs::write(
+        std::path::Path::new(&config.get_trajectory_dir(&problem.id))
+            .join("codebase_tree_response.txt"),
+        "```json\n[\"*.rs\"]\n```",
+    )
+    .context("Failed to write file selection response")?;
+
+    let error = relevance::process_codebase(&config, &config.codebase, problem)
+        .await
+        .unwrap_err();
+    assert!(is_auth_error(&error));
+
+    // The first failure stops the stage instead of trying every file
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
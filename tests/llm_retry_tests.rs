@@ -0,0 +1,272 @@
+use anyhow::Result;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{create_client, ChatRequest, LLMClient};
+use engine_builder::llm::error::LLMError;
+use engine_builder::llm::retry::{is_retryable, parse_retry_after, RetryPolicy, RetryingClient};
+use reqwest::header::{HeaderMap, HeaderValue};
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{MockServer, Reply};
+
+const OK_BODY: &str =
+    r#"{"content":[{"type":"text","text":"ok"}],"usage":{"input_tokens":10,"output_tokens":2}}"#;
+
+fn test_config(base_url: String, max_retries: u32) -> LLMConfig {
+    LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries,
+    }
+}
+
+/// A retrying Anthropic client with short delays
+fn retrying_client(base_url: String, max_retries: u32) -> Result<RetryingClient> {
+    let config = test_config(base_url, max_retries);
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+        ..RetryPolicy::from_config(&config)
+    };
+    Ok(RetryingClient::new(
+        Box::new(AnthropicClient::new(&config)?),
+        policy,
+    ))
+}
+
+fn request() -> ChatRequest {
+    ChatRequest::new(100, 0.0).with_user("Hello")
+}
+
+#[tokio::test]
+async fn test_retries_rate_limits_until_success() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::status("429 Too Many Requests", r#"{"type":"error"}"#),
+            Reply::status("529 Overloaded", r#"{"type":"error"}"#),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+    let client = retrying_client(server.url(), 3)?;
+
+    let response = client.chat(&request()).await?;
+    assert_eq!(response.content, "ok");
+    assert_eq!(server.request_count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gives_up_after_max_retries() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![Reply::status("503 Service Unavailable", "down")])
+        .start()
+        .await;
+    let client = retrying_client(server.url(), 2)?;
+
+    let error = client.chat(&request()).await.unwrap_err();
+    match error.downcast_ref::<LLMError>() {
+        Some(LLMError::Overloaded {
+            status, message, ..
+        }) => {
+            assert_eq!(status.as_u16(), 503);
+            assert_eq!(message, "down");
+        }
+        other => panic!("Expected an overloaded error, got {:?}", other),
+    }
+    assert_eq!(server.request_count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_errors_are_not_retried() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![Reply::status("400 Bad Request", "invalid request")])
+        .start()
+        .await;
+    let client = retrying_client(server.url(), 3)?;
+
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(!is_retryable(&error));
+    assert!(error
+        .to_string()
+        .contains("Anthropic API error (400 Bad Request): invalid request"));
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_honors_retry_after_header() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::status("429 Too Many Requests", "slow down").with_header("retry-after", "0.3"),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+    let config = test_config(server.url(), 1);
+    let client = RetryingClient::new(
+        Box::new(AnthropicClient::new(&config)?),
+        RetryPolicy {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+            ..RetryPolicy::from_config(&config)
+        },
+    );
+
+    // The requested delay is longer than the backoff would be
+    let start = Instant::now();
+    client.chat(&request()).await?;
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert_eq!(server.request_count(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_after_beyond_max_delay_fails_fast() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::status("429 Too Many Requests", "slow down").with_header("retry-after", "30"),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+    let client = retrying_client(server.url(), 3)?;
+
+    // The policy waits at most 50ms, so the error is returned without waiting
+    let start = Instant::now();
+    let error = client.chat(&request()).await.unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(matches!(
+        LLMError::find(&error),
+        Some(LLMError::RateLimited { retry_after: Some(delay), .. }) if *delay == Duration::from_secs(30)
+    ));
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slow_attempts_time_out_and_retry() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::json(OK_BODY).with_delay(Duration::from_secs(3)),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+    let config = test_config(server.url(), 1);
+    let client = RetryingClient::new(
+        Box::new(AnthropicClient::new(&config)?),
+        RetryPolicy {
+            base_delay: Duration::from_millis(10),
+            attempt_timeout: Some(Duration::from_millis(200)),
+            ..RetryPolicy::from_config(&config)
+        },
+    );
+
+    let start = Instant::now();
+    let response = client.chat(&request()).await?;
+    assert_eq!(response.content, "ok");
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert_eq!(server.request_count(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_created_clients_retry() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::status("529 Overloaded", r#"{"type":"error"}"#)
+                .with_header("retry-after-ms", "10"),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+
+    let client = create_client(&test_config(server.url(), 1)).await?;
+    let response = client.chat(&request()).await?;
+    assert_eq!(response.content, "ok");
+    assert_eq!(server.request_count(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streams_retry_until_opened() -> Result<()> {
+    let server = MockServer::messages()
+        .replies(vec![
+            Reply::status("500 Internal Server Error", "oops"),
+            Reply::json(OK_BODY),
+        ])
+        .start()
+        .await;
+    let client = retrying_client(server.url(), 1)?;
+
+    let mut deltas = String::new();
+    let response = client
+        .chat_stream_with_tracing(&request(), None, None, None, &mut |delta| {
+            deltas.push_str(delta)
+        })
+        .await?;
+    assert_eq!(server.request_count(), 2);
+    assert_eq!(response.content, deltas);
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_retry_after() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(parse_retry_after(&headers), None);
+
+    headers.insert("retry-after", HeaderValue::from_static("2"));
+    assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(2)));
+
+    // The millisecond header is more precise, so it takes priority
+    headers.insert("retry-after-ms", HeaderValue::from_static("1500"));
+    assert_eq!(
+        parse_retry_after(&headers),
+        Some(Duration::from_millis(1500))
+    );
+
+    // HTTP dates and garbage are ignored
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "retry-after",
+        HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+    );
+    assert_eq!(parse_retry_after(&headers), None);
+}
+
+#[test]
+fn test_backoff_grows_with_jitter() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(1000),
+        attempt_timeout: None,
+    };
+    let error = anyhow::anyhow!("connection reset");
+
+    for _ in 0..20 {
+        let first = policy.delay(1, &error).unwrap();
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+
+        let third = policy.delay(3, &error).unwrap();
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+
+        // Capped at the maximum delay
+        let tenth = policy.delay(10, &error).unwrap();
+        assert!(tenth >= Duration::from_millis(500) && tenth <= Duration::from_millis(1000));
+    }
+}
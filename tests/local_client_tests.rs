@@ -0,0 +1,77 @@
+use anyhow::Result;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::client::LLMClient;
+use engine_builder::llm::local::LocalClient;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Start a mock server that answers like an Ollama instance
+async fn start_mock_ollama() -> MockServer {
+    MockServer::builder()
+        .respond_with(|request, _| {
+            if request.head.starts_with("POST /v1/chat/completions") {
+                Reply::json(
+                    r#"{"choices":[{"message":{"content":"local answer"}}],
+                        "usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#,
+                )
+            } else if request.head.starts_with("GET /v1/models") {
+                Reply::json(r#"{"data":[{"id":"llama3.1:8b"}]}"#)
+            } else if request.head.starts_with("POST /api/show") {
+                Reply::json(
+                    r#"{"model_info":{"general.architecture":"llama","llama.context_length":131072}}"#,
+                )
+            } else {
+                Reply::status("404 Not Found", r#"{"error":"not found"}"#)
+            }
+        })
+        .start()
+        .await
+}
+
+fn local_config(base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: "local".to_string(),
+        model: "llama3.1:8b".to_string(),
+        api_key: "".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+#[tokio::test]
+async fn test_local_client_completion_without_auth() -> Result<()> {
+    let server = start_mock_ollama().await;
+    let client = LocalClient::new(&local_config(format!("{}/v1", server.url())))?;
+
+    let response = client.completion("Hello", 100, 0.0).await?;
+
+    assert_eq!(response.content, "local answer");
+    assert_eq!(response.usage.prompt_tokens, 12);
+    assert_eq!(response.usage.total_tokens, 15);
+    assert_eq!(client.name(), "local");
+
+    // Local servers don't need auth, so no key should be sent
+    let requests = server.requests();
+    assert!(!requests[0].head.to_lowercase().contains("authorization:"));
+
+    // Self-hosted models are free
+    let cost = client.calculate_cost(&response.usage);
+    assert_eq!(cost.total_cost, 0.0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_local_client_discovers_context_window() -> Result<()> {
+    let server = start_mock_ollama().await;
+    let client = LocalClient::new(&local_config(format!("{}/v1", server.url())))?;
+    assert_eq!(client.context_window(), None);
+
+    // The model listing has no context length, so discovery falls through to /api/show
+    client.fetch_model_info().await?;
+    assert_eq!(client.context_window(), Some(131072));
+
+    Ok(())
+}
@@ -64,8 +64,11 @@ impl LLMClient for MockLLMClient {
             usage: TokenUsage {
                 prompt_tokens: 100,
                 completion_tokens: 100,
-                total_tokens: 200,
+                total_tokens: 280,
+                cache_creation_input_tokens: 0,
+                cache_read_input_tokens: 80,
             },
+            tool_calls: Vec::new(),
         })
     }
 
@@ -135,18 +138,32 @@ async fn test_end_to_end_pipeline_compatibility() -> Result<()> {
     let global_config = Config {
         anthropic_api_key: "dummy_key".to_string(),
         model: "test-model".to_string(),
+        llm: Default::default(),
         relevance: RelevanceConfig {
             model: Some("test-model".to_string()),
+            models: Vec::new(),
             max_tokens: 1000,
             max_file_tokens: 10000,
+            chunk_overlap_lines: 20,
+            max_chunks: 10,
+            batch_small_files: false,
+            small_file_tokens: 500,
+            batch_max_files: 20,
+            batch_max_tokens: 8000,
             max_workers: 4,
             timeout: 30.0,
+            llm: Default::default(),
         },
         ranking: RankingConfig {
             model: Some("test-model".to_string()),
+            models: Vec::new(),
             max_tokens: 1000,
             max_workers: 4,
             temperature: 0.0,
+            num_rankings: 1,
+            shuffle_files: false,
+            aggregation: Default::default(),
+            llm: Default::default(),
         },
         output_path: Some(temp_path.clone()),
         codebase: CodebaseConfig {
@@ -154,12 +171,18 @@ async fn test_end_to_end_pipeline_compatibility() -> Result<()> {
             exclusions_path: "exclusions.json".to_string(),
             problem_id: "e2e_test".to_string(),
             problem_statement: "Test problem statement".to_string(),
+            file_rules: Default::default(),
+            git_scope: None,
+            tree_max_tokens: 20_000,
         },
         dockerfile: Default::default(),
         scripts: Default::default(),
         chat: Default::default(),
         container: Default::default(),
         observability: Default::default(),
+        cache: Default::default(),
+        budget: Default::default(),
+        pricing: Default::default(),
     };
 
     // Extract configs from global config
@@ -220,6 +243,8 @@ These files are most likely to be relevant to the issue described."#;
             message: "Main file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains the main entry point".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -229,6 +254,8 @@ These files are most likely to be relevant to the issue described."#;
             message: "Library file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains core functionality".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -238,6 +265,8 @@ These files are most likely to be relevant to the issue described."#;
             message: "Model file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Defines file structures".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -270,6 +299,14 @@ These files are most likely to be relevant to the issue described."#;
     assert!(ranked_paths.contains(&&"src/lib.rs".to_string()));
     assert!(ranked_paths.contains(&&"src/models/file.rs".to_string()));
 
+    // The ranking call's prompt caching usage is recorded alongside it
+    assert_eq!(ranking.prompt_caching_usages.len(), 1);
+    let usage = &ranking.prompt_caching_usages[0];
+    assert_eq!(usage["input_tokens"], 100);
+    assert_eq!(usage["output_tokens"], 100);
+    assert_eq!(usage["cache_creation_input_tokens"], 0);
+    assert_eq!(usage["cache_read_input_tokens"], 80);
+
     // Cleanup
     temp_dir.close()?;
     codebase_dir.close()?;
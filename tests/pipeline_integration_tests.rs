@@ -42,6 +42,8 @@ fn setup_mock_pipeline(store: &TrajectoryStore) -> Result<()> {
             message: "Main file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains the main entry point".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -51,6 +53,8 @@ fn setup_mock_pipeline(store: &TrajectoryStore) -> Result<()> {
             message: "Library file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains core functionality".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -60,6 +64,8 @@ fn setup_mock_pipeline(store: &TrajectoryStore) -> Result<()> {
             message: "Model file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Defines file structures".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -150,6 +156,8 @@ fn test_relevance_to_ranking_format_compatibility() -> Result<()> {
             message: "Main file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains the main entry point".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -159,6 +167,8 @@ fn test_relevance_to_ranking_format_compatibility() -> Result<()> {
             message: "Library file is relevant".to_string(),
             status: RelevanceStatus::Relevant,
             summary: Some("Contains core functionality".to_string()),
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -168,6 +178,8 @@ fn test_relevance_to_ranking_format_compatibility() -> Result<()> {
             message: "This file is not relevant".to_string(),
             status: RelevanceStatus::NotRelevant,
             summary: None,
+            confidence: None,
+            relevant_lines: Vec::new(),
         },
     );
 
@@ -259,6 +271,7 @@ fn test_full_pipeline_format_compatibility() -> Result<()> {
     let context = ProblemContext {
         model_rankings: vec![],
         ranked_files,
+        agreement: vec![],
         prompt_caching_usages: vec![],
     };
 
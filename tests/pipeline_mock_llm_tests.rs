@@ -41,8 +41,9 @@ These files are most likely to be relevant to the issue described."#.to_string()
         responses.insert(
             "relevance_src/main.rs".to_string(),
             r#"
-RELEVANCE: Relevant
-SUMMARY: This file contains the main entry point for the application and handles CLI arguments.
+```json
+{"status": "relevant", "summary": "This file contains the main entry point for the application and handles CLI arguments."}
+```
 "#
             .to_string(),
         );
@@ -50,8 +51,9 @@ SUMMARY: This file contains the main entry point for the application and handles
         responses.insert(
             "relevance_src/lib.rs".to_string(),
             r#"
-RELEVANCE: Relevant
-SUMMARY: This file exports the core modules and functionality of the library.
+```json
+{"status": "relevant", "summary": "This file exports the core modules and functionality of the library."}
+```
 "#
             .to_string(),
         );
@@ -59,8 +61,9 @@ SUMMARY: This file exports the core modules and functionality of the library.
         responses.insert(
             "relevance_src/models/file.rs".to_string(),
             r#"
-RELEVANCE: Relevant
-SUMMARY: This file defines the file data structures used throughout the codebase.
+```json
+{"status": "relevant", "summary": "This file defines the file data structures used throughout the codebase."}
+```
 "#
             .to_string(),
         );
@@ -148,7 +151,9 @@ impl LLMClient for MockLLMClient {
                 prompt_tokens: 100,
                 completion_tokens: 100,
                 total_tokens: 200,
+                ..Default::default()
             },
+            tool_calls: Vec::new(),
         })
     }
 
@@ -196,30 +201,50 @@ fn create_test_configs() -> (Config, RelevanceConfig, CodebaseConfig, RankingCon
     let global_config = Config {
         anthropic_api_key: "dummy_key".to_string(),
         model: "test-model".to_string(),
+        llm: Default::default(),
         relevance: RelevanceConfig {
             model: Some("test-model".to_string()),
+            models: Vec::new(),
             max_tokens: 1000,
             max_file_tokens: 10000,
+            chunk_overlap_lines: 20,
+            max_chunks: 10,
+            batch_small_files: false,
+            small_file_tokens: 500,
+            batch_max_files: 20,
+            batch_max_tokens: 8000,
             max_workers: 4,
             timeout: 30.0,
+            llm: Default::default(),
         },
         ranking: RankingConfig {
             model: Some("test-model".to_string()),
+            models: Vec::new(),
             max_tokens: 1000,
             max_workers: 4,
             temperature: 0.0,
+            num_rankings: 1,
+            shuffle_files: false,
+            aggregation: Default::default(),
+            llm: Default::default(),
         },
         codebase: CodebaseConfig {
             path: temp_dir.path().to_path_buf(),
             exclusions_path: "exclusions.json".to_string(),
             problem_id: "test_problem".to_string(),
             problem_statement: "Test problem statement".to_string(),
+            file_rules: Default::default(),
+            git_scope: None,
+            tree_max_tokens: 20_000,
         },
         dockerfile: Default::default(),
         scripts: Default::default(),
         chat: Default::default(),
         container: Default::default(),
         observability: Default::default(),
+        cache: Default::default(),
+        budget: Default::default(),
+        pricing: Default::default(),
         output_path: Some(temp_path),
     };
 
@@ -344,6 +369,8 @@ These files are most likely to be relevant to the issue described."#,
                 message: format!("{} is relevant", path),
                 status: engine_builder::models::relevance::RelevanceStatus::Relevant,
                 summary: Some(format!("Summary for {}", path)),
+                confidence: None,
+                relevant_lines: Vec::new(),
             },
         );
     }
@@ -392,6 +419,7 @@ These files are most likely to be relevant to the issue described."#,
                 tokens: 70,
             },
         ],
+        agreement: vec![],
         prompt_caching_usages: vec![],
     };
 
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use engine_builder::config::{LLMConfig, ModelPrice, PricingConfig};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{LLMClient, TokenUsage};
+use engine_builder::llm::pricing::{set_pricing_catalog, PricingCatalog};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn price(input: f64, output: f64) -> ModelPrice {
+    ModelPrice {
+        input,
+        output,
+        cache_write: None,
+        cache_read: None,
+    }
+}
+
+fn overrides(provider: &str, model: &str, price: ModelPrice) -> PricingConfig {
+    PricingConfig {
+        path: None,
+        models: [(
+            provider.to_string(),
+            HashMap::from([(model.to_string(), price)]),
+        )]
+        .into(),
+    }
+}
+
+#[test]
+fn test_bundled_pricing_matches_model_families() {
+    let catalog = PricingCatalog::bundled();
+    let source = format!("bundled pricing {}", catalog.version());
+
+    // Dated versions get their family's price
+    let sonnet = catalog.pricing("anthropic", "claude-3-7-sonnet-20250219");
+    assert_eq!(sonnet.price.input, 3.0);
+    assert_eq!(sonnet.price.output, 15.0);
+    assert_eq!(sonnet.source, source);
+
+    // The longest matching name wins
+    assert_eq!(catalog.pricing("openai", "gpt-4o-mini").price.input, 0.15);
+    assert_eq!(
+        catalog.pricing("openai", "gpt-4o-2024-08-06").price.input,
+        2.5
+    );
+    assert_eq!(catalog.pricing("openai", "gpt-4-0613").price.input, 30.0);
+
+    // Every provider is covered
+    assert_eq!(catalog.pricing("local", "llama3").price.input, 0.0);
+}
+
+#[test]
+fn test_cost_uses_cache_prices() {
+    let catalog = PricingCatalog::bundled();
+    let usage = TokenUsage {
+        prompt_tokens: 1_000_000,
+        completion_tokens: 1_000_000,
+        cache_creation_input_tokens: 1_000_000,
+        cache_read_input_tokens: 1_000_000,
+        ..Default::default()
+    };
+
+    let cost = catalog.pricing("openai", "gpt-4.1").cost(&usage);
+    assert!((cost.prompt_cost - 2.0).abs() < 1e-9);
+    assert!((cost.completion_cost - 8.0).abs() < 1e-9);
+    assert!((cost.cache_write_cost - 2.0).abs() < 1e-9);
+    assert!((cost.cache_read_cost - 0.5).abs() < 1e-9);
+
+    // Without cache prices, the usual multiples of the input price are used
+    let catalog =
+        PricingCatalog::load(&overrides("openai", "my-model", price(10.0, 20.0))).unwrap();
+    let cost = catalog.pricing("openai", "my-model").cost(&usage);
+    assert!((cost.cache_write_cost - 12.5).abs() < 1e-9);
+    assert!((cost.cache_read_cost - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_unknown_models_use_provider_default() {
+    let catalog = PricingCatalog::bundled();
+
+    assert!(catalog.lookup("anthropic", "claude-99").is_none());
+    let pricing = catalog.pricing("anthropic", "claude-99");
+    assert_eq!(pricing.source, "anthropic default");
+    assert_eq!(pricing.price.input, 3.0);
+
+    let pricing = catalog.pricing("some-provider", "some-model");
+    assert_eq!(pricing.source, "unknown");
+    assert_eq!(pricing.price.input, 0.0);
+}
+
+#[test]
+fn test_config_overrides_bundled_prices() -> Result<()> {
+    let catalog = PricingCatalog::load(&overrides(
+        "anthropic",
+        "claude-3-7-sonnet",
+        price(1.0, 2.0),
+    ))?;
+
+    let pricing = catalog.pricing("anthropic", "claude-3-7-sonnet-20250219");
+    assert_eq!(pricing.price, price(1.0, 2.0));
+    assert_eq!(pricing.source, "config");
+
+    // Other models keep their bundled prices
+    assert_eq!(
+        catalog.pricing("anthropic", "claude-3-opus").price.input,
+        15.0
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_pricing_table_loaded_from_file() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("pricing.json");
+    std::fs::write(
+        &path,
+        r#"{"version": "test-1", "providers": {"openai": {"models": {"gpt-4o": {"input": 1.0, "output": 4.0}}}}}"#,
+    )?;
+
+    let catalog = PricingCatalog::load(&PricingConfig {
+        path: Some(path.to_string_lossy().to_string()),
+        ..Default::default()
+    })?;
+    assert_eq!(catalog.version(), "test-1");
+
+    let pricing = catalog.pricing("openai", "gpt-4o");
+    assert_eq!(pricing.price.output, 4.0);
+    assert_eq!(pricing.source, format!("{} test-1", path.display()));
+
+    // Only the file's models are known
+    assert_eq!(catalog.pricing("openai", "gpt-4.1").source, "unknown");
+
+    Ok(())
+}
+
+#[test]
+fn test_clients_price_usage_from_current_catalog() -> Result<()> {
+    let client = AnthropicClient::new(&LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-custom".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: None,
+        timeout: 5,
+        max_retries: 0,
+    })?;
+    let usage = TokenUsage {
+        prompt_tokens: 1000,
+        completion_tokens: 1000,
+        ..Default::default()
+    };
+
+    set_pricing_catalog(Some(Arc::new(PricingCatalog::load(&overrides(
+        "anthropic",
+        "claude-custom",
+        price(2.0, 6.0),
+    ))?)));
+    let pricing = client.pricing();
+    let cost = client.calculate_cost(&usage);
+    let (prompt_price, completion_price) = client.get_token_prices();
+    set_pricing_catalog(None);
+
+    assert_eq!(pricing.source, "config");
+    assert!((cost.total_cost - 0.008).abs() < 1e-9);
+    assert!((prompt_price - 0.002).abs() < 1e-12);
+    assert!((completion_price - 0.006).abs() < 1e-12);
+
+    Ok(())
+}
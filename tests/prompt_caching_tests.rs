@@ -0,0 +1,177 @@
+use anyhow::Result;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{ChatRequest, LLMClient, Message, TokenCost, TokenUsage};
+use serde_json::json;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Start a mock server that answers like the Anthropic messages API,
+/// reporting a cache hit
+async fn start_mock_anthropic() -> MockServer {
+    MockServer::builder()
+        .reply(Reply::json(
+            r#"{"content":[{"type":"text","text":"ok"}],"usage":{"input_tokens":50,"output_tokens":10,"cache_creation_input_tokens":0,"cache_read_input_tokens":2000}}"#,
+        ))
+        .start()
+        .await
+}
+
+fn anthropic_config(base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+#[test]
+fn test_token_cost_prices_cache_reads_and_writes() {
+    let usage = TokenUsage {
+        prompt_tokens: 1000,
+        completion_tokens: 1000,
+        total_tokens: 5000,
+        cache_creation_input_tokens: 2000,
+        cache_read_input_tokens: 1000,
+    };
+
+    // Cache writes cost 1.25x and reads 0.1x the prompt price
+    let cost = TokenCost::from_usage(&usage, 0.003, 0.015);
+    assert!((cost.prompt_cost - 0.003).abs() < 1e-9);
+    assert!((cost.completion_cost - 0.015).abs() < 1e-9);
+    assert!((cost.cache_write_cost - 0.0075).abs() < 1e-9);
+    assert!((cost.cache_read_cost - 0.0003).abs() < 1e-9);
+    assert!((cost.total_cost - 0.0258).abs() < 1e-9);
+
+    // Without cache usage the cost is unchanged
+    let usage = TokenUsage {
+        prompt_tokens: 1000,
+        completion_tokens: 1000,
+        total_tokens: 2000,
+        ..Default::default()
+    };
+    let cost = TokenCost::from_usage(&usage, 0.003, 0.015);
+    assert!((cost.total_cost - 0.018).abs() < 1e-9);
+    assert_eq!(cost.cache_write_cost, 0.0);
+}
+
+#[test]
+fn test_token_usage_accumulates_cache_tokens() {
+    let mut total = TokenUsage::default();
+    let usage = TokenUsage {
+        prompt_tokens: 10,
+        completion_tokens: 5,
+        total_tokens: 115,
+        cache_creation_input_tokens: 100,
+        cache_read_input_tokens: 0,
+    };
+    total += &usage;
+    total += &TokenUsage {
+        cache_creation_input_tokens: 0,
+        cache_read_input_tokens: 100,
+        ..usage.clone()
+    };
+
+    assert_eq!(total.prompt_tokens, 20);
+    assert_eq!(total.total_tokens, 230);
+    assert_eq!(total.cache_creation_input_tokens, 100);
+    assert_eq!(total.cache_read_input_tokens, 100);
+
+    let map = total.to_usage_map();
+    assert_eq!(map["input_tokens"], json!(20));
+    assert_eq!(map["output_tokens"], json!(10));
+    assert_eq!(map["cache_creation_input_tokens"], json!(100));
+    assert_eq!(map["cache_read_input_tokens"], json!(100));
+}
+
+#[test]
+fn test_cached_prefix_message() {
+    let message = Message::user_with_cached_prefix("<issue>bug</issue>\n", "File: a.rs");
+    assert_eq!(message.content, "<issue>bug</issue>\nFile: a.rs");
+    assert_eq!(message.cache_breakpoint, Some(19));
+    assert_eq!(
+        &message.content[..message.cache_breakpoint.unwrap()],
+        "<issue>bug</issue>\n"
+    );
+
+    // The full content is still available to providers without caching
+    let request = ChatRequest::new(100, 0.0).with_message(message);
+    assert_eq!(request.to_prompt(), "<issue>bug</issue>\nFile: a.rs");
+}
+
+#[tokio::test]
+async fn test_anthropic_marks_cacheable_prefixes() -> Result<()> {
+    let server = start_mock_anthropic().await;
+    let client = AnthropicClient::new(&anthropic_config(server.url()))?;
+
+    let request = ChatRequest::new(500, 0.0)
+        .with_cached_system("You assess relevance")
+        .with_message(Message::user_with_cached_prefix(
+            "Issue: crash on start\n",
+            "File: src/main.rs",
+        ));
+    let response = client.chat(&request).await?;
+
+    // Cache reads are parsed and counted towards the total
+    assert_eq!(response.usage.prompt_tokens, 50);
+    assert_eq!(response.usage.completion_tokens, 10);
+    assert_eq!(response.usage.cache_read_input_tokens, 2000);
+    assert_eq!(response.usage.cache_creation_input_tokens, 0);
+    assert_eq!(response.usage.total_tokens, 2060);
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+    assert_eq!(
+        body["system"],
+        json!([{
+            "type": "text",
+            "text": "You assess relevance",
+            "cache_control": { "type": "ephemeral" },
+        }])
+    );
+    assert_eq!(
+        body["messages"][0]["content"],
+        json!([
+            {
+                "type": "text",
+                "text": "Issue: crash on start\n",
+                "cache_control": { "type": "ephemeral" },
+            },
+            { "type": "text", "text": "File: src/main.rs" },
+        ])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_anthropic_limits_cache_breakpoints() -> Result<()> {
+    let server = start_mock_anthropic().await;
+    let client = AnthropicClient::new(&anthropic_config(server.url()))?;
+
+    // The system prompt and five cached messages exceed the limit of four
+    let mut request = ChatRequest::new(500, 0.0).with_cached_system("System");
+    for i in 0..5 {
+        request = request
+            .with_message(Message::user_with_cached_prefix(
+                format!("Prefix {}", i),
+                "!",
+            ))
+            .with_message(Message::assistant("ok"));
+    }
+    client.chat(&request).await?;
+
+    let bodies = server.json_bodies();
+    let body = &bodies[0];
+    let body_text = body.to_string();
+    assert_eq!(body_text.matches("cache_control").count(), 4);
+
+    // Messages past the limit are sent as plain text
+    assert_eq!(body["messages"][8]["content"], "Prefix 4!");
+
+    Ok(())
+}
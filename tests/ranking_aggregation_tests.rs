@@ -0,0 +1,282 @@
+use anyhow::Result;
+use engine_builder::config::{Config, RankAggregation};
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::relevance::RelevanceDecision;
+use engine_builder::stages::ranking::{self, aggregate_rankings};
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::MockServer;
+
+fn ranking(paths: &[&str]) -> Vec<String> {
+    paths.iter().map(|path| path.to_string()).collect()
+}
+
+fn merged_paths(rankings: &[Vec<String>], method: RankAggregation) -> Vec<String> {
+    aggregate_rankings(rankings, method)
+        .into_iter()
+        .map(|file| file.path)
+        .collect()
+}
+
+#[test]
+fn test_single_ranking_is_unchanged() {
+    let rankings = vec![ranking(&["c.rs", "a.rs", "b.rs"])];
+
+    for method in [RankAggregation::Borda, RankAggregation::ReciprocalRank] {
+        let merged = aggregate_rankings(&rankings, method);
+        let paths: Vec<&str> = merged.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["c.rs", "a.rs", "b.rs"]);
+        assert!(merged.iter().all(|file| file.agreement == 1.0));
+        assert_eq!(merged[1].positions, vec![Some(2)]);
+    }
+}
+
+#[test]
+fn test_borda_count() {
+    let rankings = vec![
+        ranking(&["a.rs", "b.rs", "c.rs"]),
+        ranking(&["b.rs", "c.rs", "a.rs"]),
+        ranking(&["b.rs", "a.rs"]),
+    ];
+
+    let merged = aggregate_rankings(&rankings, RankAggregation::Borda);
+
+    // b: 2 + 3 + 3, a: 3 + 1 + 2, c: 1 + 2
+    let scores: Vec<(&str, f64)> = merged
+        .iter()
+        .map(|file| (file.path.as_str(), file.score))
+        .collect();
+    assert_eq!(scores, vec![("b.rs", 8.0), ("a.rs", 6.0), ("c.rs", 3.0)]);
+
+    let c = &merged[2];
+    assert!((c.agreement - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!(c.positions, vec![Some(3), Some(2), None]);
+}
+
+#[test]
+fn test_reciprocal_rank_fusion() {
+    // A file first in one ranking and missing from the other loses to one
+    // ranked second by both
+    let rankings = vec![
+        ranking(&["a.rs", "b.rs", "c.rs"]),
+        ranking(&["c.rs", "b.rs"]),
+    ];
+
+    let merged = aggregate_rankings(&rankings, RankAggregation::ReciprocalRank);
+
+    let paths: Vec<&str> = merged.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(paths, vec!["c.rs", "b.rs", "a.rs"]);
+    assert!((merged[1].score - 2.0 / 62.0).abs() < 1e-12);
+    assert!((merged[2].score - 1.0 / 61.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_ties_keep_order_of_first_appearance() {
+    let rankings = vec![ranking(&["a.rs", "b.rs"]), ranking(&["b.rs", "a.rs"])];
+
+    assert_eq!(
+        merged_paths(&rankings, RankAggregation::Borda),
+        vec!["a.rs", "b.rs"]
+    );
+    assert_eq!(
+        merged_paths(&rankings, RankAggregation::ReciprocalRank),
+        vec!["a.rs", "b.rs"]
+    );
+}
+
+#[test]
+fn test_repeated_paths_count_once() {
+    let rankings = vec![ranking(&["a.rs", "a.rs", "a.rs", "b.rs"])];
+
+    let merged = aggregate_rankings(&rankings, RankAggregation::Borda);
+
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].positions, vec![Some(1)]);
+    assert_eq!(merged[1].positions, vec![Some(2)]);
+    assert_eq!(merged[1].score, 1.0);
+}
+
+#[test]
+fn test_aggregation_config() -> Result<()> {
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "codebase": { "problem_id": "test_problem", "problem_statement": "The build is broken" },
+        "ranking": {
+            "num_rankings": 5,
+            "shuffle_files": true,
+            "aggregation": "reciprocal-rank",
+        },
+    }))?;
+    assert_eq!(config.ranking.num_rankings, 5);
+    assert!(config.ranking.shuffle_files);
+    assert_eq!(config.ranking.aggregation, RankAggregation::ReciprocalRank);
+
+    let config = Config::default();
+    assert_eq!(config.ranking.num_rankings, 1);
+    assert!(!config.ranking.shuffle_files);
+    assert_eq!(config.ranking.aggregation, RankAggregation::Borda);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ranking_samples_are_merged() -> Result<()> {
+    let server = MockServer::messages()
+        .answers(&[
+            "```json\n[\"a.rs\", \"b.rs\", \"c.rs\"]\n```",
+            "```json\n[\"b.rs\", \"a.rs\", \"c.rs\"]\n```",
+            "```json\n[\"a.rs\", \"c.rs\", \"made_up.rs\", \"d.rs\"]\n```",
+        ])
+        .start()
+        .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    for name in ["a", "b", "c", "d"] {
+        std::fs::write(
+            codebase_dir.path().join(format!("{}.rs", name)),
+            format!("fn {}() {{}}\n", name),
+        )?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+        "ranking": {
+            "num_rankings": 3,
+            "shuffle_files": true,
+            "max_workers": 1,
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    )
+    .with_codebase_path(codebase_dir.path());
+
+    // Mark every file relevant, as the earlier stages would
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    let trajectory_store = TrajectoryStore::new(&trajectory_dir, &problem)?;
+    std::fs::write(
+        trajectory_store.problem_dir().join("file_patterns.json"),
+        "[]",
+    )?;
+    for name in ["a", "b", "c", "d"] {
+        trajectory_store.save_per_file_relevance_decision(
+            &format!("{}.rs", name),
+            RelevanceDecision::relevant(String::new(), format!("Defines {}", name)),
+        )?;
+    }
+
+    ranking::process_rankings(&config, problem.clone()).await?;
+
+    let context = trajectory_store.load_ranking()?;
+    assert_eq!(context.model_rankings.len(), 3);
+    assert_eq!(context.prompt_caching_usages.len(), 3);
+
+    // a: 4 + 3 + 4, b: 3 + 4, c: 2 + 2 + 3, d: 2, counting only known files
+    let agreement: Vec<(&str, f64, f64)> = context
+        .agreement
+        .iter()
+        .map(|file| (file.path.as_str(), file.score, file.agreement))
+        .collect();
+    assert_eq!(agreement.len(), 4);
+    assert_eq!(agreement[0], ("a.rs", 11.0, 1.0));
+    assert_eq!(agreement[1].0, "b.rs");
+    assert_eq!(agreement[2], ("c.rs", 7.0, 1.0));
+    assert_eq!(agreement[3].0, "d.rs");
+
+    // Files are ranked by their merged score, even those most rankings leave
+    // out
+    let ranked: Vec<&str> = context
+        .ranked_files
+        .iter()
+        .map(|file| file.path.as_str())
+        .collect();
+    assert_eq!(ranked, vec!["a.rs", "b.rs", "c.rs", "d.rs"]);
+
+    // Later samples list the files in a different order
+    let prompts: Vec<String> = server
+        .json_bodies()
+        .iter()
+        .map(|body| body["messages"][0].to_string())
+        .collect();
+    assert_eq!(prompts.len(), 3);
+    assert!(prompts[1] != prompts[0] || prompts[2] != prompts[0]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ranking_recovered_without_json_does_not_fall_back() -> Result<()> {
+    // No JSON array, but the paths can still be picked out of the answer
+    let server = MockServer::messages()
+        .answers(&["Most important first: \"b.rs\", then \"a.rs\"."])
+        .start()
+        .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    for name in ["a", "b"] {
+        std::fs::write(
+            codebase_dir.path().join(format!("{}.rs", name)),
+            format!("fn {}() {{}}\n", name),
+        )?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+        "ranking": {
+            "models": ["claude-3-5-haiku-20241022", "claude-3-7-sonnet-20250219"],
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    )
+    .with_codebase_path(codebase_dir.path());
+
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    let trajectory_store = TrajectoryStore::new(&trajectory_dir, &problem)?;
+    std::fs::write(
+        trajectory_store.problem_dir().join("file_patterns.json"),
+        "[]",
+    )?;
+    for name in ["a", "b"] {
+        trajectory_store.save_per_file_relevance_decision(
+            &format!("{}.rs", name),
+            RelevanceDecision::relevant(String::new(), format!("Defines {}", name)),
+        )?;
+    }
+
+    ranking::process_rankings(&config, problem.clone()).await?;
+
+    // The first model's answer is used, so the second is never asked
+    let bodies = server.json_bodies();
+    assert_eq!(bodies.len(), 1);
+    assert_eq!(bodies[0]["model"], "claude-3-5-haiku-20241022");
+    let ranked: Vec<String> = trajectory_store
+        .load_ranking()?
+        .ranked_files
+        .into_iter()
+        .map(|file| file.path)
+        .collect();
+    assert_eq!(ranked, vec!["b.rs", "a.rs"]);
+    Ok(())
+}
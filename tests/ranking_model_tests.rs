@@ -104,6 +104,7 @@ fn test_problem_context() {
     let context = ProblemContext {
         model_rankings: vec![ranking1, ranking2],
         ranked_files: ranked_files,
+        agreement: Vec::new(),
         prompt_caching_usages: vec![usage1, usage2],
     };
 
@@ -0,0 +1,242 @@
+use anyhow::Result;
+use engine_builder::config::Config;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::relevance::{
+    RelevanceDecision, RelevanceStatus, RelevanceVerdict, StructuredBatchRelevance,
+};
+use engine_builder::stages::relevance;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use std::collections::HashMap;
+use tempfile::tempdir;
+
+mod common;
+use common::{MockServer, Reply};
+
+const BATCH_DECISIONS: &str = "Package markers don't matter here, but the test runner settings do.\n\n```json\n{\"decisions\": [{\"path\": \"pkg/__init__.py\", \"status\": \"not_relevant\"}, {\"path\": \"setup.cfg\", \"status\": \"relevant\", \"summary\": \"Configures the test runner.\", \"confidence\": 0.9}, {\"path\": \"pkg/io/__init__.py\", \"status\": \"not_relevant\"}]}\n```";
+const UNFENCED_BATCH_DECISIONS: &str = "{\"decisions\": [{\"path\": \"pkg/__init__.py\", \"status\": \"not_relevant\"}, {\"path\": \"setup.cfg\", \"status\": \"relevant\", \"summary\": \"Configures the test runner.\"}, {\"path\": \"pkg/io/__init__.py\", \"status\": \"not_relevant\"}]}";
+const PARTIAL_BATCH_DECISIONS: &str = "```json\n{\"decisions\": [{\"path\": \"pkg/__init__.py\", \"status\": \"not_relevant\"}, {\"path\": \"pkg/io/__init__.py\", \"status\": \"not_relevant\"}]}\n```";
+const NOT_RELEVANT: &str = "```json\n{\"status\": \"not_relevant\"}\n```";
+
+/// Run the relevance stage with small files batched over a codebase of three
+/// small files and one larger one, returning the saved decisions and the
+/// requests sent. The files in `assessed` already have a decision.
+async fn assess_with_batching(
+    batch_answer: &'static str,
+    assessed: &[&str],
+) -> Result<(HashMap<String, RelevanceDecision>, Vec<String>)> {
+    // Batches of files get `batch_answer`, single files NOT_RELEVANT
+    let server = MockServer::messages()
+        .respond_with(move |request, _| {
+            if request.body.contains("small files instead of one") {
+                Reply::text(batch_answer)
+            } else {
+                Reply::text(NOT_RELEVANT)
+            }
+        })
+        .start()
+        .await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    let files = [
+        ("pkg/__init__.py", "from .core import run\n".to_string()),
+        ("setup.cfg", "[tool:pytest]\naddopts = -x\n".to_string()),
+        ("pkg/io/__init__.py", "# io\n".to_string()),
+        ("pkg/core.py", "def run():\n    return 1\n".repeat(50)),
+    ];
+    for (path, content) in &files {
+        let full_path = codebase_dir.path().join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap())?;
+        std::fs::write(full_path, content)?;
+    }
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "relevance": {
+            "max_workers": 1,
+            "batch_small_files": true,
+            "small_file_tokens": 50,
+        },
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The tests stop at the first failure",
+            // Have the model assess the setup.cfg rather than the built-in rules
+            "file_rules": { "builtin": false },
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The tests stop at the first failure".to_string(),
+    );
+
+    // Select every file for assessment
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    std::fs::create_dir_all(&trajectory_dir)?;
+    std::fs::write(
+        std::path::Path::new(&trajectory_dir).join("codebase_tree_response.txt"),
+        "```json\n[\"**/*\"]\n```",
+    )?;
+    let trajectory_store = TrajectoryStore::new(&trajectory_dir, &problem)?;
+    for path in assessed {
+        trajectory_store.save_per_file_relevance_decision(
+            path,
+            RelevanceDecision::not_relevant("Assessed earlier".to_string()),
+        )?;
+    }
+
+    relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
+
+    let decisions = trajectory_store.load_relevance_decisions()?;
+    Ok((decisions, server.bodies()))
+}
+
+fn paths(paths: &[&str]) -> Vec<String> {
+    paths.iter().map(|path| path.to_string()).collect()
+}
+
+fn batch(json: serde_json::Value) -> StructuredBatchRelevance {
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn test_batch_decisions_follow_the_file_order() {
+    let decisions = batch(json!({"decisions": [
+        {"path": "b.cfg", "status": "not_relevant"},
+        {"path": "a.cfg", "status": "relevant", "summary": "Sets the flags."},
+    ]}))
+    .into_decisions(&paths(&["a.cfg", "b.cfg"]))
+    .unwrap();
+
+    assert_eq!(decisions[0].0, "a.cfg");
+    assert_eq!(decisions[0].1.status, RelevanceVerdict::Relevant);
+    assert_eq!(decisions[1].0, "b.cfg");
+    assert_eq!(decisions[1].1.status, RelevanceVerdict::NotRelevant);
+}
+
+#[test]
+fn test_invalid_batch_decisions_are_rejected() {
+    let files = paths(&["a.cfg", "b.cfg"]);
+
+    let missing = batch(json!({"decisions": [{"path": "a.cfg", "status": "not_relevant"}]}))
+        .into_decisions(&files)
+        .unwrap_err();
+    assert_eq!(missing, "decisions are missing for b.cfg");
+
+    let unknown = batch(json!({"decisions": [
+        {"path": "a.cfg", "status": "not_relevant"},
+        {"path": "b.cfg", "status": "not_relevant"},
+        {"path": "c.cfg", "status": "not_relevant"},
+    ]}))
+    .into_decisions(&files)
+    .unwrap_err();
+    assert!(unknown.contains("\"c.cfg\" is not one of the files"));
+
+    let repeated = batch(json!({"decisions": [
+        {"path": "a.cfg", "status": "not_relevant"},
+        {"path": "a.cfg", "status": "not_relevant"},
+        {"path": "b.cfg", "status": "not_relevant"},
+    ]}))
+    .into_decisions(&files)
+    .unwrap_err();
+    assert!(repeated.contains("more than one decision"));
+
+    let no_summary = batch(json!({"decisions": [
+        {"path": "a.cfg", "status": "relevant"},
+        {"path": "b.cfg", "status": "not_relevant"},
+    ]}))
+    .into_decisions(&files)
+    .unwrap_err();
+    assert!(no_summary.contains("the decision for \"a.cfg\" is invalid"));
+}
+
+#[test]
+fn test_batching_is_off_by_default() {
+    let config = Config::default();
+    assert!(!config.relevance.batch_small_files);
+    assert_eq!(config.relevance.small_file_tokens, 500);
+}
+
+#[tokio::test]
+async fn test_small_files_are_assessed_in_one_request() -> Result<()> {
+    let (decisions, requests) = assess_with_batching(BATCH_DECISIONS, &[]).await?;
+
+    // One request for the batch and one for the larger file
+    assert_eq!(requests.len(), 2);
+    let batch_request = requests
+        .iter()
+        .find(|body| body.contains("small files instead of one"))
+        .unwrap();
+    assert!(batch_request.contains("This request covers 3 small files"));
+    assert!(!batch_request.contains("def run()"));
+
+    // The verdicts are saved per file
+    assert_eq!(decisions.len(), 4);
+    let setup = &decisions["setup.cfg"];
+    assert_eq!(setup.status, RelevanceStatus::Relevant);
+    assert_eq!(
+        setup.summary.as_deref(),
+        Some("Configures the test runner.")
+    );
+    assert_eq!(setup.confidence, Some(0.9));
+    assert_eq!(
+        decisions["pkg/__init__.py"].status,
+        RelevanceStatus::NotRelevant
+    );
+    assert_eq!(
+        decisions["pkg/io/__init__.py"].status,
+        RelevanceStatus::NotRelevant
+    );
+    assert_eq!(
+        decisions["pkg/core.py"].status,
+        RelevanceStatus::NotRelevant
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unfenced_batch_answer_is_parsed_without_reprompt() -> Result<()> {
+    let (decisions, requests) = assess_with_batching(UNFENCED_BATCH_DECISIONS, &[]).await?;
+
+    // The whole answer is the decisions, not the last object nested in it
+    assert_eq!(requests.len(), 2);
+    assert!(requests
+        .iter()
+        .all(|body| !body.contains("Your decisions could not be parsed")));
+    assert_eq!(decisions["setup.cfg"].status, RelevanceStatus::Relevant);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_assessed_files_are_left_out_of_batches() -> Result<()> {
+    let (decisions, requests) =
+        assess_with_batching(PARTIAL_BATCH_DECISIONS, &["setup.cfg"]).await?;
+
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|body| !body.contains("addopts")));
+    assert_eq!(decisions["setup.cfg"].message, "Assessed earlier");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unparseable_batch_falls_back_to_one_request_per_file() -> Result<()> {
+    let (decisions, requests) = assess_with_batching(PARTIAL_BATCH_DECISIONS, &[]).await?;
+
+    // The batch, its re-prompt, then a request for each small file and the
+    // larger one
+    assert_eq!(requests.len(), 6);
+    let reprompt = requests
+        .iter()
+        .find(|body| body.contains("Your decisions could not be parsed"))
+        .unwrap();
+    assert!(reprompt.contains("decisions are missing for setup.cfg"));
+    assert_eq!(decisions.len(), 4);
+    assert!(decisions
+        .values()
+        .all(|decision| decision.status == RelevanceStatus::NotRelevant));
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use engine_builder::config::Config;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::relevance::{LineRange, RelevanceDecision, RelevanceStatus};
+use engine_builder::stages::relevance;
+use engine_builder::utils::chunking::split_into_chunks;
+use engine_builder::utils::token_counter::WhitespaceTokenizer;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::MockServer;
+
+const NOT_RELEVANT: &str =
+    "Nothing here matters.\n\n```json\n{\"status\": \"not_relevant\", \"confidence\": 0.7}\n```";
+const RELEVANT: &str = "These lines set the compiler flags.\n\n```json\n{\"status\": \"relevant\", \"summary\": \"Sets the compiler flags.\", \"confidence\": 0.9}\n```";
+
+/// A makefile-like file of `sections` sections of four lines, separated by
+/// blank lines
+fn sectioned_file(sections: usize) -> String {
+    (0..sections)
+        .map(|i| {
+            format!(
+                "target_{}:\n\tstep one {}\n\tstep two {}\n\tstep three {}\n\n",
+                i, i, i, i
+            )
+        })
+        .collect()
+}
+
+/// Run the relevance stage over a single long `Makefile` with the given
+/// relevance settings, returning the saved decision and the requests sent
+async fn assess_makefile(
+    answers: Vec<&'static str>,
+    relevance_config: serde_json::Value,
+) -> Result<(RelevanceDecision, Vec<serde_json::Value>)> {
+    let server = MockServer::messages().answers(&answers).start().await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    std::fs::write(codebase_dir.path().join("Makefile"), sectioned_file(60))?;
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "relevance": relevance_config,
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build uses the wrong compiler flags",
+            // Have the model assess the Makefile rather than the built-in rules
+            "file_rules": { "builtin": false },
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build uses the wrong compiler flags".to_string(),
+    );
+
+    // Select every file for assessment
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    std::fs::create_dir_all(&trajectory_dir)?;
+    std::fs::write(
+        std::path::Path::new(&trajectory_dir).join("codebase_tree_response.txt"),
+        "```json\n[\"Makefile\"]\n```",
+    )?;
+
+    relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
+
+    let mut decisions =
+        TrajectoryStore::new(&trajectory_dir, &problem)?.load_relevance_decisions()?;
+    let decision = decisions
+        .remove("Makefile")
+        .context("No decision saved for Makefile")?;
+    Ok((decision, server.json_bodies()))
+}
+
+#[test]
+fn test_chunks_cover_the_file_with_overlap() {
+    let content = sectioned_file(40);
+    let total_lines = content.lines().count();
+
+    let chunks = split_into_chunks(&content, 100, 3, &WhitespaceTokenizer);
+
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.tokens <= 100));
+    assert_eq!(chunks[0].lines.start, 1);
+    assert_eq!(chunks.last().unwrap().lines.end, total_lines);
+    for pair in chunks.windows(2) {
+        // Each chunk starts a few lines before the last one ended
+        assert!(pair[1].lines.start <= pair[0].lines.end);
+        assert!(pair[1].lines.start > pair[0].lines.start);
+        assert!(pair[0].lines.end - pair[1].lines.start < 3);
+    }
+}
+
+#[test]
+fn test_chunks_end_at_blank_lines() {
+    let content = sectioned_file(40);
+
+    let chunks = split_into_chunks(&content, 100, 0, &WhitespaceTokenizer);
+
+    // Every chunk but the last ends with a whole section
+    for chunk in &chunks[..chunks.len() - 1] {
+        assert!(chunk.content.ends_with("\n\n"));
+        assert!(chunk.content.starts_with("target_"));
+    }
+}
+
+#[test]
+fn test_short_file_is_one_chunk() {
+    let chunks = split_into_chunks("a b c\nd e f\n", 100, 5, &WhitespaceTokenizer);
+
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].lines, LineRange::new(1, 2));
+    assert_eq!(chunks[0].content, "a b c\nd e f\n");
+}
+
+#[test]
+fn test_merged_chunks_are_relevant_if_any_chunk_is() {
+    let mut second = RelevanceDecision::relevant("b".to_string(), "Sets flags.".to_string());
+    second.confidence = Some(0.6);
+    let mut third = RelevanceDecision::relevant("c".to_string(), "Picks the compiler.".to_string());
+    third.confidence = Some(0.8);
+
+    let decision = RelevanceDecision::merge_chunks(vec![
+        (
+            LineRange::new(1, 50),
+            RelevanceDecision::not_relevant("a".to_string()),
+        ),
+        (LineRange::new(45, 100), second),
+        (LineRange::new(95, 150), third),
+        (
+            LineRange::new(200, 250),
+            RelevanceDecision::not_relevant("d".to_string()),
+        ),
+    ]);
+
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(
+        decision.summary.as_deref(),
+        Some("Lines 45-100: Sets flags.\nLines 95-150: Picks the compiler.")
+    );
+    assert_eq!(decision.confidence, Some(0.8));
+    assert_eq!(decision.relevant_lines, vec![LineRange::new(45, 150)]);
+    assert!(decision.message.contains("## Lines 200-250\n\nd"));
+}
+
+#[test]
+fn test_merged_chunks_without_a_relevant_one() {
+    let decision = RelevanceDecision::merge_chunks(vec![
+        (
+            LineRange::new(1, 50),
+            RelevanceDecision::not_relevant("a".to_string()),
+        ),
+        (
+            LineRange::new(45, 100),
+            RelevanceDecision::not_relevant("b".to_string()),
+        ),
+    ]);
+    assert_eq!(decision.status, RelevanceStatus::NotRelevant);
+    assert!(decision.relevant_lines.is_empty());
+
+    // An unparsed chunk might have been the relevant one
+    let decision = RelevanceDecision::merge_chunks(vec![
+        (
+            LineRange::new(1, 50),
+            RelevanceDecision::not_relevant("a".to_string()),
+        ),
+        (
+            LineRange::new(45, 100),
+            RelevanceDecision::parse_error("b".to_string()),
+        ),
+    ]);
+    assert_eq!(decision.status, RelevanceStatus::ParseError);
+}
+
+#[test]
+fn test_skipped_status_serialization() -> Result<()> {
+    let decision = RelevanceDecision::skipped("too long");
+    assert!(decision.is_skipped());
+
+    let serialized = serde_json::to_value(&decision)?;
+    assert_eq!(
+        serialized["status"],
+        json!({"Skipped": {"reason": "too long"}})
+    );
+    assert!(serialized.get("relevant_lines").is_none());
+
+    let deserialized: RelevanceDecision = serde_json::from_value(serialized)?;
+    assert_eq!(
+        deserialized.status,
+        RelevanceStatus::Skipped {
+            reason: "too long".to_string()
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_long_file_is_assessed_in_chunks() -> Result<()> {
+    let (decision, requests) = assess_makefile(
+        vec![NOT_RELEVANT, RELEVANT, NOT_RELEVANT],
+        json!({ "max_workers": 1, "max_file_tokens": 300, "chunk_overlap_lines": 2 }),
+    )
+    .await?;
+
+    // One request per chunk, each showing its lines
+    assert!(requests.len() > 2);
+    let first_prompt = requests[0]["messages"][0].to_string();
+    assert!(first_prompt.contains("Lines: 1 to "));
+    assert!(first_prompt.contains("target_0:"));
+    assert!(!first_prompt.contains("target_59:"));
+
+    // Only the second chunk was relevant
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(decision.relevant_lines.len(), 1);
+    assert!(decision.relevant_lines[0].start > 1);
+    let summary = decision.summary.unwrap();
+    assert!(summary.starts_with(&format!("Lines {}: ", decision.relevant_lines[0])));
+    assert!(summary.ends_with("Sets the compiler flags."));
+    assert_eq!(decision.confidence, Some(0.9));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_needing_too_many_chunks_is_skipped() -> Result<()> {
+    let (decision, requests) = assess_makefile(
+        vec![RELEVANT],
+        json!({ "max_workers": 1, "max_file_tokens": 300, "max_chunks": 2 }),
+    )
+    .await?;
+
+    assert!(requests.is_empty());
+    match decision.status {
+        RelevanceStatus::Skipped { reason } => assert!(reason.contains("more than the limit of 2")),
+        status => panic!("expected a skipped decision, got {:?}", status),
+    }
+    Ok(())
+}
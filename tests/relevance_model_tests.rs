@@ -1,4 +1,6 @@
-use engine_builder::models::relevance::{RelevanceDecision, RelevanceStatus};
+use engine_builder::models::relevance::{
+    RelevanceDecision, RelevanceStatus, RelevanceVerdict, StructuredRelevance,
+};
 use serde_json;
 
 #[test]
@@ -90,3 +92,56 @@ fn test_relevance_decision_parse_error() {
     assert_eq!(deserialized.summary, None);
     assert!(!deserialized.is_relevant());
 }
+
+#[test]
+fn test_decisions_saved_before_confidence_still_load() {
+    let decision: RelevanceDecision = serde_json::from_str(
+        r#"{"message": "Relevant", "status": "Relevant", "summary": "Builds the crate"}"#,
+    )
+    .unwrap();
+
+    assert_eq!(decision.confidence, None);
+    assert!(!serde_json::to_string(&decision)
+        .unwrap()
+        .contains("confidence"));
+}
+
+#[test]
+fn test_structured_relevance_schema() {
+    let structured: StructuredRelevance = serde_json::from_str(
+        r#"{"status": "relevant", "summary": " Builds the crate ", "confidence": 0.75}"#,
+    )
+    .unwrap();
+    assert_eq!(structured.status, RelevanceVerdict::Relevant);
+    assert!(structured.validate().is_ok());
+
+    let decision = structured.into_decision("full answer".to_string());
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(decision.summary, Some("Builds the crate".to_string()));
+    assert_eq!(decision.confidence, Some(0.75));
+    assert_eq!(decision.message, "full answer");
+
+    // Irrelevant files need no summary
+    let structured: StructuredRelevance =
+        serde_json::from_str(r#"{"status": "not_relevant"}"#).unwrap();
+    assert!(structured.validate().is_ok());
+    assert_eq!(
+        structured.into_decision(String::new()).status,
+        RelevanceStatus::NotRelevant
+    );
+
+    // Unknown statuses and fields are rejected
+    assert!(serde_json::from_str::<StructuredRelevance>(r#"{"status": "maybe"}"#).is_err());
+    assert!(serde_json::from_str::<StructuredRelevance>(
+        r#"{"status": "not_relevant", "reason": "unused"}"#
+    )
+    .is_err());
+
+    // So are relevant files without a summary and confidences outside 0..=1
+    let structured: StructuredRelevance =
+        serde_json::from_str(r#"{"status": "relevant", "summary": " "}"#).unwrap();
+    assert!(structured.validate().is_err());
+    let structured: StructuredRelevance =
+        serde_json::from_str(r#"{"status": "not_relevant", "confidence": 1.5}"#).unwrap();
+    assert!(structured.validate().is_err());
+}
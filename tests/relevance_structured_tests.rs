@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use engine_builder::config::Config;
+use engine_builder::models::problem::SWEBenchProblem;
+use engine_builder::models::relevance::{RelevanceDecision, RelevanceStatus};
+use engine_builder::stages::relevance;
+use engine_builder::utils::trajectory_store::TrajectoryStore;
+use serde_json::json;
+use tempfile::tempdir;
+
+mod common;
+use common::MockServer;
+
+/// Assess the relevance of a single file against a mock server answering
+/// with `answers`, returning the saved decision and the requests sent
+async fn assess_one_file(
+    answers: Vec<&'static str>,
+) -> Result<(RelevanceDecision, Vec<serde_json::Value>)> {
+    let server = MockServer::messages().answers(&answers).start().await;
+
+    let output_dir = tempdir()?;
+    let codebase_dir = tempdir()?;
+    std::fs::write(codebase_dir.path().join("build.rs"), "fn main() {}\n")?;
+
+    let config: Config = serde_json::from_value(json!({
+        "anthropic_api_key": "dummy_key",
+        "base_url": server.url(),
+        "request_max_retries": 0,
+        "output_path": output_dir.path(),
+        "relevance": { "max_workers": 1, "max_tokens": 4000 },
+        "codebase": {
+            "path": codebase_dir.path(),
+            "problem_id": "test_problem",
+            "problem_statement": "The build is broken",
+        },
+    }))?;
+    let problem = SWEBenchProblem::new(
+        "test_problem".to_string(),
+        "The build is broken".to_string(),
+    );
+
+    // Select every file for assessment
+    let trajectory_dir = config.get_trajectory_dir(&problem.id);
+    std::fs::create_dir_all(&trajectory_dir)?;
+    std::fs::write(
+        std::path::Path::new(&trajectory_dir).join("codebase_tree_response.txt"),
+        "```json\n[\"*.rs\"]\n```",
+    )
+    .context("Failed to write file selection response")?;
+
+    relevance::process_codebase(&config, &config.codebase, problem.clone()).await?;
+
+    let mut decisions =
+        TrajectoryStore::new(&trajectory_dir, &problem)?.load_relevance_decisions()?;
+    let decision = decisions
+        .remove("build.rs")
+        .context("No decision saved for build.rs")?;
+    Ok((decision, server.json_bodies()))
+}
+
+#[tokio::test]
+async fn test_structured_decision_is_parsed() -> Result<()> {
+    let (decision, requests) = assess_one_file(vec![
+        "The build script decides how the crate is compiled.\n\n```json\n{\"status\": \"relevant\", \"summary\": \"Configures the build.\", \"confidence\": 0.9}\n```",
+    ])
+    .await?;
+
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(decision.summary.as_deref(), Some("Configures the build."));
+    assert_eq!(decision.confidence, Some(0.9));
+    assert_eq!(requests.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unfenced_decision_with_braces_in_its_summary_is_parsed() -> Result<()> {
+    let (decision, requests) = assess_one_file(vec![
+        "The build script writes a {version} header.\n\n{\"status\": \"relevant\", \"summary\": \"Generates {version} for the crate.\"}",
+    ])
+    .await?;
+
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(
+        decision.summary.as_deref(),
+        Some("Generates {version} for the crate.")
+    );
+    assert_eq!(requests.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reasoning_does_not_sway_the_decision() -> Result<()> {
+    // Prose that a keyword search would take as "relevant"
+    let (decision, _) = assess_one_file(vec![
+        "This file is not irrelevant to Rust builds in general, and is relevant to compilation, but not to this issue.\n\n```json\n{\"status\": \"not_relevant\"}\n```",
+    ])
+    .await?;
+
+    assert_eq!(decision.status, RelevanceStatus::NotRelevant);
+    assert_eq!(decision.summary, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unparseable_decision_is_reprompted() -> Result<()> {
+    let (decision, requests) = assess_one_file(vec![
+        "The build script matters here.\n\nRELEVANCE: Relevant",
+        "```json\n{\"status\": \"relevant\", \"summary\": \"Configures the build.\"}\n```",
+    ])
+    .await?;
+
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(decision.summary.as_deref(), Some("Configures the build."));
+    // Both answers are kept
+    assert!(decision.message.contains("The build script matters here."));
+    assert!(decision.message.contains("Configures the build."));
+
+    // The follow-up continues the conversation and only asks for the decision
+    assert_eq!(requests.len(), 2);
+    let followup = &requests[1];
+    let messages = followup["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert!(messages[2].to_string().contains("could not be parsed"));
+    assert!(followup["max_tokens"].as_u64().unwrap() < 4000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_decision_breaking_the_schema_is_reprompted() -> Result<()> {
+    let (decision, requests) = assess_one_file(vec![
+        "```json\n{\"status\": \"relevant\"}\n```",
+        "```json\n{\"status\": \"relevant\", \"summary\": \"Configures the build.\", \"confidence\": 0.6}\n```",
+    ])
+    .await?;
+
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1]["messages"]
+        .to_string()
+        .contains("\\\"summary\\\" is required"));
+    assert_eq!(decision.status, RelevanceStatus::Relevant);
+    assert_eq!(decision.confidence, Some(0.6));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_decision_still_unparseable_after_reprompt_is_a_parse_error() -> Result<()> {
+    let (decision, requests) = assess_one_file(vec![
+        "I think this file is relevant.",
+        "Yes, it is relevant.",
+    ])
+    .await?;
+
+    assert_eq!(decision.status, RelevanceStatus::ParseError);
+    assert_eq!(requests.len(), 2);
+    Ok(())
+}
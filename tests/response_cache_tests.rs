@@ -0,0 +1,288 @@
+use anyhow::Result;
+use engine_builder::config::{CacheMode, LLMConfig};
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::cache::{CachingClient, ResponseCache};
+use engine_builder::llm::client::{ChatRequest, LLMClient, Message, TokenUsage};
+use std::path::Path;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Start a mock server that answers requests to the messages API with
+/// "reply N", where N counts the requests received, streamed if the request
+/// asks for it
+async fn start_mock_server() -> MockServer {
+    MockServer::messages()
+        .respond_with(|request, index| {
+            if request.body.contains(r#""stream":true"#) {
+                Reply::sse(stream_body(index + 1))
+            } else {
+                Reply::json(json_body(index + 1))
+            }
+        })
+        .start()
+        .await
+}
+
+fn json_body(index: usize) -> String {
+    format!(
+        r#"{{"content":[{{"type":"text","text":"reply {}"}}],"usage":{{"input_tokens":10,"output_tokens":2}}}}"#,
+        index
+    )
+}
+
+fn stream_body(index: usize) -> String {
+    format!(
+        concat!(
+            "event: message_start\n",
+            "data: {{\"type\":\"message_start\",\"message\":{{\"usage\":{{\"input_tokens\":10,\"output_tokens\":1}}}}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {{\"type\":\"content_block_delta\",\"index\":0,\"delta\":{{\"type\":\"text_delta\",\"text\":\"reply {}\"}}}}\n\n",
+            "event: message_delta\n",
+            "data: {{\"type\":\"message_delta\",\"usage\":{{\"output_tokens\":2}}}}\n\n",
+            "event: message_stop\n",
+            "data: {{\"type\":\"message_stop\"}}\n\n",
+        ),
+        index
+    )
+}
+
+/// An Anthropic client for the mock server, behind a response cache
+fn caching_client(base_url: &str, cache_dir: &Path, mode: CacheMode) -> Result<CachingClient> {
+    let config = LLMConfig {
+        model_type: "anthropic".to_string(),
+        model: "claude-3-7-sonnet-20250219".to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url.to_string()),
+        timeout: 5,
+        max_retries: 0,
+    };
+    Ok(CachingClient::new(
+        Box::new(AnthropicClient::new(&config)?),
+        ResponseCache::new(cache_dir, mode),
+    ))
+}
+
+fn request() -> ChatRequest {
+    ChatRequest::new(100, 0.0)
+        .with_system("You are a helpful assistant")
+        .with_user("Hello")
+}
+
+/// The number of entries in a cache directory, including its subdirectories
+fn cache_entries(dir: &Path) -> usize {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| {
+                let path = entry.path();
+                if path.is_dir() {
+                    cache_entries(&path)
+                } else {
+                    usize::from(path.extension().is_some_and(|ext| ext == "json"))
+                }
+            })
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+#[tokio::test]
+async fn test_read_write_reuses_responses() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+    let client = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?;
+
+    let first = client.chat(&request()).await?;
+    assert_eq!(first.content, "reply 1");
+    assert_eq!(first.usage.completion_tokens, 2);
+
+    // The repeated request is answered from the cache, without using any tokens
+    let second = client.chat(&request()).await?;
+    assert_eq!(second.content, "reply 1");
+    assert_eq!(second.usage, TokenUsage::default());
+    assert_eq!(server.request_count(), 1);
+    assert_eq!(cache_entries(cache_dir.path()), 1);
+
+    // A different request is a miss
+    let other = client
+        .chat(&ChatRequest::new(100, 0.0).with_user("Goodbye"))
+        .await?;
+    assert_eq!(other.content, "reply 2");
+    assert_eq!(server.request_count(), 2);
+    assert_eq!(cache_entries(cache_dir.path()), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_only_does_not_write() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+
+    let read_only = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadOnly)?;
+    read_only.chat(&request()).await?;
+    read_only.chat(&request()).await?;
+    assert_eq!(server.request_count(), 2);
+    assert_eq!(cache_entries(cache_dir.path()), 0);
+
+    // Existing entries are still read
+    caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?
+        .chat(&request())
+        .await?;
+    let response = read_only.chat(&request()).await?;
+    assert_eq!(response.content, "reply 3");
+    assert_eq!(server.request_count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_replaces_entries() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+    let read_write = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?;
+    let refresh = caching_client(&server.url(), cache_dir.path(), CacheMode::Refresh)?;
+
+    read_write.chat(&request()).await?;
+
+    // Refreshing always sends the request, and overwrites the cached response
+    assert_eq!(refresh.chat(&request()).await?.content, "reply 2");
+    assert_eq!(refresh.chat(&request()).await?.content, "reply 3");
+    assert_eq!(cache_entries(cache_dir.path()), 1);
+
+    assert_eq!(read_write.chat(&request()).await?.content, "reply 3");
+    assert_eq!(server.request_count(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_off_bypasses_cache() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+    let client = caching_client(&server.url(), cache_dir.path(), CacheMode::Off)?;
+
+    client.chat(&request()).await?;
+    client.chat(&request()).await?;
+    assert_eq!(server.request_count(), 2);
+    assert_eq!(cache_entries(cache_dir.path()), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streamed_responses_are_cached() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+    let client = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?;
+
+    // Streamed misses are cached once the stream completes
+    let mut stream = client.chat_stream(&request()).await?;
+    while let Some(event) = futures::StreamExt::next(&mut stream).await {
+        event?;
+    }
+    assert_eq!(cache_entries(cache_dir.path()), 1);
+
+    // Hits are replayed to the delta callback
+    let mut deltas = String::new();
+    let response = client
+        .chat_stream_with_tracing(&request(), None, None, None, &mut |delta| {
+            deltas.push_str(delta)
+        })
+        .await?;
+    assert_eq!(response.content, "reply 1");
+    assert_eq!(deltas, "reply 1");
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streams_without_usage_are_cached() -> Result<()> {
+    // A stream that ends without a message_delta, so no usage is reported
+    let server = MockServer::messages()
+        .reply(Reply::sse(concat!(
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"no usage\"}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        )))
+        .start()
+        .await;
+    let cache_dir = tempfile::tempdir()?;
+    let client = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?;
+
+    let mut stream = client.chat_stream(&request()).await?;
+    while let Some(event) = futures::StreamExt::next(&mut stream).await {
+        event?;
+    }
+    assert_eq!(cache_entries(cache_dir.path()), 1);
+
+    let response = client.chat(&request()).await?;
+    assert_eq!(response.content, "no usage");
+    assert_eq!(response.usage, TokenUsage::default());
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreadable_entries_are_misses() -> Result<()> {
+    let server = start_mock_server().await;
+    let cache_dir = tempfile::tempdir()?;
+    let client = caching_client(&server.url(), cache_dir.path(), CacheMode::ReadWrite)?;
+
+    let key = ResponseCache::key("claude-3-7-sonnet-20250219", &request());
+    let entry_dir = cache_dir.path().join(&key[..2]);
+    std::fs::create_dir_all(&entry_dir)?;
+    std::fs::write(entry_dir.join(format!("{}.json", key)), "not json")?;
+
+    assert_eq!(client.chat(&request()).await?.content, "reply 1");
+    assert_eq!(client.chat(&request()).await?.content, "reply 1");
+    assert_eq!(server.request_count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_key_covers_model_and_parameters() {
+    let model = "claude-3-7-sonnet-20250219";
+    let key = ResponseCache::key(model, &request());
+    assert_eq!(key, ResponseCache::key(model, &request()));
+
+    assert_ne!(key, ResponseCache::key("gpt-4o", &request()));
+    assert_ne!(
+        key,
+        ResponseCache::key(
+            model,
+            &ChatRequest {
+                temperature: 0.5,
+                ..request()
+            }
+        )
+    );
+    assert_ne!(
+        key,
+        ResponseCache::key(
+            model,
+            &ChatRequest {
+                max_tokens: 200,
+                ..request()
+            }
+        )
+    );
+    assert_ne!(
+        key,
+        ResponseCache::key(model, &request().with_user("And again"))
+    );
+
+    // Cache breakpoints don't change the response, so they don't change the key
+    let plain = ChatRequest::new(100, 0.0).with_message(Message::user("prefix rest"));
+    let cached = ChatRequest::new(100, 0.0)
+        .with_message(Message::user_with_cached_prefix("prefix", " rest"));
+    assert_eq!(
+        ResponseCache::key(model, &plain),
+        ResponseCache::key(model, &cached)
+    );
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use engine_builder::chat::ui::ChatApp;
+use engine_builder::chat::ChatUpdate;
+use engine_builder::config::LLMConfig;
+use engine_builder::llm::anthropic::AnthropicClient;
+use engine_builder::llm::client::{
+    collect_stream, ChatRequest, LLMClient, LLMResponse, StreamEvent, TokenUsage,
+};
+use engine_builder::llm::openai::OpenAIClient;
+use engine_builder::llm::sse::{sse_events, SseEvent};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+mod common;
+use common::{MockServer, Reply};
+
+/// Start a mock server that answers every request with the given event
+/// stream, written in small pieces so events arrive split across reads.
+/// Returns its base URL.
+async fn start_mock_sse_server(body: &'static str) -> String {
+    MockServer::builder()
+        .reply(Reply::sse(body))
+        .chunk_size(7)
+        .start()
+        .await
+        .url()
+}
+
+fn test_config(model_type: &str, model: &str, base_url: String) -> LLMConfig {
+    LLMConfig {
+        model_type: model_type.to_string(),
+        model: model.to_string(),
+        api_key: "dummy_key".to_string(),
+        base_url: Some(base_url),
+        timeout: 5,
+        max_retries: 0,
+    }
+}
+
+/// Decode the given chunks into events
+async fn decode(chunks: &[&str]) -> Vec<SseEvent> {
+    let chunks: Vec<std::result::Result<Vec<u8>, std::io::Error>> =
+        chunks.iter().map(|c| Ok(c.as_bytes().to_vec())).collect();
+    sse_events(futures::stream::iter(chunks))
+        .map(|event| event.unwrap())
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn test_sse_events_split_across_chunks() {
+    let events = decode(&[
+        "event: ping\nda",
+        "ta: {\"a\":1}\n",
+        "\n: keep-alive comment\n\ndata: line one\r\n",
+        "data: line two\r\n\r",
+        "\ndata: trailing",
+    ])
+    .await;
+
+    assert_eq!(
+        events,
+        vec![
+            SseEvent {
+                event: Some("ping".to_string()),
+                data: "{\"a\":1}".to_string(),
+            },
+            SseEvent {
+                event: None,
+                data: "line one\nline two".to_string(),
+            },
+            // A final event without a blank line is still delivered
+            SseEvent {
+                event: None,
+                data: "trailing".to_string(),
+            },
+        ]
+    );
+}
+
+/// A client that only implements plain completions
+struct CompletionOnlyClient;
+
+#[async_trait]
+impl LLMClient for CompletionOnlyClient {
+    async fn completion(
+        &self,
+        prompt: &str,
+        _max_tokens: usize,
+        _temperature: f64,
+    ) -> Result<LLMResponse> {
+        Ok(LLMResponse {
+            content: format!("echo: {}", prompt),
+            usage: TokenUsage {
+                prompt_tokens: 3,
+                completion_tokens: 4,
+                total_tokens: 7,
+                ..Default::default()
+            },
+            tool_calls: Vec::new(),
+        })
+    }
+
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn model_name(&self) -> &str {
+        "mock-model"
+    }
+
+    fn get_token_prices(&self) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+}
+
+#[tokio::test]
+async fn test_default_chat_stream_sends_whole_response() -> Result<()> {
+    // Clients without native streaming send the whole response as one delta
+    let request = ChatRequest::new(100, 0.0).with_user("hi");
+    let mut deltas = Vec::new();
+    let response = collect_stream(
+        CompletionOnlyClient.chat_stream(&request).await?,
+        &mut |delta| deltas.push(delta.to_string()),
+    )
+    .await?;
+
+    assert_eq!(deltas, vec!["echo: hi"]);
+    assert_eq!(response.content, "echo: hi");
+    assert_eq!(response.usage.total_tokens, 7);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_anthropic_chat_stream() -> Result<()> {
+    let base_url = start_mock_sse_server(concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: ping\n",
+        "data: {\"type\":\"ping\"}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"FROM \"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"rust:1.75\"}}\n\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":6}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    ))
+    .await;
+    let client = AnthropicClient::new(&test_config(
+        "anthropic",
+        "claude-3-7-sonnet-20250219",
+        base_url,
+    ))?;
+
+    let request = ChatRequest::new(100, 0.0).with_user("Write a Dockerfile");
+    let mut deltas = Vec::new();
+    let response = collect_stream(client.chat_stream(&request).await?, &mut |delta| {
+        deltas.push(delta.to_string())
+    })
+    .await?;
+
+    assert_eq!(deltas, vec!["FROM ", "rust:1.75"]);
+    assert_eq!(response.content, "FROM rust:1.75");
+    assert_eq!(response.usage.prompt_tokens, 25);
+    assert_eq!(response.usage.completion_tokens, 6);
+    assert_eq!(response.usage.total_tokens, 31);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_openai_chat_stream() -> Result<()> {
+    let base_url = start_mock_sse_server(concat!(
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"echo \"}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"ok\"}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2,\"total_tokens\":11}}\n\n",
+        "data: [DONE]\n\n",
+    ))
+    .await;
+    let client = OpenAIClient::new(&test_config("openai", "gpt-4o", base_url))?;
+
+    let request = ChatRequest::new(100, 0.0).with_user("Write a script");
+    let mut stream = client.chat_stream(&request).await?;
+
+    let mut content = String::new();
+    let mut usage = None;
+    while let Some(event) = stream.next().await {
+        match event? {
+            StreamEvent::Delta(text) => content.push_str(&text),
+            StreamEvent::ToolCall(call) => panic!("unexpected tool call: {:?}", call),
+            StreamEvent::Usage(u) => usage = Some(u),
+        }
+    }
+
+    assert_eq!(content, "echo ok");
+    let usage = usage.expect("usage should be reported at the end of the stream");
+    assert_eq!(usage.prompt_tokens, 9);
+    assert_eq!(usage.total_tokens, 11);
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_ui_applies_streamed_deltas() {
+    let (tx, _rx) = mpsc::channel(1);
+    let mut app = ChatApp::new(tx);
+
+    app.apply_update(ChatUpdate::Thinking);
+    assert!(app.thinking);
+
+    // The thinking status gives way to the streamed response
+    app.apply_update(ChatUpdate::Delta("Hello".to_string()));
+    assert!(!app.thinking);
+    app.apply_update(ChatUpdate::Delta(", world".to_string()));
+    app.apply_update(ChatUpdate::StreamEnd);
+
+    assert_eq!(app.messages.len(), 1);
+    assert_eq!(app.messages[0].content, "Hello, world");
+
+    // A new stream starts a new message
+    app.apply_update(ChatUpdate::Delta("Again".to_string()));
+    assert_eq!(app.messages.len(), 2);
+    assert_eq!(app.messages[1].role, "assistant");
+    assert_eq!(app.messages[1].content, "Again");
+}
+
+#[tokio::test]
+async fn test_anthropic_chat_stream_with_tool_use() -> Result<()> {
+    let base_url = start_mock_sse_server(concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":40,\"output_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Building.\"}}\n\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"build_image\",\"input\":{}}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"tag\\\": \\\"app\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"(v1)\\\"}\"}}\n\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":12}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    ))
+    .await;
+    let client = AnthropicClient::new(&test_config(
+        "anthropic",
+        "claude-3-7-sonnet-20250219",
+        base_url,
+    ))?;
+
+    let request = ChatRequest::new(100, 0.0).with_user("Build the image");
+    let response = collect_stream(client.chat_stream(&request).await?, &mut |_| {}).await?;
+
+    assert_eq!(response.content, "Building.");
+    assert_eq!(response.tool_calls.len(), 1);
+    assert_eq!(response.tool_calls[0].id, "toolu_1");
+    assert_eq!(response.tool_calls[0].name, "build_image");
+    assert_eq!(
+        response.tool_calls[0].input,
+        serde_json::json!({ "tag": "app(v1)" })
+    );
+    assert_eq!(response.usage.total_tokens, 52);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_openai_chat_stream_with_tool_calls() -> Result<()> {
+    let base_url = start_mock_sse_server(concat!(
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_a\",\"type\":\"function\",\"function\":{\"name\":\"build_image\",\"arguments\":\"\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"tag\\\":\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"x\\\"}\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":1,\"id\":\"call_b\",\"type\":\"function\",\"function\":{\"name\":\"run_lint\",\"arguments\":\"\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":20,\"completion_tokens\":10,\"total_tokens\":30}}\n\n",
+        "data: [DONE]\n\n",
+    ))
+    .await;
+    let client = OpenAIClient::new(&test_config("openai", "gpt-4o", base_url))?;
+
+    let request = ChatRequest::new(100, 0.0).with_user("Build and lint");
+    let response = collect_stream(client.chat_stream(&request).await?, &mut |_| {}).await?;
+
+    assert_eq!(response.content, "");
+    let calls: Vec<_> = response
+        .tool_calls
+        .iter()
+        .map(|call| (call.id.as_str(), call.name.as_str(), call.input.clone()))
+        .collect();
+    assert_eq!(
+        calls,
+        vec![
+            ("call_a", "build_image", serde_json::json!({ "tag": "x" })),
+            ("call_b", "run_lint", serde_json::json!({})),
+        ]
+    );
+    assert_eq!(response.usage.total_tokens, 30);
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_ui_stops_thinking_for_tool_only_response() {
+    let (tx, _rx) = mpsc::channel(1);
+    let mut app = ChatApp::new(tx);
+
+    // A response with only tool calls streams no text, and leaves nothing in
+    // the transcript
+    app.apply_update(ChatUpdate::Thinking);
+    app.apply_update(ChatUpdate::StreamEnd);
+
+    assert!(!app.thinking);
+    assert!(app.messages.is_empty());
+}
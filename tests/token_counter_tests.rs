@@ -1,4 +1,9 @@
-use engine_builder::utils::token_counter::{count_tokens, count_tokens_with_fallback};
+use engine_builder::utils::token_counter::{
+    count_tokens, count_tokens_for_model, count_tokens_with_fallback, tokenizer_for_model,
+    BpeTokenizer, CachedTokenizer, Tokenizer, WhitespaceTokenizer, CLAUDE_TOKEN_RATIO,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 #[test]
 fn test_count_tokens_empty_string() {
@@ -19,33 +24,33 @@ fn test_count_tokens_multiple_tokens() {
 }
 
 #[test]
-fn test_count_tokens_multiple_spaces() {
+fn test_whitespace_tokenizer_multiple_spaces() {
     let text = "hello   world"; // Multiple spaces
-    assert_eq!(count_tokens(text), 2);
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 2);
 }
 
 #[test]
-fn test_count_tokens_newlines() {
+fn test_whitespace_tokenizer_newlines() {
     let text = "hello\nworld";
-    assert_eq!(count_tokens(text), 2);
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 2);
 }
 
 #[test]
-fn test_count_tokens_mixed_whitespace() {
+fn test_whitespace_tokenizer_mixed_whitespace() {
     let text = "hello\n  world\t  test";
-    assert_eq!(count_tokens(text), 3);
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 3);
 }
 
 #[test]
-fn test_count_tokens_trailing_space() {
+fn test_whitespace_tokenizer_trailing_space() {
     let text = "hello world ";
-    assert_eq!(count_tokens(text), 2);
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 2);
 }
 
 #[test]
-fn test_count_tokens_leading_space() {
+fn test_whitespace_tokenizer_leading_space() {
     let text = " hello world";
-    assert_eq!(count_tokens(text), 2);
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 2);
 }
 
 #[test]
@@ -59,3 +64,94 @@ fn test_count_tokens_with_fallback_normal() {
     let text = "hello world";
     assert_eq!(count_tokens_with_fallback(text), 2);
 }
+
+#[test]
+fn test_minified_code_counts_more_than_words() {
+    // Minified code has few spaces, but many tokens
+    let text = "(a,b)=>a.map(x=>x*b).filter(y=>y>0).reduce((s,v)=>s+v,0)";
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 1);
+    assert!(count_tokens(text) > 20);
+}
+
+#[test]
+fn test_cjk_text_counts_more_than_words() {
+    let text = "这是一个没有空格的中文句子用于测试分词器";
+    assert_eq!(WhitespaceTokenizer.count_tokens(text), 1);
+    assert!(count_tokens(text) >= 10);
+}
+
+#[test]
+fn test_tokenizer_chosen_by_model() {
+    assert_eq!(tokenizer_for_model("gpt-4").name(), "cl100k_base");
+    assert_eq!(tokenizer_for_model("gpt-4o-mini").name(), "o200k_base");
+    assert_eq!(tokenizer_for_model("openai/o3-mini").name(), "o200k_base");
+    assert_eq!(
+        tokenizer_for_model("claude-3-7-sonnet-20250219").name(),
+        "claude"
+    );
+    assert_eq!(
+        tokenizer_for_model("some-unknown-model").name(),
+        "cl100k_base"
+    );
+}
+
+#[test]
+fn test_claude_counts_are_scaled_from_bpe_counts() {
+    let text = "fn main() {\n    println!(\"Hello, world!\");\n}\n".repeat(20);
+    let bpe = BpeTokenizer::cl100k().unwrap().count_tokens(&text);
+    let claude = count_tokens_for_model("claude-3-5-haiku-latest", &text);
+    assert!(claude > bpe);
+    assert!(claude < bpe * 2);
+}
+
+#[test]
+fn test_claude_estimates_stay_within_the_ratio_band() {
+    let samples = [
+        include_str!("../src/utils/token_counter.rs"),
+        include_str!("../README.md"),
+        "x",
+    ];
+    let cl100k = BpeTokenizer::cl100k().unwrap();
+    for text in samples {
+        let bpe = cl100k.count_tokens(text) as f64;
+        let claude = count_tokens_for_model("claude-3-5-haiku-latest", text) as f64;
+        // Never below the scaled count, and at most one token of rounding above it
+        assert!(
+            claude >= bpe * CLAUDE_TOKEN_RATIO,
+            "{claude} < {bpe} * ratio"
+        );
+        assert!(
+            claude <= bpe * CLAUDE_TOKEN_RATIO + 1.0,
+            "{claude} > {bpe} * ratio + 1"
+        );
+    }
+    assert!((1.1..=1.3).contains(&CLAUDE_TOKEN_RATIO));
+}
+
+/// Counts words, remembering how many times it was asked
+struct CountingTokenizer(AtomicUsize);
+
+impl Tokenizer for CountingTokenizer {
+    fn name(&self) -> &str {
+        "counting"
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        WhitespaceTokenizer.count_tokens(text)
+    }
+}
+
+#[test]
+fn test_cached_tokenizer_counts_each_content_once() {
+    let inner = Arc::new(CountingTokenizer(AtomicUsize::new(0)));
+    let tokenizer = CachedTokenizer::new(inner.clone());
+
+    assert_eq!(tokenizer.count_tokens("hello world"), 2);
+    assert_eq!(tokenizer.count_tokens("hello world"), 2);
+    assert_eq!(tokenizer.count_tokens("goodbye"), 1);
+
+    assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+    assert_eq!(tokenizer.len(), 2);
+    assert_eq!(tokenizer.name(), "counting");
+}
@@ -0,0 +1,160 @@
+use engine_builder::utils::token_counter::{Tokenizer, WhitespaceTokenizer};
+use engine_builder::utils::tree_renderer::{render_tree, TreeBudget};
+
+mod common;
+use common::strings;
+
+fn is_build_file(path: &str) -> bool {
+    path.ends_with("Cargo.toml") || path.ends_with("setup.py")
+}
+
+fn budget(max_tokens: usize) -> TreeBudget<'static> {
+    TreeBudget {
+        max_tokens,
+        tokenizer: &WhitespaceTokenizer,
+        is_build_file: &is_build_file,
+    }
+}
+
+/// A codebase with a few source files and a large fixtures directory
+fn large_codebase() -> Vec<String> {
+    let mut files = strings(&[
+        "Cargo.toml",
+        "README.md",
+        "src/lib.rs",
+        "src/main.rs",
+        "tests/test_api.py",
+    ]);
+    for i in 0..1100 {
+        files.push(format!("tests/fixtures/case_{:04}.json", i));
+    }
+    for i in 0..104 {
+        files.push(format!("tests/fixtures/gen/case_{:03}.py", i));
+    }
+    files
+}
+
+#[test]
+fn test_small_tree_is_rendered_in_full() {
+    let files = strings(&["src/main.rs", "Cargo.toml", "src/util/mod.rs", "README.md"]);
+    let dirs = strings(&["empty"]);
+    let expected = "\
+└── ./
+    ├── empty/
+    ├── src/
+    │   ├── util/
+    │   │   └── mod.rs
+    │   └── main.rs
+    ├── Cargo.toml
+    └── README.md
+";
+
+    assert_eq!(render_tree(&files, &dirs, None), expected);
+    assert_eq!(render_tree(&files, &dirs, Some(&budget(1000))), expected);
+    // A budget of 0 doesn't limit the tree
+    assert_eq!(render_tree(&files, &dirs, Some(&budget(0))), expected);
+}
+
+#[test]
+fn test_large_directory_is_collapsed_into_summary() {
+    let tree = render_tree(&large_codebase(), &[], Some(&budget(60)));
+
+    assert_eq!(
+        tree,
+        "\
+└── ./
+    ├── src/
+    │   ├── lib.rs
+    │   └── main.rs
+    ├── tests/
+    │   ├── fixtures/ (1,204 files: .json ×1,100, .py ×104)
+    │   └── test_api.py
+    ├── Cargo.toml
+    └── README.md
+"
+    );
+}
+
+#[test]
+fn test_tree_fits_budget() {
+    let files = large_codebase();
+    let full = render_tree(&files, &[], None);
+    let full_tokens = WhitespaceTokenizer.count_tokens(&full);
+
+    for max_tokens in [40, 100, 500, 2000] {
+        let tree = render_tree(&files, &[], Some(&budget(max_tokens)));
+        let tokens = WhitespaceTokenizer.count_tokens(&tree);
+        assert!(
+            tokens <= max_tokens,
+            "{} tokens for a budget of {}",
+            tokens,
+            max_tokens
+        );
+        assert!(tokens < full_tokens);
+    }
+
+    // With room for everything, nothing is collapsed. Line estimates err
+    // high, so leave some room.
+    let tree = render_tree(&files, &[], Some(&budget(2 * full_tokens)));
+    assert_eq!(tree, full);
+}
+
+#[test]
+fn test_build_files_are_listed_first() {
+    let mut files = strings(&["setup.py", "web/Cargo.toml", "web/app.js", "lib/util.js"]);
+    for i in 0..50 {
+        files.push(format!("script_{:02}.sh", i));
+    }
+
+    let tree = render_tree(&files, &[], Some(&budget(38)));
+
+    // The root lists its build files and summarizes the others, and the
+    // directory with build files is expanded before its sibling
+    assert_eq!(
+        tree,
+        "\
+└── ./
+    ├── lib/ (1 file: .js ×1)
+    ├── web/
+    │   ├── Cargo.toml
+    │   └── app.js
+    ├── setup.py
+    └── … 50 more files: .sh ×50
+"
+    );
+}
+
+#[test]
+fn test_summary_names_the_most_common_extensions() {
+    let mut files = Vec::new();
+    for (extension, count) in [("json", 1500), ("py", 20), ("txt", 20), ("md", 3), ("c", 1)] {
+        for i in 0..count {
+            files.push(format!("data/file_{}.{}", i, extension));
+        }
+    }
+    files.push("data/Makefile".to_string());
+
+    let tree = render_tree(&files, &[], Some(&budget(20)));
+
+    assert_eq!(
+        tree,
+        "\
+└── ./
+    └── data/ (1,545 files: .json ×1,500, .py ×20, .txt ×20, other ×5)
+"
+    );
+}
+
+#[test]
+fn test_output_does_not_depend_on_input_order() {
+    let files = large_codebase();
+    let mut reversed = files.clone();
+    reversed.reverse();
+
+    for max_tokens in [0, 60, 300] {
+        assert_eq!(
+            render_tree(&files, &[], Some(&budget(max_tokens))),
+            render_tree(&reversed, &[], Some(&budget(max_tokens)))
+        );
+    }
+}